@@ -0,0 +1,294 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use crate::{
+    NodeId, WayId,
+    error::Result,
+    osm_parser::{Node, SimpleNode, SimpleWay},
+    utils::{self, Quadkey, TileNaming},
+};
+
+/// A house number on a street, for snapping destination addresses to the graph
+#[derive(Clone, Debug, bincode::Encode, bincode::Decode)]
+pub(crate) struct Address {
+    pub(crate) street: String,
+    pub(crate) housenumber: String,
+    pub(crate) lat: f64,
+    pub(crate) lon: f64,
+}
+
+/// Which house numbers an `addr:interpolation` way stands for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) enum Interpolation {
+    All,
+    Odd,
+    Even,
+}
+
+/// A way that addresses are derived from once its nodes are known
+#[derive(Clone, Debug, bincode::Encode, bincode::Decode)]
+pub(crate) enum AddressWay {
+    /// A building or other area with an address, located at its centroid
+    Area {
+        street: String,
+        housenumber: String,
+        nodes: Vec<NodeId>,
+    },
+    /// A line between address nodes, with the numbers in between spread along it
+    Interpolation {
+        id: WayId,
+        interpolation: Interpolation,
+        /// Taken from the end nodes when not tagged on the way
+        street: Option<String>,
+        nodes: Vec<NodeId>,
+    },
+}
+impl AddressWay {
+    pub(crate) fn nodes(&self) -> &[NodeId] {
+        match self {
+            AddressWay::Area { nodes, .. } | AddressWay::Interpolation { nodes, .. } => nodes,
+        }
+    }
+}
+
+/// The addresses within a tile, written as `<quadkey>.addr`
+#[derive(Debug, Default, bincode::Encode, bincode::Decode)]
+pub(crate) struct AddressTile {
+    /// Sorted by street and house number
+    pub(crate) addresses: Vec<Address>,
+}
+
+/// Reads the address of a node, `None` without both street and house number
+pub(crate) fn parse_address_node<N: SimpleNode>(node: &N) -> Option<(NodeId, Address)> {
+    let mut street = None;
+    let mut housenumber = None;
+    for (key, value) in node.tags() {
+        match key {
+            "addr:street" => street = Some(value),
+            "addr:housenumber" => housenumber = Some(value),
+            _ => {}
+        }
+    }
+    Some((
+        NodeId(node.id()),
+        Address {
+            street: street?.to_string(),
+            housenumber: housenumber?.to_string(),
+            lat: node.lat(),
+            lon: node.lon(),
+        },
+    ))
+}
+
+/// Reads an addressed area or an interpolation line
+pub(crate) fn parse_address_way<W: SimpleWay>(way: &W) -> Option<AddressWay> {
+    let mut street = None;
+    let mut housenumber = None;
+    let mut interpolation = None;
+    for (key, value) in way.tags() {
+        match key {
+            "addr:street" => street = Some(value.to_string()),
+            "addr:housenumber" => housenumber = Some(value.to_string()),
+            "addr:interpolation" => {
+                interpolation = match value {
+                    "all" => Some(Interpolation::All),
+                    "odd" => Some(Interpolation::Odd),
+                    "even" => Some(Interpolation::Even),
+                    _ => None,
+                }
+            }
+            _ => {}
+        }
+    }
+    let nodes = way.refs().map(NodeId).collect();
+    match interpolation {
+        Some(interpolation) => Some(AddressWay::Interpolation {
+            id: WayId(way.id()),
+            interpolation,
+            street,
+            nodes,
+        }),
+        None => Some(AddressWay::Area {
+            street: street?,
+            housenumber: housenumber?,
+            nodes,
+        }),
+    }
+}
+
+/// Locates the addresses of all nodes and ways, interpolating house numbers
+pub(crate) fn resolve_addresses(
+    address_nodes: Vec<(NodeId, Address)>,
+    address_ways: &[AddressWay],
+    node_table: &HashMap<NodeId, Node>,
+) -> Vec<Address> {
+    let address_nodes = address_nodes.into_iter().collect::<HashMap<_, _>>();
+    let mut addresses = address_nodes.values().cloned().collect::<Vec<_>>();
+    for address_way in address_ways {
+        let coords = address_way
+            .nodes()
+            .iter()
+            .filter_map(|node_id| node_table.get(node_id))
+            .map(|node| (node.loc.lat, node.loc.lon))
+            .collect::<Vec<_>>();
+        match address_way {
+            AddressWay::Area {
+                street,
+                housenumber,
+                nodes,
+            } => {
+                // Closed ways repeat their first node, which would pull the centroid towards it
+                let num_coords = if nodes.first() == nodes.last() {
+                    coords.len().saturating_sub(1)
+                } else {
+                    coords.len()
+                };
+                if num_coords == 0 {
+                    continue;
+                }
+                let (lat, lon) = coords[..num_coords]
+                    .iter()
+                    .fold((0.0, 0.0), |sum, coord| (sum.0 + coord.0, sum.1 + coord.1));
+                addresses.push(Address {
+                    street: street.clone(),
+                    housenumber: housenumber.clone(),
+                    lat: lat / num_coords as f64,
+                    lon: lon / num_coords as f64,
+                });
+            }
+            AddressWay::Interpolation {
+                id,
+                interpolation,
+                street,
+                nodes,
+            } => {
+                if coords.len() != nodes.len() {
+                    println!(
+                        "WARN: Skipping interpolation way {} with nodes outside the data",
+                        id.0
+                    );
+                    continue;
+                }
+                addresses.extend(interpolate(
+                    *interpolation,
+                    street.as_deref(),
+                    nodes,
+                    &coords,
+                    &address_nodes,
+                ));
+            }
+        }
+    }
+    addresses
+}
+
+/// Spreads the house numbers between each pair of numbered nodes along the line
+fn interpolate(
+    interpolation: Interpolation,
+    street: Option<&str>,
+    nodes: &[NodeId],
+    coords: &[(f64, f64)],
+    address_nodes: &HashMap<NodeId, Address>,
+) -> Vec<Address> {
+    // Distance along the line to every node
+    let mut distances = vec![0.0];
+    for pair in coords.windows(2) {
+        let distance = utils::haversine_distance(pair[0].0, pair[0].1, pair[1].0, pair[1].1);
+        distances.push(distances.last().copied().unwrap_or_default() + distance);
+    }
+    let numbered = nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(index, node_id)| {
+            let address = address_nodes.get(node_id)?;
+            let number = leading_number(&address.housenumber)?;
+            Some((index, number, address.street.as_str()))
+        })
+        .collect::<Vec<_>>();
+
+    let mut addresses = Vec::new();
+    for pair in numbered.windows(2) {
+        let ((start, first, first_street), (end, last, _)) = (pair[0], pair[1]);
+        let street = street.unwrap_or(first_street);
+        let (low, high) = (first.min(last), first.max(last));
+        let (step, start_number) = match interpolation {
+            Interpolation::All => (1, low + 1),
+            Interpolation::Odd | Interpolation::Even => (2, low + 2),
+        };
+        let span = f64::from(high - low);
+        for number in (start_number..high).step_by(step) {
+            // Measured from the first node, whichever direction the numbers run in
+            let fraction = f64::from(number.abs_diff(first)) / span;
+            let distance = distances[start] + (distances[end] - distances[start]) * fraction;
+            let (lat, lon) = point_at(coords, &distances, distance);
+            addresses.push(Address {
+                street: street.to_string(),
+                housenumber: number.to_string(),
+                lat,
+                lon,
+            });
+        }
+    }
+    addresses
+}
+
+/// The number a house number starts with, `12` for `12B`
+fn leading_number(housenumber: &str) -> Option<u32> {
+    let digits = housenumber
+        .trim()
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?;
+    digits.parse().ok()
+}
+
+/// The coordinate at a distance along a line, given the distance to each of its nodes
+fn point_at(coords: &[(f64, f64)], distances: &[f64], distance: f64) -> (f64, f64) {
+    let index = distances
+        .windows(2)
+        .position(|pair| distance <= pair[1])
+        .unwrap_or(coords.len().saturating_sub(2));
+    let (from, to) = (coords[index], coords[(index + 1).min(coords.len() - 1)]);
+    let length = distances[(index + 1).min(distances.len() - 1)] - distances[index];
+    if length <= 0.0 {
+        return from;
+    }
+    let fraction = (distance - distances[index]) / length;
+    (
+        from.0 + (to.0 - from.0) * fraction,
+        from.1 + (to.1 - from.1) * fraction,
+    )
+}
+
+/// Writes addresses into per-tile files at a zoom level, returning the number of files
+pub(crate) fn write_addresses(
+    output_dir: &Path,
+    naming: TileNaming,
+    zoom: u8,
+    addresses: Vec<Address>,
+) -> Result<usize> {
+    let mut tiles: BTreeMap<String, AddressTile> = BTreeMap::new();
+    for address in addresses {
+        match utils::lat_lon_to_quadkey(address.lat, address.lon, zoom) {
+            Ok(quadkey) => tiles.entry(quadkey).or_default().addresses.push(address),
+            Err(err) => println!(
+                "WARN: Skipping address {} {}: {}",
+                address.street, address.housenumber, err
+            ),
+        }
+    }
+    for (quadkey, tile) in &mut tiles {
+        tile.addresses.sort_by(|a, b| {
+            (&a.street, leading_number(&a.housenumber), &a.housenumber).cmp(&(
+                &b.street,
+                leading_number(&b.housenumber),
+                &b.housenumber,
+            ))
+        });
+        let fname =
+            utils::tile_path(output_dir, &Quadkey(quadkey.clone()), naming).with_extension("addr");
+        utils::write_encoded(&fname, tile)?;
+    }
+    Ok(tiles.len())
+}
@@ -0,0 +1,95 @@
+use std::ops::Range;
+
+use bincode::{Decode, Encode};
+
+use crate::{Edge, NodeId, osm_parser::Loc};
+
+/// The edges at every node of a tile, so a search can expand a node without
+/// first building a map over all edges
+///
+/// Nodes are referred to by their index in `Tile::node_locations` and edges by
+/// their index in `Tile::edges`. Tiles without reverse edges can be traversed
+/// against the direction of two-way edges, which is what `incoming` is for.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub(crate) struct Adjacency {
+    /// Edges grouped by the node they start at
+    pub(crate) outgoing: EdgeGroups,
+    /// Edges grouped by the node they end at
+    pub(crate) incoming: EdgeGroups,
+}
+impl Adjacency {
+    /// Groups the edges by their ends, edges ending at unlocated nodes are left out
+    pub(crate) fn of_edges(node_locations: &[(NodeId, Loc)], edges: &[Edge]) -> Self {
+        let index = |node_id: NodeId| {
+            node_locations
+                .binary_search_by_key(&node_id.0, |(node_id, _loc)| node_id.0)
+                .ok()
+        };
+        let (from, to): (Vec<_>, Vec<_>) = edges
+            .iter()
+            .map(|edge| (index(edge.from), index(edge.to)))
+            .unzip();
+        Self {
+            outgoing: EdgeGroups::of_nodes(node_locations.len(), &from),
+            incoming: EdgeGroups::of_nodes(node_locations.len(), &to),
+        }
+    }
+
+    /// Checks that the groups cover the nodes and refer to existing edges, after
+    /// reading them from a tile
+    pub(crate) fn is_valid(&self, num_nodes: usize, num_edges: usize) -> bool {
+        self.outgoing.is_valid(num_nodes, num_edges) && self.incoming.is_valid(num_nodes, num_edges)
+    }
+}
+
+/// Indices of edges grouped by node, in compressed sparse row form
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub(crate) struct EdgeGroups {
+    /// Where the group of every node starts in `edges`, with one more entry for the
+    /// end of the last group
+    first_edge: Vec<u32>,
+    edges: Vec<u32>,
+}
+impl EdgeGroups {
+    /// Groups edges by the index of one of their nodes, keeping their order within groups
+    fn of_nodes(num_nodes: usize, nodes: &[Option<usize>]) -> Self {
+        let mut first_edge = vec![0u32; num_nodes + 1];
+        for node in nodes.iter().flatten() {
+            first_edge[node + 1] += 1;
+        }
+        for node in 0..num_nodes {
+            first_edge[node + 1] += first_edge[node];
+        }
+        let mut next = first_edge.clone();
+        let mut edges = vec![0; first_edge[num_nodes] as usize];
+        for (edge, node) in nodes.iter().enumerate() {
+            if let Some(node) = node {
+                edges[next[*node] as usize] = edge as u32;
+                next[*node] += 1;
+            }
+        }
+        Self { first_edge, edges }
+    }
+
+    /// Indices of the edges of the node at an index of `Tile::node_locations`
+    pub(crate) fn edges(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges[self.range(node)]
+            .iter()
+            .map(|edge| *edge as usize)
+    }
+
+    fn range(&self, node: usize) -> Range<usize> {
+        match (self.first_edge.get(node), self.first_edge.get(node + 1)) {
+            (Some(&start), Some(&end)) => start as usize..end as usize,
+            _ => 0..0,
+        }
+    }
+
+    fn is_valid(&self, num_nodes: usize, num_edges: usize) -> bool {
+        self.first_edge.len() == num_nodes + 1
+            && self.first_edge.first() == Some(&0)
+            && self.first_edge.windows(2).all(|pair| pair[0] <= pair[1])
+            && self.first_edge.last().map(|last| *last as usize) == Some(self.edges.len())
+            && self.edges.iter().all(|edge| (*edge as usize) < num_edges)
+    }
+}
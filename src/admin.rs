@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use crate::{
+    error::{IoContext, Result},
+    region::Region,
+};
+
+/// An administrative area and the bounding box to quickly rule points out
+#[derive(Debug)]
+struct AdminArea {
+    code: String,
+    region: Region,
+    bounds: (f64, f64, f64, f64),
+}
+impl AdminArea {
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        let (min_lat, min_lon, max_lat, max_lon) = self.bounds;
+        (min_lat..=max_lat).contains(&lat)
+            && (min_lon..=max_lon).contains(&lon)
+            && self.region.contains(lat, lon)
+    }
+}
+
+/// Country and region boundaries to stamp edges with
+///
+/// Read from a directory of Osmosis `.poly` files named by their ISO 3166 code,
+/// `SE.poly` for a country and `SE-AB.poly` for one of its regions.
+#[derive(Debug, Default)]
+pub(crate) struct AdminAreas {
+    countries: Vec<AdminArea>,
+    regions: Vec<AdminArea>,
+}
+impl AdminAreas {
+    pub(crate) fn from_dir(dir: &Path) -> Result<Self> {
+        let mut admin_areas = Self::default();
+        for entry in std::fs::read_dir(dir)
+            .io_context(|| format!("Failed listing directory {}", dir.display()))?
+        {
+            let path = entry
+                .io_context(|| format!("Failed listing directory {}", dir.display()))?
+                .path();
+            if path.extension().is_none_or(|ext| ext != "poly") {
+                continue;
+            }
+            let Some(code) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let region = Region::from_poly_file(&path)?;
+            let area = AdminArea {
+                code: code.to_uppercase(),
+                bounds: region.bounds(),
+                region,
+            };
+            if area.code.contains('-') {
+                admin_areas.regions.push(area);
+            } else {
+                admin_areas.countries.push(area);
+            }
+        }
+        // Keep lookups deterministic where boundaries overlap
+        admin_areas.countries.sort_by(|a, b| a.code.cmp(&b.code));
+        admin_areas.regions.sort_by(|a, b| a.code.cmp(&b.code));
+        println!(
+            "INFO: Loaded {} countries and {} regions from {}",
+            admin_areas.countries.len(),
+            admin_areas.regions.len(),
+            dir.display()
+        );
+        Ok(admin_areas)
+    }
+
+    /// The country and region codes of a location
+    ///
+    /// A region implies its country, even when no boundary for the country is loaded.
+    pub(crate) fn lookup(&self, lat: f64, lon: f64) -> (Option<String>, Option<String>) {
+        let region = self
+            .regions
+            .iter()
+            .find(|area| area.contains(lat, lon))
+            .map(|area| area.code.clone());
+        let country = match &region {
+            Some(region) => region.split('-').next().map(str::to_string),
+            None => self
+                .countries
+                .iter()
+                .find(|area| area.contains(lat, lon))
+                .map(|area| area.code.clone()),
+        };
+        (country, region)
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use crate::{
+    cancel::CancellationToken,
+    error::{GladsheimError, IoContext, Result},
+    geometry, names,
+    osm_parser::Node,
+    utils::{TileSelector, TileStore},
+};
+
+/// Meters per degree of latitude, for the local projection used in distances
+const METERS_PER_DEGREE: f64 = 111_320.0;
+/// Size in degrees of the cells that external segments are indexed by
+const CELL_DEGREES: f64 = 0.002;
+/// Segments whose overall directions differ more than this, in degrees, are never matched
+const MAX_ANGLE_DEGREES: f64 = 45.0;
+
+/// A stretch of road in one of the networks being aligned
+pub(crate) struct Segment {
+    /// Identifies the segment in the crosswalk
+    pub(crate) id: String,
+    /// Normalized with `names::normalize`
+    pub(crate) name: Option<String>,
+    /// Coordinates as `(lat, lon)`
+    pub(crate) coords: Vec<(f64, f64)>,
+}
+
+/// A road network whose segments can be aligned with the edges of the tiles
+pub(crate) trait SegmentSource {
+    fn segments(&self, cancel: &CancellationToken) -> Result<Vec<Segment>>;
+}
+
+/// The edges of a tile directory, identified as `<way id>:<from node>:<to node>`
+///
+/// These ids are stable between builds as long as the way isn't split differently,
+/// which makes an older build a network to align with too.
+pub(crate) struct TileSegments<'a> {
+    pub(crate) tiles_dir: &'a Path,
+    pub(crate) selector: &'a TileSelector,
+}
+impl SegmentSource for TileSegments<'_> {
+    fn segments(&self, cancel: &CancellationToken) -> Result<Vec<Segment>> {
+        let tiles = TileStore::open(self.tiles_dir)?.select(self.selector);
+        let mut segments = Vec::new();
+        for (index, tile) in tiles.iter().enumerate() {
+            cancel.check(|| format!("reading {} of {} tiles", index, tiles.len()))?;
+            let (_quadkey, tile) = tile?;
+            let node_table = tile
+                .node_locations
+                .into_iter()
+                .map(|(node_id, loc)| {
+                    (
+                        node_id,
+                        Node {
+                            loc,
+                            ..Default::default()
+                        },
+                    )
+                })
+                .collect::<HashMap<_, _>>();
+            // Reverse edges share the geometry of their forward edge
+            segments.extend(
+                tile.edges
+                    .iter()
+                    .filter(|edge| !edge.is_reversed)
+                    .map(|edge| Segment {
+                        id: format!("{}:{}:{}", edge.way_id.0, edge.from.0, edge.to.0),
+                        name: edge.name.as_deref().map(names::normalize),
+                        coords: geometry::edge_coordinates(edge, &node_table),
+                    }),
+            );
+        }
+        Ok(segments)
+    }
+}
+
+/// Segments in a tab separated file with an id, a name and a WKT `LINESTRING` per line
+///
+/// Vendor networks, like those of HERE or TomTom, can be exported to this with e.g.
+/// `ogr2ogr -f CSV -lco GEOMETRY=AS_WKT -lco SEPARATOR=TAB`. Lines starting with
+/// `#` and a header line starting with `id` are skipped, and the name may be empty.
+pub(crate) struct WktSegments<'a> {
+    pub(crate) fname: &'a Path,
+}
+impl SegmentSource for WktSegments<'_> {
+    fn segments(&self, cancel: &CancellationToken) -> Result<Vec<Segment>> {
+        let file = std::fs::File::open(self.fname)
+            .io_context(|| format!("Failed opening file {}", self.fname.display()))?;
+        let mut segments = Vec::new();
+        for (line_index, line) in std::io::BufReader::new(file).lines().enumerate() {
+            let line =
+                line.io_context(|| format!("Failed reading file {}", self.fname.display()))?;
+            if line_index % 100_000 == 0 {
+                cancel
+                    .check(|| format!("reading line {} of {}", line_index, self.fname.display()))?;
+            }
+            if line.trim().is_empty() || line.starts_with('#') || line.starts_with("id\t") {
+                continue;
+            }
+            let invalid = || {
+                GladsheimError::parse(format!(
+                    "Invalid segment on line {} of {}, expected id, name and LINESTRING separated by tabs",
+                    line_index + 1,
+                    self.fname.display()
+                ))
+            };
+            let mut fields = line.split('\t');
+            let (Some(id), Some(name), Some(wkt)) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            let coords = parse_wkt_linestring(wkt).ok_or_else(invalid)?;
+            segments.push(Segment {
+                id: id.to_string(),
+                name: Some(names::normalize(name)).filter(|name| !name.is_empty()),
+                coords,
+            });
+        }
+        Ok(segments)
+    }
+}
+
+/// Opens a network to align with, a tile directory or a WKT file for `WktSegments`
+pub(crate) fn open_source<'a>(
+    path: &'a Path,
+    selector: &'a TileSelector,
+) -> Box<dyn SegmentSource + 'a> {
+    if path.is_dir() {
+        Box::new(TileSegments {
+            tiles_dir: path,
+            selector,
+        })
+    } else {
+        Box::new(WktSegments { fname: path })
+    }
+}
+
+/// Reads `LINESTRING (lon lat, lon lat, ...)` as `(lat, lon)` coordinates
+fn parse_wkt_linestring(wkt: &str) -> Option<Vec<(f64, f64)>> {
+    let wkt = wkt.trim().trim_matches('"');
+    let points = wkt
+        .strip_prefix("LINESTRING")?
+        .trim()
+        .strip_prefix('(')?
+        .strip_suffix(')')?;
+    let coords = points
+        .split(',')
+        .map(|point| {
+            let mut values = point.split_whitespace().map(str::parse::<f64>);
+            match (values.next(), values.next()) {
+                (Some(Ok(lon)), Some(Ok(lat))) => Some((lat, lon)),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (coords.len() >= 2).then_some(coords)
+}
+
+/// An edge of the tiles matched to a segment of the other network
+pub(crate) struct Alignment<'a> {
+    pub(crate) edge: &'a Segment,
+    pub(crate) other: &'a Segment,
+    /// Mean distance in meters between the two geometries
+    pub(crate) distance_m: f64,
+    /// Whether the other segment runs against the edge
+    pub(crate) is_reversed: bool,
+    /// Whether both have the same name, `None` when either is unnamed
+    pub(crate) name_matches: Option<bool>,
+}
+
+/// Matches every edge to the most similar segment of the other network, if any
+///
+/// Similarity is the mean distance between the geometries, which has to be within
+/// `max_distance_m`, weighted by whether the names agree. Segments running in
+/// opposite directions match, crossing ones don't.
+pub(crate) fn align<'a>(
+    edges: &'a [Segment],
+    others: &'a [Segment],
+    max_distance_m: f64,
+    cancel: &CancellationToken,
+) -> Result<Vec<Alignment<'a>>> {
+    let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (index, other) in others.iter().enumerate() {
+        for cell in cells_around(&other.coords, 0.0) {
+            cells.entry(cell).or_default().push(index);
+        }
+    }
+
+    let mut alignments = Vec::new();
+    for (edge_index, edge) in edges.iter().enumerate() {
+        if edge_index % 10_000 == 0 {
+            cancel.check(|| format!("aligning {} of {} edges", edge_index, edges.len()))?;
+        }
+        let Some(edge_bearing) = overall_bearing(&edge.coords) else {
+            continue;
+        };
+        let mut candidates = cells_around(&edge.coords, max_distance_m)
+            .filter_map(|cell| cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let best = candidates
+            .into_iter()
+            .filter_map(|index| {
+                let other = &others[index];
+                let angle = angle_between(edge_bearing, overall_bearing(&other.coords)?);
+                let is_reversed = angle > 90.0;
+                if angle.min(180.0 - angle) > MAX_ANGLE_DEGREES {
+                    return None;
+                }
+                let distance_m = mean_distance(&edge.coords, &other.coords);
+                if distance_m > max_distance_m {
+                    return None;
+                }
+                let name_matches = match (&edge.name, &other.name) {
+                    (Some(a), Some(b)) => Some(a == b),
+                    _ => None,
+                };
+                Some(Alignment {
+                    edge,
+                    other,
+                    distance_m,
+                    is_reversed,
+                    name_matches,
+                })
+            })
+            .min_by(|a, b| a.score().total_cmp(&b.score()));
+        alignments.extend(best);
+    }
+    Ok(alignments)
+}
+
+impl Alignment<'_> {
+    /// Lower is better, the distance made smaller by equal names and larger by differing ones
+    fn score(&self) -> f64 {
+        let factor = match self.name_matches {
+            Some(true) => 0.5,
+            Some(false) => 2.0,
+            None => 1.0,
+        };
+        self.distance_m * factor
+    }
+}
+
+/// Writes the crosswalk between edges and the other network as tab separated values
+pub(crate) fn write_crosswalk(writer: &mut impl Write, alignments: &[Alignment]) -> Result<()> {
+    let context = || "Failed writing the crosswalk".to_string();
+    writeln!(
+        writer,
+        "edge_id\tother_id\tdistance_m\treversed\tname_matches"
+    )
+    .io_context(context)?;
+    for alignment in alignments {
+        writeln!(
+            writer,
+            "{}\t{}\t{:.1}\t{}\t{}",
+            alignment.edge.id,
+            alignment.other.id,
+            alignment.distance_m,
+            alignment.is_reversed,
+            alignment
+                .name_matches
+                .map_or(String::new(), |matches| matches.to_string())
+        )
+        .io_context(context)?;
+    }
+    writer.flush().io_context(context)
+}
+
+/// The index cells covering the bounding box of a line grown by a margin in meters
+fn cells_around(coords: &[(f64, f64)], margin_m: f64) -> impl Iterator<Item = (i64, i64)> + use<> {
+    let (min_lat, min_lon, max_lat, max_lon) = coords.iter().fold(
+        (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+        |(min_lat, min_lon, max_lat, max_lon), (lat, lon)| {
+            (
+                min_lat.min(*lat),
+                min_lon.min(*lon),
+                max_lat.max(*lat),
+                max_lon.max(*lon),
+            )
+        },
+    );
+    let margin_lat = margin_m / METERS_PER_DEGREE;
+    let margin_lon = margin_lat
+        / min_lat
+            .abs()
+            .max(max_lat.abs())
+            .to_radians()
+            .cos()
+            .max(0.01);
+    let cell = |degrees: f64| (degrees / CELL_DEGREES).floor() as i64;
+    let (lat_cells, lon_cells) = (
+        cell(min_lat - margin_lat)..=cell(max_lat + margin_lat),
+        cell(min_lon - margin_lon)..=cell(max_lon + margin_lon),
+    );
+    lat_cells.flat_map(move |lat| lon_cells.clone().map(move |lon| (lat, lon)))
+}
+
+/// Bearing from the start to the end of a line, `None` for lines without length
+fn overall_bearing(coords: &[(f64, f64)]) -> Option<f64> {
+    let (first, last) = (coords.first()?, coords.last()?);
+    (first != last).then(|| geometry::bearing(first.0, first.1, last.0, last.1))
+}
+
+/// Difference between two bearings in degrees, in `[0, 180]`
+fn angle_between(a: f64, b: f64) -> f64 {
+    let difference = (a - b).rem_euclid(360.0);
+    difference.min(360.0 - difference)
+}
+
+/// Mean of the distances from the vertices of each line to the other line, in meters
+fn mean_distance(a: &[(f64, f64)], b: &[(f64, f64)]) -> f64 {
+    let sum = a
+        .iter()
+        .map(|point| distance_to_line(*point, b))
+        .sum::<f64>()
+        + b.iter()
+            .map(|point| distance_to_line(*point, a))
+            .sum::<f64>();
+    sum / (a.len() + b.len()) as f64
+}
+
+/// Distance in meters from a point to the closest point of a line
+///
+/// Uses a flat projection around the point, which is accurate at the short
+/// distances that matter for matching.
+fn distance_to_line(point: (f64, f64), line: &[(f64, f64)]) -> f64 {
+    let lon_scale = point.0.to_radians().cos();
+    let project = |(lat, lon): (f64, f64)| {
+        (
+            (lon - point.1) * lon_scale * METERS_PER_DEGREE,
+            (lat - point.0) * METERS_PER_DEGREE,
+        )
+    };
+    line.windows(2)
+        .map(|pair| {
+            let (a, b) = (project(pair[0]), project(pair[1]));
+            let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+            let length_squared = dx * dx + dy * dy;
+            // Position of the closest point along the segment, clamped to its ends
+            let t = if length_squared > 0.0 {
+                (-(a.0 * dx + a.1 * dy) / length_squared).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            (a.0 + t * dx).hypot(a.1 + t * dy)
+        })
+        .fold(f64::INFINITY, f64::min)
+}
@@ -0,0 +1,250 @@
+use std::{
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{GladsheimError, IoContext, Result},
+    utils::{Quadkey, TileContainer},
+};
+
+/// Name of the archive holding all tiles of a directory, instead of a file per tile
+pub(crate) const ARCHIVE_FILE_NAME: &str = "tiles.grta";
+/// Start of every archive, followed by `ARCHIVE_VERSION`
+const ARCHIVE_MAGIC: [u8; 4] = *b"GRTA";
+const ARCHIVE_VERSION: u16 = 1;
+/// Magic bytes, version and the length of the index
+const PREAMBLE_LEN: u64 = 4 + 2 + 8;
+
+/// Where the bytes of a tile are in an archive, relative to the end of the index
+#[derive(Clone, Debug, bincode::Encode, bincode::Decode)]
+struct ArchiveEntry {
+    quadkey: String,
+    offset: u64,
+    len: u64,
+}
+
+/// A single file holding the tiles of a directory, for file systems where many
+/// small files are slow
+///
+/// The file starts with the magic bytes, a version and the length of an index
+/// from quadkey to the position of each tile, followed by the index and the
+/// tiles, each encoded exactly like a `.grt` file.
+pub(crate) struct TileArchive {
+    fname: PathBuf,
+    /// Sorted by quadkey
+    entries: Vec<ArchiveEntry>,
+    data_start: u64,
+}
+impl TileArchive {
+    /// Reads the index of an archive, leaving the tiles for `read`
+    pub(crate) fn open(fname: &Path) -> Result<Self> {
+        let mut file = std::fs::File::open(fname)
+            .io_context(|| format!("Failed opening file {}", fname.display()))?;
+        let mut preamble = [0; PREAMBLE_LEN as usize];
+        file.read_exact(&mut preamble)
+            .io_context(|| format!("Failed reading file {}", fname.display()))?;
+        if preamble[..4] != ARCHIVE_MAGIC {
+            return Err(GladsheimError::parse(format!(
+                "{} is not a tile archive",
+                fname.display()
+            )));
+        }
+        let version = u16::from_le_bytes([preamble[4], preamble[5]]);
+        if version != ARCHIVE_VERSION {
+            return Err(GladsheimError::parse(format!(
+                "Archive {} has version {}, while this build reads version {}",
+                fname.display(),
+                version,
+                ARCHIVE_VERSION
+            )));
+        }
+        let mut index_len = [0; 8];
+        index_len.copy_from_slice(&preamble[6..]);
+        let index_len = u64::from_le_bytes(index_len);
+        let mut index = Vec::new();
+        file.take(index_len)
+            .read_to_end(&mut index)
+            .io_context(|| format!("Failed reading file {}", fname.display()))?;
+        let (entries, _len): (Vec<ArchiveEntry>, _) =
+            bincode::decode_from_slice(&index, bincode::config::standard()).map_err(|source| {
+                GladsheimError::TileFormat {
+                    context: format!("Failed decoding the index of {}", fname.display()),
+                    source: Box::new(source),
+                }
+            })?;
+        Ok(Self {
+            fname: fname.to_path_buf(),
+            entries,
+            data_start: PREAMBLE_LEN + index_len,
+        })
+    }
+}
+impl TileContainer for TileArchive {
+    fn fname(&self) -> &Path {
+        &self.fname
+    }
+
+    fn quadkeys(&self) -> Vec<Quadkey> {
+        self.entries
+            .iter()
+            .map(|entry| Quadkey(entry.quadkey.clone()))
+            .collect()
+    }
+
+    fn read(&self, quadkey: &Quadkey) -> Result<Option<Vec<u8>>> {
+        let Ok(index) = self
+            .entries
+            .binary_search_by(|entry| entry.quadkey.cmp(&quadkey.0))
+        else {
+            return Ok(None);
+        };
+        let entry = &self.entries[index];
+        let read = || -> std::io::Result<Vec<u8>> {
+            let mut file = std::fs::File::open(&self.fname)?;
+            file.seek(SeekFrom::Start(self.data_start + entry.offset))?;
+            let mut bytes = Vec::new();
+            file.take(entry.len).read_to_end(&mut bytes)?;
+            if bytes.len() as u64 != entry.len {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(bytes)
+        };
+        let bytes = read().io_context(|| {
+            format!(
+                "Failed reading tile {} from {}",
+                quadkey.0,
+                self.fname.display()
+            )
+        })?;
+        Ok(Some(bytes))
+    }
+}
+
+/// Writes encoded tiles into an archive, returning whether the file changed
+///
+/// Like `utils::write_bytes`, an archive already holding the same bytes is
+/// left alone, and otherwise the archive is streamed into a temporary file
+/// next to it that is renamed into place, without a copy of it in memory.
+pub(crate) fn write_archive(fname: &Path, mut tiles: Vec<(Quadkey, Vec<u8>)>) -> Result<bool> {
+    tiles.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+    let mut offset = 0;
+    let entries = tiles
+        .iter()
+        .map(|(quadkey, bytes)| {
+            let entry = ArchiveEntry {
+                quadkey: quadkey.0.clone(),
+                offset,
+                len: bytes.len() as u64,
+            };
+            offset += entry.len;
+            entry
+        })
+        .collect::<Vec<_>>();
+    let index =
+        bincode::encode_to_vec(&entries, bincode::config::standard()).map_err(|source| {
+            GladsheimError::TileFormat {
+                context: format!("Failed encoding the index of {}", fname.display()),
+                source: Box::new(source),
+            }
+        })?;
+
+    let mut preamble = Vec::with_capacity(PREAMBLE_LEN as usize);
+    preamble.extend(ARCHIVE_MAGIC);
+    preamble.extend(ARCHIVE_VERSION.to_le_bytes());
+    preamble.extend((index.len() as u64).to_le_bytes());
+    let parts = || {
+        [preamble.as_slice(), index.as_slice()]
+            .into_iter()
+            .chain(tiles.iter().map(|(_quadkey, bytes)| bytes.as_slice()))
+    };
+    let len = PREAMBLE_LEN + index.len() as u64 + offset;
+    if holds_parts(fname, len, parts()) {
+        return Ok(false);
+    }
+
+    if let Some(dir) = fname.parent() {
+        std::fs::create_dir_all(dir)
+            .io_context(|| format!("Failed creating directory {}", dir.display()))?;
+    }
+    let mut tmp_name = fname.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_fname = PathBuf::from(tmp_name);
+    let write = || -> std::io::Result<()> {
+        let mut file = BufWriter::new(std::fs::File::create(&tmp_fname)?);
+        for part in parts() {
+            file.write_all(part)?;
+        }
+        // Flushed before the rename, or a crash could leave the renamed file empty
+        file.into_inner()
+            .map_err(|error| error.into_error())?
+            .sync_all()?;
+        std::fs::rename(&tmp_fname, fname)
+    };
+    if let Err(error) = write() {
+        let _ = std::fs::remove_file(&tmp_fname);
+        return Err(error).io_context(|| format!("Failed writing to file {}", fname.display()));
+    }
+    Ok(true)
+}
+
+/// Whether a file holds exactly the given parts one after the other, reading
+/// it back only when it has their length
+fn holds_parts<'a>(fname: &Path, len: u64, parts: impl Iterator<Item = &'a [u8]>) -> bool {
+    if !std::fs::metadata(fname).is_ok_and(|metadata| metadata.len() == len) {
+        return false;
+    }
+    let Ok(file) = std::fs::File::open(fname) else {
+        return false;
+    };
+    let mut reader = BufReader::new(file);
+    let mut existing = Vec::new();
+    for part in parts {
+        existing.resize(part.len(), 0);
+        if reader.read_exact(&mut existing).is_err() || existing != part {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives_round_trip_and_unchanged_ones_are_kept() {
+        let dir = std::env::temp_dir().join(format!("gladsheim-archive-{}", std::process::id()));
+        let fname = dir.join(ARCHIVE_FILE_NAME);
+        let tiles = vec![
+            (Quadkey("1203".to_string()), vec![7; 300]),
+            (Quadkey("0123".to_string()), b"a tile".to_vec()),
+            (Quadkey("2".to_string()), Vec::new()),
+        ];
+        let written = write_archive(&fname, tiles.clone());
+        let rewritten = write_archive(&fname, tiles.clone());
+        let mut changed = tiles.clone();
+        changed[1].1[0] = b'A';
+        let changed_written = write_archive(&fname, changed);
+        let archive = TileArchive::open(&fname).unwrap();
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        let read = tiles
+            .iter()
+            .map(|(quadkey, _bytes)| archive.read(quadkey).unwrap())
+            .collect::<Vec<_>>();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(written.unwrap());
+        assert!(!rewritten.unwrap());
+        assert!(changed_written.unwrap());
+        assert_eq!(leftovers, 1);
+        assert_eq!(
+            archive.quadkeys(),
+            ["0123", "1203", "2"].map(|quadkey| Quadkey(quadkey.to_string()))
+        );
+        assert_eq!(read[0].as_deref(), Some(&[7; 300][..]));
+        assert_eq!(read[1].as_deref(), Some(&b"A tile"[..]));
+        assert_eq!(read[2].as_deref(), Some(&[][..]));
+        assert!(archive.read(&Quadkey("3".to_string())).unwrap().is_none());
+    }
+}
@@ -0,0 +1,52 @@
+use crate::NodeId;
+
+/// Bits per node id, giving about 1% false positives with `NUM_HASHES` hashes
+const BITS_PER_NODE: usize = 10;
+const NUM_HASHES: u32 = 7;
+
+/// A Bloom filter over the node ids of a tile
+///
+/// Answers whether a tile may contain a node without decoding its edges. False
+/// positives are possible, so a hit has to be confirmed against the tile itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) struct NodeFilter {
+    bits: Vec<u64>,
+}
+impl NodeFilter {
+    pub(crate) fn from_nodes<'a>(node_ids: impl ExactSizeIterator<Item = &'a NodeId>) -> Self {
+        let num_words = (node_ids.len() * BITS_PER_NODE).max(64).div_ceil(64);
+        let num_bits = num_words * 64;
+        let mut filter = Self {
+            bits: vec![0; num_words],
+        };
+        for node_id in node_ids {
+            for bit in bit_indices(num_bits, *node_id) {
+                filter.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        filter
+    }
+
+    pub(crate) fn may_contain(&self, node_id: NodeId) -> bool {
+        !self.bits.is_empty()
+            && bit_indices(self.bits.len() * 64, node_id)
+                .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// The bits of a node id in a filter, by double hashing two halves of a 64-bit mix
+fn bit_indices(num_bits: usize, node_id: NodeId) -> impl Iterator<Item = usize> {
+    let hash = mix(node_id.0 as u64);
+    let (h1, h2) = (hash & 0xFFFF_FFFF, (hash >> 32) | 1);
+    let num_bits = num_bits as u64;
+    (0..u64::from(NUM_HASHES))
+        .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+}
+
+/// The SplitMix64 finalizer, spreading consecutive ids over all bits
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
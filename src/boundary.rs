@@ -0,0 +1,114 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    NodeId,
+    utils::{Quadkey, Tile},
+};
+
+/// A graph node of a tile that edges stored in other tiles end at too
+///
+/// Edges are stored in the tile of their first node, so an edge crossing into a
+/// tile can't be seen from that tile. A router going tile by tile continues from
+/// a boundary node by loading the tiles listed here.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct BoundaryNode {
+    pub(crate) node_id: NodeId,
+    /// The other tiles with edges starting or ending at the node, sorted by quadkey
+    pub(crate) tiles: Vec<Quadkey>,
+}
+
+/// Finds the boundary nodes of every tile that shares graph nodes with another
+///
+/// Tiles without any are left out, the nodes of each tile are sorted by id.
+pub(crate) fn boundary_nodes<'a>(
+    tiles: impl IntoIterator<Item = (&'a Quadkey, &'a Tile)>,
+) -> HashMap<Quadkey, Vec<BoundaryNode>> {
+    let mut tiles_at: HashMap<NodeId, Vec<&Quadkey>> = HashMap::new();
+    for (quadkey, tile) in tiles {
+        for node_id in tile.edges.iter().flat_map(|edge| [edge.from, edge.to]) {
+            let quadkeys = tiles_at.entry(node_id).or_default();
+            if !quadkeys.contains(&quadkey) {
+                quadkeys.push(quadkey);
+            }
+        }
+    }
+
+    let mut boundaries: HashMap<Quadkey, Vec<BoundaryNode>> = HashMap::new();
+    for (node_id, quadkeys) in tiles_at
+        .iter()
+        .filter(|(_node_id, quadkeys)| quadkeys.len() > 1)
+    {
+        for quadkey in quadkeys {
+            let mut others = quadkeys
+                .iter()
+                .filter(|other| other != &quadkey)
+                .map(|other| (*other).clone())
+                .collect::<Vec<_>>();
+            others.sort_by(|a, b| a.0.cmp(&b.0));
+            boundaries
+                .entry((*quadkey).clone())
+                .or_default()
+                .push(BoundaryNode {
+                    node_id: *node_id,
+                    tiles: others,
+                });
+        }
+    }
+    for nodes in boundaries.values_mut() {
+        nodes.sort_by_key(|node| node.node_id.0);
+    }
+    boundaries
+}
+
+/// Stores the boundary nodes of a complete set of tiles in each of them
+pub(crate) fn link_tiles(tiles: &mut [(Quadkey, Tile)]) {
+    let mut boundaries = boundary_nodes(tiles.iter().map(|(quadkey, tile)| (quadkey, tile)));
+    for (quadkey, tile) in tiles {
+        tile.boundary_nodes = boundaries.remove(quadkey).unwrap_or_default();
+    }
+}
+
+/// Updates the boundary nodes of some tiles of a directory, keeping the links
+/// to the tiles left out, which still have the same edges
+///
+/// Returns the quadkeys of the tiles whose boundary nodes changed.
+pub(crate) fn relink_tiles(tiles: &mut HashMap<Quadkey, Tile>) -> Vec<Quadkey> {
+    let mut boundaries = boundary_nodes(tiles.iter());
+    let read = tiles.keys().cloned().collect::<HashSet<_>>();
+    let mut changed = Vec::new();
+    for (quadkey, tile) in tiles.iter_mut() {
+        let mut nodes = boundaries.remove(quadkey).unwrap_or_default();
+        let graph_nodes = tile
+            .edges
+            .iter()
+            .flat_map(|edge| [edge.from, edge.to])
+            .collect::<HashSet<_>>();
+        for old in &tile.boundary_nodes {
+            let unread = old
+                .tiles
+                .iter()
+                .filter(|other| !read.contains(*other))
+                .cloned()
+                .collect::<Vec<_>>();
+            if unread.is_empty() || !graph_nodes.contains(&old.node_id) {
+                continue;
+            }
+            match nodes.iter_mut().find(|node| node.node_id == old.node_id) {
+                Some(node) => {
+                    node.tiles.extend(unread);
+                    node.tiles.sort_by(|a, b| a.0.cmp(&b.0));
+                }
+                None => nodes.push(BoundaryNode {
+                    node_id: old.node_id,
+                    tiles: unread,
+                }),
+            }
+        }
+        nodes.sort_by_key(|node| node.node_id.0);
+        if tile.boundary_nodes != nodes {
+            tile.boundary_nodes = nodes;
+            changed.push(quadkey.clone());
+        }
+    }
+    changed
+}
@@ -0,0 +1,33 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use crate::error::{GladsheimError, Result};
+
+/// A flag shared between a caller and long-running work, to stop it cooperatively
+///
+/// Clones share the same flag. Work checks it between steps and when cancelled
+/// returns `GladsheimError::Cancelled` describing how far it got.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CancellationToken(Arc<AtomicBool>);
+impl CancellationToken {
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fails when cancelled, with `progress` describing the work completed so far
+    pub(crate) fn check(&self, progress: impl FnOnce() -> String) -> Result<()> {
+        if self.is_cancelled() {
+            Err(GladsheimError::Cancelled {
+                progress: progress(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
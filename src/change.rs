@@ -0,0 +1,550 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    Edge, NodeId, Way, WayId,
+    bloom::NodeFilter,
+    boundary,
+    cancel::CancellationToken,
+    error::{GladsheimError, IoContext, Result},
+    flat_tile, manifest, names,
+    osm_parser::{self, Node, OwnedElement, ParseOptions},
+    osm_xml::{self, ChangeAction},
+    profile::Profile,
+    tile_proto,
+    utils::{self, Quadkey, Tile, TileStorage, TileStore},
+};
+
+/// Applies an osmChange file to an existing directory of tiles
+///
+/// Only the tiles holding changed nodes, or nodes of changed ways, are read and
+/// rewritten. They're found through the node filters of tiles built with
+/// `--node-filter`, and otherwise through the locations of those nodes and the
+/// boundary nodes of the tiles read, which misses edges only passing through a
+/// tile without ending at a node of interest there. Nodes of changed ways are
+/// located from the change, the tiles or, when given, the `source` extract, which
+/// locates the nodes of ways that a change of tags makes routable.
+///
+/// Ways are re-split against the intersections already present in the tiles,
+/// and existing edges are split where a new way joins them midway. Optional
+/// edge attributes such as resampled geometry and administrative areas are not
+/// recomputed, level crossing delays use the defaults, and changed
+/// destination-only ways form zones of their own.
+pub(crate) fn apply_osm_change(
+    change_file: &Path,
+    tiles_dir: &Path,
+    source: Option<&Path>,
+    profile: Profile,
+    extra_profiles: &[Profile],
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let start_time = std::time::Instant::now();
+    let changes = osm_xml::read_change(change_file)?;
+    let store = TileStore::open(tiles_dir)?;
+    // New tiles follow the naming of the existing ones
+    let naming = store.naming();
+    let storage = store.storage();
+    // Flat and protobuf tiles are kept up to date when the tiles were built with them
+    let has_flat_tiles = store
+        .quadkeys()
+        .any(|quadkey| flat_tile::flat_tile_path(tiles_dir, quadkey, naming).exists());
+    let has_proto_tiles = store
+        .quadkeys()
+        .any(|quadkey| tile_proto::proto_tile_path(tiles_dir, quadkey, naming).exists());
+    // The headers tell what the tiles were built for, without decoding them
+    let mut headers = Vec::with_capacity(store.len());
+    for quadkey in store.quadkeys() {
+        cancel.check(|| "reading the tile headers".to_string())?;
+        if let Some(header) = store.load_header(quadkey)? {
+            headers.push((quadkey, header));
+        }
+    }
+    if let Some((quadkey, header)) = headers.iter().find(|(_quadkey, header)| {
+        header.profile != profile || header.extra_profiles != extra_profiles
+    }) {
+        return Err(GladsheimError::parse(format!(
+            "Tile {} was built for the profiles {:?}, not {:?}",
+            quadkey.0,
+            std::iter::once(header.profile)
+                .chain(header.extra_profiles.iter().copied())
+                .collect::<Vec<_>>(),
+            std::iter::once(profile)
+                .chain(extra_profiles.iter().copied())
+                .collect::<Vec<_>>()
+        )));
+    }
+    if headers
+        .iter()
+        .any(|(_quadkey, header)| header.max_class.is_some())
+    {
+        return Err(GladsheimError::parse(format!(
+            "Tiles in {} are an overview level, update the base tiles and rebuild it instead",
+            tiles_dir.display()
+        )));
+    }
+    // New tiles follow the zoom level of the existing ones too
+    let zoom = headers
+        .first()
+        .map_or(osm_parser::DEFAULT_TILE_ZOOM, |(_quadkey, header)| {
+            header.zoom
+        });
+    if headers.iter().any(|(_quadkey, header)| header.zoom != zoom) {
+        return Err(GladsheimError::parse(format!(
+            "Tiles in {} have different zoom levels, rebuild them instead",
+            tiles_dir.display()
+        )));
+    }
+    // The change carries no timestamp of its own, so new tiles keep that of the dataset
+    let source_timestamp = headers
+        .iter()
+        .find_map(|(_quadkey, header)| header.source_timestamp);
+
+    let mut change_nodes = HashMap::new();
+    let mut deleted_nodes = HashSet::new();
+    let mut changed_nodes = HashSet::new();
+    // Changed ways, `None` when deleted or no longer routable in any of the profiles
+    let mut changed_ways: HashMap<WayId, Option<Way>> = HashMap::new();
+    for (action, element) in &changes {
+        match (action, element) {
+            (ChangeAction::Delete, OwnedElement::Node(node)) => {
+                change_nodes.remove(&NodeId(node.id));
+                deleted_nodes.insert(NodeId(node.id));
+                changed_nodes.insert(NodeId(node.id));
+            }
+            (_, OwnedElement::Node(node)) => {
+                let node_id = NodeId(node.id);
+                let interest = HashSet::from([node_id]);
+                change_nodes.extend(osm_parser::parse_node(node, &interest).into_nodes());
+                deleted_nodes.remove(&node_id);
+                changed_nodes.insert(node_id);
+            }
+            (ChangeAction::Delete, OwnedElement::Way(way)) => {
+                changed_ways.insert(WayId(way.id), None);
+            }
+            (_, OwnedElement::Way(way)) => {
+                let parsed = osm_parser::parse_way(way, profile, extra_profiles)
+                    .into_ways()
+                    .pop();
+                changed_ways.insert(WayId(way.id), parsed);
+            }
+            // Transit lines are only extracted when parsing a full extract
+            (_, OwnedElement::Relation(_)) => {}
+        }
+    }
+    println!(
+        "INFO: Read {} changes and {} tile headers in {}ms",
+        changes.len(),
+        headers.len(),
+        start_time.elapsed().as_millis()
+    );
+
+    let start_time = std::time::Instant::now();
+    // The nodes whose tiles the change may edit
+    let interest = changed_nodes
+        .iter()
+        .chain(changed_ways.values().flatten().flat_map(|way| &way.nodes))
+        .copied()
+        .collect::<HashSet<_>>();
+    let mut wanted = HashSet::new();
+    let mut has_node_filters = false;
+    for (quadkey, _header) in &headers {
+        cancel.check(|| "reading the node filters".to_string())?;
+        let Some(filter) = store.load_node_filter(quadkey)? else {
+            continue;
+        };
+        has_node_filters = true;
+        if interest.iter().any(|node_id| filter.may_contain(*node_id)) {
+            wanted.insert((*quadkey).clone());
+        }
+    }
+    let mut locations = change_nodes
+        .iter()
+        .map(|(node_id, node)| (*node_id, node.loc.clone()))
+        .collect::<HashMap<_, _>>();
+    let mut source_nodes = HashMap::new();
+    let mut tiles: HashMap<Quadkey, Tile> = HashMap::new();
+    let mut requested = HashSet::new();
+    let mut has_read_source = false;
+    loop {
+        // The tiles the nodes of interest lie in, and those with edges ending at them
+        for loc in locations.values() {
+            wanted.insert(Quadkey(utils::lat_lon_to_quadkey(loc.lat, loc.lon, zoom)?));
+        }
+        for tile in tiles.values() {
+            wanted.extend(
+                tile.boundary_nodes
+                    .iter()
+                    .filter(|node| interest.contains(&node.node_id))
+                    .flat_map(|node| node.tiles.iter().cloned()),
+            );
+        }
+        let to_read = wanted.difference(&requested).cloned().collect::<Vec<_>>();
+        if to_read.is_empty() {
+            let unlocated = changed_ways
+                .values()
+                .flatten()
+                .flat_map(|way| &way.nodes)
+                .filter(|node_id| !locations.contains_key(node_id))
+                .copied()
+                .collect::<HashSet<_>>();
+            let Some(source) = source.filter(|_| !has_read_source && !unlocated.is_empty()) else {
+                break;
+            };
+            println!(
+                "INFO: Locating {} nodes of changed ways in {}",
+                unlocated.len(),
+                source.display()
+            );
+            source_nodes = osm_parser::read_nodes(source, &unlocated, cancel)?;
+            locations.extend(
+                source_nodes
+                    .iter()
+                    .map(|(node_id, node)| (*node_id, node.loc.clone())),
+            );
+            has_read_source = true;
+            continue;
+        }
+        for quadkey in to_read {
+            cancel.check(|| format!("reading {} tiles", tiles.len()))?;
+            if let Some(tile) = store.load(&quadkey)? {
+                for node_id in &interest {
+                    if let Some(index) = tile.node_index(*node_id) {
+                        locations
+                            .entry(*node_id)
+                            .or_insert_with(|| tile.node_locations[index].1.clone());
+                    }
+                }
+                tiles.insert(quadkey.clone(), tile);
+            }
+            requested.insert(quadkey);
+        }
+    }
+    if tiles.values().any(|tile| tile.is_contracted) {
+        // Contracted edges no longer end where other ways may join them
+        return Err(GladsheimError::parse(format!(
+            "Tiles in {} have contracted chains, rebuild them instead",
+            tiles_dir.display()
+        )));
+    }
+    if tiles.values().any(|tile| tile.hierarchy.is_some()) {
+        // The shortcuts would keep standing in for the roads the change edits
+        return Err(GladsheimError::parse(format!(
+            "Tiles in {} have a contraction hierarchy, apply the change to tiles without it and run BuildCh again",
+            tiles_dir.display()
+        )));
+    }
+    println!(
+        "INFO: Read {} of {} tiles{} in {}ms",
+        tiles.len(),
+        headers.len(),
+        if has_node_filters {
+            ", picked by their node filters,"
+        } else {
+            ""
+        },
+        start_time.elapsed().as_millis()
+    );
+
+    let start_time = std::time::Instant::now();
+    let mut node_table = node_table_from_tiles(tiles.values());
+    for (node_id, node) in source_nodes {
+        node_table.entry(node_id).or_insert(node);
+    }
+    node_table.extend(change_nodes);
+    for node_id in &deleted_nodes {
+        node_table.remove(node_id);
+    }
+
+    let mut touched = HashSet::new();
+
+    // Drop the old edges of changed ways
+    for (quadkey, tile) in tiles.iter_mut() {
+        let num_edges = tile.edges.len();
+        tile.edges
+            .retain(|edge| !changed_ways.contains_key(&edge.way_id));
+        if tile.edges.len() != num_edges {
+            touched.insert(quadkey.clone());
+        }
+    }
+
+    let options = ParseOptions {
+        profile,
+        extra_profiles: extra_profiles.to_vec(),
+        // Changed ways get reverse edges like the rest of the tiles
+        reverse_edges: tiles.values().any(|tile| tile.has_reverse_edges),
+        tile_zoom: zoom,
+        ..Default::default()
+    };
+
+    // A node of a changed way is an intersection when any other way passes through
+    // it, or when it's shared by the changed ways themselves
+    let mut intersection_nodes = osm_parser::shared_nodes(changed_ways.values().flatten());
+    {
+        let existing_nodes = tiles
+            .values()
+            .flat_map(|tile| &tile.edges)
+            .flat_map(|edge| &edge.nodes)
+            .copied()
+            .collect::<HashSet<_>>();
+        intersection_nodes.extend(
+            changed_ways
+                .values()
+                .flatten()
+                .flat_map(|way| &way.nodes)
+                .filter(|node_id| existing_nodes.contains(node_id)),
+        );
+        if options.splits_at_crossings() {
+            intersection_nodes.extend(
+                changed_ways
+                    .values()
+                    .flatten()
+                    .flat_map(|way| &way.nodes)
+                    .filter(|node_id| node_table.get(node_id).is_some_and(|node| node.is_crossing)),
+            );
+        }
+        osm_parser::split_loops(changed_ways.values().flatten(), &mut intersection_nodes);
+    }
+
+    let mut new_edges = Vec::new();
+    for way in changed_ways.values().flatten() {
+        if let Some(node_id) = way
+            .nodes
+            .iter()
+            .find(|node_id| !node_table.contains_key(node_id))
+        {
+            println!(
+                "WARN: Skipping way {} referencing node {} without known location{}",
+                way.id.0,
+                node_id.0,
+                if source.is_some() {
+                    ""
+                } else {
+                    ", pass the extract with --source to locate it"
+                }
+            );
+            continue;
+        }
+        for mut edge in osm_parser::split_way(way, &intersection_nodes) {
+            if options.reverse_edges {
+                new_edges.extend(osm_parser::reverse_edge(&mut edge));
+            }
+            new_edges.push(edge);
+        }
+    }
+
+    // Existing edges joined midway by a changed way are split at the new intersection,
+    // and edges through moved nodes need to be re-tiled
+    for (quadkey, tile) in tiles.iter_mut() {
+        if tile
+            .node_locations
+            .iter()
+            .any(|(node_id, _loc)| changed_nodes.contains(node_id))
+        {
+            touched.insert(quadkey.clone());
+        }
+        let edges = std::mem::take(&mut tile.edges);
+        for edge in edges {
+            let is_split = edge
+                .nodes_before_to()
+                .iter()
+                .skip(1)
+                .any(|node_id| intersection_nodes.contains(node_id));
+            let is_moved = edge
+                .nodes
+                .iter()
+                .any(|node_id| changed_nodes.contains(node_id));
+            if is_split {
+                new_edges.extend(split_edge(&edge, &intersection_nodes));
+                touched.insert(quadkey.clone());
+            } else if is_moved {
+                new_edges.push(edge);
+                touched.insert(quadkey.clone());
+            } else {
+                tile.edges.push(edge);
+            }
+        }
+    }
+
+    let num_new_edges = new_edges.len();
+    for mut edge in new_edges {
+        osm_parser::annotate_edge(&mut edge, &node_table, &options);
+        match osm_parser::edge_quadkey(&edge, &node_table, options.tile_zoom) {
+            Ok(quadkey) => {
+                tiles.entry(quadkey.clone()).or_default().edges.push(edge);
+                touched.insert(quadkey);
+            }
+            Err(err) => {
+                println!("ERROR: Could not create quadkey: {}", err);
+            }
+        }
+    }
+    println!(
+        "INFO: Rebuilt {} edges of {} changed ways in {}ms",
+        num_new_edges,
+        changed_ways.len(),
+        start_time.elapsed().as_millis()
+    );
+
+    // Edges moving between tiles change the boundary nodes of their neighbours too
+    touched.extend(boundary::relink_tiles(&mut tiles));
+
+    // Tiles are only consistent with each other once all are written, so
+    // cancelling is not possible past this point
+    cancel.check(|| {
+        format!(
+            "rebuilding {} edges, without updating any tiles",
+            num_new_edges
+        )
+    })?;
+    let start_time = std::time::Instant::now();
+    // The headers of the tiles written, replacing those read for the manifest
+    let mut new_headers = HashMap::new();
+    let overviews = store.overviews().to_vec();
+    manifest::remove_manifest(tiles_dir)?;
+    for quadkey in &touched {
+        let tile = tiles
+            .get_mut(quadkey)
+            // Touched quadkeys always come from the tiles map, so unwrap is ok
+            .unwrap();
+        if tile.edges.is_empty() {
+            let fname = utils::tile_path(tiles_dir, quadkey, naming);
+            if fname.exists() {
+                std::fs::remove_file(&fname)
+                    .io_context(|| format!("Failed removing file {}", fname.display()))?;
+            }
+            names::remove_name_index(tiles_dir, quadkey, naming)?;
+            flat_tile::remove_flat_tile(tiles_dir, quadkey, naming)?;
+            tile_proto::remove_proto_tile(tiles_dir, quadkey, naming)?;
+        } else {
+            tile.profile = options.profile;
+            tile.extra_profiles = options.extra_profiles.clone();
+            tile.zoom = options.tile_zoom;
+            tile.source_timestamp = tile.source_timestamp.or(source_timestamp);
+            tile.has_reverse_edges = options.reverse_edges;
+            osm_parser::merge_duplicate_edges(&mut tile.edges);
+            osm_parser::annotate_tile(tile, &node_table, options.splits_at_crossings());
+            // Keep filters in the tiles that were built with them
+            if tile.node_filter.is_some() {
+                tile.node_filter = Some(NodeFilter::from_nodes(
+                    tile.node_locations.iter().map(|(node_id, _loc)| node_id),
+                ));
+            }
+            if storage == TileStorage::Files {
+                let (header, _is_written) = utils::write_tile(tiles_dir, quadkey, naming, tile)?;
+                new_headers.insert(quadkey.clone(), header);
+            }
+            names::write_name_index(tiles_dir, quadkey, naming, tile)?;
+            if has_flat_tiles {
+                flat_tile::write_flat_tile(tiles_dir, quadkey, naming, tile)?;
+            }
+            if has_proto_tiles {
+                tile_proto::write_proto_tile(tiles_dir, quadkey, naming, tile)?;
+            }
+        }
+    }
+    // An archive or database is written as a whole, copying the tiles that weren't read
+    if let Some(container_fname) = storage.container_fname(tiles_dir) {
+        let mut encoded = Vec::with_capacity(store.len());
+        for quadkey in store.quadkeys() {
+            if tiles.contains_key(quadkey) {
+                continue;
+            }
+            if let Some(bytes) = store.load_encoded(quadkey)? {
+                encoded.push((quadkey.clone(), bytes));
+            }
+        }
+        for (quadkey, tile) in &tiles {
+            if !tile.edges.is_empty() {
+                let (header, bytes) = utils::encode_tile(
+                    tile,
+                    &format!("{} in {}", quadkey.0, container_fname.display()),
+                )?;
+                new_headers.insert(quadkey.clone(), header);
+                encoded.push((quadkey.clone(), bytes));
+            }
+        }
+        storage.write_container(&container_fname, encoded)?;
+    }
+    let num_headers = headers.len();
+    let mut manifest_tiles = headers
+        .into_iter()
+        .filter(|(quadkey, _header)| {
+            !touched.contains(*quadkey) && !new_headers.contains_key(*quadkey)
+        })
+        .map(|(quadkey, header)| (quadkey.clone(), header))
+        .collect::<Vec<_>>();
+    manifest_tiles.extend(new_headers);
+    manifest::write_manifest(tiles_dir, naming, &overviews, manifest_tiles)?;
+    println!(
+        "INFO: Updated {} of {} tiles in {}ms",
+        touched.len(),
+        num_headers,
+        start_time.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+/// Recovers node locations and flags from the data stored in the tiles
+pub(crate) fn node_table_from_tiles<'a>(
+    tiles: impl Iterator<Item = &'a Tile> + Clone,
+) -> HashMap<NodeId, Node> {
+    let mut node_table = HashMap::new();
+    for tile in tiles.clone() {
+        for (node_id, loc) in &tile.node_locations {
+            node_table.insert(
+                *node_id,
+                Node {
+                    loc: loc.clone(),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+    for node_id in tiles.clone().flat_map(|tile| &tile.crossings) {
+        if let Some(node) = node_table.get_mut(node_id) {
+            node.is_crossing = true;
+        }
+    }
+    for (node_id, level_crossing) in tiles.flat_map(|tile| &tile.level_crossings) {
+        if let Some(node) = node_table.get_mut(node_id) {
+            node.level_crossing = Some(*level_crossing);
+        }
+    }
+    node_table
+}
+
+/// Splits an existing edge at intersections within it, keeping its attributes
+pub(crate) fn split_edge(edge: &Edge, intersection_nodes: &HashSet<NodeId>) -> Vec<Edge> {
+    let way = Way {
+        id: edge.way_id,
+        name: edge.name.clone(),
+        road_ref: edge.road_ref.clone(),
+        refs: edge.refs.clone(),
+        class: edge.class,
+        is_oneway: edge.is_oneway,
+        access: edge.access,
+        oneway: edge.oneway,
+        sidewalk: edge.sidewalk,
+        service: edge.service,
+        track_type: edge.track_type,
+        smoothness: edge.smoothness,
+        width: edge.width,
+        is_narrow: edge.is_narrow,
+        is_toll: edge.is_toll,
+        is_ferry: edge.is_ferry,
+        incline: edge.incline,
+        speed_kmh: edge.speed_kmh,
+        destination_zone: edge.destination_zone,
+        not_before: edge.not_before,
+        nodes: edge.nodes.clone(),
+    };
+    let mut edges = osm_parser::split_way(&way, intersection_nodes);
+    for split in &mut edges {
+        split.is_reversed = edge.is_reversed;
+        split.country = edge.country.clone();
+        split.subdivision = edge.subdivision.clone();
+    }
+    edges
+}
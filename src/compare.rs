@@ -0,0 +1,344 @@
+use std::{fmt::Write as _, io::Write};
+
+use clap::ValueEnum;
+
+use crate::{
+    cancel::CancellationToken,
+    error::{GladsheimError, IoContext, Result},
+    http::HttpClient,
+    json::{self, Value},
+    profile::Profile,
+    routing::{self, Coordinate, RouteOptions, TileGraph},
+};
+
+/// Quantiles of the divergences reported
+const QUANTILES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+/// An external routing engine to compare routes with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Engine {
+    /// The `route` service of OSRM
+    Osrm,
+    /// The `route` action of Valhalla
+    Valhalla,
+}
+impl Engine {
+    /// The URL of the fastest route between two locations for a profile
+    fn url(self, endpoint: &str, profile: Profile, from: Coordinate, to: Coordinate) -> String {
+        let endpoint = endpoint.trim_end_matches('/');
+        match self {
+            Engine::Osrm => {
+                let profile = match profile {
+                    Profile::Car | Profile::Bus => "driving",
+                    Profile::Foot => "walking",
+                };
+                format!(
+                    "{}/route/v1/{}/{:.7},{:.7};{:.7},{:.7}?overview=false",
+                    endpoint, profile, from.lon, from.lat, to.lon, to.lat
+                )
+            }
+            Engine::Valhalla => {
+                let costing = match profile {
+                    Profile::Car => "auto",
+                    Profile::Bus => "bus",
+                    Profile::Foot => "pedestrian",
+                };
+                let query = format!(
+                    r#"{{"locations":[{{"lat":{:.7},"lon":{:.7}}},{{"lat":{:.7},"lon":{:.7}}}],"costing":"{}","units":"kilometers"}}"#,
+                    from.lat, from.lon, to.lat, to.lon, costing
+                );
+                format!("{}/route?json={}", endpoint, percent_encoded(&query))
+            }
+        }
+    }
+
+    /// The route of a response, `None` when the engine found none
+    fn route(self, body: &str) -> std::result::Result<Option<Summary>, String> {
+        let response = json::parse(body)?;
+        let number = |value: Option<&Value>, what: &str| {
+            value
+                .and_then(Value::as_f64)
+                .ok_or_else(|| format!("No {} in the response", what))
+        };
+        match self {
+            Engine::Osrm => match response.get("code").and_then(Value::as_str) {
+                Some("Ok") => {
+                    let route = response
+                        .get("routes")
+                        .and_then(Value::as_array)
+                        .and_then(|routes| routes.first());
+                    Ok(Some(Summary {
+                        distance_m: number(
+                            route.and_then(|route| route.get("distance")),
+                            "distance",
+                        )?,
+                        duration_s: number(
+                            route.and_then(|route| route.get("duration")),
+                            "duration",
+                        )?,
+                    }))
+                }
+                // No roads connect the locations, or none are near one of them
+                Some("NoRoute" | "NoSegment") => Ok(None),
+                code => Err(format!(
+                    "OSRM answered {}",
+                    code.or_else(|| response.get("message").and_then(Value::as_str))
+                        .unwrap_or("without a code")
+                )),
+            },
+            Engine::Valhalla => {
+                if let Some(error_code) = response.get("error_code").and_then(Value::as_f64) {
+                    // Unconnected regions, no roads near a location and no path found
+                    return match error_code as u32 {
+                        170 | 171 | 442 => Ok(None),
+                        _ => Err(format!(
+                            "Valhalla answered {}: {}",
+                            error_code,
+                            response
+                                .get("error")
+                                .and_then(Value::as_str)
+                                .unwrap_or_default()
+                        )),
+                    };
+                }
+                let summary = response.get("trip").and_then(|trip| trip.get("summary"));
+                Ok(Some(Summary {
+                    distance_m: 1000.0
+                        * number(summary.and_then(|summary| summary.get("length")), "length")?,
+                    duration_s: number(summary.and_then(|summary| summary.get("time")), "time")?,
+                }))
+            }
+        }
+    }
+}
+
+/// The length and travel time of a route
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Summary {
+    pub(crate) distance_m: f64,
+    pub(crate) duration_s: f64,
+}
+
+/// What an engine answered a query with
+#[derive(Clone, Debug)]
+pub(crate) enum Outcome {
+    Route(Summary),
+    NoRoute,
+    /// The request failed or its response couldn't be read, with why
+    Failed(String),
+}
+impl Outcome {
+    fn route(&self) -> Option<Summary> {
+        match self {
+            Outcome::Route(summary) => Some(*summary),
+            Outcome::NoRoute | Outcome::Failed(_) => None,
+        }
+    }
+}
+
+/// The same query answered by Gladsheim and by the other engine
+pub(crate) struct Comparison {
+    pub(crate) from: Coordinate,
+    pub(crate) to: Coordinate,
+    pub(crate) gladsheim: Outcome,
+    pub(crate) other: Outcome,
+}
+impl Comparison {
+    /// How much longer and slower the route of Gladsheim is than that of the
+    /// other engine as shares of the latter, when both found one
+    fn divergence(&self) -> Option<(f64, f64)> {
+        let (gladsheim, other) = (self.gladsheim.route()?, self.other.route()?);
+        let relative = |ours: f64, theirs: f64| {
+            if theirs > 0.0 {
+                ours / theirs - 1.0
+            } else if ours > 0.0 {
+                f64::INFINITY
+            } else {
+                0.0
+            }
+        };
+        Some((
+            relative(gladsheim.distance_m, other.distance_m),
+            relative(gladsheim.duration_s, other.duration_s),
+        ))
+    }
+}
+
+/// Routes between consecutive pairs of locations with Gladsheim and with an
+/// external engine, the latter queried over the shared HTTP client
+///
+/// Queries Gladsheim finds no route for count as such, only failing to read
+/// the tiles stops the comparison. Failed requests to the other engine are
+/// kept as failed queries, unless all of them fail, which is more likely a
+/// wrong endpoint than a struggling engine.
+pub(crate) fn compare(
+    graph: &mut TileGraph,
+    client: &HttpClient,
+    engine: Engine,
+    endpoint: &str,
+    locations: &[(Coordinate, Coordinate)],
+    options: &RouteOptions,
+    cancel: &CancellationToken,
+) -> Result<Vec<Comparison>> {
+    let urls = locations
+        .iter()
+        .map(|(from, to)| engine.url(endpoint, graph.profile(), *from, *to))
+        .collect::<Vec<_>>();
+    let responses = client.get_all(&urls);
+    let others = responses
+        .into_iter()
+        .map(|response| match response {
+            Ok(body) => match engine.route(&body) {
+                Ok(Some(summary)) => Outcome::Route(summary),
+                Ok(None) => Outcome::NoRoute,
+                Err(error) => Outcome::Failed(error),
+            },
+            // The context names the URL, the source what went wrong with it
+            Err(error) => Outcome::Failed(match std::error::Error::source(&error) {
+                Some(source) => format!("{}: {}", error, source),
+                None => error.to_string(),
+            }),
+        })
+        .collect::<Vec<_>>();
+    if let Some(Outcome::Failed(error)) = others.first() {
+        if others
+            .iter()
+            .all(|other| matches!(other, Outcome::Failed(_)))
+        {
+            return Err(GladsheimError::parse(format!(
+                "All {} queries to {} failed, the first with: {}",
+                others.len(),
+                endpoint,
+                error
+            )));
+        }
+    }
+
+    let mut comparisons = Vec::with_capacity(locations.len());
+    for (index, ((from, to), other)) in locations.iter().zip(others).enumerate() {
+        cancel.check(|| format!("routing {} of {} queries", index, locations.len()))?;
+        let gladsheim = match routing::route(graph, &[*from, *to], options, cancel) {
+            Ok(route) => Outcome::Route(Summary {
+                distance_m: route.legs.iter().map(|leg| leg.distance_m).sum(),
+                duration_s: route.legs.iter().map(|leg| leg.duration_s).sum(),
+            }),
+            Err(GladsheimError::Routing { .. }) => Outcome::NoRoute,
+            Err(error) => return Err(error),
+        };
+        comparisons.push(Comparison {
+            from: *from,
+            to: *to,
+            gladsheim,
+            other,
+        });
+    }
+    Ok(comparisons)
+}
+
+/// Writes every query as tab separated values, with the distance and duration
+/// of both routes and the divergence between them, leaving the fields of a
+/// route empty where none was found
+pub(crate) fn write_comparisons(writer: &mut impl Write, comparisons: &[Comparison]) -> Result<()> {
+    let context = || "Failed writing the comparisons".to_string();
+    writeln!(
+        writer,
+        "from\tto\tdistance_m\tduration_s\tother_distance_m\tother_duration_s\tdistance_divergence\tduration_divergence\tother_error"
+    )
+    .io_context(context)?;
+    let route_fields = |outcome: &Outcome| match outcome.route() {
+        Some(summary) => format!("{:.1}\t{:.1}", summary.distance_m, summary.duration_s),
+        None => "\t".to_string(),
+    };
+    for comparison in comparisons {
+        let divergence = match comparison.divergence() {
+            Some((distance, duration)) => format!("{:.4}\t{:.4}", distance, duration),
+            None => "\t".to_string(),
+        };
+        let error = match &comparison.other {
+            Outcome::Failed(error) => error.replace(['\t', '\n'], " "),
+            Outcome::Route(_) | Outcome::NoRoute => String::new(),
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            comparison.from,
+            comparison.to,
+            route_fields(&comparison.gladsheim),
+            route_fields(&comparison.other),
+            divergence,
+            error
+        )
+        .io_context(context)?;
+    }
+    writer.flush().io_context(context)
+}
+
+/// A report of how the routes of the engines diverge: how many queries each
+/// found a route for, and the quantiles of the divergences in distance and
+/// duration over the queries both did, as percentages of the other engine
+pub(crate) fn report(comparisons: &[Comparison]) -> String {
+    let count = |matches: fn(&Comparison) -> bool| {
+        comparisons
+            .iter()
+            .filter(|comparison| matches(comparison))
+            .count()
+    };
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "Queries: {}, routed by both: {}, only by Gladsheim: {}, only by the other engine: {}, by neither: {}, failed: {}",
+        comparisons.len(),
+        count(|comparison| comparison.divergence().is_some()),
+        count(|comparison| {
+            comparison.gladsheim.route().is_some() && matches!(comparison.other, Outcome::NoRoute)
+        }),
+        count(|comparison| {
+            comparison.gladsheim.route().is_none() && comparison.other.route().is_some()
+        }),
+        count(|comparison| {
+            comparison.gladsheim.route().is_none() && matches!(comparison.other, Outcome::NoRoute)
+        }),
+        count(|comparison| matches!(comparison.other, Outcome::Failed(_)))
+    );
+    let (mut distances, mut durations): (Vec<f64>, Vec<f64>) = comparisons
+        .iter()
+        .filter_map(Comparison::divergence)
+        .unzip();
+    if distances.is_empty() {
+        return report;
+    }
+    let _ = write!(report, "{:<10}", "");
+    for quantile in QUANTILES {
+        let _ = write!(report, "{:>9}", format!("p{}", (quantile * 100.0).round()));
+    }
+    let _ = writeln!(report, "{:>9}{:>9}", "mean|d|", "max|d|");
+    for (name, divergences) in [("distance", &mut distances), ("duration", &mut durations)] {
+        divergences.sort_by(f64::total_cmp);
+        let _ = write!(report, "{:<10}", name);
+        for quantile in QUANTILES {
+            let index = (quantile * (divergences.len() - 1) as f64).round() as usize;
+            let _ = write!(report, "{:>8.1}%", 100.0 * divergences[index]);
+        }
+        let absolute = divergences.iter().map(|divergence| divergence.abs());
+        let _ = writeln!(
+            report,
+            "{:>8.1}%{:>8.1}%",
+            100.0 * absolute.clone().sum::<f64>() / divergences.len() as f64,
+            100.0 * absolute.fold(0.0, f64::max)
+        );
+    }
+    report
+}
+
+/// Encodes a URL query value, keeping only the characters that need no escaping
+fn percent_encoded(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+    encoded
+}
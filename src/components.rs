@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use crate::{Edge, NodeId, profile::Profile};
+
+/// What to do with edges of small strongly connected components
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Islands {
+    /// Leave them as they are
+    #[default]
+    Keep,
+    /// Mark them with `is_island`, so they can be skipped when snapping
+    Flag,
+    /// Leave them out of the tiles
+    Prune,
+}
+
+/// Marks or removes the edges touching strongly connected components of fewer than
+/// `min_size` nodes, e.g. parking lots cut off from the network by clipping
+///
+/// Components are computed over the edges usable in the profile and in the
+/// directions it may travel, other edges are left alone. Returns the number of
+/// island edges.
+pub(crate) fn handle_islands(
+    edges: &mut Vec<Edge>,
+    profile: Profile,
+    min_size: usize,
+    islands: Islands,
+) -> usize {
+    if islands == Islands::Keep {
+        return 0;
+    }
+    let mut indices: HashMap<NodeId, usize> = HashMap::new();
+    let mut neighbours: Vec<Vec<usize>> = Vec::new();
+    let mut index_of = |node_id: NodeId, neighbours: &mut Vec<Vec<usize>>| {
+        *indices.entry(node_id).or_insert_with(|| {
+            neighbours.push(Vec::new());
+            neighbours.len() - 1
+        })
+    };
+    let routable = |edge: &Edge| edge.access.contains(profile);
+    for edge in edges.iter().filter(|edge| routable(edge)) {
+        let from = index_of(edge.from, &mut neighbours);
+        let to = index_of(edge.to, &mut neighbours);
+        neighbours[from].push(to);
+        if !edge.oneway.contains(profile) {
+            neighbours[to].push(from);
+        }
+    }
+    let component = strongly_connected_components(&neighbours);
+    let mut sizes = HashMap::<usize, usize>::new();
+    for component in &component {
+        *sizes.entry(*component).or_default() += 1;
+    }
+    let is_island = |edge: &Edge| {
+        routable(edge)
+            && [edge.from, edge.to]
+                .iter()
+                .any(|node_id| sizes[&component[indices[node_id]]] < min_size)
+    };
+
+    let num_islands = edges.iter().filter(|edge| is_island(edge)).count();
+    match islands {
+        Islands::Keep => {}
+        Islands::Flag => {
+            for edge in edges.iter_mut() {
+                edge.is_island = is_island(edge);
+            }
+        }
+        Islands::Prune => edges.retain(|edge| !is_island(edge)),
+    }
+    num_islands
+}
+
+/// The strongly connected component of every node, by Tarjan's algorithm
+///
+/// Iterative, since road networks are deep enough to overflow the stack.
+fn strongly_connected_components(neighbours: &[Vec<usize>]) -> Vec<usize> {
+    const UNVISITED: usize = usize::MAX;
+    let num_nodes = neighbours.len();
+    let mut order = vec![UNVISITED; num_nodes];
+    let mut low_link = vec![0; num_nodes];
+    let mut on_stack = vec![false; num_nodes];
+    let mut component = vec![UNVISITED; num_nodes];
+    let mut stack = Vec::new();
+    let mut next_order = 0;
+    let mut num_components = 0;
+    // Nodes being visited and how many of their neighbours were handled
+    let mut call_stack: Vec<(usize, usize)> = Vec::new();
+
+    for root in 0..num_nodes {
+        if order[root] != UNVISITED {
+            continue;
+        }
+        call_stack.push((root, 0));
+        while let Some((node, next_neighbour)) = call_stack.last_mut() {
+            let node = *node;
+            if *next_neighbour == 0 {
+                order[node] = next_order;
+                low_link[node] = next_order;
+                next_order += 1;
+                stack.push(node);
+                on_stack[node] = true;
+            }
+            if let Some(&neighbour) = neighbours[node].get(*next_neighbour) {
+                *next_neighbour += 1;
+                if order[neighbour] == UNVISITED {
+                    call_stack.push((neighbour, 0));
+                } else if on_stack[neighbour] {
+                    low_link[node] = low_link[node].min(order[neighbour]);
+                }
+                continue;
+            }
+            call_stack.pop();
+            if let Some((parent, _)) = call_stack.last() {
+                low_link[*parent] = low_link[*parent].min(low_link[node]);
+            }
+            if low_link[node] == order[node] {
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component[member] = num_components;
+                    if member == node {
+                        break;
+                    }
+                }
+                num_components += 1;
+            }
+        }
+    }
+    component
+}
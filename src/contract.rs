@@ -0,0 +1,124 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{Edge, NodeId, WayId};
+
+/// Merges chains of edges through nodes where only two edges meet into single edges
+///
+/// The nodes in between stay in `nodes`, so the geometry is kept while the graph
+/// shrinks. Edges are only merged when all their attributes agree and they run the
+/// same way, and never through `protected_nodes` or along `protected_ways`, which
+/// are referenced from elsewhere. Returns the remaining edges and the number of merges.
+pub(crate) fn contract_chains(
+    edges: Vec<Edge>,
+    protected_nodes: &HashSet<NodeId>,
+    protected_ways: &HashSet<WayId>,
+) -> (Vec<Edge>, usize) {
+    let mut incident: HashMap<NodeId, Vec<usize>> = HashMap::new();
+    for (index, edge) in edges.iter().enumerate() {
+        incident.entry(edge.from).or_default().push(index);
+        incident.entry(edge.to).or_default().push(index);
+    }
+    let mut edges = edges.into_iter().map(Some).collect::<Vec<_>>();
+
+    let mut num_merges = 0;
+    let mut candidates = incident
+        .iter()
+        .filter(|(node_id, indices)| indices.len() == 2 && !protected_nodes.contains(node_id))
+        .map(|(node_id, _indices)| *node_id)
+        .collect::<Vec<_>>();
+    // Merge in a fixed order, so the same data gives the same tiles
+    candidates.sort_by_key(|node_id| node_id.0);
+    for node_id in candidates {
+        let [first, second] = incident[&node_id][..] else {
+            continue;
+        };
+        // A loop from the node back to itself is both of its edges
+        if first == second {
+            continue;
+        }
+        let (Some(a), Some(b)) = (&edges[first], &edges[second]) else {
+            continue;
+        };
+        // Which edge leads into the node and which leaves it, turning two-way edges
+        // around where both meet the node with the same end
+        let is_two_way = a.oneway.is_empty() && b.oneway.is_empty();
+        let (into, out_of, flip_into, flip_out_of) = if a.to == node_id && b.from == node_id {
+            (first, second, false, false)
+        } else if b.to == node_id && a.from == node_id {
+            (second, first, false, false)
+        } else if is_two_way && a.to == node_id {
+            (first, second, false, true)
+        } else if is_two_way {
+            (first, second, true, false)
+        } else {
+            continue;
+        };
+        let (Some(mut into_edge), Some(mut out_of_edge)) =
+            (edges[into].take(), edges[out_of].take())
+        else {
+            continue;
+        };
+        if flip_into {
+            flip_edge(&mut into_edge);
+        }
+        if flip_out_of {
+            flip_edge(&mut out_of_edge);
+        }
+        // Merging both ends of a chain into each other would create a self-loop
+        if out_of_edge.to == into_edge.from || !can_merge(&into_edge, &out_of_edge, protected_ways)
+        {
+            if flip_into {
+                flip_edge(&mut into_edge);
+            }
+            if flip_out_of {
+                flip_edge(&mut out_of_edge);
+            }
+            edges[into] = Some(into_edge);
+            edges[out_of] = Some(out_of_edge);
+            continue;
+        }
+        into_edge.nodes.extend(out_of_edge.nodes.iter().skip(1));
+        into_edge.to = out_of_edge.to;
+        edges[into] = Some(into_edge);
+        if let Some(indices) = incident.get_mut(&out_of_edge.to) {
+            for index in indices.iter_mut().filter(|index| **index == out_of) {
+                *index = into;
+            }
+        }
+        num_merges += 1;
+    }
+    (edges.into_iter().flatten().collect(), num_merges)
+}
+
+/// Whether two edges describe the same road, so that joining them loses nothing
+fn can_merge(a: &Edge, b: &Edge, protected_ways: &HashSet<WayId>) -> bool {
+    !protected_ways.contains(&a.way_id)
+        && !protected_ways.contains(&b.way_id)
+        && a.name == b.name
+        && a.road_ref == b.road_ref
+        && a.refs == b.refs
+        && a.class == b.class
+        && a.is_oneway == b.is_oneway
+        && a.access == b.access
+        && a.oneway == b.oneway
+        && a.is_reversed == b.is_reversed
+        && a.sidewalk == b.sidewalk
+        && a.service == b.service
+        && a.track_type == b.track_type
+        && a.smoothness == b.smoothness
+        && a.width == b.width
+        && a.is_narrow == b.is_narrow
+        && a.is_toll == b.is_toll
+        && a.is_ferry == b.is_ferry
+        && a.incline == b.incline
+        && a.speed_kmh == b.speed_kmh
+        && a.destination_zone == b.destination_zone
+        && a.not_before == b.not_before
+}
+
+/// Turns a two-way edge around
+fn flip_edge(edge: &mut Edge) {
+    std::mem::swap(&mut edge.from, &mut edge.to);
+    edge.nodes.reverse();
+    edge.incline = edge.incline.map(|incline| -incline);
+}
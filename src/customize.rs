@@ -0,0 +1,404 @@
+use std::{
+    collections::{BinaryHeap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    Edge, NodeId,
+    cancel::CancellationToken,
+    error::{GladsheimError, Result},
+    hierarchy::{self, Graph, GraphEntry},
+    partition::{self, Partition},
+    traffic::Traffic,
+    utils,
+};
+
+/// What a route minimizes, the cost of the arcs in a customization
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, bincode::Encode, bincode::Decode,
+)]
+pub(crate) enum Metric {
+    /// Seconds of travel for the profile of the tiles
+    #[default]
+    Duration,
+    /// Meters along the roads
+    Distance,
+}
+impl Metric {
+    /// The cost of an edge, for durations at its current speed when there is
+    /// one, up to the fastest the profile goes
+    fn cost(self, edge: &Edge, traffic: Option<&Traffic>, max_speed_kmh: f32) -> f64 {
+        match self {
+            Metric::Duration => match traffic.and_then(|traffic| traffic.speed_kmh(edge)) {
+                Some(speed_kmh) => f64::from(edge.length_m / (speed_kmh.min(max_speed_kmh) / 3.6)),
+                None => f64::from(edge.duration_s),
+            },
+            Metric::Distance => f64::from(edge.length_m),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Duration => "duration",
+            Metric::Distance => "distance",
+        }
+    }
+}
+
+/// Path of the customization for a metric in a tile directory
+pub(crate) fn customization_path(tiles_dir: &Path, metric: Metric) -> PathBuf {
+    tiles_dir.join(format!("customization-{}.bin", metric.name()))
+}
+
+/// The costs across the cells of a partition for one metric and day
+///
+/// Written into the tile directory as `customization-<metric>.bin`, next to
+/// the `partition.bin` it was computed for.
+#[derive(Debug, bincode::Encode, bincode::Decode)]
+pub(crate) struct Customization {
+    pub(crate) metric: Metric,
+    /// The day as `YYYYMMDD` whose open roads the costs are over
+    pub(crate) date: u32,
+    /// The cells of every level from the smallest, numbered as in the partition
+    pub(crate) levels: Vec<Vec<CellMatrix>>,
+}
+
+/// The cheapest costs within a cell between the nodes at its boundary
+#[derive(Debug, Default, bincode::Encode, bincode::Decode)]
+pub(crate) struct CellMatrix {
+    /// Nodes of the cell with arcs to or from other cells of the level, sorted by id
+    pub(crate) boundary: Vec<NodeId>,
+    /// Cost from every boundary node to every one, a row per node it starts at,
+    /// infinite when the cell has no way between them
+    pub(crate) costs: Vec<f32>,
+}
+
+/// Numbers of one level of a customization, for reporting
+pub(crate) struct LevelStats {
+    pub(crate) num_cells: usize,
+    pub(crate) num_boundary_nodes: usize,
+    pub(crate) max_boundary_nodes: usize,
+    pub(crate) num_entries: usize,
+}
+
+/// Numbers of a customization, for reporting
+pub(crate) struct CustomizeStats {
+    pub(crate) num_nodes: usize,
+    pub(crate) levels: Vec<LevelStats>,
+}
+
+/// Computes the costs between the boundary nodes of every cell of the
+/// partition of a tile directory, over the roads open on a day, writing them
+/// into `customization-<metric>.bin`
+///
+/// Cells of the lowest level are searched over their arcs, the cells above
+/// over the costs of the cells they contain and the arcs between those, so
+/// changing costs only needs this rerun rather than a new partition. Cells are
+/// customized in parallel, a level at a time. Durations are at the current
+/// speeds of `traffic` where it has them, so a traffic feed is taken in by
+/// customizing again.
+pub(crate) fn customize(
+    tiles_dir: &Path,
+    metric: Metric,
+    date: u32,
+    traffic: Option<&Traffic>,
+    cancel: &CancellationToken,
+) -> Result<CustomizeStats> {
+    let start_time = std::time::Instant::now();
+    let partition = partition::read_partition(tiles_dir)?;
+    let (_store, tiles) = hierarchy::read_base_tiles(tiles_dir, cancel)?;
+    let max_speed_kmh = f32::from(tiles[0].1.profile.max_speed_kmh());
+    let graph = Graph::with_costs(&tiles, date, |edge| {
+        metric.cost(edge, traffic, max_speed_kmh)
+    });
+    let index_of = graph
+        .node_ids
+        .iter()
+        .map(|node_id| {
+            partition
+                .node_ids
+                .binary_search_by_key(&node_id.0, |partitioned| partitioned.0)
+                .map(|index| index as u32)
+                .map_err(|_missing| {
+                    GladsheimError::parse(format!(
+                        "Node {} isn't in the partition of {}, run Partition again",
+                        node_id.0,
+                        tiles_dir.display()
+                    ))
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let cells = Cells {
+        partition: &partition,
+        index_of: &index_of,
+    };
+    println!(
+        "INFO: Read {} tiles with {} nodes and {} arcs in {}ms",
+        tiles.len(),
+        graph.node_ids.len(),
+        graph.num_arcs(),
+        start_time.elapsed().as_millis()
+    );
+
+    let mut levels: Vec<Vec<CellMatrix>> = Vec::new();
+    // The boundary nodes of the cells of the level below, as indices into the graph
+    let mut boundaries: Vec<Vec<u32>> = Vec::new();
+    for level in 0..partition.cell_sizes.len() {
+        let start_time = std::time::Instant::now();
+        let num_cells = partition.cells[level]
+            .iter()
+            .max()
+            .map_or(0, |cell| *cell as usize + 1);
+        let mut boundary = vec![Vec::new(); num_cells];
+        for node in 0..graph.node_ids.len() as u32 {
+            let cell = cells.of(level, node);
+            if graph.outgoing[node as usize]
+                .iter()
+                .chain(&graph.incoming[node as usize])
+                .any(|(other, _cost)| cells.of(level, *other) != cell)
+            {
+                boundary[cell as usize].push(node);
+            }
+        }
+        for nodes in &mut boundary {
+            nodes.sort_by_key(|node| graph.node_ids[*node as usize].0);
+        }
+        let mut members = vec![Vec::new(); num_cells];
+        if level == 0 {
+            for node in 0..graph.node_ids.len() as u32 {
+                members[cells.of(0, node) as usize].push(node);
+            }
+        } else {
+            for (subcell, nodes) in boundaries.iter().enumerate() {
+                if let Some(node) = nodes.first() {
+                    members[cells.of(level, *node) as usize].push(subcell as u32);
+                }
+            }
+        }
+        let matrices = boundary
+            .par_iter()
+            .zip(&members)
+            .enumerate()
+            .map(|(cell, (boundary, members))| {
+                cancel.check(|| format!("customizing cell {} of level {}", cell, level))?;
+                let arcs = if level == 0 {
+                    cell_arcs(&graph, members)
+                } else {
+                    overlay_arcs(
+                        &graph,
+                        &cells,
+                        level,
+                        members,
+                        &boundaries,
+                        &levels[level - 1],
+                    )
+                };
+                Ok(cell_matrix(&arcs, boundary))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        println!(
+            "INFO: Customized {} cells of level {} in {}ms",
+            num_cells,
+            level,
+            start_time.elapsed().as_millis()
+        );
+        levels.push(
+            matrices
+                .into_iter()
+                .zip(&boundary)
+                .map(|(costs, nodes)| CellMatrix {
+                    boundary: nodes
+                        .iter()
+                        .map(|node| graph.node_ids[*node as usize])
+                        .collect(),
+                    costs,
+                })
+                .collect(),
+        );
+        boundaries = boundary;
+    }
+
+    let stats = CustomizeStats {
+        num_nodes: graph.node_ids.len(),
+        levels: levels
+            .iter()
+            .map(|matrices| LevelStats {
+                num_cells: matrices.len(),
+                num_boundary_nodes: matrices.iter().map(|matrix| matrix.boundary.len()).sum(),
+                max_boundary_nodes: matrices
+                    .iter()
+                    .map(|matrix| matrix.boundary.len())
+                    .max()
+                    .unwrap_or_default(),
+                num_entries: matrices.iter().map(|matrix| matrix.costs.len()).sum(),
+            })
+            .collect(),
+    };
+    let customization = Customization {
+        metric,
+        date,
+        levels,
+    };
+    utils::write_encoded(&customization_path(tiles_dir, metric), &customization)?;
+    Ok(stats)
+}
+
+/// Looks up the cells of the nodes of a graph in a partition
+struct Cells<'a> {
+    partition: &'a Partition,
+    /// Index into the partition of every node of the graph
+    index_of: &'a [u32],
+}
+impl Cells<'_> {
+    fn of(&self, level: usize, node: u32) -> u32 {
+        self.partition.cells[level][self.index_of[node as usize] as usize]
+    }
+}
+
+/// The arcs within a cell, between its nodes numbered densely
+struct CellGraph {
+    /// Local number of every node of the graph in the cell
+    local: HashMap<u32, u32>,
+    /// Arcs leaving every node by local number, with the node they lead to and their cost
+    outgoing: Vec<Vec<(u32, f64)>>,
+}
+impl CellGraph {
+    fn new(nodes: impl Iterator<Item = u32>) -> Self {
+        let local = nodes
+            .enumerate()
+            .map(|(index, node)| (node, index as u32))
+            .collect::<HashMap<_, _>>();
+        Self {
+            outgoing: vec![Vec::new(); local.len()],
+            local,
+        }
+    }
+
+    fn add_arc(&mut self, from: u32, to: u32, cost: f64) {
+        let (Some(from), Some(to)) = (self.local.get(&from), self.local.get(&to)) else {
+            return;
+        };
+        self.outgoing[*from as usize].push((*to, cost));
+    }
+}
+
+/// The arcs of a graph between the nodes of a cell of the lowest level
+fn cell_arcs(graph: &Graph, members: &[u32]) -> CellGraph {
+    let mut arcs = CellGraph::new(members.iter().copied());
+    for node in members {
+        for (head, cost) in &graph.outgoing[*node as usize] {
+            arcs.add_arc(*node, *head, *cost);
+        }
+    }
+    arcs
+}
+
+/// The arcs over a cell above the lowest level between the boundary nodes of
+/// the cells it contains, those of the costs across them and those between them
+fn overlay_arcs(
+    graph: &Graph,
+    cells: &Cells,
+    level: usize,
+    subcells: &[u32],
+    boundaries: &[Vec<u32>],
+    matrices: &[CellMatrix],
+) -> CellGraph {
+    let mut arcs = CellGraph::new(
+        subcells
+            .iter()
+            .flat_map(|subcell| boundaries[*subcell as usize].iter().copied()),
+    );
+    for subcell in subcells {
+        let boundary = &boundaries[*subcell as usize];
+        let costs = &matrices[*subcell as usize].costs;
+        for (row, node) in boundary.iter().enumerate() {
+            for (column, head) in boundary.iter().enumerate() {
+                let cost = costs[row * boundary.len() + column];
+                if row != column && cost.is_finite() {
+                    arcs.add_arc(*node, *head, f64::from(cost));
+                }
+            }
+            for (head, cost) in &graph.outgoing[*node as usize] {
+                if cells.of(level - 1, *head) != *subcell {
+                    arcs.add_arc(*node, *head, *cost);
+                }
+            }
+        }
+    }
+    arcs
+}
+
+/// The cheapest costs between the boundary nodes of a cell over its arcs, by
+/// a Dijkstra search from each of them
+fn cell_matrix(arcs: &CellGraph, boundary: &[u32]) -> Vec<f32> {
+    let boundary = boundary
+        .iter()
+        .map(|node| arcs.local[node] as usize)
+        .collect::<Vec<_>>();
+    let mut costs = Vec::with_capacity(boundary.len() * boundary.len());
+    let mut best = vec![f64::INFINITY; arcs.outgoing.len()];
+    let mut queue = BinaryHeap::new();
+    for source in &boundary {
+        best.fill(f64::INFINITY);
+        best[*source] = 0.0;
+        queue.push(GraphEntry {
+            duration: 0.0,
+            node: *source as u32,
+        });
+        while let Some(GraphEntry { duration, node }) = queue.pop() {
+            if duration > best[node as usize] {
+                continue;
+            }
+            for (head, cost) in &arcs.outgoing[node as usize] {
+                if duration + cost < best[*head as usize] {
+                    best[*head as usize] = duration + cost;
+                    queue.push(GraphEntry {
+                        duration: duration + cost,
+                        node: *head,
+                    });
+                }
+            }
+        }
+        costs.extend(boundary.iter().map(|target| best[*target] as f32));
+    }
+    costs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WayId;
+
+    #[test]
+    fn durations_are_costed_by_current_speeds() {
+        let fname = std::env::temp_dir().join(format!(
+            "gladsheim-customize-traffic-{}.tsv",
+            std::process::id()
+        ));
+        std::fs::write(&fname, "edge_id\tspeed_kmh\n7:1:2\t36\n8:2:3\t360\n").unwrap();
+        let traffic = Traffic::read(&fname).unwrap();
+        std::fs::remove_file(&fname).unwrap();
+        let edge = |way_id: i64, from: i64, to: i64| Edge {
+            way_id: WayId(way_id),
+            from: NodeId(from),
+            to: NodeId(to),
+            length_m: 100.0,
+            duration_s: 5.0,
+            ..Default::default()
+        };
+        let duration = |edge: &Edge| Metric::Duration.cost(edge, Some(&traffic), 72.0);
+        // 100 m at 36 km/h
+        assert_eq!(duration(&edge(7, 1, 2)), 10.0);
+        // No faster than the profile goes, 100 m at 72 km/h
+        assert_eq!(duration(&edge(8, 2, 3)), 5.0);
+        // The durations of the tiles where there's no current speed
+        assert_eq!(duration(&edge(9, 3, 4)), 5.0);
+        assert_eq!(Metric::Duration.cost(&edge(7, 1, 2), None, 72.0), 5.0);
+        assert_eq!(
+            Metric::Distance.cost(&edge(7, 1, 2), Some(&traffic), 72.0),
+            100.0
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+
+use crate::{
+    error::{GladsheimError, Result},
+    tile_format,
+    utils::{Quadkey, TileContainer},
+};
+
+/// Name of the SQLite database holding all tiles of a directory, instead of a file per tile
+pub(crate) const DATABASE_FILE_NAME: &str = "tiles.sqlite";
+
+/// Like MBTiles, a table of tiles keyed by quadkey instead of z/x/y, next to a
+/// table of name and value pairs describing them
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS metadata (name TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS tiles (quadkey TEXT PRIMARY KEY, tile_data BLOB NOT NULL) WITHOUT ROWID;
+";
+
+/// A SQLite database holding the tiles of a directory, for serving them from a
+/// single file that other tools can query and update in place
+///
+/// Every row of the `tiles` table holds a tile encoded exactly like a `.grt` file.
+pub(crate) struct TileDatabase {
+    fname: PathBuf,
+    /// Connections can move between threads but not be shared by them
+    connection: Mutex<Connection>,
+    /// Sorted by quadkey
+    quadkeys: Vec<Quadkey>,
+}
+impl TileDatabase {
+    /// Lists the tiles of a database, leaving the tiles for `read`
+    pub(crate) fn open(fname: &Path) -> Result<Self> {
+        let connection = Connection::open_with_flags(fname, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(database_error(fname))?;
+        let quadkeys = connection
+            .prepare("SELECT quadkey FROM tiles ORDER BY quadkey")
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], |row| row.get(0).map(Quadkey))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(database_error(fname))?;
+        Ok(Self {
+            fname: fname.to_path_buf(),
+            connection: Mutex::new(connection),
+            quadkeys,
+        })
+    }
+}
+impl TileContainer for TileDatabase {
+    fn fname(&self) -> &Path {
+        &self.fname
+    }
+
+    fn quadkeys(&self) -> Vec<Quadkey> {
+        self.quadkeys.clone()
+    }
+
+    fn read(&self, quadkey: &Quadkey) -> Result<Option<Vec<u8>>> {
+        let connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        connection
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE quadkey = ?1",
+                params![quadkey.0],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|source| GladsheimError::TileFormat {
+                context: format!(
+                    "Failed reading tile {} from {}",
+                    quadkey.0,
+                    self.fname.display()
+                ),
+                source: Box::new(source),
+            })
+    }
+}
+
+/// Writes encoded tiles into a database, replacing the tiles it held before and
+/// returning whether any of them changed
+///
+/// Unchanged tiles are left alone, so that a database copied with tools such as
+/// `sqldiff` only transfers the changes.
+pub(crate) fn write_database(fname: &Path, tiles: Vec<(Quadkey, Vec<u8>)>) -> Result<bool> {
+    let write = || -> rusqlite::Result<bool> {
+        let mut connection = Connection::open(fname)?;
+        connection.execute_batch(SCHEMA)?;
+        let transaction = connection.transaction()?;
+        let mut num_changed = 0;
+        {
+            let mut insert = transaction
+                .prepare("INSERT OR REPLACE INTO metadata (name, value) VALUES (?1, ?2)")?;
+            insert.execute(params!["format", "grt"])?;
+            insert.execute(params![
+                "format_version",
+                tile_format::FORMAT_VERSION.to_string()
+            ])?;
+
+            let mut select =
+                transaction.prepare("SELECT tile_data FROM tiles WHERE quadkey = ?1")?;
+            let mut insert = transaction
+                .prepare("INSERT OR REPLACE INTO tiles (quadkey, tile_data) VALUES (?1, ?2)")?;
+            for (quadkey, bytes) in &tiles {
+                let existing: Option<Vec<u8>> = select
+                    .query_row(params![quadkey.0], |row| row.get(0))
+                    .optional()?;
+                if existing.as_ref() != Some(bytes) {
+                    insert.execute(params![quadkey.0, bytes])?;
+                    num_changed += 1;
+                }
+            }
+
+            let kept = tiles
+                .iter()
+                .map(|(quadkey, _bytes)| quadkey.0.as_str())
+                .collect::<HashSet<_>>();
+            let stale = transaction
+                .prepare("SELECT quadkey FROM tiles")?
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+                .into_iter()
+                .filter(|quadkey| !kept.contains(quadkey.as_str()))
+                .collect::<Vec<_>>();
+            let mut delete = transaction.prepare("DELETE FROM tiles WHERE quadkey = ?1")?;
+            for quadkey in &stale {
+                delete.execute(params![quadkey])?;
+                num_changed += 1;
+            }
+        }
+        transaction.commit()?;
+        Ok(num_changed > 0)
+    };
+    write().map_err(database_error(fname))
+}
+
+fn database_error(fname: &Path) -> impl FnOnce(rusqlite::Error) -> GladsheimError + '_ {
+    move |source| GladsheimError::TileFormat {
+        context: format!("Failed accessing tile database {}", fname.display()),
+        source: Box::new(source),
+    }
+}
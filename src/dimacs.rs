@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    NodeId,
+    error::{GladsheimError, IoContext, Result},
+    osm_parser::Node,
+    profile::Profile,
+    utils::{Quadkey, Tile},
+};
+
+/// Writes the graph in the 9th DIMACS implementation challenge format
+///
+/// Produces `<prefix>.gr` with one arc per driving direction, weighted by
+/// edge length in meters, and `<prefix>.co` with the coordinates of every
+/// graph node in millionths of a degree. DIMACS requires dense 1-based node
+/// ids so OSM ids are remapped in order of first appearance. Only the edges
+/// usable in the profile are exported.
+pub(crate) fn write_dimacs(
+    prefix: &Path,
+    tiles: &[(Quadkey, Tile)],
+    node_table: &HashMap<NodeId, Node>,
+    profile: Profile,
+) -> Result<()> {
+    let mut dimacs_ids: HashMap<NodeId, usize> = HashMap::new();
+    let mut ordered_nodes = Vec::new();
+    let mut arcs = Vec::new();
+
+    // Sort by quadkey so the output is deterministic between runs
+    let mut sorted_tiles = tiles.iter().collect::<Vec<_>>();
+    sorted_tiles.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+
+    for (_quadkey, tile) in sorted_tiles {
+        for edge in tile
+            .edges
+            .iter()
+            .filter(|edge| edge.access.contains(profile))
+        {
+            let mut dimacs_id = |node_id: NodeId| {
+                *dimacs_ids.entry(node_id).or_insert_with(|| {
+                    ordered_nodes.push(node_id);
+                    ordered_nodes.len()
+                })
+            };
+            let from = dimacs_id(edge.from);
+            let to = dimacs_id(edge.to);
+
+            let weight = edge.length_m.round().max(1.0) as u64;
+            arcs.push((from, to, weight));
+            if !edge.oneway.contains(profile) {
+                arcs.push((to, from, weight));
+            }
+        }
+    }
+
+    let nodes = ordered_nodes
+        .iter()
+        .map(|node_id| {
+            node_table.get(node_id).ok_or_else(|| {
+                GladsheimError::parse(format!("Missing coordinates for node {}", node_id.0))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let fname_gr = prefix.with_extension("gr");
+    let write_gr = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(std::fs::File::create(&fname_gr)?);
+        writeln!(writer, "c Gladsheim road graph, arc weights are meters")?;
+        writeln!(writer, "p sp {} {}", nodes.len(), arcs.len())?;
+        for (from, to, weight) in &arcs {
+            writeln!(writer, "a {from} {to} {weight}")?;
+        }
+        writer.flush()
+    };
+    write_gr().io_context(|| format!("Failed writing to file {}", fname_gr.display()))?;
+
+    let fname_co = prefix.with_extension("co");
+    let write_co = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(std::fs::File::create(&fname_co)?);
+        writeln!(
+            writer,
+            "c Gladsheim road graph coordinates, lon/lat in 1e-6 degrees"
+        )?;
+        writeln!(writer, "p aux sp co {}", nodes.len())?;
+        for (index, node) in nodes.iter().enumerate() {
+            writeln!(
+                writer,
+                "v {} {} {}",
+                index + 1,
+                (node.loc.lon * 1e6).round() as i64,
+                (node.loc.lat * 1e6).round() as i64
+            )?;
+        }
+        writer.flush()
+    };
+    write_co().io_context(|| format!("Failed writing to file {}", fname_co.display()))?;
+
+    println!(
+        "INFO: Wrote DIMACS graph with {} nodes and {} arcs to {}",
+        nodes.len(),
+        arcs.len(),
+        fname_gr.display()
+    );
+    Ok(())
+}
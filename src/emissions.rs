@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use crate::{Edge, NodeId, geometry, osm_parser::Node, profile::Profile};
+
+/// Grams of CO2 per joule of work at the wheels, for petrol burnt at 25% efficiency
+const CO2_G_PER_JOULE: f64 = 2310.0 / 32.0e6 / 0.25;
+/// Acceleration and deceleration in m/s² when stopping and starting again
+const ACCELERATION: f64 = 1.0;
+/// Relative increase in consumption per unit of gradient, 1% uphill costs 10% more
+const GRADIENT_SENSITIVITY: f64 = 10.0;
+/// Downhill consumption never drops below this fraction of driving on the flat
+const MIN_GRADIENT_FACTOR: f64 = 0.3;
+
+/// Vehicle parameters of the emissions model
+struct Vehicle {
+    mass_kg: f64,
+    /// Scales the passenger car emission curve
+    scale: f64,
+}
+impl Vehicle {
+    fn of(profile: Profile) -> Option<Self> {
+        match profile {
+            Profile::Car => Some(Self {
+                mass_kg: 1500.0,
+                scale: 1.0,
+            }),
+            Profile::Bus => Some(Self {
+                mass_kg: 12000.0,
+                scale: 5.0,
+            }),
+            Profile::Foot => None,
+        }
+    }
+
+    /// Grams of CO2 per km at a constant speed, shaped like the COPERT curves for
+    /// Euro 6 petrol cars with the least emissions around 60-70 km/h
+    fn co2_g_per_km(&self, speed_kmh: f64) -> f64 {
+        let speed_kmh = speed_kmh.max(5.0);
+        self.scale * (3300.0 / speed_kmh + 100.0 + 0.012 * speed_kmh * speed_kmh)
+    }
+
+    /// Grams of CO2 for accelerating from one speed to another, nothing when slowing down
+    fn co2_g_accelerating(&self, from_kmh: f64, to_kmh: f64) -> f64 {
+        let (from, to) = (from_kmh / 3.6, to_kmh / 3.6);
+        (0.5 * self.mass_kg * (to * to - from * from)).max(0.0) * CO2_G_PER_JOULE
+    }
+}
+
+/// The driving conditions along an edge and the CO2 they lead to
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+pub(crate) struct Emissions {
+    /// Average slope as rise over run, positive uphill in the direction of the edge
+    pub(crate) gradient: f32,
+    /// Traffic signals, stop signs and level crossings per km
+    pub(crate) stops_per_km: f32,
+    /// Share of the edge driven while braking or accelerating, in `[0, 1]`
+    pub(crate) speed_variability: f32,
+    /// Grams of CO2 for driving the edge from `from` to `to`
+    pub(crate) co2_g: f32,
+    /// Grams of CO2 for driving the edge from `to` to `from`
+    pub(crate) co2_reverse_g: f32,
+}
+impl Emissions {
+    /// Estimates the emissions of an edge, `None` for profiles without a vehicle
+    pub(crate) fn estimate(
+        edge: &Edge,
+        coords: &[(f64, f64)],
+        node_table: &HashMap<NodeId, Node>,
+        profile: Profile,
+    ) -> Option<Self> {
+        let vehicle = Vehicle::of(profile)?;
+        let length = geometry::length(coords);
+        if length <= 0.0 {
+            return None;
+        }
+        let gradient = edge.incline.map(f64::from).or_else(|| {
+            // Elevation is rarely mapped, so only the ends of the edge are used
+            let from = node_table.get(edge.nodes.first()?)?.ele?;
+            let to = node_table.get(&edge.to)?.ele?;
+            Some(f64::from(to - from) / length)
+        });
+        let gradient = gradient.unwrap_or_default();
+
+        let speed_kmh = f64::from(edge.speed_kmh)
+            * f64::from(
+                edge.smoothness
+                    .and_then(|smoothness| profile.smoothness_speed_factor(smoothness))
+                    .unwrap_or(1.0),
+            );
+        // Crossings at the `to` node belong to the edges leaving it
+        let num_stops = edge
+            .nodes_before_to()
+            .iter()
+            .filter_map(|node_id| node_table.get(node_id))
+            .filter(|node| node.is_stop || node.level_crossing.is_some())
+            .count() as f64;
+        let curve_speed_kmh = edge
+            .curvature
+            .map(|curvature| f64::from(curvature.safe_speed_kmh))
+            .filter(|safe_speed_kmh| *safe_speed_kmh < speed_kmh);
+
+        // Distance needed to come to a stop and to get back up to speed
+        let speed = speed_kmh / 3.6;
+        let transient_m = num_stops * speed * speed / ACCELERATION
+            + curve_speed_kmh.map_or(0.0, |curve_speed_kmh| {
+                let curve_speed = curve_speed_kmh / 3.6;
+                (speed * speed - curve_speed * curve_speed) / ACCELERATION
+            });
+        let speed_variability = (transient_m / length).min(1.0);
+
+        let accelerating_g = num_stops * vehicle.co2_g_accelerating(0.0, speed_kmh)
+            + curve_speed_kmh.map_or(0.0, |curve_speed_kmh| {
+                vehicle.co2_g_accelerating(curve_speed_kmh, speed_kmh)
+            });
+        let cruising_g = vehicle.co2_g_per_km(speed_kmh) * length / 1000.0;
+        let co2_g = |gradient: f64| {
+            let factor = (1.0 + GRADIENT_SENSITIVITY * gradient).max(MIN_GRADIENT_FACTOR);
+            (cruising_g * factor + accelerating_g) as f32
+        };
+        Some(Self {
+            gradient: gradient as f32,
+            stops_per_km: (num_stops * 1000.0 / length) as f32,
+            speed_variability: speed_variability as f32,
+            co2_g: co2_g(gradient),
+            co2_reverse_g: co2_g(-gradient),
+        })
+    }
+}
@@ -0,0 +1,82 @@
+use std::error::Error;
+
+/// Failures of the library layer, so callers can tell them apart without
+/// matching on messages. Only the CLI in `main.rs` converts them into `anyhow`.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum GladsheimError {
+    /// Input data or arguments describing it that couldn't be understood
+    #[error("{message}")]
+    Parse {
+        message: String,
+        #[source]
+        source: Option<Box<dyn Error + Send + Sync>>,
+    },
+    /// A tile that couldn't be encoded or decoded
+    #[error("{context}")]
+    TileFormat {
+        context: String,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// A request to an external service that failed
+    #[error("{context}")]
+    Http {
+        context: String,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// A route that couldn't be found, e.g. between places the roads don't connect
+    #[error("{message}")]
+    Routing { message: String },
+    /// Stopped through a `CancellationToken` before finishing
+    #[error("Cancelled after {progress}")]
+    Cancelled { progress: String },
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+impl GladsheimError {
+    pub(crate) fn parse(message: impl Into<String>) -> Self {
+        GladsheimError::Parse {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub(crate) fn routing(message: impl Into<String>) -> Self {
+        GladsheimError::Routing {
+            message: message.into(),
+        }
+    }
+}
+
+pub(crate) type Result<T, E = GladsheimError> = std::result::Result<T, E>;
+
+/// Describes what was being done when an IO operation failed
+pub(crate) trait IoContext<T> {
+    fn io_context(self, context: impl FnOnce() -> String) -> Result<T>;
+}
+impl<T> IoContext<T> for std::io::Result<T> {
+    fn io_context(self, context: impl FnOnce() -> String) -> Result<T> {
+        self.map_err(|source| GladsheimError::Io {
+            context: context(),
+            source,
+        })
+    }
+}
+
+/// Describes which input couldn't be parsed, keeping the underlying error as source
+pub(crate) trait ParseContext<T> {
+    fn parse_context(self, message: impl FnOnce() -> String) -> Result<T>;
+}
+impl<T, E: Error + Send + Sync + 'static> ParseContext<T> for std::result::Result<T, E> {
+    fn parse_context(self, message: impl FnOnce() -> String) -> Result<T> {
+        self.map_err(|source| GladsheimError::Parse {
+            message: message(),
+            source: Some(Box::new(source)),
+        })
+    }
+}
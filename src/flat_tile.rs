@@ -0,0 +1,187 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    NodeId,
+    error::{GladsheimError, IoContext, Result},
+    utils::{self, Quadkey, Tile, TileNaming},
+};
+
+/// Start of every flat tile, followed by `FLAT_VERSION`
+const FLAT_MAGIC: [u8; 4] = *b"GRF\x00";
+/// Incremented whenever the layout of flat tiles changes
+const FLAT_VERSION: u16 = 1;
+/// Magic bytes, version, padding and the number of nodes and arcs
+const HEADER_LEN: usize = 16;
+/// Way id, node index, length, duration, access, oneway and flags, with one byte of padding
+const ARC_LEN: usize = 24;
+/// Set in the flags of an arc running against its edge
+const BACKWARD_FLAG: u8 = 1;
+
+/// Path of the flat tile for a quadkey in a tile directory
+pub(crate) fn flat_tile_path(tiles_dir: &Path, quadkey: &Quadkey, naming: TileNaming) -> PathBuf {
+    utils::tile_path(tiles_dir, quadkey, naming).with_extension("grf")
+}
+
+/// Writes the flat layout of a tile next to it, returning whether the file changed
+pub(crate) fn write_flat_tile(
+    tiles_dir: &Path,
+    quadkey: &Quadkey,
+    naming: TileNaming,
+    tile: &Tile,
+) -> Result<bool> {
+    utils::write_bytes(
+        &flat_tile_path(tiles_dir, quadkey, naming),
+        &encode_flat_tile(tile),
+    )
+}
+
+/// Removes the flat layout of a tile that no longer exists
+pub(crate) fn remove_flat_tile(
+    tiles_dir: &Path,
+    quadkey: &Quadkey,
+    naming: TileNaming,
+) -> Result<()> {
+    let fname = flat_tile_path(tiles_dir, quadkey, naming);
+    if fname.exists() {
+        std::fs::remove_file(&fname)
+            .io_context(|| format!("Failed removing file {}", fname.display()))?;
+    }
+    Ok(())
+}
+
+/// Lays a tile out as fixed size little-endian records
+///
+/// After the header come the sorted node ids as `i64`, their latitudes and then
+/// longitudes as `f64`, and the index of the first arc of every node as `u32`
+/// with one more for the end of the last. The arcs follow, grouped by the node
+/// they leave, with every edge giving an arc in its direction from its first node
+/// and one against it from its last node.
+fn encode_flat_tile(tile: &Tile) -> Vec<u8> {
+    let num_nodes = tile.node_locations.len();
+    let indices = tile
+        .node_locations
+        .iter()
+        .enumerate()
+        .map(|(index, (node_id, _loc))| (*node_id, index as u32))
+        .collect::<HashMap<_, _>>();
+    let mut arcs_at = vec![Vec::new(); num_nodes];
+    for edge in &tile.edges {
+        let (Some(&from), Some(&to)) = (indices.get(&edge.from), indices.get(&edge.to)) else {
+            continue;
+        };
+        arcs_at[from as usize].push((edge, to, 0));
+        arcs_at[to as usize].push((edge, from, BACKWARD_FLAG));
+    }
+    let num_arcs = arcs_at.iter().map(Vec::len).sum::<usize>();
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + num_nodes * 28 + 4 + 4 + num_arcs * ARC_LEN);
+    bytes.extend(FLAT_MAGIC);
+    bytes.extend(FLAT_VERSION.to_le_bytes());
+    bytes.extend([0; 2]);
+    bytes.extend((num_nodes as u32).to_le_bytes());
+    bytes.extend((num_arcs as u32).to_le_bytes());
+    for (node_id, _loc) in &tile.node_locations {
+        bytes.extend(node_id.0.to_le_bytes());
+    }
+    for (_node_id, loc) in &tile.node_locations {
+        bytes.extend(loc.lat.to_le_bytes());
+    }
+    for (_node_id, loc) in &tile.node_locations {
+        bytes.extend(loc.lon.to_le_bytes());
+    }
+    let mut first_arc = 0u32;
+    for arcs in &arcs_at {
+        bytes.extend(first_arc.to_le_bytes());
+        first_arc += arcs.len() as u32;
+    }
+    bytes.extend(first_arc.to_le_bytes());
+    // Keep the arcs 8-byte aligned in the mapped file
+    bytes.resize(bytes.len().next_multiple_of(8), 0);
+    for (edge, other, flags) in arcs_at.into_iter().flatten() {
+        bytes.extend(edge.way_id.0.to_le_bytes());
+        bytes.extend(other.to_le_bytes());
+        bytes.extend(edge.length_m.to_le_bytes());
+        bytes.extend(edge.duration_s.to_le_bytes());
+        bytes.extend([edge.access.bits(), edge.oneway.bits(), flags, 0]);
+    }
+    bytes
+}
+
+/// A tile memory-mapped in the layout of `encode_flat_tile`
+///
+/// Nothing is decoded up front, node ids are read from the mapped bytes as
+/// they're searched, so opening a tile takes no time and pages that are never
+/// read never take up memory.
+pub(crate) struct FlatTile {
+    map: memmap2::Mmap,
+    num_nodes: usize,
+}
+impl FlatTile {
+    /// Maps a flat tile, checking its header and size
+    pub(crate) fn open(fname: &Path) -> Result<Self> {
+        let file = std::fs::File::open(fname)
+            .io_context(|| format!("Failed opening file {}", fname.display()))?;
+        // The tiles are only written by replacing the whole file, never in place
+        let map = unsafe { memmap2::Mmap::map(&file) }
+            .io_context(|| format!("Failed mapping file {}", fname.display()))?;
+        let invalid = |reason: &str| {
+            GladsheimError::parse(format!(
+                "{} is not a flat tile: {}",
+                fname.display(),
+                reason
+            ))
+        };
+        if map.len() < HEADER_LEN || map[..4] != FLAT_MAGIC {
+            return Err(invalid("missing magic bytes"));
+        }
+        let version = u16::from_le_bytes([map[4], map[5]]);
+        if version != FLAT_VERSION {
+            return Err(invalid(&format!(
+                "version {version}, while this build reads version {FLAT_VERSION}"
+            )));
+        }
+        let mut tile = Self { num_nodes: 0, map };
+        tile.num_nodes = tile.read_u32(8) as usize;
+        let num_arcs = tile.read_u32(12) as usize;
+        if tile.map.len() != tile.arcs_start() + num_arcs * ARC_LEN {
+            return Err(invalid("size not matching the header"));
+        }
+        Ok(tile)
+    }
+
+    /// The index of a node by binary search over the ids, `None` if it's not in the tile
+    pub(crate) fn node_index(&self, node_id: NodeId) -> Option<usize> {
+        let (mut low, mut high) = (0, self.num_nodes);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.node_id(middle).0.cmp(&node_id.0) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    pub(crate) fn node_id(&self, index: usize) -> NodeId {
+        NodeId(i64::from_le_bytes(self.read(HEADER_LEN + index * 8)))
+    }
+
+    fn arcs_start(&self) -> usize {
+        (HEADER_LEN + self.num_nodes * 24 + (self.num_nodes + 1) * 4).next_multiple_of(8)
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.read(offset))
+    }
+
+    /// Reads bytes at an offset that `open` made sure is within the file
+    fn read<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.map[offset..offset + N]);
+        bytes
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    Edge,
+    cancel::CancellationToken,
+    error::{IoContext, Result},
+    geometry,
+    osm_parser::Node,
+    profile::Profile,
+    utils::{Quadkey, TileSelector, TileStore},
+};
+
+/// Writes the edges of the selected tiles as a GeoJSON FeatureCollection of
+/// LineStrings, returning the number of features
+///
+/// Meant for looking at the built graph in QGIS or geojson.io, so each feature
+/// carries the attributes that parsing decides on as properties.
+pub(crate) fn export_geojson(
+    tiles_dir: &Path,
+    selector: &TileSelector,
+    output: &Path,
+    cancel: &CancellationToken,
+) -> Result<usize> {
+    let store = TileStore::open(tiles_dir)?.select(selector);
+    let file = std::fs::File::create(output)
+        .io_context(|| format!("Failed creating file {}", output.display()))?;
+    let mut writer = BufWriter::new(file);
+    let write_error = || format!("Failed writing file {}", output.display());
+
+    writer
+        .write_all(b"{\"type\":\"FeatureCollection\",\"features\":[")
+        .io_context(write_error)?;
+    let mut num_features = 0;
+    for (index, tile) in store.iter().enumerate() {
+        cancel.check(|| format!("exporting {} of {} tiles", index, store.len()))?;
+        let (quadkey, tile) = tile?;
+        let node_table = tile
+            .node_locations
+            .into_iter()
+            .map(|(node_id, loc)| {
+                (
+                    node_id,
+                    Node {
+                        loc,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        for edge in &tile.edges {
+            let coords = geometry::edge_coordinates(edge, &node_table);
+            if coords.len() < 2 {
+                continue;
+            }
+            if num_features > 0 {
+                writer.write_all(b",").io_context(write_error)?;
+            }
+            writer
+                .write_all(feature(edge, &coords, &quadkey).as_bytes())
+                .io_context(write_error)?;
+            num_features += 1;
+        }
+    }
+    writer.write_all(b"]}\n").io_context(write_error)?;
+    writer.flush().io_context(write_error)?;
+    Ok(num_features)
+}
+
+/// A GeoJSON Feature of an edge with its coordinates as `(lat, lon)`
+fn feature(edge: &Edge, coords: &[(f64, f64)], quadkey: &Quadkey) -> String {
+    let mut feature = String::from(
+        "{\"type\":\"Feature\",\"geometry\":{\"type\":\"LineString\",\"coordinates\":[",
+    );
+    for (index, (lat, lon)) in coords.iter().enumerate() {
+        if index > 0 {
+            feature.push(',');
+        }
+        // Writing into a String can't fail
+        let _ = write!(feature, "[{:.7},{:.7}]", lon, lat);
+    }
+    feature.push_str("]},\"properties\":{");
+    let _ = write!(
+        feature,
+        "\"way_id\":{},\"from\":{},\"to\":{},\"name\":{},\"ref\":{},\"refs\":[",
+        edge.way_id.0,
+        edge.from.0,
+        edge.to.0,
+        optional_json_string(edge.name.as_deref()),
+        optional_json_string(edge.road_ref.as_deref()),
+    );
+    for (index, road_ref) in edge.refs.iter().enumerate() {
+        if index > 0 {
+            feature.push(',');
+        }
+        let _ = write!(
+            feature,
+            "{{\"prefix\":{},\"number\":{},\"is_international\":{}}}",
+            optional_json_string(road_ref.prefix.as_deref()),
+            json_string(&road_ref.number),
+            road_ref.is_international
+        );
+    }
+    let access = Profile::value_variants()
+        .iter()
+        .filter(|profile| edge.access.contains(**profile))
+        .map(|profile| json_string(&value_name(*profile)))
+        .collect::<Vec<_>>();
+    let _ = write!(
+        feature,
+        "],\"class\":{},\"oneway\":{},\"access\":[{}],\"is_reversed\":{},\"speed_kmh\":{},\"length_m\":{:.1},\"duration_s\":{:.1},\"tile\":{}}}}}",
+        json_string(&value_name(edge.class)),
+        edge.is_oneway,
+        access.join(","),
+        edge.is_reversed,
+        edge.speed_kmh,
+        edge.length_m,
+        edge.duration_s,
+        json_string(&quadkey.0),
+    );
+    feature
+}
+
+/// The name of a value as given on the command line, e.g. `living-street`
+pub(crate) fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |value| value.get_name().to_string())
+}
+
+fn optional_json_string(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+/// Quotes a string for JSON, escaping quotes, backslashes and control characters
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", u32::from(c));
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
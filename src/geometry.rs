@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use crate::{Edge, NodeId, osm_parser::Node, utils};
+
+/// A point on an edge geometry together with the direction of travel there
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+pub(crate) struct GeometrySample {
+    pub(crate) lat: f64,
+    pub(crate) lon: f64,
+    /// Degrees clockwise from north, in `[0, 360)`
+    pub(crate) bearing: f64,
+}
+
+/// The coordinates along an edge as `(lat, lon)`, from its first node up to and including `to`
+///
+/// Nodes missing from the table are skipped.
+pub(crate) fn edge_coordinates(edge: &Edge, node_table: &HashMap<NodeId, Node>) -> Vec<(f64, f64)> {
+    edge.nodes
+        .iter()
+        .filter_map(|node_id| node_table.get(node_id))
+        .map(|node| (node.loc.lat, node.loc.lon))
+        .collect()
+}
+
+/// Length in meters of a line through the coordinates
+pub(crate) fn length(coords: &[(f64, f64)]) -> f64 {
+    coords
+        .windows(2)
+        .map(|pair| utils::haversine_distance(pair[0].0, pair[0].1, pair[1].0, pair[1].1))
+        .sum()
+}
+
+/// Where a point comes closest to a line
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LineProjection {
+    /// The closest point on the line as `(lat, lon)`
+    pub(crate) point: (f64, f64),
+    /// Meters from the point to the line
+    pub(crate) distance_m: f64,
+    /// Meters along the line from its start up to the closest point
+    pub(crate) along_m: f64,
+}
+
+/// Projects a point onto a line through coordinates given as `(lat, lon)`,
+/// `None` for lines of fewer than two coordinates
+///
+/// Segments are projected in a local equirectangular projection around the
+/// point, which is accurate for the short distances roads are snapped over.
+pub(crate) fn project_onto_line(
+    coords: &[(f64, f64)],
+    lat: f64,
+    lon: f64,
+) -> Option<LineProjection> {
+    let scale_x = lat.to_radians().cos();
+    let local = |(other_lat, other_lon): (f64, f64)| ((other_lon - lon) * scale_x, other_lat - lat);
+    let mut closest: Option<LineProjection> = None;
+    let mut along_m = 0.0;
+    for pair in coords.windows(2) {
+        let (a, b) = (local(pair[0]), local(pair[1]));
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len_sq = dx * dx + dy * dy;
+        let t = if len_sq > 0.0 {
+            (-(a.0 * dx + a.1 * dy) / len_sq).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let point = (
+            pair[0].0 + t * (pair[1].0 - pair[0].0),
+            pair[0].1 + t * (pair[1].1 - pair[0].1),
+        );
+        let segment_m = utils::haversine_distance(pair[0].0, pair[0].1, pair[1].0, pair[1].1);
+        let distance_m = utils::haversine_distance(lat, lon, point.0, point.1);
+        if closest.is_none_or(|closest| distance_m < closest.distance_m) {
+            closest = Some(LineProjection {
+                point,
+                distance_m,
+                along_m: along_m + t * segment_m,
+            });
+        }
+        along_m += segment_m;
+    }
+    closest
+}
+
+/// Initial great-circle bearing in degrees from the first to the second coordinate
+pub(crate) fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lon = (lon2 - lon1).to_radians();
+    let y = d_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// Encodes a line as a polyline with `precision` decimals, 5 as by Google and
+/// 6 as used by OSRM and Valhalla
+pub(crate) fn encode_polyline(
+    coords: &[(f64, f64)],
+    precision: u32,
+) -> Result<String, polyline::errors::PolylineError> {
+    let line = coords
+        .iter()
+        .map(|(lat, lon)| geo_types::coord! { x: *lon, y: *lat })
+        .collect::<geo_types::LineString<f64>>();
+    polyline::encode_coordinates(line, precision)
+}
+
+/// The part of a line between two shares of its length, reversed when `to`
+/// comes before `from`
+pub(crate) fn sub_line(coords: &[(f64, f64)], from: f64, to: f64) -> Vec<(f64, f64)> {
+    if to < from {
+        let mut reversed = sub_line(coords, to, from);
+        reversed.reverse();
+        return reversed;
+    }
+    let total = length(coords);
+    let (from_m, to_m) = (from * total, to * total);
+    let point_at = |pair: &[(f64, f64)], walked: f64, distance: f64, at_m: f64| {
+        let fraction = if distance > 0.0 {
+            ((at_m - walked) / distance).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (
+            pair[0].0 + (pair[1].0 - pair[0].0) * fraction,
+            pair[0].1 + (pair[1].1 - pair[0].1) * fraction,
+        )
+    };
+    let mut line = Vec::new();
+    let mut walked = 0.0;
+    for pair in coords.windows(2) {
+        let distance = utils::haversine_distance(pair[0].0, pair[0].1, pair[1].0, pair[1].1);
+        if line.is_empty() && from_m <= walked + distance {
+            line.push(point_at(pair, walked, distance, from_m));
+        }
+        if !line.is_empty() {
+            if to_m <= walked + distance {
+                line.push(point_at(pair, walked, distance, to_m));
+                break;
+            }
+            line.push(pair[1]);
+        }
+        walked += distance;
+    }
+    line
+}
+
+/// Resamples a line at fixed intervals, recording the bearing at each sample
+///
+/// The first and last coordinates are always included, so a line shorter than
+/// the interval produces two samples. Degenerate lines produce no samples.
+pub(crate) fn resample(coords: &[(f64, f64)], interval_m: f64) -> Vec<GeometrySample> {
+    let segments = coords
+        .windows(2)
+        .map(|pair| {
+            let (from, to) = (pair[0], pair[1]);
+            (
+                from,
+                to,
+                utils::haversine_distance(from.0, from.1, to.0, to.1),
+            )
+        })
+        .filter(|(_from, _to, distance)| *distance > 0.0)
+        .collect::<Vec<_>>();
+    let (Some(first), Some(last)) = (segments.first(), segments.last()) else {
+        return Vec::new();
+    };
+
+    let mut samples = vec![GeometrySample {
+        lat: first.0.0,
+        lon: first.0.1,
+        bearing: bearing(first.0.0, first.0.1, first.1.0, first.1.1),
+    }];
+    // Distance left to walk until the next sample is due
+    let mut remaining = interval_m;
+    for (from, to, distance) in &segments {
+        let segment_bearing = bearing(from.0, from.1, to.0, to.1);
+        let mut walked = 0.0;
+        while distance - walked >= remaining {
+            walked += remaining;
+            let fraction = walked / distance;
+            samples.push(GeometrySample {
+                lat: from.0 + (to.0 - from.0) * fraction,
+                lon: from.1 + (to.1 - from.1) * fraction,
+                bearing: segment_bearing,
+            });
+            remaining = interval_m;
+        }
+        remaining -= distance - walked;
+    }
+
+    let end = GeometrySample {
+        lat: last.1.0,
+        lon: last.1.1,
+        bearing: bearing(last.0.0, last.0.1, last.1.0, last.1.1),
+    };
+    if samples
+        .last()
+        .is_none_or(|sample| sample.lat != end.lat || sample.lon != end.lon)
+    {
+        samples.push(end);
+    }
+    samples
+}
+
+/// Lateral acceleration in m/s² considered comfortable when driving through a curve
+const COMFORTABLE_LATERAL_ACCELERATION: f64 = 2.5;
+/// Curves allowing at least this speed in km/h are not considered limiting
+const MAX_ADVISORY_SPEED_KMH: f64 = 130.0;
+/// Shorter segments are ignored since they're dominated by mapping noise
+const MIN_CURVATURE_SEGMENT_M: f64 = 2.0;
+
+/// The sharpest curve of an edge and the advisory speed for driving through it
+#[derive(Clone, Copy, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+pub(crate) struct Curvature {
+    /// Radius in meters of the sharpest curve
+    pub(crate) min_radius: f32,
+    /// Speed in km/h for taking the sharpest curve comfortably
+    pub(crate) safe_speed_kmh: u8,
+}
+impl Curvature {
+    /// Estimates the curvature of a line, `None` if it contains no limiting curve
+    pub(crate) fn from_coords(coords: &[(f64, f64)]) -> Option<Self> {
+        let min_radius = coords
+            .windows(3)
+            .filter_map(|triple| circumradius(triple[0], triple[1], triple[2]))
+            .reduce(f64::min)?;
+        let safe_speed_kmh = (COMFORTABLE_LATERAL_ACCELERATION * min_radius).sqrt() * 3.6;
+        if safe_speed_kmh >= MAX_ADVISORY_SPEED_KMH {
+            return None;
+        }
+        Some(Self {
+            min_radius: min_radius as f32,
+            safe_speed_kmh: safe_speed_kmh.round() as u8,
+        })
+    }
+}
+
+/// Radius of the circle through three coordinates, `None` when they are collinear
+fn circumradius(p1: (f64, f64), p2: (f64, f64), p3: (f64, f64)) -> Option<f64> {
+    let a = utils::haversine_distance(p1.0, p1.1, p2.0, p2.1);
+    let b = utils::haversine_distance(p2.0, p2.1, p3.0, p3.1);
+    let c = utils::haversine_distance(p1.0, p1.1, p3.0, p3.1);
+    if a < MIN_CURVATURE_SEGMENT_M || b < MIN_CURVATURE_SEGMENT_M {
+        return None;
+    }
+    // Heron's formula for the area of the triangle
+    let s = (a + b + c) / 2.0;
+    let area = (s * (s - a) * (s - b) * (s - c)).max(0.0).sqrt();
+    if area < f64::EPSILON {
+        return None;
+    }
+    Some(a * b * c / (4.0 * area))
+}
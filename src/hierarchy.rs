@@ -0,0 +1,687 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    Edge, NodeId,
+    boundary::BoundaryNode,
+    cancel::CancellationToken,
+    error::{GladsheimError, Result},
+    flat_tile, manifest,
+    osm_parser::{self, Loc, ParseOptions},
+    tile_proto,
+    utils::{self, Quadkey, Tile, TileStore},
+};
+
+/// Nodes a witness search settles before giving up and adding the shortcut
+///
+/// A missed witness only costs a superfluous shortcut, never a wrong route.
+const WITNESS_SETTLE_LIMIT: usize = 500;
+
+/// A shortcut of a contraction hierarchy, standing in for the fastest path
+/// between two nodes over nodes contracted before either of them
+#[derive(Clone, Debug)]
+pub(crate) struct Shortcut {
+    pub(crate) from: NodeId,
+    pub(crate) to: NodeId,
+    /// The node the shortcut was added for, it unpacks into the fastest way
+    /// from `from` to `via` and from `via` to `to`
+    pub(crate) via: NodeId,
+    pub(crate) duration_s: f32,
+}
+
+/// The part of a contraction hierarchy stored in a tile
+///
+/// Every tile holds the shortcuts starting or ending at nodes located in it,
+/// just as it holds the edges starting at those nodes, and lists the other
+/// tiles with shortcuts or edges at its nodes, so all ways out of and into a
+/// node are found by reading the tiles listed along with any one of them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TileHierarchy {
+    /// The day the hierarchy was built as `YYYYMMDD`, roads opening later are
+    /// left out of it
+    pub(crate) built_on: u32,
+    /// The contraction order of the nodes of the edges and shortcuts, nodes
+    /// contracted later rank higher, sorted by node id
+    pub(crate) ranks: Vec<(NodeId, u32)>,
+    pub(crate) shortcuts: Vec<Shortcut>,
+    /// The other tiles with edges or shortcuts at the nodes of the tile, where
+    /// `Tile::boundary_nodes` doesn't list them already
+    pub(crate) boundary_nodes: Vec<BoundaryNode>,
+}
+
+/// Numbers of a built hierarchy, for reporting
+pub(crate) struct HierarchyStats {
+    pub(crate) num_nodes: usize,
+    pub(crate) num_arcs: usize,
+    pub(crate) num_shortcuts: usize,
+    pub(crate) num_tiles: usize,
+}
+
+/// Contracts the road network of a tile directory into a contraction hierarchy
+/// and writes it back into the tiles
+///
+/// Nodes are contracted one at a time in the order of their edge difference,
+/// the shortcuts they need minus the arcs they remove, and each contraction
+/// adds a shortcut between two neighbours unless a witness search finds a way
+/// between them that is as fast without the node. The hierarchy covers the
+/// roads the profile of the tiles may use on the day it's built, except those
+/// of destination-only zones, whose use depends on where a route starts and
+/// ends. Tiles are rewritten in their own storage and naming, and a tile is
+/// added where nodes of shortcuts lie in none.
+pub(crate) fn build_hierarchy(
+    tiles_dir: &Path,
+    cancel: &CancellationToken,
+) -> Result<HierarchyStats> {
+    let start_time = std::time::Instant::now();
+    let (store, mut tiles) = read_base_tiles(tiles_dir, cancel)?;
+    let naming = store.naming();
+    let template = &tiles[0].1;
+    let built_on = utils::today();
+    let graph = Graph::of_tiles(&tiles, built_on);
+    println!(
+        "INFO: Read {} tiles with {} nodes and {} arcs in {}ms",
+        tiles.len(),
+        graph.node_ids.len(),
+        graph.num_arcs(),
+        start_time.elapsed().as_millis()
+    );
+
+    let start_time = std::time::Instant::now();
+    let num_arcs = graph.num_arcs();
+    let contraction = graph.contract(cancel)?;
+    println!(
+        "INFO: Contracted {} nodes, adding {} shortcuts, in {}ms",
+        contraction.ranks.len(),
+        contraction.shortcuts.len(),
+        start_time.elapsed().as_millis()
+    );
+
+    let zoom = template.zoom;
+    let template = empty_tile_like(template);
+    let mut hierarchies: HashMap<Quadkey, TileHierarchy> = HashMap::new();
+    let tile_of = |loc: &Loc| utils::lat_lon_to_quadkey(loc.lat, loc.lon, zoom).map(Quadkey);
+    for shortcut in &contraction.shortcuts {
+        let mut quadkeys = vec![
+            tile_of(&contraction.locations[&shortcut.from])?,
+            tile_of(&contraction.locations[&shortcut.to])?,
+        ];
+        quadkeys.dedup();
+        for quadkey in quadkeys {
+            hierarchies
+                .entry(quadkey)
+                .or_default()
+                .shortcuts
+                .push(shortcut.clone());
+        }
+    }
+    for (quadkey, _tile) in &tiles {
+        hierarchies.entry(quadkey.clone()).or_default();
+    }
+    let mut indices = tiles
+        .iter()
+        .enumerate()
+        .map(|(index, (quadkey, _tile))| (quadkey.clone(), index))
+        .collect::<HashMap<_, _>>();
+    for (quadkey, mut hierarchy) in hierarchies {
+        let index = *indices.entry(quadkey.clone()).or_insert_with(|| {
+            tiles.push((quadkey.clone(), empty_tile_like(&template)));
+            tiles.len() - 1
+        });
+        let tile = &mut tiles[index].1;
+        let mut ranked = tile
+            .edges
+            .iter()
+            .flat_map(|edge| [edge.from, edge.to])
+            .chain(
+                hierarchy
+                    .shortcuts
+                    .iter()
+                    .flat_map(|shortcut| [shortcut.from, shortcut.to]),
+            )
+            .filter_map(|node_id| Some((node_id, *contraction.ranks.get(&node_id)?)))
+            .collect::<Vec<_>>();
+        ranked.sort_by_key(|(node_id, _rank)| node_id.0);
+        ranked.dedup();
+        hierarchy.ranks = ranked;
+        hierarchy.built_on = built_on;
+        tile.hierarchy = Some(hierarchy);
+    }
+    link_hierarchies(&mut tiles);
+    tiles.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+
+    let options = ParseOptions {
+        naming,
+        storage: store.storage(),
+        flat: store
+            .quadkeys()
+            .any(|quadkey| flat_tile::flat_tile_path(tiles_dir, quadkey, naming).exists()),
+        protobuf: store
+            .quadkeys()
+            .any(|quadkey| tile_proto::proto_tile_path(tiles_dir, quadkey, naming).exists()),
+        ..Default::default()
+    };
+    cancel.check(|| format!("contracting {} tiles, before writing them", tiles.len()))?;
+    manifest::remove_manifest(tiles_dir)?;
+    let headers = osm_parser::write_tiles(tiles_dir, &tiles, &options, cancel)?;
+    manifest::write_manifest(tiles_dir, naming, store.overviews(), headers)?;
+    Ok(HierarchyStats {
+        num_nodes: contraction.ranks.len(),
+        num_arcs,
+        num_shortcuts: contraction.shortcuts.len(),
+        num_tiles: tiles.len(),
+    })
+}
+
+/// Reads all tiles of a directory, which must hold base tiles
+pub(crate) fn read_base_tiles(
+    tiles_dir: &Path,
+    cancel: &CancellationToken,
+) -> Result<(TileStore, Vec<(Quadkey, Tile)>)> {
+    let store = TileStore::open(tiles_dir)?;
+    let tiles = store
+        .iter()
+        .map(|tile| {
+            cancel.check(|| format!("reading the tiles of {}", tiles_dir.display()))?;
+            tile
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let Some((_quadkey, first)) = tiles.first() else {
+        return Err(GladsheimError::parse(format!(
+            "No tiles in {}",
+            tiles_dir.display()
+        )));
+    };
+    if first.max_class.is_some() {
+        return Err(GladsheimError::parse(format!(
+            "{} holds overview tiles, use the base tiles instead",
+            tiles_dir.display()
+        )));
+    }
+    Ok((store, tiles))
+}
+
+/// The nodes of the edges and shortcuts of a tile, with repetitions
+fn nodes_of(tile: &Tile) -> impl Iterator<Item = NodeId> + '_ {
+    tile.edges
+        .iter()
+        .flat_map(|edge| [edge.from, edge.to])
+        .chain(
+            tile.hierarchy
+                .iter()
+                .flat_map(|hierarchy| &hierarchy.shortcuts)
+                .flat_map(|shortcut| [shortcut.from, shortcut.to]),
+        )
+}
+
+/// Lists the other tiles with edges or shortcuts at the nodes of every tile, in
+/// its hierarchy, unless its boundary nodes list them already
+fn link_hierarchies(tiles: &mut [(Quadkey, Tile)]) {
+    let mut tiles_at: HashMap<NodeId, Vec<Quadkey>> = HashMap::new();
+    for (quadkey, tile) in tiles.iter() {
+        for node_id in nodes_of(tile) {
+            let quadkeys = tiles_at.entry(node_id).or_default();
+            if !quadkeys.contains(quadkey) {
+                quadkeys.push(quadkey.clone());
+            }
+        }
+    }
+    for (quadkey, tile) in tiles.iter_mut() {
+        let listed = tile
+            .boundary_nodes
+            .iter()
+            .map(|node| (node.node_id, &node.tiles))
+            .collect::<HashMap<_, _>>();
+        let mut boundary_nodes = nodes_of(tile)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter_map(|node_id| {
+                let mut others = tiles_at[&node_id]
+                    .iter()
+                    .filter(|other| {
+                        *other != quadkey
+                            && !listed
+                                .get(&node_id)
+                                .is_some_and(|listed| listed.contains(other))
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                others.sort_by(|a, b| a.0.cmp(&b.0));
+                (!others.is_empty()).then_some(BoundaryNode {
+                    node_id,
+                    tiles: others,
+                })
+            })
+            .collect::<Vec<_>>();
+        boundary_nodes.sort_by_key(|node| node.node_id.0);
+        if let Some(hierarchy) = &mut tile.hierarchy {
+            hierarchy.boundary_nodes = boundary_nodes;
+        }
+    }
+}
+
+/// A tile without edges, built like another one
+fn empty_tile_like(tile: &Tile) -> Tile {
+    Tile {
+        profile: tile.profile,
+        extra_profiles: tile.extra_profiles.clone(),
+        compression: tile.compression,
+        zoom: tile.zoom,
+        source_timestamp: tile.source_timestamp,
+        has_reverse_edges: tile.has_reverse_edges,
+        is_contracted: tile.is_contracted,
+        ..Default::default()
+    }
+}
+
+/// The road network of all tiles as arcs between densely numbered nodes
+#[derive(Clone)]
+pub(crate) struct Graph {
+    pub(crate) node_ids: Vec<NodeId>,
+    pub(crate) locations: Vec<Loc>,
+    /// Arcs leaving every node, with the node they lead to and their duration
+    pub(crate) outgoing: Vec<Vec<(u32, f64)>>,
+    /// Arcs entering every node, with the node they come from and their duration
+    pub(crate) incoming: Vec<Vec<(u32, f64)>>,
+}
+impl Graph {
+    /// The arcs of the edges the profile may use on a day, in every direction
+    /// they can be travelled, keeping the fastest of parallel arcs
+    pub(crate) fn of_tiles(tiles: &[(Quadkey, Tile)], date: u32) -> Self {
+        Self::with_costs(tiles, date, |edge| f64::from(edge.duration_s))
+    }
+
+    /// Like `of_tiles`, with arcs costing what `cost` gives for their edge
+    /// instead of its duration
+    pub(crate) fn with_costs(
+        tiles: &[(Quadkey, Tile)],
+        date: u32,
+        cost: impl Fn(&Edge) -> f64,
+    ) -> Self {
+        let mut graph = Graph {
+            node_ids: Vec::new(),
+            locations: Vec::new(),
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+        };
+        let mut indices: HashMap<NodeId, u32> = HashMap::new();
+        for (_quadkey, tile) in tiles {
+            let profile = tile.profile;
+            let location = |node_id: NodeId| {
+                tile.node_index(node_id)
+                    .map(|index| tile.node_locations[index].1.clone())
+            };
+            for edge in &tile.edges {
+                if !edge.access.contains(profile)
+                    || edge.not_before.is_some_and(|not_before| not_before > date)
+                    || edge.destination_zone.is_some()
+                {
+                    continue;
+                }
+                let (Some(from_loc), Some(to_loc)) = (location(edge.from), location(edge.to))
+                else {
+                    continue;
+                };
+                let mut index = |node_id: NodeId, loc: Loc| {
+                    *indices.entry(node_id).or_insert_with(|| {
+                        graph.node_ids.push(node_id);
+                        graph.locations.push(loc);
+                        graph.outgoing.push(Vec::new());
+                        graph.incoming.push(Vec::new());
+                        graph.node_ids.len() as u32 - 1
+                    })
+                };
+                let from = index(edge.from, from_loc);
+                let to = index(edge.to, to_loc);
+                let cost = cost(edge);
+                graph.add_arc(from, to, cost);
+                if !tile.has_reverse_edges && !edge.oneway.contains(profile) {
+                    graph.add_arc(to, from, cost);
+                }
+            }
+        }
+        graph
+    }
+
+    pub(crate) fn num_arcs(&self) -> usize {
+        self.outgoing.iter().map(Vec::len).sum()
+    }
+
+    /// Adds an arc unless there's one as fast already, returning whether it was
+    fn add_arc(&mut self, from: u32, to: u32, duration_s: f64) -> bool {
+        if from == to {
+            return false;
+        }
+        let outgoing = &mut self.outgoing[from as usize];
+        match outgoing.iter_mut().find(|(head, _duration)| *head == to) {
+            Some((_head, duration)) if *duration <= duration_s => return false,
+            Some((_head, duration)) => *duration = duration_s,
+            None => outgoing.push((to, duration_s)),
+        }
+        let incoming = &mut self.incoming[to as usize];
+        match incoming.iter_mut().find(|(tail, _duration)| *tail == from) {
+            Some((_tail, duration)) => *duration = duration_s,
+            None => incoming.push((from, duration_s)),
+        }
+        true
+    }
+
+    /// Contracts every node, returning their ranks and the shortcuts added
+    pub(crate) fn contract(mut self, cancel: &CancellationToken) -> Result<Contraction> {
+        let num_nodes = self.node_ids.len();
+        let mut is_contracted = vec![false; num_nodes];
+        let mut contracted_neighbours = vec![0i64; num_nodes];
+        let mut witness = WitnessSearch::new(num_nodes);
+        let mut queue = BinaryHeap::new();
+        for node in 0..num_nodes as u32 {
+            let priority =
+                self.priority(node, &is_contracted, &contracted_neighbours, &mut witness);
+            queue.push(Priority { priority, node });
+        }
+
+        let mut ranks = HashMap::with_capacity(num_nodes);
+        // Shortcut arcs by their ends, with their duration and the node they skip
+        let mut shortcuts: HashMap<(u32, u32), (f64, u32)> = HashMap::new();
+        while let Some(Priority { node, .. }) = queue.pop() {
+            if ranks.len() % 1024 == 0 {
+                cancel.check(|| format!("contracting {} of {} nodes", ranks.len(), num_nodes))?;
+            }
+            // Priorities only grow as neighbours are contracted, so a node whose
+            // priority went up waits for its turn again
+            let priority =
+                self.priority(node, &is_contracted, &contracted_neighbours, &mut witness);
+            if queue
+                .peek()
+                .is_some_and(|next: &Priority| priority > next.priority)
+            {
+                queue.push(Priority { priority, node });
+                continue;
+            }
+
+            for (from, to, duration_s) in self.needed_shortcuts(node, &is_contracted, &mut witness)
+            {
+                if self.add_arc(from, to, duration_s) {
+                    shortcuts.insert((from, to), (duration_s, node));
+                }
+            }
+            is_contracted[node as usize] = true;
+            ranks.insert(self.node_ids[node as usize], ranks.len() as u32);
+            for (neighbour, _duration) in self.outgoing[node as usize]
+                .iter()
+                .chain(&self.incoming[node as usize])
+            {
+                contracted_neighbours[*neighbour as usize] += 1;
+            }
+        }
+
+        let node_id = |node: u32| self.node_ids[node as usize];
+        let mut shortcuts = shortcuts
+            .into_iter()
+            .map(|((from, to), (duration_s, via))| Shortcut {
+                from: node_id(from),
+                to: node_id(to),
+                via: node_id(via),
+                duration_s: duration_s as f32,
+            })
+            .collect::<Vec<_>>();
+        shortcuts.sort_by_key(|shortcut| (shortcut.from.0, shortcut.to.0));
+        let locations = self
+            .node_ids
+            .iter()
+            .copied()
+            .zip(self.locations.iter().cloned())
+            .collect();
+        Ok(Contraction {
+            ranks,
+            shortcuts,
+            locations,
+        })
+    }
+
+    /// The edge difference of contracting a node, plus its contracted neighbours
+    /// to spread the contraction evenly over the network
+    fn priority(
+        &self,
+        node: u32,
+        is_contracted: &[bool],
+        contracted_neighbours: &[i64],
+        witness: &mut WitnessSearch,
+    ) -> i64 {
+        let num_shortcuts = self.needed_shortcuts(node, is_contracted, witness).len() as i64;
+        let num_removed = self.outgoing[node as usize]
+            .iter()
+            .chain(&self.incoming[node as usize])
+            .filter(|(neighbour, _duration)| !is_contracted[*neighbour as usize])
+            .count() as i64;
+        num_shortcuts - num_removed + contracted_neighbours[node as usize]
+    }
+
+    /// The shortcuts between the remaining neighbours of a node that contracting
+    /// it needs, those without a witness path as fast as the way over the node
+    fn needed_shortcuts(
+        &self,
+        node: u32,
+        is_contracted: &[bool],
+        witness: &mut WitnessSearch,
+    ) -> Vec<(u32, u32, f64)> {
+        let remaining = |arcs: &[(u32, f64)]| {
+            arcs.iter()
+                .filter(|(neighbour, _duration)| !is_contracted[*neighbour as usize])
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        let incoming = remaining(&self.incoming[node as usize]);
+        let outgoing = remaining(&self.outgoing[node as usize]);
+        let mut shortcuts = Vec::new();
+        for &(from, in_duration) in &incoming {
+            let targets = outgoing
+                .iter()
+                .filter(|(to, _duration)| *to != from)
+                .map(|(to, out_duration)| (*to, in_duration + out_duration))
+                .collect::<Vec<_>>();
+            let Some(max_duration) = targets
+                .iter()
+                .map(|(_to, duration)| *duration)
+                .max_by(f64::total_cmp)
+            else {
+                continue;
+            };
+            witness.run(self, from, node, max_duration, is_contracted);
+            for (to, duration) in targets {
+                if witness.duration(to) > duration {
+                    shortcuts.push((from, to, duration));
+                }
+            }
+        }
+        shortcuts
+    }
+}
+
+/// The result of contracting a graph, by node id
+pub(crate) struct Contraction {
+    pub(crate) ranks: HashMap<NodeId, u32>,
+    /// Sorted by their ends
+    shortcuts: Vec<Shortcut>,
+    locations: HashMap<NodeId, Loc>,
+}
+
+/// A node to contract, the heap pops the smallest priority first
+#[derive(PartialEq, Eq)]
+struct Priority {
+    priority: i64,
+    node: u32,
+}
+impl Ord for Priority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .cmp(&self.priority)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+impl PartialOrd for Priority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A Dijkstra search over the remaining graph that skips the node being
+/// contracted, reusing its buffers between searches
+struct WitnessSearch {
+    durations: Vec<f64>,
+    /// Nodes whose duration was set, to reset them before the next search
+    touched: Vec<u32>,
+}
+impl WitnessSearch {
+    fn new(num_nodes: usize) -> Self {
+        Self {
+            durations: vec![f64::INFINITY; num_nodes],
+            touched: Vec::new(),
+        }
+    }
+
+    /// The fastest way found from the start of the last search, infinite if none
+    fn duration(&self, node: u32) -> f64 {
+        self.durations[node as usize]
+    }
+
+    fn run(
+        &mut self,
+        graph: &Graph,
+        start: u32,
+        skipped: u32,
+        max_duration: f64,
+        is_contracted: &[bool],
+    ) {
+        for node in self.touched.drain(..) {
+            self.durations[node as usize] = f64::INFINITY;
+        }
+        let mut queue = BinaryHeap::new();
+        let mut settled = HashSet::new();
+        self.durations[start as usize] = 0.0;
+        self.touched.push(start);
+        queue.push(GraphEntry {
+            duration: 0.0,
+            node: start,
+        });
+        while let Some(GraphEntry { duration, node }) = queue.pop() {
+            if duration > max_duration || settled.len() >= WITNESS_SETTLE_LIMIT {
+                break;
+            }
+            if !settled.insert(node) {
+                continue;
+            }
+            for &(head, arc_duration) in &graph.outgoing[node as usize] {
+                if head == skipped || is_contracted[head as usize] {
+                    continue;
+                }
+                let duration = duration + arc_duration;
+                if duration < self.durations[head as usize] {
+                    if self.durations[head as usize].is_infinite() {
+                        self.touched.push(head);
+                    }
+                    self.durations[head as usize] = duration;
+                    queue.push(GraphEntry {
+                        duration,
+                        node: head,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// A node reached by a search over a `Graph`, the heap pops the fastest first
+#[derive(Clone, Copy)]
+pub(crate) struct GraphEntry {
+    pub(crate) duration: f64,
+    pub(crate) node: u32,
+}
+impl PartialEq for GraphEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for GraphEntry {}
+impl PartialOrd for GraphEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for GraphEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.duration.total_cmp(&self.duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A graph of numbered nodes joined by arcs with their durations
+    fn graph(num_nodes: u32, arcs: &[(u32, u32, f64)]) -> Graph {
+        let mut graph = Graph {
+            node_ids: (0..num_nodes)
+                .map(|node| NodeId(100 + i64::from(node)))
+                .collect(),
+            locations: (0..num_nodes)
+                .map(|node| Loc {
+                    lat: 59.33,
+                    lon: 18.06 + f64::from(node) * 0.001,
+                })
+                .collect(),
+            outgoing: vec![Vec::new(); num_nodes as usize],
+            incoming: vec![Vec::new(); num_nodes as usize],
+        };
+        for &(from, to, duration_s) in arcs {
+            graph.add_arc(from, to, duration_s);
+        }
+        graph
+    }
+
+    /// The fastest durations from a node to all others, by plain Dijkstra
+    fn durations_from(graph: &Graph, start: u32) -> Vec<f64> {
+        let mut witness = WitnessSearch::new(graph.node_ids.len());
+        let is_contracted = vec![false; graph.node_ids.len()];
+        witness.run(graph, start, u32::MAX, f64::INFINITY, &is_contracted);
+        (0..graph.node_ids.len() as u32)
+            .map(|node| witness.duration(node))
+            .collect()
+    }
+
+    #[test]
+    fn shortcuts_keep_the_fastest_durations() {
+        // A two-way street with a slow bypass and a fast one-way way back
+        let mut arcs = (0..4)
+            .flat_map(|node| [(node, node + 1, 10.0), (node + 1, node, 10.0)])
+            .collect::<Vec<_>>();
+        arcs.extend([(0, 4, 100.0), (4, 0, 5.0)]);
+        let graph = graph(5, &arcs);
+        let durations = (0..5)
+            .map(|node| durations_from(&graph, node))
+            .collect::<Vec<_>>();
+        let index = |node_id: NodeId| (node_id.0 - 100) as usize;
+
+        let contraction = graph.contract(&CancellationToken::default()).unwrap();
+        let mut ranks = contraction.ranks.values().copied().collect::<Vec<_>>();
+        ranks.sort();
+        assert_eq!(ranks, vec![0, 1, 2, 3, 4]);
+        assert!(!contraction.shortcuts.is_empty());
+        for shortcut in &contraction.shortcuts {
+            let (from, to, via) = (
+                index(shortcut.from),
+                index(shortcut.to),
+                index(shortcut.via),
+            );
+            assert_eq!(f64::from(shortcut.duration_s), durations[from][to]);
+            assert_eq!(
+                durations[from][via] + durations[via][to],
+                durations[from][to]
+            );
+            assert!(contraction.ranks[&shortcut.via] < contraction.ranks[&shortcut.from]);
+            assert!(contraction.ranks[&shortcut.via] < contraction.ranks[&shortcut.to]);
+        }
+    }
+}
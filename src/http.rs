@@ -0,0 +1,203 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::error::{GladsheimError, IoContext, Result};
+
+/// Settings shared by all requests to external services
+#[derive(Clone, Debug)]
+pub(crate) struct HttpOptions {
+    /// Limit for a whole request, including reading the response
+    pub(crate) timeout: Duration,
+    /// Proxy URL, falls back to the `HTTP_PROXY`/`HTTPS_PROXY` environment variables
+    pub(crate) proxy: Option<String>,
+    /// Number of requests in flight at once
+    pub(crate) workers: usize,
+}
+
+/// Counters over all requests made by a client
+#[derive(Debug, Default)]
+pub(crate) struct HttpMetrics {
+    pub(crate) requests: AtomicU64,
+    pub(crate) failures: AtomicU64,
+    pub(crate) bytes: AtomicU64,
+}
+
+/// The HTTP client used for all external services
+///
+/// Connections are pooled and reused between requests, and batches of requests
+/// run on a dedicated pool of worker threads so they don't starve the parsing.
+pub(crate) struct HttpClient {
+    agent: ureq::Agent,
+    workers: rayon::ThreadPool,
+    pub(crate) metrics: HttpMetrics,
+}
+impl HttpClient {
+    pub(crate) fn new(options: &HttpOptions) -> Result<Self> {
+        let proxy = match &options.proxy {
+            Some(proxy) => {
+                Some(
+                    ureq::Proxy::new(proxy).map_err(|source| GladsheimError::Http {
+                        context: format!("Invalid proxy {}", proxy),
+                        source: Box::new(source),
+                    })?,
+                )
+            }
+            None => ureq::Proxy::try_from_env(),
+        };
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(options.timeout))
+            .proxy(proxy)
+            .max_idle_connections(options.workers.max(1))
+            .user_agent(concat!("gladsheim/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .into();
+        let workers = rayon::ThreadPoolBuilder::new()
+            .num_threads(options.workers.max(1))
+            .thread_name(|index| format!("http-{index}"))
+            .build()
+            .map_err(|source| GladsheimError::Http {
+                context: "Failed starting HTTP workers".to_string(),
+                source: Box::new(source),
+            })?;
+        Ok(Self {
+            agent,
+            workers,
+            metrics: HttpMetrics::default(),
+        })
+    }
+
+    /// Downloads each URL to its file, returning the result of every download in order
+    pub(crate) fn download_all(&self, downloads: &[(String, PathBuf)]) -> Vec<Result<u64>> {
+        use rayon::prelude::*;
+        self.workers.install(|| {
+            downloads
+                .par_iter()
+                .map(|(url, fname)| {
+                    let result = self.download(url, fname);
+                    self.metrics.requests.fetch_add(1, Ordering::Relaxed);
+                    match &result {
+                        Ok(bytes) => self.metrics.bytes.fetch_add(*bytes, Ordering::Relaxed),
+                        Err(_) => self.metrics.failures.fetch_add(1, Ordering::Relaxed),
+                    };
+                    result
+                })
+                .collect()
+        })
+    }
+
+    /// Downloads a URL into a temporary file next to `fname` that is renamed into
+    /// place once complete, so a failed download never leaves a truncated file
+    /// that a rerun would take for the input
+    fn download(&self, url: &str, fname: &Path) -> Result<u64> {
+        let response = self
+            .agent
+            .get(url)
+            .call()
+            .map_err(|source| GladsheimError::Http {
+                context: format!("Failed requesting {}", url),
+                source: Box::new(source),
+            })?;
+        let mut tmp_name = fname.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_fname = PathBuf::from(tmp_name);
+        let download = || -> std::io::Result<u64> {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp_fname)?);
+            let bytes = std::io::copy(&mut response.into_body().into_reader(), &mut file)?;
+            file.into_inner()
+                .map_err(|error| error.into_error())?
+                .sync_all()?;
+            std::fs::rename(&tmp_fname, fname)?;
+            Ok(bytes)
+        };
+        download().or_else(|error| {
+            let _ = std::fs::remove_file(&tmp_fname);
+            Err(error).io_context(|| format!("Failed downloading {} to {}", url, fname.display()))
+        })
+    }
+
+    /// Requests each URL, returning the body of every response in order
+    ///
+    /// Responses with error statuses are returned too, since services such as
+    /// OSRM describe why a query failed in the body.
+    pub(crate) fn get_all(&self, urls: &[String]) -> Vec<Result<String>> {
+        use rayon::prelude::*;
+        self.workers.install(|| {
+            urls.par_iter()
+                .map(|url| {
+                    let result = self.get(url);
+                    self.metrics.requests.fetch_add(1, Ordering::Relaxed);
+                    match &result {
+                        Ok(body) => self
+                            .metrics
+                            .bytes
+                            .fetch_add(body.len() as u64, Ordering::Relaxed),
+                        Err(_) => self.metrics.failures.fetch_add(1, Ordering::Relaxed),
+                    };
+                    result
+                })
+                .collect()
+        })
+    }
+
+    fn get(&self, url: &str) -> Result<String> {
+        let http_error = |source: ureq::Error| GladsheimError::Http {
+            context: format!("Failed requesting {}", url),
+            source: Box::new(source),
+        };
+        self.agent
+            .get(url)
+            .config()
+            .http_status_as_error(false)
+            .build()
+            .call()
+            .map_err(http_error)?
+            .body_mut()
+            .read_to_string()
+            .map_err(http_error)
+    }
+
+    pub(crate) fn log_metrics(&self) {
+        println!(
+            "INFO: HTTP {} requests, {} failed, {}MB received",
+            self.metrics.requests.load(Ordering::Relaxed),
+            self.metrics.failures.load(Ordering::Relaxed),
+            self.metrics.bytes.load(Ordering::Relaxed) / 1_000_000
+        );
+    }
+}
+
+/// Downloads an input file given as an `http(s)://` URL into a directory
+///
+/// Returns the path to read the input from, which is the input itself when it's a local file.
+pub(crate) fn fetch_input(client: &HttpClient, input: &Path, dir: &Path) -> Result<PathBuf> {
+    let Some(url) = input
+        .to_str()
+        .filter(|input| input.starts_with("http://") || input.starts_with("https://"))
+    else {
+        return Ok(input.to_owned());
+    };
+    // Keep the file name, since the input format is detected from its extension
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| GladsheimError::parse(format!("No file name in URL {}", url)))?;
+    let fname = dir.join(name);
+    println!("INFO: Downloading {} to {}", url, fname.display());
+    let result = client
+        .download_all(&[(url.to_string(), fname.clone())])
+        .pop()
+        .unwrap_or_else(|| {
+            Err(GladsheimError::parse(format!(
+                "Nothing downloaded from {}",
+                url
+            )))
+        });
+    client.log_metrics();
+    result?;
+    Ok(fname)
+}
@@ -0,0 +1,769 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    NodeId,
+    cancel::CancellationToken,
+    error::{GladsheimError, IoContext, Result},
+    hierarchy::{self, Graph, GraphEntry},
+    osm_parser::Loc,
+    sample::SplitMix64,
+    utils::{self, Quadkey, TileNaming, TileStore},
+};
+
+/// Start of every label file, followed by `LABELS_VERSION`
+const LABELS_MAGIC: [u8; 4] = *b"GHL\x00";
+/// Incremented whenever the layout of label files changes
+const LABELS_VERSION: u16 = 1;
+/// Magic bytes, version, zoom, padding, the number of nodes and the day of the build
+const HEADER_LEN: usize = 16;
+
+/// Hubs of a node by index with the seconds to or from them, sorted by hub
+type Label = Vec<(u32, f32)>;
+
+/// Path of the label file for a quadkey in a tile directory
+pub(crate) fn labels_path(tiles_dir: &Path, quadkey: &Quadkey, naming: TileNaming) -> PathBuf {
+    utils::tile_path(tiles_dir, quadkey, naming).with_extension("hl")
+}
+
+/// Numbers of built labels, for reporting
+pub(crate) struct LabelStats {
+    pub(crate) num_nodes: usize,
+    pub(crate) num_entries: usize,
+    pub(crate) max_label_len: usize,
+    pub(crate) num_files: usize,
+    /// Bytes of all label files together
+    pub(crate) num_bytes: usize,
+}
+
+/// Builds hub labels for the nodes of a tile directory by pruned labeling,
+/// writing them into a label file next to the tile at the location of each node
+///
+/// Every node becomes a hub in turn, most important first, and a Dijkstra
+/// search from it in each direction labels the nodes it reaches, except those
+/// the labels so far already give as fast a way for, where the search stops
+/// expanding. Importance is the rank in the contraction hierarchy of the tiles,
+/// contracting a copy of the network when they don't have one, which keeps the
+/// labels small. Like the hierarchy, the labels cover the roads the profile may
+/// use on the day they are built, except those of destination-only zones.
+pub(crate) fn build_hub_labels(tiles_dir: &Path, cancel: &CancellationToken) -> Result<LabelStats> {
+    let start_time = std::time::Instant::now();
+    let (store, tiles) = hierarchy::read_base_tiles(tiles_dir, cancel)?;
+    let zoom = tiles[0].1.zoom;
+    let built_on = tiles
+        .iter()
+        .find_map(|(_quadkey, tile)| Some(tile.hierarchy.as_ref()?.built_on))
+        .unwrap_or_else(utils::today);
+    let graph = Graph::of_tiles(&tiles, built_on);
+    let mut ranks = tiles
+        .iter()
+        .flat_map(|(_quadkey, tile)| &tile.hierarchy)
+        .flat_map(|hierarchy| hierarchy.ranks.iter().copied())
+        .collect::<HashMap<_, _>>();
+    if ranks.is_empty() {
+        println!("INFO: No contraction hierarchy in the tiles, contracting to order the hubs");
+        ranks = graph.clone().contract(cancel)?.ranks;
+    }
+    let mut order = (0..graph.node_ids.len() as u32).collect::<Vec<_>>();
+    order
+        .sort_by_key(|node| std::cmp::Reverse(ranks.get(&graph.node_ids[*node as usize]).copied()));
+    println!(
+        "INFO: Read {} tiles with {} nodes and {} arcs in {}ms",
+        tiles.len(),
+        graph.node_ids.len(),
+        graph.num_arcs(),
+        start_time.elapsed().as_millis()
+    );
+
+    let start_time = std::time::Instant::now();
+    let (forward, backward) = label(&graph, &order, cancel)?;
+    println!(
+        "INFO: Labelled {} nodes in {}ms",
+        graph.node_ids.len(),
+        start_time.elapsed().as_millis()
+    );
+
+    let mut stats = LabelStats {
+        num_nodes: graph.node_ids.len(),
+        num_entries: forward.iter().chain(&backward).map(Vec::len).sum(),
+        max_label_len: forward
+            .iter()
+            .chain(&backward)
+            .map(Vec::len)
+            .max()
+            .unwrap_or_default(),
+        num_files: 0,
+        num_bytes: 0,
+    };
+    let mut nodes_of: HashMap<Quadkey, Vec<usize>> = HashMap::new();
+    for (node, loc) in graph.locations.iter().enumerate() {
+        let quadkey = Quadkey(utils::lat_lon_to_quadkey(loc.lat, loc.lon, zoom)?);
+        nodes_of.entry(quadkey).or_default().push(node);
+    }
+    let naming = store.naming();
+    for (quadkey, mut nodes) in nodes_of {
+        cancel.check(|| format!("writing the labels of tile {}", quadkey.0))?;
+        nodes.sort_by_key(|node| graph.node_ids[*node].0);
+        let labels = nodes
+            .iter()
+            .map(|node| (graph.node_ids[*node], &forward[*node], &backward[*node]))
+            .collect::<Vec<_>>();
+        let bytes = encode_labels(zoom, built_on, &labels);
+        stats.num_files += 1;
+        stats.num_bytes += bytes.len();
+        utils::write_bytes(&labels_path(tiles_dir, &quadkey, naming), &bytes)?;
+    }
+    Ok(stats)
+}
+
+/// Lays the labels of the nodes of a tile out as little-endian records
+///
+/// After the header come the sorted node ids as `i64`, then the byte offsets of
+/// the forward and of the backward label of every node, interleaved, as `u32`
+/// with one more for the end of the last. Offsets count from the end of them,
+/// where the labels follow as their entries, each the hub index as the LEB128
+/// varint of its difference to that of the previous entry, and the seconds as
+/// `f32`.
+fn encode_labels(zoom: u8, built_on: u32, labels: &[(NodeId, &Label, &Label)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut offsets = Vec::with_capacity(labels.len() * 2 + 1);
+    for label in labels
+        .iter()
+        .flat_map(|(_node_id, forward, backward)| [forward, backward])
+    {
+        offsets.push(data.len() as u32);
+        let mut previous = 0;
+        for &(hub, duration) in label.iter() {
+            let mut delta = hub - previous;
+            previous = hub;
+            while delta >= 0x80 {
+                data.push(delta as u8 | 0x80);
+                delta >>= 7;
+            }
+            data.push(delta as u8);
+            data.extend(duration.to_le_bytes());
+        }
+    }
+    offsets.push(data.len() as u32);
+
+    let mut bytes =
+        Vec::with_capacity(HEADER_LEN + labels.len() * 8 + offsets.len() * 4 + data.len());
+    bytes.extend(LABELS_MAGIC);
+    bytes.extend(LABELS_VERSION.to_le_bytes());
+    bytes.extend([zoom, 0]);
+    bytes.extend((labels.len() as u32).to_le_bytes());
+    bytes.extend(built_on.to_le_bytes());
+    for (node_id, _forward, _backward) in labels {
+        bytes.extend(node_id.0.to_le_bytes());
+    }
+    for offset in offsets {
+        bytes.extend(offset.to_le_bytes());
+    }
+    bytes.extend(data);
+    bytes
+}
+
+/// The hub labels of a tile, memory-mapped in the layout of `encode_labels`
+///
+/// Like `FlatTile`, nothing is decoded up front, labels are read from the mapped
+/// bytes as they're asked for.
+pub(crate) struct LabelTile {
+    map: memmap2::Mmap,
+    num_nodes: usize,
+    built_on: u32,
+}
+impl LabelTile {
+    /// Maps a label file, checking its header and offsets
+    pub(crate) fn open(fname: &Path) -> Result<Self> {
+        let file = std::fs::File::open(fname)
+            .io_context(|| format!("Failed opening file {}", fname.display()))?;
+        // Label files are only written by replacing the whole file, never in place
+        let map = unsafe { memmap2::Mmap::map(&file) }
+            .io_context(|| format!("Failed mapping file {}", fname.display()))?;
+        let invalid = |reason: &str| {
+            GladsheimError::parse(format!(
+                "{} is not a hub label file: {}",
+                fname.display(),
+                reason
+            ))
+        };
+        if map.len() < HEADER_LEN || map[..4] != LABELS_MAGIC {
+            return Err(invalid("missing magic bytes"));
+        }
+        let version = u16::from_le_bytes([map[4], map[5]]);
+        if version != LABELS_VERSION {
+            return Err(invalid(&format!(
+                "version {version}, while this build reads version {LABELS_VERSION}"
+            )));
+        }
+        let mut tile = Self {
+            num_nodes: 0,
+            built_on: 0,
+            map,
+        };
+        tile.num_nodes = tile.read_u32(8) as usize;
+        tile.built_on = tile.read_u32(12);
+        if tile.map.len() < tile.data_start() {
+            return Err(invalid("size not matching the header"));
+        }
+        let num_offsets = tile.num_nodes * 2 + 1;
+        let offsets = (0..num_offsets).map(|index| tile.offset(index));
+        let is_sorted = offsets
+            .clone()
+            .zip(offsets.skip(1))
+            .all(|(offset, next)| offset <= next);
+        if !is_sorted || tile.data_start() + tile.offset(num_offsets - 1) != tile.map.len() {
+            return Err(invalid("label offsets not matching the size"));
+        }
+        Ok(tile)
+    }
+
+    /// The day the labels were built as `YYYYMMDD`
+    pub(crate) fn built_on(&self) -> u32 {
+        self.built_on
+    }
+
+    /// The index of a node by binary search over the ids, `None` if it's not in the file
+    pub(crate) fn node_index(&self, node_id: NodeId) -> Option<usize> {
+        let (mut low, mut high) = (0, self.num_nodes);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.node_id(middle).0.cmp(&node_id.0) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    fn node_id(&self, index: usize) -> NodeId {
+        NodeId(i64::from_le_bytes(self.read(HEADER_LEN + index * 8)))
+    }
+
+    /// The hubs a node reaches with the seconds to them, sorted by hub
+    pub(crate) fn forward(&self, index: usize) -> LabelEntries<'_> {
+        self.entries(index * 2)
+    }
+
+    /// The hubs reaching a node with the seconds from them, sorted by hub
+    pub(crate) fn backward(&self, index: usize) -> LabelEntries<'_> {
+        self.entries(index * 2 + 1)
+    }
+
+    fn entries(&self, label: usize) -> LabelEntries<'_> {
+        let start = self.data_start() + self.offset(label);
+        let end = self.data_start() + self.offset(label + 1);
+        LabelEntries {
+            bytes: &self.map[start..end],
+            hub: 0,
+        }
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        self.read_u32(HEADER_LEN + self.num_nodes * 8 + index * 4) as usize
+    }
+
+    fn data_start(&self) -> usize {
+        HEADER_LEN + self.num_nodes * 8 + (self.num_nodes * 2 + 1) * 4
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.read(offset))
+    }
+
+    /// Reads bytes at an offset that `open` made sure is within the file
+    fn read<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.map[offset..offset + N]);
+        bytes
+    }
+}
+
+/// The entries of a label as `(hub, seconds)`, decoded as they're iterated
+pub(crate) struct LabelEntries<'a> {
+    bytes: &'a [u8],
+    hub: u32,
+}
+impl Iterator for LabelEntries<'_> {
+    type Item = (u32, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut delta = 0u32;
+        let mut shift = 0;
+        loop {
+            let (&byte, rest) = self.bytes.split_first()?;
+            self.bytes = rest;
+            delta |= u32::from(byte & 0x7f).checked_shl(shift)?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let (duration, rest) = self.bytes.split_first_chunk::<4>()?;
+        self.bytes = rest;
+        self.hub += delta;
+        Some((self.hub, f32::from_le_bytes(*duration)))
+    }
+}
+
+/// The hub labels of a tile directory, mapping label files as they're needed
+pub(crate) struct LabelStore {
+    tiles_dir: PathBuf,
+    naming: TileNaming,
+    zoom: u8,
+    /// Every label file asked for, `None` where there is none
+    tiles: HashMap<Quadkey, Option<LabelTile>>,
+    /// The day the labels were built as `YYYYMMDD`, which all label files must agree on
+    built_on: u32,
+}
+impl LabelStore {
+    /// Opens the labels of a tile directory built by `BuildHubLabels`
+    pub(crate) fn open(tiles_dir: &Path) -> Result<Self> {
+        let store = TileStore::open(tiles_dir)?;
+        let header = match store.quadkeys().next() {
+            Some(quadkey) => store.load_header(quadkey)?,
+            None => None,
+        };
+        let Some(header) = header else {
+            return Err(GladsheimError::parse(format!(
+                "No tiles in {}",
+                tiles_dir.display()
+            )));
+        };
+        let naming = store.naming();
+        let Some(first) = store
+            .quadkeys()
+            .map(|quadkey| labels_path(tiles_dir, quadkey, naming))
+            .find(|fname| fname.exists())
+        else {
+            return Err(GladsheimError::parse(format!(
+                "No hub labels in {}, run BuildHubLabels first",
+                tiles_dir.display()
+            )));
+        };
+        Ok(Self {
+            tiles_dir: tiles_dir.to_path_buf(),
+            naming,
+            zoom: header.zoom,
+            tiles: HashMap::new(),
+            built_on: LabelTile::open(&first)?.built_on(),
+        })
+    }
+
+    /// The day the labels were built as `YYYYMMDD`, roads opening later aren't in them
+    pub(crate) fn built_on(&self) -> u32 {
+        self.built_on
+    }
+
+    /// The label file holding the labels of nodes at a location
+    fn tile_at(&mut self, loc: &Loc) -> Result<Option<&LabelTile>> {
+        let quadkey = Quadkey(utils::lat_lon_to_quadkey(loc.lat, loc.lon, self.zoom)?);
+        if !self.tiles.contains_key(&quadkey) {
+            let fname = labels_path(&self.tiles_dir, &quadkey, self.naming);
+            let tile = if fname.exists() {
+                let tile = LabelTile::open(&fname)?;
+                // Files of different builds would give wrong travel times together
+                if tile.built_on() != self.built_on {
+                    return Err(GladsheimError::parse(format!(
+                        "{} is from another build of the hub labels, run BuildHubLabels again",
+                        fname.display()
+                    )));
+                }
+                Some(tile)
+            } else {
+                None
+            };
+            self.tiles.insert(quadkey.clone(), tile);
+        }
+        Ok(self.tiles[&quadkey].as_ref())
+    }
+
+    /// Seconds of the fastest route between two nodes at their locations,
+    /// `None` when there's none or a node has no labels
+    pub(crate) fn duration_s(
+        &mut self,
+        from: (NodeId, &Loc),
+        to: (NodeId, &Loc),
+    ) -> Result<Option<f64>> {
+        let forward = match self.tile_at(from.1)? {
+            Some(tile) => tile
+                .node_index(from.0)
+                .map(|index| tile.forward(index).collect::<Vec<_>>()),
+            None => None,
+        };
+        let Some(forward) = forward else {
+            return Ok(None);
+        };
+        let Some(tile) = self.tile_at(to.1)? else {
+            return Ok(None);
+        };
+        let Some(index) = tile.node_index(to.0) else {
+            return Ok(None);
+        };
+        // Both labels are sorted by hub, so they're merged like sorted lists
+        let mut forward = forward.into_iter().peekable();
+        let mut best: Option<f64> = None;
+        for (hub, backward_s) in tile.backward(index) {
+            while forward.next_if(|(other, _seconds)| *other < hub).is_some() {}
+            if let Some((_hub, forward_s)) = forward.next_if(|(other, _seconds)| *other == hub) {
+                let duration = f64::from(forward_s) + f64::from(backward_s);
+                best = Some(best.map_or(duration, |best| best.min(duration)));
+            }
+        }
+        Ok(best)
+    }
+}
+
+/// Travel times from labels may be this many seconds off, from storing them as `f32`
+const MAX_ERROR_S: f64 = 0.01;
+
+/// How the travel times of hub labels compare to those of a Dijkstra search
+pub(crate) struct VerifyStats {
+    pub(crate) num_pairs: usize,
+    /// Pairs both agree there's no route between
+    pub(crate) num_unreachable: usize,
+    /// Pairs the labels give a travel time more than `MAX_ERROR_S` off for, or
+    /// disagree on whether there's a route
+    pub(crate) num_wrong: usize,
+    pub(crate) mean_error_s: f64,
+    pub(crate) max_error_s: f64,
+    /// The largest error as a share of the travel time
+    pub(crate) max_relative_error: f64,
+}
+
+/// Compares the travel times the hub labels of a tile directory give between
+/// random pairs of nodes with those of a Dijkstra search over the tiles
+///
+/// The search goes over the roads the labels were built for, those open on the
+/// day they were built, so any difference is an error in the labels. The first
+/// wrong pairs are reported as warnings.
+pub(crate) fn verify_hub_labels(
+    tiles_dir: &Path,
+    num_samples: usize,
+    seed: u64,
+    cancel: &CancellationToken,
+) -> Result<VerifyStats> {
+    let mut labels = LabelStore::open(tiles_dir)?;
+    let (_store, tiles) = hierarchy::read_base_tiles(tiles_dir, cancel)?;
+    let graph = Graph::of_tiles(&tiles, labels.built_on());
+    if graph.node_ids.is_empty() {
+        return Err(GladsheimError::parse(format!(
+            "No roads in {}",
+            tiles_dir.display()
+        )));
+    }
+
+    let mut random = SplitMix64(seed);
+    let mut stats = VerifyStats {
+        num_pairs: 0,
+        num_unreachable: 0,
+        num_wrong: 0,
+        mean_error_s: 0.0,
+        max_error_s: 0.0,
+        max_relative_error: 0.0,
+    };
+    let mut total_error_s = 0.0;
+    let mut num_compared = 0;
+    for _ in 0..num_samples {
+        cancel.check(|| format!("verifying {} of {} pairs", stats.num_pairs, num_samples))?;
+        let mut pick = || (random.next_f64() * graph.node_ids.len() as f64) as u32;
+        let (from, to) = (pick(), pick());
+        let node = |node: u32| {
+            (
+                graph.node_ids[node as usize],
+                &graph.locations[node as usize],
+            )
+        };
+        let expected = fastest_duration(&graph, from, to);
+        let actual = labels.duration_s(node(from), node(to))?;
+        stats.num_pairs += 1;
+        let error_s = match (expected, actual) {
+            (None, None) => {
+                stats.num_unreachable += 1;
+                continue;
+            }
+            (Some(expected), Some(actual)) => {
+                let error_s = (actual - expected).abs();
+                total_error_s += error_s;
+                num_compared += 1;
+                stats.max_error_s = stats.max_error_s.max(error_s);
+                if expected > 0.0 {
+                    stats.max_relative_error = stats.max_relative_error.max(error_s / expected);
+                }
+                error_s
+            }
+            _ => f64::INFINITY,
+        };
+        if error_s > MAX_ERROR_S {
+            stats.num_wrong += 1;
+            if stats.num_wrong <= 10 {
+                let seconds = |duration: Option<f64>| {
+                    duration.map_or("no route".to_string(), |duration| format!("{duration:.2}s"))
+                };
+                println!(
+                    "WARN: From node {} to {} the labels give {} but there is {}",
+                    node(from).0.0,
+                    node(to).0.0,
+                    seconds(actual),
+                    seconds(expected)
+                );
+            }
+        }
+    }
+    if num_compared > 0 {
+        stats.mean_error_s = total_error_s / num_compared as f64;
+    }
+    Ok(stats)
+}
+
+/// Seconds of the fastest way between two nodes of a graph by Dijkstra
+fn fastest_duration(graph: &Graph, from: u32, to: u32) -> Option<f64> {
+    let mut durations = HashMap::from([(from, 0.0)]);
+    let mut queue = BinaryHeap::from([GraphEntry {
+        duration: 0.0,
+        node: from,
+    }]);
+    while let Some(GraphEntry { duration, node }) = queue.pop() {
+        if node == to {
+            return Some(duration);
+        }
+        if duration > durations[&node] {
+            continue;
+        }
+        for &(head, arc_duration) in &graph.outgoing[node as usize] {
+            let duration = duration + arc_duration;
+            if durations.get(&head).is_none_or(|known| duration < *known) {
+                durations.insert(head, duration);
+                queue.push(GraphEntry {
+                    duration,
+                    node: head,
+                });
+            }
+        }
+    }
+    None
+}
+
+/// The forward and backward labels of every node of a graph, taking the nodes
+/// as hubs in the given order
+fn label(
+    graph: &Graph,
+    order: &[u32],
+    cancel: &CancellationToken,
+) -> Result<(Vec<Label>, Vec<Label>)> {
+    let num_nodes = graph.node_ids.len();
+    let mut forward = vec![Label::new(); num_nodes];
+    let mut backward = vec![Label::new(); num_nodes];
+    // The label of the current hub by hub, to check labels against it quickly
+    let mut hub_label = vec![f64::INFINITY; num_nodes];
+    let mut durations = vec![f64::INFINITY; num_nodes];
+    let mut touched = Vec::new();
+    for (hub_index, &hub) in order.iter().enumerate() {
+        if hub_index % 1024 == 0 {
+            cancel.check(|| format!("labelling {} of {} nodes", hub_index, num_nodes))?;
+        }
+        // From the hub, labelling the backward labels of the nodes it reaches,
+        // then to it, labelling the forward ones
+        for is_from_hub in [true, false] {
+            let (own, others, arcs) = if is_from_hub {
+                (&forward, &mut backward, &graph.outgoing)
+            } else {
+                (&backward, &mut forward, &graph.incoming)
+            };
+            for &(other_hub, duration) in &own[hub as usize] {
+                hub_label[other_hub as usize] = f64::from(duration);
+            }
+            let mut queue = BinaryHeap::new();
+            durations[hub as usize] = 0.0;
+            touched.push(hub);
+            queue.push(GraphEntry {
+                duration: 0.0,
+                node: hub,
+            });
+            while let Some(GraphEntry { duration, node }) = queue.pop() {
+                if duration > durations[node as usize] {
+                    continue;
+                }
+                let known = others[node as usize]
+                    .iter()
+                    .map(|(other_hub, other)| hub_label[*other_hub as usize] + f64::from(*other))
+                    .min_by(f64::total_cmp)
+                    .unwrap_or(f64::INFINITY);
+                if node != hub && known.total_cmp(&duration) != Ordering::Greater {
+                    continue;
+                }
+                others[node as usize].push((hub_index as u32, duration as f32));
+                for &(head, arc_duration) in &arcs[node as usize] {
+                    let duration = duration + arc_duration;
+                    if duration < durations[head as usize] {
+                        if durations[head as usize].is_infinite() {
+                            touched.push(head);
+                        }
+                        durations[head as usize] = duration;
+                        queue.push(GraphEntry {
+                            duration,
+                            node: head,
+                        });
+                    }
+                }
+            }
+            for node in touched.drain(..) {
+                durations[node as usize] = f64::INFINITY;
+            }
+            for &(other_hub, _duration) in &own[hub as usize] {
+                hub_label[other_hub as usize] = f64::INFINITY;
+            }
+        }
+    }
+    Ok((forward, backward))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Edge,
+        adjacency::Adjacency,
+        osm_parser::Loc,
+        profile::{Profile, ProfileMask},
+        utils::{Quadkey, Tile},
+    };
+
+    /// Five nodes on a two-way street with a slow bypass and a fast oneway
+    /// way back, as seconds between nodes
+    fn street() -> Graph {
+        let node_locations = (1..=5)
+            .map(|id| {
+                let lon = 18.06 + id as f64 * 0.001;
+                (NodeId(id), Loc { lat: 59.33, lon })
+            })
+            .collect::<Vec<_>>();
+        let mut car = ProfileMask::default();
+        car.insert(Profile::Car);
+        let edge = |from: i64, to: i64, duration_s: f32, is_oneway: bool| Edge {
+            from: NodeId(from),
+            to: NodeId(to),
+            nodes: vec![NodeId(from), NodeId(to)],
+            access: car,
+            oneway: if is_oneway {
+                car
+            } else {
+                ProfileMask::default()
+            },
+            is_oneway,
+            duration_s,
+            ..Default::default()
+        };
+        let mut edges = (1..5)
+            .map(|id| edge(id, id + 1, 10.0, false))
+            .collect::<Vec<_>>();
+        edges.extend([edge(1, 5, 100.0, true), edge(5, 1, 5.0, true)]);
+        let tile = Tile {
+            adjacency: Adjacency::of_edges(&node_locations, &edges),
+            node_locations,
+            edges,
+            ..Default::default()
+        };
+        Graph::of_tiles(&[(Quadkey("0".to_string()), tile)], 20260101)
+    }
+
+    /// The fastest durations from a node to all others, by plain Dijkstra
+    fn durations_from(graph: &Graph, start: u32) -> Vec<f64> {
+        let mut durations = vec![f64::INFINITY; graph.node_ids.len()];
+        let mut queue = BinaryHeap::new();
+        durations[start as usize] = 0.0;
+        queue.push(GraphEntry {
+            duration: 0.0,
+            node: start,
+        });
+        while let Some(GraphEntry { duration, node }) = queue.pop() {
+            if duration > durations[node as usize] {
+                continue;
+            }
+            for &(head, arc_duration) in &graph.outgoing[node as usize] {
+                if duration + arc_duration < durations[head as usize] {
+                    durations[head as usize] = duration + arc_duration;
+                    queue.push(GraphEntry {
+                        duration: duration + arc_duration,
+                        node: head,
+                    });
+                }
+            }
+        }
+        durations
+    }
+
+    #[test]
+    fn labels_give_the_fastest_durations() {
+        let graph = street();
+        let num_nodes = graph.node_ids.len() as u32;
+        assert_eq!(num_nodes, 5);
+        // Along the street one way, and over the fast oneway back
+        assert_eq!(durations_from(&graph, 0)[4], 40.0);
+        assert_eq!(durations_from(&graph, 4)[0], 5.0);
+        // Any order of the hubs gives exact labels, only their size differs
+        for order in [
+            (0..num_nodes).collect::<Vec<_>>(),
+            (0..num_nodes).rev().collect(),
+        ] {
+            let (forward, backward) = label(&graph, &order, &CancellationToken::default()).unwrap();
+            for from in 0..num_nodes {
+                let expected = durations_from(&graph, from);
+                for to in 0..num_nodes {
+                    let duration = forward[from as usize]
+                        .iter()
+                        .filter_map(|(hub, to_hub)| {
+                            let (_hub, from_hub) = backward[to as usize]
+                                .iter()
+                                .find(|(other, _duration)| other == hub)?;
+                            Some(f64::from(to_hub + from_hub))
+                        })
+                        .min_by(f64::total_cmp);
+                    assert_eq!(duration, Some(expected[to as usize]), "{from} to {to}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn label_files_round_trip() {
+        let dir = std::env::temp_dir().join(format!("gladsheim-labels-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fname = dir.join("0.hl");
+        // Hubs far apart take more than a byte to store
+        let forward: Label = vec![(0, 0.0), (3, 12.5), (300, 1e5)];
+        let backward: Label = vec![(2, 7.0)];
+        let empty = Label::new();
+        let labels = [
+            (NodeId(-4), &empty, &backward),
+            (NodeId(17), &forward, &empty),
+        ];
+        let bytes = encode_labels(14, 20260101, &labels);
+        utils::write_bytes(&fname, &bytes).unwrap();
+        let tile = LabelTile::open(&fname);
+        let truncated = &bytes[..bytes.len() - 1];
+        utils::write_bytes(&fname, truncated).unwrap();
+        let truncated = LabelTile::open(&fname);
+        let mut newer = bytes.clone();
+        newer[4] += 1;
+        utils::write_bytes(&fname, &newer).unwrap();
+        let newer = LabelTile::open(&fname);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let tile = tile.unwrap();
+        assert_eq!(tile.built_on(), 20260101);
+        assert_eq!(tile.node_index(NodeId(-4)), Some(0));
+        assert_eq!(tile.node_index(NodeId(17)), Some(1));
+        assert_eq!(tile.node_index(NodeId(5)), None);
+        assert_eq!(tile.forward(0).count(), 0);
+        assert_eq!(tile.backward(0).collect::<Vec<_>>(), backward);
+        assert_eq!(tile.forward(1).collect::<Vec<_>>(), forward);
+        assert_eq!(tile.backward(1).count(), 0);
+        assert!(truncated.is_err());
+        assert!(newer.is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use crate::{
+    cancel::CancellationToken,
+    error::{GladsheimError, Result},
+    routing::{self, Coordinate, Reached, RouteOptions, Snap, TileGraph},
+    utils,
+};
+
+/// The area reachable from a location within a time budget
+pub(crate) struct Isochrone {
+    pub(crate) budget_s: f64,
+    /// Outer rings of the area as `(lat, lon)`, counterclockwise and closed
+    pub(crate) rings: Vec<Vec<(f64, f64)>>,
+}
+
+/// The areas reachable from a location within several time budgets
+pub(crate) struct Isochrones {
+    pub(crate) origin: Snap,
+    /// One area per budget, the largest first
+    pub(crate) isochrones: Vec<Isochrone>,
+}
+impl Isochrones {
+    /// The areas as a GeoJSON FeatureCollection with a MultiPolygon per budget,
+    /// the largest first so the smaller ones are drawn on top of it
+    pub(crate) fn to_geojson(&self) -> String {
+        let mut json = String::from(r#"{"type":"FeatureCollection","features":["#);
+        for (index, isochrone) in self.isochrones.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            json.push_str(r#"{"type":"Feature","geometry":{"type":"MultiPolygon","coordinates":["#);
+            for (index, ring) in isochrone.rings.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                json.push_str("[[");
+                for (index, (lat, lon)) in ring.iter().enumerate() {
+                    if index > 0 {
+                        json.push(',');
+                    }
+                    let _ = write!(json, "[{:.7},{:.7}]", lon, lat);
+                }
+                json.push_str("]]");
+            }
+            let _ = write!(
+                json,
+                r#"]}},"properties":{{"seconds":{},"origin":{}}}}}"#,
+                isochrone.budget_s,
+                self.origin.to_json()
+            );
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Finds the areas reachable from a location within each of the budgets, by a
+/// single search bounded by the largest of them
+///
+/// The roads reached in time are laid onto a grid of `cell_size_m` cells, and
+/// the outlines of the cells they cross make up the areas. Holes are filled,
+/// a block enclosed by reachable roads being counted as reachable, so smaller
+/// cells follow the roads more closely but leave more gaps between them.
+pub(crate) fn isochrones(
+    graph: &mut TileGraph,
+    origin: Coordinate,
+    budgets_s: &[f64],
+    cell_size_m: f64,
+    options: &RouteOptions,
+    cancel: &CancellationToken,
+) -> Result<Isochrones> {
+    if budgets_s.is_empty() || budgets_s.iter().any(|budget_s| *budget_s <= 0.0) {
+        return Err(GladsheimError::parse(format!(
+            "Invalid time budgets {:?}, expected positive seconds",
+            budgets_s
+        )));
+    }
+    if cell_size_m <= 0.0 {
+        return Err(GladsheimError::parse(format!(
+            "Invalid cell size {}m, expected a positive size",
+            cell_size_m
+        )));
+    }
+    let mut budgets_s = budgets_s.to_vec();
+    budgets_s.sort_by(|a, b| b.total_cmp(a));
+    budgets_s.dedup();
+
+    let origin = graph.snap(origin, options)?;
+    let reached = routing::reachable(graph, &origin, budgets_s[0], options, cancel)?;
+    let grid = Grid::new(origin.point, cell_size_m);
+    // The coordinates of every edge reached, in meters on the grid
+    let mut lines = HashMap::new();
+    let isochrones = budgets_s
+        .iter()
+        .map(|budget_s| {
+            cancel.check(|| format!("outlining the area within {}s", budget_s))?;
+            let mut cells = HashSet::new();
+            for stretch in &reached {
+                let Some((start, end)) = clip(stretch, *budget_s) else {
+                    continue;
+                };
+                let line = lines.entry(stretch.traversal.edge).or_insert_with(|| {
+                    graph
+                        .edge_coordinates(stretch.traversal.edge)
+                        .into_iter()
+                        .map(|coordinate| grid.project(coordinate))
+                        .collect::<Vec<_>>()
+                });
+                grid.mark(line, start.min(end), start.max(end), &mut cells);
+            }
+            Ok(Isochrone {
+                budget_s: *budget_s,
+                rings: outlines(&cells)
+                    .into_iter()
+                    .map(|ring| {
+                        ring.into_iter()
+                            .map(|corner| grid.unproject(corner))
+                            .collect()
+                    })
+                    .collect(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Isochrones { origin, isochrones })
+}
+
+/// The shares of its edge a stretch covers within a budget, from where it
+/// starts, `None` when the budget runs out before it
+fn clip(stretch: &Reached, budget_s: f64) -> Option<(f64, f64)> {
+    let Reached {
+        traversal,
+        start_s,
+        end_s,
+    } = *stretch;
+    if start_s > budget_s {
+        return None;
+    }
+    let within = if end_s > start_s {
+        ((budget_s - start_s) / (end_s - start_s)).min(1.0)
+    } else {
+        1.0
+    };
+    Some((
+        traversal.start,
+        traversal.start + (traversal.end - traversal.start) * within,
+    ))
+}
+
+/// Square cells laid out in meters east and north of a location
+struct Grid {
+    origin: (f64, f64),
+    cell_size_m: f64,
+    meters_per_lat: f64,
+    meters_per_lon: f64,
+}
+impl Grid {
+    fn new(origin: (f64, f64), cell_size_m: f64) -> Self {
+        let meters_per_lat =
+            utils::haversine_distance(origin.0, origin.1, origin.0 + 1.0, origin.1);
+        Self {
+            origin,
+            cell_size_m,
+            meters_per_lat,
+            meters_per_lon: meters_per_lat * origin.0.to_radians().cos(),
+        }
+    }
+
+    /// A coordinate as `(lat, lon)` in meters east and north of the origin
+    fn project(&self, (lat, lon): (f64, f64)) -> (f64, f64) {
+        (
+            (lon - self.origin.1) * self.meters_per_lon,
+            (lat - self.origin.0) * self.meters_per_lat,
+        )
+    }
+
+    /// A corner of the cells as `(lat, lon)`
+    fn unproject(&self, (x, y): (i32, i32)) -> (f64, f64) {
+        (
+            self.origin.0 + f64::from(y) * self.cell_size_m / self.meters_per_lat,
+            self.origin.1 + f64::from(x) * self.cell_size_m / self.meters_per_lon,
+        )
+    }
+
+    fn cell(&self, (x, y): (f64, f64)) -> (i32, i32) {
+        (
+            (x / self.cell_size_m).floor() as i32,
+            (y / self.cell_size_m).floor() as i32,
+        )
+    }
+
+    /// Marks the cells a line crosses between two shares of its length
+    fn mark(&self, line: &[(f64, f64)], from: f64, to: f64, cells: &mut HashSet<(i32, i32)>) {
+        let lengths = line
+            .windows(2)
+            .map(|pair| (pair[1].0 - pair[0].0).hypot(pair[1].1 - pair[0].1))
+            .collect::<Vec<_>>();
+        let total = lengths.iter().sum::<f64>();
+        let (from, to) = (from * total, to * total);
+        // Steps short enough not to skip a cell
+        let step = self.cell_size_m / 4.0;
+        let mut walked = 0.0;
+        for (pair, length) in line.windows(2).zip(lengths) {
+            let (start, end) = (from.max(walked), to.min(walked + length));
+            if start <= end && length > 0.0 {
+                let num_steps = ((end - start) / step).ceil().max(1.0) as usize;
+                for index in 0..=num_steps {
+                    let along =
+                        (start + (end - start) * index as f64 / num_steps as f64 - walked) / length;
+                    cells.insert(self.cell((
+                        pair[0].0 + (pair[1].0 - pair[0].0) * along,
+                        pair[0].1 + (pair[1].1 - pair[0].1) * along,
+                    )));
+                }
+            }
+            walked += length;
+        }
+        if line.len() == 1 {
+            cells.insert(self.cell(line[0]));
+        }
+    }
+}
+
+/// The outer rings around groups of cells as closed lists of cell corners,
+/// counterclockwise and without corners along straight sides
+///
+/// Every side of a cell without a neighbour across it is a side of a ring,
+/// directed with the cell on its left. Rings only touching at a corner are
+/// kept apart by turning left there, and the clockwise rings around holes are
+/// left out.
+fn outlines(cells: &HashSet<(i32, i32)>) -> Vec<Vec<(i32, i32)>> {
+    let mut sides: HashMap<(i32, i32), Vec<(i32, i32)>> = HashMap::new();
+    for &(x, y) in cells {
+        let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+        let neighbours = [(x, y - 1), (x + 1, y), (x, y + 1), (x - 1, y)];
+        for (index, neighbour) in neighbours.iter().enumerate() {
+            if !cells.contains(neighbour) {
+                sides
+                    .entry(corners[index])
+                    .or_default()
+                    .push(corners[(index + 1) % 4]);
+            }
+        }
+    }
+
+    let mut starts = sides.keys().copied().collect::<Vec<_>>();
+    starts.sort_unstable();
+    let mut rings = Vec::new();
+    for start in starts {
+        while let Some(next) = sides.get_mut(&start).and_then(Vec::pop) {
+            let mut ring = vec![start, next];
+            let mut corner = next;
+            while corner != start {
+                let (from, to) = (ring[ring.len() - 2], corner);
+                let direction = (to.0 - from.0, to.1 - from.1);
+                let Some(ends) = sides.get_mut(&corner).filter(|ends| !ends.is_empty()) else {
+                    break;
+                };
+                // The leftmost turn, the cross product of the directions being largest
+                let (index, _) = ends
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(_index, end)| {
+                        direction.0 * (end.1 - corner.1) - direction.1 * (end.0 - corner.0)
+                    })
+                    .expect("a side leaves the corner");
+                corner = ends.swap_remove(index);
+                ring.push(corner);
+            }
+            if corner == start && signed_area(&ring) > 0 {
+                rings.push(simplify(ring));
+            }
+        }
+    }
+    rings
+}
+
+/// Twice the area of a closed ring, positive when counterclockwise
+fn signed_area(ring: &[(i32, i32)]) -> i64 {
+    ring.windows(2)
+        .map(|pair| {
+            i64::from(pair[0].0) * i64::from(pair[1].1)
+                - i64::from(pair[1].0) * i64::from(pair[0].1)
+        })
+        .sum()
+}
+
+/// Drops the corners of a closed ring along straight sides
+fn simplify(ring: Vec<(i32, i32)>) -> Vec<(i32, i32)> {
+    // Without the repeated corner closing it
+    let corners = &ring[..ring.len() - 1];
+    let mut simplified = corners
+        .iter()
+        .enumerate()
+        .filter(|(index, corner)| {
+            let previous = corners[(index + corners.len() - 1) % corners.len()];
+            let next = corners[(index + 1) % corners.len()];
+            (corner.0 - previous.0) * (next.1 - corner.1)
+                != (corner.1 - previous.1) * (next.0 - corner.0)
+        })
+        .map(|(_index, corner)| *corner)
+        .collect::<Vec<_>>();
+    simplified.push(simplified[0]);
+    simplified
+}
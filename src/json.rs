@@ -0,0 +1,218 @@
+/// A JSON value, as read by `parse`
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// The members in the order given
+    Object(Vec<(String, Value)>),
+}
+impl Value {
+    /// The member of an object with a key, `None` for other values
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(name, _value)| name == key)
+                .map(|(_name, value)| value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a JSON document, describing what's wrong and where when it isn't valid
+pub(crate) fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl Parser<'_> {
+    fn error(&self, what: &str) -> String {
+        format!("Invalid JSON at byte {}: {}", self.pos, what)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips a literal such as `null` when it comes next
+    fn literal(&mut self, literal: &str) -> bool {
+        let is_next = self.bytes[self.pos..].starts_with(literal.as_bytes());
+        if is_next {
+            self.pos += literal.len();
+        }
+        is_next
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ if self.literal("null") => Ok(Value::Null),
+            _ if self.literal("true") => Ok(Value::Bool(true)),
+            _ if self.literal("false") => Ok(Value::Bool(false)),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.literal("}") {
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.bytes.get(self.pos) != Some(&b'"') {
+                return Err(self.error("expected a key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.literal(":") {
+                return Err(self.error("expected ':'"));
+            }
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            if self.literal("}") {
+                return Ok(Value::Object(members));
+            }
+            if !self.literal(",") {
+                return Err(self.error("expected ',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.literal("]") {
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            if self.literal("]") {
+                return Ok(Value::Array(values));
+            }
+            if !self.literal(",") {
+                return Err(self.error("expected ',' or ']'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            let start = self.pos;
+            while self
+                .bytes
+                .get(self.pos)
+                .is_some_and(|byte| *byte != b'"' && *byte != b'\\')
+            {
+                self.pos += 1;
+            }
+            // Splitting only at ASCII quotes and backslashes keeps the UTF-8 valid
+            string.push_str(
+                std::str::from_utf8(&self.bytes[start..self.pos])
+                    .map_err(|_utf8| self.error("invalid UTF-8"))?,
+            );
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    let escaped = match self.bytes.get(self.pos + 1) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let code = self
+                                .bytes
+                                .get(self.pos + 2..self.pos + 6)
+                                .and_then(|hex| std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .ok_or_else(|| self.error("invalid unicode escape"))?;
+                            self.pos += 4;
+                            // Halves of surrogate pairs don't make up characters on their own
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    string.push(escaped);
+                    self.pos += 2;
+                }
+                _ => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Value::Number)
+            .ok_or_else(|| self.error("invalid number"))
+    }
+}
@@ -0,0 +1,462 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    Edge, NodeId, RoadClass, WayId,
+    cancel::CancellationToken,
+    error::{IoContext, Result},
+    osm_parser::Loc,
+    profile::Profile,
+    restrictions::{TurnRestriction, Via},
+    utils::{self, TileSelector, TileStore},
+};
+
+/// Components smaller than this containing important roads are reported as isolated
+const MAX_ISOLATED_COMPONENT_EDGES: usize = 20;
+
+/// A suspicious pattern in the data, most likely a mapping error
+#[derive(Debug)]
+enum Issue {
+    /// Traffic can enter the node along oneways but never leave it
+    OnewaySink { node: NodeId, ways: Vec<WayId> },
+    /// Traffic can leave the node along oneways but never reach it
+    OnewaySource { node: NodeId, ways: Vec<WayId> },
+    /// A small island of the graph that contains important roads
+    IsolatedHighClassRoad { ways: Vec<WayId>, class: RoadClass },
+    /// A turn restriction with a member that isn't a road of the tiles, such as a
+    /// deleted way or one the restricted vehicles may not use
+    RestrictionMissingMember {
+        restriction: TurnRestriction,
+        role: &'static str,
+        way: WayId,
+    },
+    /// A turn restriction whose `from` or `to` way doesn't touch its via node
+    RestrictionDetachedMember {
+        restriction: TurnRestriction,
+        role: &'static str,
+        way: WayId,
+        node: NodeId,
+    },
+    /// Roads open to a profile that connect to the network only over roads closed
+    /// to it, such as a street only reached through a pedestrian zone
+    AccessIsland { profile: Profile, ways: Vec<WayId> },
+}
+
+impl Issue {
+    fn description(&self) -> String {
+        match self {
+            Issue::OnewaySink { node, .. } => {
+                format!(
+                    "Oneway dead-end, node {} can be entered but not left",
+                    node.0
+                )
+            }
+            Issue::OnewaySource { node, .. } => {
+                format!(
+                    "Oneway dead-end, node {} can be left but not entered",
+                    node.0
+                )
+            }
+            Issue::IsolatedHighClassRoad { ways, class } => format!(
+                "Isolated {:?} road, island of {} ways is not connected to the network",
+                class,
+                ways.len()
+            ),
+            Issue::RestrictionMissingMember {
+                restriction,
+                role,
+                way,
+            } => format!(
+                "Turn restriction {} references {} way {}, which is not a road it applies to",
+                restriction.relation_id, role, way.0
+            ),
+            Issue::RestrictionDetachedMember {
+                restriction,
+                role,
+                way,
+                node,
+            } => format!(
+                "Turn restriction {} has {} way {} not touching its via node {}",
+                restriction.relation_id, role, way.0, node.0
+            ),
+            Issue::AccessIsland { profile, ways } => format!(
+                "Roads open to {:?} only reachable over roads closed to it, island of {} ways",
+                profile,
+                ways.len()
+            ),
+        }
+    }
+
+    /// The involved objects as JOSM ids, e.g. `n1` for node 1
+    fn objects(&self) -> Vec<String> {
+        let mut objects = Vec::new();
+        match self {
+            Issue::OnewaySink { node, ways } | Issue::OnewaySource { node, ways } => {
+                objects.push(format!("n{}", node.0));
+                objects.extend(ways.iter().map(|way| format!("w{}", way.0)));
+            }
+            Issue::IsolatedHighClassRoad { ways, .. } | Issue::AccessIsland { ways, .. } => {
+                objects.extend(ways.iter().map(|way| format!("w{}", way.0)));
+            }
+            Issue::RestrictionMissingMember { restriction, .. }
+            | Issue::RestrictionDetachedMember { restriction, .. } => {
+                objects.push(format!("r{}", restriction.relation_id));
+            }
+        }
+        objects
+    }
+
+    /// A JOSM remote control link that loads the involved objects for editing
+    fn josm_link(&self) -> String {
+        format!(
+            "http://127.0.0.1:8111/load_object?new_layer=true&relation_members=true&objects={}",
+            self.objects().join(",")
+        )
+    }
+
+    /// A node to place the issue at on a map
+    fn anchor(&self, way_nodes: &HashMap<WayId, HashSet<NodeId>>) -> Option<NodeId> {
+        let first_node = |way: &WayId| {
+            way_nodes
+                .get(way)?
+                .iter()
+                .min_by_key(|node| node.0)
+                .copied()
+        };
+        match self {
+            Issue::OnewaySink { node, .. }
+            | Issue::OnewaySource { node, .. }
+            | Issue::RestrictionDetachedMember { node, .. } => Some(*node),
+            Issue::IsolatedHighClassRoad { ways, .. } | Issue::AccessIsland { ways, .. } => {
+                ways.iter().find_map(first_node)
+            }
+            Issue::RestrictionMissingMember { restriction, .. } => match &restriction.via {
+                Via::Node(node) => Some(*node),
+                Via::Ways(ways) => [restriction.from, restriction.to]
+                    .iter()
+                    .chain(ways)
+                    .find_map(first_node),
+            },
+        }
+    }
+}
+
+/// Checks all tiles in a directory for suspicious patterns and prints them
+///
+/// Each issue is printed together with a JOSM remote control link so mappers
+/// can open the affected objects directly, and with `osm_output` also written
+/// as a fixme node of an .osm file to open in JOSM as a to-do layer. Returns
+/// the number of issues found. Restrictions at the edge of a selection of tiles
+/// may be reported as missing members that are roads of the tiles left out.
+pub(crate) fn lint_tiles(
+    tiles_dir: &Path,
+    selector: &TileSelector,
+    osm_output: Option<&Path>,
+    cancel: &CancellationToken,
+) -> Result<usize> {
+    let mut edges = Vec::new();
+    let mut restrictions = Vec::new();
+    let mut profiles = Vec::new();
+    let mut node_locations = HashMap::new();
+    let tiles = TileStore::open(tiles_dir)?.select(selector);
+    for (index, tile) in tiles.iter().enumerate() {
+        cancel.check(|| format!("reading {} of {} tiles", index, tiles.len()))?;
+        let tile = tile?.1;
+        for profile in std::iter::once(tile.profile).chain(tile.extra_profiles) {
+            if !profiles.contains(&profile) {
+                profiles.push(profile);
+            }
+        }
+        let graph_nodes = tile
+            .edges
+            .iter()
+            .flat_map(|edge| [edge.from, edge.to])
+            .collect::<HashSet<_>>();
+        node_locations.extend(
+            tile.node_locations
+                .into_iter()
+                .filter(|(node_id, _loc)| graph_nodes.contains(node_id)),
+        );
+        edges.extend(tile.edges);
+        restrictions.extend(tile.restrictions);
+    }
+    println!(
+        "INFO: Linting {} edges of {} tiles",
+        utils::format_count(edges.len()),
+        tiles.len()
+    );
+
+    // The graph nodes of every way, to tell which ways a restriction may use
+    let mut way_nodes: HashMap<WayId, HashSet<NodeId>> = HashMap::new();
+    for edge in &edges {
+        way_nodes
+            .entry(edge.way_id)
+            .or_default()
+            .extend([edge.from, edge.to]);
+    }
+
+    let mut issues = find_oneway_dead_ends(&edges);
+    issues.extend(find_isolated_high_class_roads(&edges));
+    issues.extend(find_broken_restrictions(
+        &edges,
+        restrictions,
+        &profiles,
+        &way_nodes,
+    ));
+    issues.extend(find_access_islands(&edges, &profiles));
+
+    for issue in &issues {
+        println!("{}\t{}", issue.description(), issue.josm_link());
+    }
+    if let Some(osm_output) = osm_output {
+        write_osm(osm_output, &issues, &way_nodes, &node_locations)?;
+    }
+    Ok(issues.len())
+}
+
+/// Writes the issues as fixme nodes of an .osm file, which JOSM refuses to upload
+fn write_osm(
+    output: &Path,
+    issues: &[Issue],
+    way_nodes: &HashMap<WayId, HashSet<NodeId>>,
+    node_locations: &HashMap<NodeId, Loc>,
+) -> Result<()> {
+    let file = std::fs::File::create(output)
+        .io_context(|| format!("Failed creating file {}", output.display()))?;
+    let mut writer = BufWriter::new(file);
+    let write_error = || format!("Failed writing file {}", output.display());
+
+    writeln!(writer, "<?xml version='1.0' encoding='UTF-8'?>").io_context(write_error)?;
+    writeln!(
+        writer,
+        "<osm version='0.6' generator='gladsheim' upload='false'>"
+    )
+    .io_context(write_error)?;
+    let located = issues.iter().filter_map(|issue| {
+        let loc = node_locations.get(&issue.anchor(way_nodes)?)?;
+        Some((issue, loc))
+    });
+    for (index, (issue, loc)) in located.enumerate() {
+        // Negative ids mark new objects, which these are never meant to become
+        writeln!(
+            writer,
+            "  <node id='-{}' lat='{:.7}' lon='{:.7}'>\n    <tag k='fixme' v='{}'/>\n    <tag k='gladsheim:objects' v='{}'/>\n  </node>",
+            index + 1,
+            loc.lat,
+            loc.lon,
+            xml_escape(&issue.description()),
+            issue.objects().join(",")
+        )
+        .io_context(write_error)?;
+    }
+    writeln!(writer, "</osm>").io_context(write_error)?;
+    writer.flush().io_context(write_error)
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+        .replace('"', "&quot;")
+}
+
+fn find_oneway_dead_ends(edges: &[Edge]) -> Vec<Issue> {
+    // Per node: (number of ways entering, number of ways leaving, ways touching)
+    let mut degrees: HashMap<NodeId, (usize, usize, Vec<WayId>)> = HashMap::new();
+    for edge in edges {
+        let from = degrees.entry(edge.from).or_default();
+        from.1 += 1;
+        if !edge.is_oneway {
+            from.0 += 1;
+        }
+        from.2.push(edge.way_id);
+
+        let to = degrees.entry(edge.to).or_default();
+        to.0 += 1;
+        if !edge.is_oneway {
+            to.1 += 1;
+        }
+        to.2.push(edge.way_id);
+    }
+
+    degrees
+        .into_iter()
+        .filter_map(|(node, (num_in, num_out, ways))| {
+            if num_in > 0 && num_out == 0 {
+                Some(Issue::OnewaySink { node, ways })
+            } else if num_out > 0 && num_in == 0 {
+                Some(Issue::OnewaySource { node, ways })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Groups edges into the connected components of the undirected graph they form
+fn connected_components<'a>(edges: impl Iterator<Item = &'a Edge> + Clone) -> Vec<Vec<&'a Edge>> {
+    let mut node_index: HashMap<NodeId, usize> = HashMap::new();
+    for edge in edges.clone() {
+        for node_id in [edge.from, edge.to] {
+            let next_index = node_index.len();
+            node_index.entry(node_id).or_insert(next_index);
+        }
+    }
+
+    // Union-find over the undirected graph to find connected components
+    let mut parents = (0..node_index.len()).collect::<Vec<_>>();
+    fn find(parents: &mut [usize], mut index: usize) -> usize {
+        while parents[index] != index {
+            parents[index] = parents[parents[index]];
+            index = parents[index];
+        }
+        index
+    }
+    for edge in edges.clone() {
+        let a = find(&mut parents, node_index[&edge.from]);
+        let b = find(&mut parents, node_index[&edge.to]);
+        parents[a] = b;
+    }
+
+    let mut components: HashMap<usize, Vec<&Edge>> = HashMap::new();
+    for edge in edges {
+        let root = find(&mut parents, node_index[&edge.from]);
+        components.entry(root).or_default().push(edge);
+    }
+    components.into_values().collect()
+}
+
+/// The ways of the edges, each once
+fn ways_of(edges: &[&Edge]) -> Vec<WayId> {
+    let mut ways = edges.iter().map(|edge| edge.way_id).collect::<Vec<_>>();
+    ways.sort_unstable_by_key(|way| way.0);
+    ways.dedup();
+    ways
+}
+
+fn find_isolated_high_class_roads(edges: &[Edge]) -> Vec<Issue> {
+    connected_components(edges.iter())
+        .into_iter()
+        .filter(|component| component.len() < MAX_ISOLATED_COMPONENT_EDGES)
+        .filter_map(|component| {
+            let class = component.iter().map(|edge| edge.class).min()?;
+            if class > RoadClass::Secondary {
+                return None;
+            }
+            Some(Issue::IsolatedHighClassRoad {
+                ways: ways_of(&component),
+                class,
+            })
+        })
+        .collect()
+}
+
+/// Finds turn restrictions referencing ways that aren't roads of the tiles, or
+/// whose `from` and `to` ways don't meet at the via node
+///
+/// A restriction is kept in every tile holding its via members, so each is
+/// checked once, and only against the profiles it applies to.
+fn find_broken_restrictions(
+    edges: &[Edge],
+    mut restrictions: Vec<TurnRestriction>,
+    profiles: &[Profile],
+    way_nodes: &HashMap<WayId, HashSet<NodeId>>,
+) -> Vec<Issue> {
+    restrictions.sort_unstable_by_key(|restriction| restriction.relation_id);
+    restrictions.dedup_by_key(|restriction| restriction.relation_id);
+    // The ways usable by each profile
+    let usable_ways = profiles
+        .iter()
+        .map(|profile| {
+            let ways = edges
+                .iter()
+                .filter(|edge| edge.access.contains(*profile))
+                .map(|edge| edge.way_id)
+                .collect::<HashSet<_>>();
+            (*profile, ways)
+        })
+        .collect::<Vec<_>>();
+
+    let mut issues = Vec::new();
+    for restriction in restrictions {
+        let usable = usable_ways
+            .iter()
+            .filter(|(profile, _ways)| restriction.applies_to(*profile))
+            .map(|(_profile, ways)| ways)
+            .collect::<Vec<_>>();
+        if usable.is_empty() {
+            continue;
+        }
+        let via_ways = match &restriction.via {
+            Via::Node(_) => &[][..],
+            Via::Ways(ways) => ways,
+        };
+        let members = [("from", restriction.from), ("to", restriction.to)]
+            .into_iter()
+            .chain(via_ways.iter().map(|way| ("via", *way)))
+            .collect::<Vec<_>>();
+        for (role, way) in members {
+            if !usable.iter().any(|ways| ways.contains(&way)) {
+                issues.push(Issue::RestrictionMissingMember {
+                    restriction: restriction.clone(),
+                    role,
+                    way,
+                });
+                continue;
+            }
+            let Via::Node(node) = restriction.via else {
+                continue;
+            };
+            if role != "via"
+                && !way_nodes
+                    .get(&way)
+                    .is_some_and(|nodes| nodes.contains(&node))
+            {
+                issues.push(Issue::RestrictionDetachedMember {
+                    restriction: restriction.clone(),
+                    role,
+                    way,
+                    node,
+                });
+            }
+        }
+    }
+    issues
+}
+
+/// Finds small groups of roads open to a profile that only touch the rest of
+/// the network where roads closed to the profile leave them
+fn find_access_islands(edges: &[Edge], profiles: &[Profile]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for profile in profiles {
+        let open = edges.iter().filter(|edge| edge.access.contains(*profile));
+        let closed_nodes = edges
+            .iter()
+            .filter(|edge| !edge.access.contains(*profile))
+            .flat_map(|edge| [edge.from, edge.to])
+            .collect::<HashSet<_>>();
+        if closed_nodes.is_empty() {
+            continue;
+        }
+        issues.extend(
+            connected_components(open)
+                .into_iter()
+                .filter(|component| {
+                    component.len() < MAX_ISOLATED_COMPONENT_EDGES
+                        && component.iter().any(|edge| {
+                            closed_nodes.contains(&edge.from) || closed_nodes.contains(&edge.to)
+                        })
+                })
+                .map(|component| Issue::AccessIsland {
+                    profile: *profile,
+                    ways: ways_of(&component),
+                }),
+        );
+    }
+    issues
+}
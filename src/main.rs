@@ -1,12 +1,62 @@
-use std::{
-    collections::{HashMap, HashSet},
-    path::{Path, PathBuf},
-};
+use std::path::PathBuf;
 
-use anyhow::{Context, Result};
+use align::SegmentSource;
+use anyhow::Result;
 use clap::{Parser, Subcommand};
 
+mod address;
+mod adjacency;
+mod admin;
+mod align;
+mod archive;
+mod bloom;
+mod boundary;
+mod cancel;
+mod change;
+mod compare;
+mod components;
+mod contract;
+mod customize;
+mod database;
+mod dimacs;
+mod emissions;
+mod error;
+mod flat_tile;
+mod geojson;
+mod geometry;
+mod hierarchy;
+mod http;
+mod hub_labels;
+mod isochrone;
+mod json;
+mod lint;
+mod manifest;
+mod matching;
+mod merge;
+mod mvt;
+mod names;
+mod o5m;
 mod osm_parser;
+mod osm_xml;
+mod overview;
+mod parquet_export;
+mod partition;
+mod phast;
+mod poi;
+mod profile;
+mod region;
+mod restrictions;
+mod routing;
+mod rtree;
+mod sample;
+mod streets;
+mod table;
+mod tile_bench;
+mod tile_format;
+mod tile_proto;
+mod traffic;
+mod transit;
+mod turns;
 mod utils;
 
 #[derive(Parser)]
@@ -14,60 +64,979 @@ mod utils;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Timeout in seconds for requests to external services
+    #[arg(long, global = true, default_value_t = 300)]
+    http_timeout: u64,
+    /// Proxy for requests to external services, defaults to `HTTPS_PROXY`/`HTTP_PROXY`
+    #[arg(long, global = true)]
+    http_proxy: Option<String>,
+    /// Number of concurrent requests to external services
+    #[arg(long, global = true, default_value_t = 4)]
+    http_workers: usize,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Parsing the osm.pbf into basic routing tiles
     ParseOsmToBasicTiles {
-        /// The osm-file to parse
+        /// The osm-file to parse, either .osm.pbf, .o5m or OSM XML (.osm/.osm.bz2)
+        ///
+        /// An `http(s)://` URL is downloaded into the output directory first.
         #[arg(long)]
         fname: PathBuf,
         /// A directory to write output files to
         #[arg(long)]
         output_dir: PathBuf,
+        /// Also export the graph in 9th DIMACS challenge format as `<prefix>.gr`/`<prefix>.co`
+        #[arg(long, help_heading = "Extra outputs")]
+        dimacs_prefix: Option<PathBuf>,
+        /// The modes of transport to build the routing graph for, e.g. `car,foot`
+        ///
+        /// Edges store which of them may use them, other attributes like speeds
+        /// follow the first one.
+        #[arg(
+            long,
+            help_heading = "Graph",
+            alias = "profile",
+            value_enum,
+            value_delimiter = ',',
+            default_value = "car"
+        )]
+        profiles: Vec<profile::Profile>,
+        /// Store the edge geometry resampled every this many meters, with bearings
+        #[arg(long, help_heading = "Edge attributes")]
+        resample_meters: Option<f64>,
+        /// Estimate curvature and an advisory safe speed for every edge
+        #[arg(long, help_heading = "Edge attributes")]
+        curvature: bool,
+        /// Store the shape of every edge as an encoded polyline with 6 decimals
+        #[arg(long, help_heading = "Edge attributes")]
+        polylines: bool,
+        /// Estimate gradient, stop density and CO2 emissions for every edge
+        #[arg(long, help_heading = "Edge attributes")]
+        emissions: bool,
+        /// Seconds of delay for every railway level crossing without barriers
+        #[arg(long, help_heading = "Graph", default_value_t = osm_parser::DEFAULT_LEVEL_CROSSING_DELAY_S)]
+        level_crossing_delay: u16,
+        /// Seconds of delay for every railway level crossing with barriers
+        #[arg(long, help_heading = "Graph", default_value_t = osm_parser::DEFAULT_BARRIER_CROSSING_DELAY_S)]
+        barrier_crossing_delay: u16,
+        /// Only parse the area within `min_lon,min_lat,max_lon,max_lat`
+        #[arg(long, help_heading = "Input", conflicts_with = "poly")]
+        bbox: Option<String>,
+        /// Only parse the area within an Osmosis `.poly` polygon file
+        #[arg(long, help_heading = "Input")]
+        poly: Option<PathBuf>,
+        /// Directory of `.poly` boundaries named by ISO 3166 code, e.g. `SE.poly` and
+        /// `SE-AB.poly`, to tag edges with their country and region
+        #[arg(long, help_heading = "Edge attributes")]
+        admin_areas: Option<PathBuf>,
+        /// Also extract amenity, shop and tourism nodes into per-tile `.pois` files
+        #[arg(long, help_heading = "Extra outputs")]
+        pois: bool,
+        /// Also extract addresses, with interpolated house numbers, into per-tile `.addr` files
+        #[arg(long, help_heading = "Extra outputs")]
+        addresses: bool,
+        /// Store a Bloom filter of the node ids in each tile, to find nodes without decoding tiles
+        #[arg(long, help_heading = "Tile layout")]
+        node_filter: bool,
+        /// How to name the tile files, `zxy` nests them as `<z>/<x>/<y>.grt` for static file servers
+        /// and `sharded` as `<first 3 quadkey digits>/<quadkey>.grt` for huge extracts
+        #[arg(
+            long,
+            help_heading = "Tile layout",
+            value_enum,
+            default_value = "quadkey"
+        )]
+        naming: utils::TileNaming,
+        /// Compress the tiles with `none`, `zstd` or `zstd:<level>`, edge lists compress 3-5x
+        #[arg(long, help_heading = "Tile layout", default_value = "none")]
+        compression: tile_format::Compression,
+        /// Zoom level of the tiles, higher for smaller tiles in dense cities and lower for
+        /// fewer, larger tiles when routing across countries
+        #[arg(long, help_heading = "Tile layout", default_value_t = osm_parser::DEFAULT_TILE_ZOOM,
+            value_parser = clap::value_parser!(u8).range(1..=20))]
+        tile_zoom: u8,
+        /// Also write coarser tiles at `<zoom>:<class>`, e.g. `4:trunk`, holding only the roads
+        /// of that class and more important ones into `overview-<zoom>`, so long routes load
+        /// far fewer bytes. Can be repeated, and isn't updated by `ApplyOsmChange`
+        #[arg(long = "overview", help_heading = "Tile layout")]
+        overviews: Vec<overview::OverviewLevel>,
+        /// Write the tiles into a single `tiles.grta` file with an index, instead of a file
+        /// per tile, for file systems where many small files are slow
+        #[arg(long, help_heading = "Tile layout")]
+        archive: bool,
+        /// Write the tiles into a single `tiles.sqlite` database keyed by quadkey, like
+        /// MBTiles, which other tools can query and update in place
+        #[arg(long, help_heading = "Tile layout", conflicts_with = "archive")]
+        sqlite: bool,
+        /// Also write every tile as fixed size records into a `.grf` file, which servers can
+        /// memory-map and query without decoding
+        #[arg(long, help_heading = "Extra outputs")]
+        flat: bool,
+        /// Also write every tile as protobuf into a `.pb` file, following `proto/tile.proto`,
+        /// for services in other languages
+        #[arg(long, help_heading = "Extra outputs")]
+        protobuf: bool,
+        /// What to do with ways referencing nodes missing from the data, e.g. in clipped extracts
+        #[arg(long, help_heading = "Input", value_enum, default_value = "truncate")]
+        missing_nodes: osm_parser::MissingNodes,
+        /// Store two-way roads as an edge in each direction, so every edge is directed
+        #[arg(long, help_heading = "Graph")]
+        reverse_edges: bool,
+        /// Merge chains of edges through nodes where only two edges meet, keeping the geometry.
+        /// Such tiles can't be updated with `ApplyOsmChange`
+        #[arg(long, help_heading = "Graph")]
+        contract_chains: bool,
+        /// Also write the edge-based graph of the profile into per-tile `.turns` files, with
+        /// directed edges as nodes and the turns allowed between them as arcs
+        #[arg(long, help_heading = "Extra outputs")]
+        edge_based: bool,
+        /// What to do with edges of strongly connected components smaller than
+        /// `--min-component-size`, e.g. parking lots cut off by clipping
+        #[arg(long, help_heading = "Graph", value_enum, default_value = "keep")]
+        islands: components::Islands,
+        /// Strongly connected components of fewer nodes than this are islands
+        #[arg(long, help_heading = "Graph", default_value_t = osm_parser::DEFAULT_MIN_COMPONENT_SIZE)]
+        min_component_size: usize,
+    },
+    /// Contracts the tiles into a contraction hierarchy, so `Route` answers in milliseconds
+    BuildCh {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`, updated in place
+        #[arg(long)]
+        tiles_dir: PathBuf,
     },
     /// Builds hub-labels from the basic data built in `ParseOsmToBasicTiles`
     BuildHubLabels {
-        /// The basic routing tiles produced in previous step
+        /// Directory with the basic routing tiles produced in previous step, the labels are
+        /// written next to the tiles as `.hl` files
         #[arg(long)]
         fname: PathBuf,
-
-        /// Routing endpoint used for calculating Hub labels
-        /// Needs to be something fast like OSRM to be feasible
-        #[arg(long, default_value = "127.0.0.1:5000")]
-        directions_endpoint: String,
     },
+    /// Checks the hub labels from `BuildHubLabels` against Dijkstra between random nodes
+    VerifyHubLabels {
+        /// Directory with the routing tiles and hub labels
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Number of node pairs to check
+        #[arg(long, default_value_t = 1_000)]
+        samples: usize,
+        /// Seed of the random generator, the same seed and tiles check the same pairs
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+    },
+    /// Partitions the nodes of the tiles into nested cells for customizable route planning
+    Partition {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Comma separated most nodes per cell of every level, smallest first
+        #[arg(long, value_delimiter = ',', default_values_t = [256, 4_096, 65_536])]
+        cell_sizes: Vec<u32>,
+    },
+    /// Computes the costs across the cells of a `Partition` for a metric and day
+    Customize {
+        /// Directory with the routing tiles and their partition
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// What the costs measure
+        #[arg(long, value_enum, default_value = "duration")]
+        metric: customize::Metric,
+        /// The day as `YYYY-MM-DD` whose open roads are costed
+        #[arg(long, default_value = "today")]
+        date: DateArg,
+        /// Tab separated file of current speeds in km/h by `way:from:to` edge id, e.g. from a
+        /// live traffic feed, used for the duration metric instead of the durations of the tiles
+        #[arg(long)]
+        traffic: Option<PathBuf>,
+    },
+    /// Updates previously built tiles with an osmChange (.osc) diff
+    ApplyOsmChange {
+        /// The osmChange file to apply
+        #[arg(long)]
+        change_file: PathBuf,
+        /// Directory with the routing tiles to update in place
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// The extract the tiles were built from or a newer one, to locate the nodes of ways
+        /// that the change makes routable without changing their nodes
+        #[arg(long)]
+        source: Option<PathBuf>,
+        /// The modes of transport the tiles were built for, in the same order
+        #[arg(
+            long,
+            alias = "profile",
+            value_enum,
+            value_delimiter = ',',
+            default_value = "car"
+        )]
+        profiles: Vec<profile::Profile>,
+    },
+    /// Reports suspicious patterns in built tiles as links that open the data in JOSM
+    LintData {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Also write the issues as fixme nodes of an .osm file, to open in JOSM as a to-do layer
+        #[arg(long)]
+        osm_output: Option<PathBuf>,
+        #[command(flatten)]
+        selection: TileSelection,
+    },
+    /// Writes the edges of the tiles as GeoJSON LineStrings with their attributes
+    ExportGeojson {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// The GeoJSON file to write
+        #[arg(long)]
+        output: PathBuf,
+        #[command(flatten)]
+        selection: TileSelection,
+    },
+    /// Renders the edges of the tiles as Mapbox Vector Tiles
+    ExportMvt {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Directory to write the `<z>/<x>/<y>.mvt` vector tiles into
+        #[arg(long)]
+        output_dir: PathBuf,
+        /// Zoom level of the vector tiles, at least that of the routing tiles, which is the
+        /// default. Deeper levels show more detail, e.g. 14 for inspecting single streets.
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=20))]
+        zoom: Option<u8>,
+        #[command(flatten)]
+        selection: TileSelection,
+    },
+    /// Writes the edges of the tiles as a Parquet table with their attributes and geometry
+    ExportParquet {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// The Parquet file to write
+        #[arg(long)]
+        output: PathBuf,
+        #[command(flatten)]
+        selection: TileSelection,
+    },
+    /// Combines the tiles built from separate extracts into one directory
+    MergeTiles {
+        /// Directories with the routing tiles produced by `ParseOsmToBasicTiles`, the output
+        /// follows the naming and format of the first
+        #[arg(long = "tiles-dir", required = true)]
+        tiles_dirs: Vec<PathBuf>,
+        /// Directory to write the merged tiles into
+        #[arg(long)]
+        output_dir: PathBuf,
+    },
+    /// Lists the tiles with edges through a node
+    FindNode {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// The OSM id of the node
+        #[arg(long)]
+        node_id: i64,
+        #[command(flatten)]
+        selection: TileSelection,
+    },
+    /// Prints the metadata of tiles, such as when their OSM data is from
+    TileInfo {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        #[command(flatten)]
+        selection: TileSelection,
+    },
+    /// Times encoding and decoding a synthetic street grid tile, to compare tile layouts
+    BenchTileCodec {
+        /// Intersections along each side of the grid
+        #[arg(long, default_value_t = 120)]
+        grid_size: usize,
+        /// Nodes along the street between neighbouring intersections
+        #[arg(long, default_value_t = 2)]
+        nodes_per_block: usize,
+        /// Times to encode and decode the tile, the fastest time is printed
+        #[arg(long, default_value_t = 10)]
+        rounds: usize,
+        /// Comma separated compressions to compare, `none`, `zstd` or `zstd:<level>`
+        #[arg(long, value_delimiter = ',', default_values = ["none", "zstd"])]
+        compressions: Vec<tile_format::Compression>,
+    },
+    /// Prints random coordinates on the road network as `lat,lon`, e.g. for load testing
+    SampleCoordinates {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Number of coordinates to produce
+        #[arg(long, default_value_t = 10_000)]
+        count: usize,
+        /// Seed of the random generator, the same seed and tiles give the same coordinates
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+        #[command(flatten)]
+        selection: TileSelection,
+    },
+    /// Matches edges to the segments of another network by geometry and name
+    AlignEdges {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// The network to align with, the tile directory of another build or a tab
+        /// separated file of id, name and WKT `LINESTRING`
+        #[arg(long)]
+        other: PathBuf,
+        /// Largest mean distance in meters between the geometries of matched segments
+        #[arg(long, default_value_t = 15.0)]
+        max_distance: f64,
+        /// Where to write the crosswalk as tab separated values, defaults to stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        selection: TileSelection,
+    },
+    /// Finds the fastest route between two coordinates, printed as OSRM-style JSON
+    Route {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Where the route starts, as `lat,lon`
+        #[arg(long)]
+        from: routing::Coordinate,
+        /// Where the route ends, as `lat,lon`
+        #[arg(long)]
+        to: routing::Coordinate,
+        /// A place the route passes through on the way, as `lat,lon`, repeated for every one in
+        /// the order they're visited. The route gets a leg between every pair of consecutive
+        /// waypoints
+        #[arg(long)]
+        via: Vec<routing::Coordinate>,
+        /// How the route is searched for, the choice only changes how fast it's found. Tiles
+        /// with a contraction hierarchy from `BuildCh` are searched over it instead
+        #[arg(long, value_enum, default_value = "a-star")]
+        algorithm: routing::Algorithm,
+        /// The day of travel as `YYYY-MM-DD`, roads opening later aren't used
+        #[arg(long, default_value = "today")]
+        date: DateArg,
+        /// Seconds the search prefers staying within the tiles it has read over reading more,
+        /// which may make the route up to that much slower
+        #[arg(long, default_value_t = 0.0)]
+        tile_affinity: f64,
+        #[command(flatten)]
+        turns: TurnCostArgs,
+        /// Most slower routes to suggest besides the fastest one, searched for without the
+        /// contraction hierarchy
+        #[arg(long, default_value_t = 0)]
+        alternatives: usize,
+        /// Largest share of the distance of an alternative that may overlap the fastest route
+        /// or an alternative suggested before it
+        #[arg(long, default_value_t = 0.5)]
+        max_overlap: f64,
+        /// How the shape of the route is given
+        #[arg(long, value_enum, default_value = "polyline")]
+        geometries: routing::Geometries,
+        /// GeoJSON file with polygons the route keeps out of, such as flooded areas or
+        /// closures, leaving out every road that enters them
+        #[arg(long)]
+        avoid: Option<PathBuf>,
+        /// Comma separated kinds of roads the route keeps off, e.g. `toll,ferry`
+        #[arg(long, value_enum, value_delimiter = ',')]
+        exclude: Vec<routing::Exclude>,
+        /// Tab separated file of current speeds in km/h by `way:from:to` edge id, e.g. from a
+        /// live traffic feed, used instead of the durations of the tiles
+        #[arg(long)]
+        traffic: Option<PathBuf>,
+    },
+    /// Computes travel times and distances between locations, printed as an OSRM-style table
+    Table {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Where the routes start, as `lat,lon`, repeated for every source
+        #[arg(long = "source", required = true)]
+        sources: Vec<routing::Coordinate>,
+        /// Where the routes end, as `lat,lon`, repeated for every destination. Defaults to the
+        /// sources
+        #[arg(long = "destination")]
+        destinations: Vec<routing::Coordinate>,
+        /// The day of travel as `YYYY-MM-DD`, roads opening later aren't used
+        #[arg(long, default_value = "today")]
+        date: DateArg,
+        #[command(flatten)]
+        turns: TurnCostArgs,
+        /// Comma separated kinds of roads the routes keep off, e.g. `toll,ferry`
+        #[arg(long, value_enum, value_delimiter = ',')]
+        exclude: Vec<routing::Exclude>,
+        /// Tab separated file of current speeds in km/h by `way:from:to` edge id, e.g. from a
+        /// live traffic feed, used instead of the durations of the tiles
+        #[arg(long)]
+        traffic: Option<PathBuf>,
+    },
+    /// Matches a GPS trace onto the roads, printed as an OSRM-style match response
+    Match {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// File with the points of the trace in the order recorded, one `lat,lon` per line
+        #[arg(long)]
+        points: PathBuf,
+        /// Standard deviation in meters of the distance of the points from the road
+        #[arg(long, default_value_t = 10.0)]
+        gps_accuracy_m: f64,
+        /// Roads farther than this many meters from a point aren't matched onto
+        #[arg(long, default_value_t = 50.0)]
+        search_radius_m: f64,
+        /// The day of travel as `YYYY-MM-DD`, roads opening later aren't used
+        #[arg(long, default_value = "today")]
+        date: DateArg,
+    },
+    /// Computes the areas reachable from a location within time budgets as GeoJSON
+    Isochrone {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Where the travel starts, as `lat,lon`
+        #[arg(long)]
+        from: routing::Coordinate,
+        /// The time budgets in minutes, one area is computed for each
+        #[arg(long, value_delimiter = ',', default_values_t = [5.0, 10.0, 15.0])]
+        minutes: Vec<f64>,
+        /// Size in meters of the grid cells the reached roads are laid onto, the areas follow
+        /// the roads closer with smaller cells
+        #[arg(long, default_value_t = 100.0)]
+        cell_size_m: f64,
+        /// The day of travel as `YYYY-MM-DD`, roads opening later aren't used
+        #[arg(long, default_value = "today")]
+        date: DateArg,
+        #[command(flatten)]
+        turns: TurnCostArgs,
+    },
+    /// Compares routes between random locations with an external OSRM or Valhalla
+    CompareEngines {
+        /// Directory with the routing tiles produced by `ParseOsmToBasicTiles`
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Base URL of the other engine, e.g. `http://localhost:5000`
+        #[arg(long)]
+        endpoint: String,
+        /// Which engine answers at the endpoint
+        #[arg(long, value_enum, default_value = "osrm")]
+        engine: compare::Engine,
+        /// Number of routes to compare
+        #[arg(long, default_value_t = 1000)]
+        samples: usize,
+        /// Seed of the random generator, the same seed and tiles give the same queries
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+        /// The day of travel as `YYYY-MM-DD`, roads opening later aren't used
+        #[arg(long, default_value = "today")]
+        date: DateArg,
+        /// Where to write every query with both routes as tab separated values, e.g. to look
+        /// into the largest divergences
+        #[arg(long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        selection: TileSelection,
+    },
+    /// Computes the travel times from locations to every node by PHAST sweeps
+    TravelTimes {
+        /// Directory with the routing tiles and their contraction hierarchy
+        #[arg(long)]
+        tiles_dir: PathBuf,
+        /// Where the travel starts, as `lat,lon`, repeated for every location. Each starts at the
+        /// node closest to it
+        #[arg(long = "from", required = true)]
+        sources: Vec<routing::Coordinate>,
+        /// Where to write the travel times, defaults to stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// What turns cost a route
+#[derive(clap::Args)]
+struct TurnCostArgs {
+    /// Adds the cost of turns to routes and obeys turn restrictions, searching over the edges
+    /// nodes are reached by rather than the contraction hierarchy
+    #[arg(long)]
+    turn_costs: bool,
+    /// Seconds for a left turn of 90 degrees, sharper turns cost more and gentler ones less
+    #[arg(long, default_value_t = 8.0)]
+    left_turn_s: f64,
+    /// Seconds for a right turn of 90 degrees, scaled like left turns
+    #[arg(long, default_value_t = 3.0)]
+    right_turn_s: f64,
+    /// Seconds for turning back along the way arrived on
+    #[arg(long, default_value_t = 30.0)]
+    u_turn_s: f64,
+}
+impl TurnCostArgs {
+    fn into_turn_costs(self) -> Option<routing::TurnCosts> {
+        self.turn_costs.then_some(routing::TurnCosts {
+            left_s: self.left_turn_s,
+            right_s: self.right_turn_s,
+            u_turn_s: self.u_turn_s,
+        })
+    }
+}
+
+/// A day given as `YYYY-MM-DD`, or `today`, as a `YYYYMMDD` number
+#[derive(Clone, Copy, Debug)]
+struct DateArg(u32);
+impl std::str::FromStr for DateArg {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "today" {
+            return Ok(Self(utils::today()));
+        }
+        utils::parse_date(value)
+            .map(Self)
+            .ok_or_else(|| format!("Invalid date {value}, expected YYYY-MM-DD"))
+    }
+}
+
+/// Restricts a command to some of the tiles in a directory
+#[derive(clap::Args)]
+struct TileSelection {
+    /// Comma separated quadkey prefixes or globs, e.g. `0231` or `0231*`
+    #[arg(long, value_delimiter = ',')]
+    quadkeys: Vec<String>,
+    /// Only tiles overlapping `min_lon,min_lat,max_lon,max_lat`
+    #[arg(long)]
+    bbox: Option<String>,
+}
+impl TileSelection {
+    fn into_selector(self) -> Result<utils::TileSelector> {
+        Ok(utils::TileSelector {
+            patterns: self.quadkeys,
+            region: self
+                .bbox
+                .map(|bbox| region::Region::from_bbox(&bbox))
+                .transpose()?,
+        })
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq, bincode::Encode, bincode::Decode)]
 struct NodeId(i64);
 
-#[derive(Debug, Default, bincode::Encode, bincode::Decode)]
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq, bincode::Encode, bincode::Decode)]
 struct WayId(i64);
 
+/// Functional class of a road from the `highway=*` tag, most important first
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Hash,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    bincode::Encode,
+    bincode::Decode,
+    clap::ValueEnum,
+)]
+enum RoadClass {
+    Motorway,
+    Trunk,
+    Primary,
+    Secondary,
+    Tertiary,
+    Unclassified,
+    Residential,
+    Service,
+    LivingStreet,
+    Track,
+    Busway,
+    /// Footways, paths, steps and pedestrian streets
+    Footway,
+    #[default]
+    Other,
+}
+impl RoadClass {
+    fn from_highway(value: &str) -> Self {
+        match value {
+            "motorway" | "motorway_link" => RoadClass::Motorway,
+            "trunk" | "trunk_link" => RoadClass::Trunk,
+            "primary" | "primary_link" => RoadClass::Primary,
+            "secondary" | "secondary_link" => RoadClass::Secondary,
+            "tertiary" | "tertiary_link" => RoadClass::Tertiary,
+            "unclassified" => RoadClass::Unclassified,
+            "residential" => RoadClass::Residential,
+            "service" => RoadClass::Service,
+            "living_street" => RoadClass::LivingStreet,
+            "track" => RoadClass::Track,
+            "busway" | "bus_guideway" => RoadClass::Busway,
+            "footway" | "path" | "steps" | "pedestrian" => RoadClass::Footway,
+            _ => RoadClass::Other,
+        }
+    }
+}
+
+/// Kind of service road, from the `service=*` tag on `highway=service`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+enum ServiceClass {
+    /// Leads to a single property, only useful at the start or end of a route
+    Driveway,
+    ParkingAisle,
+    Alley,
+    DriveThrough,
+    EmergencyAccess,
+    Other,
+}
+impl ServiceClass {
+    fn from_tag(value: Option<&str>) -> Self {
+        match value {
+            Some("driveway") => ServiceClass::Driveway,
+            Some("parking_aisle") => ServiceClass::ParkingAisle,
+            Some("alley") => ServiceClass::Alley,
+            Some("drive-through") => ServiceClass::DriveThrough,
+            Some("emergency_access") => ServiceClass::EmergencyAccess,
+            _ => ServiceClass::Other,
+        }
+    }
+}
+
+/// Surface quality of a `highway=track`, from `tracktype=grade1` (paved) to `grade5` (soft)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, bincode::Encode, bincode::Decode)]
+enum TrackType {
+    Grade1,
+    Grade2,
+    Grade3,
+    Grade4,
+    Grade5,
+}
+impl TrackType {
+    fn from_tag(value: &str) -> Option<Self> {
+        match value {
+            "grade1" => Some(TrackType::Grade1),
+            "grade2" => Some(TrackType::Grade2),
+            "grade3" => Some(TrackType::Grade3),
+            "grade4" => Some(TrackType::Grade4),
+            "grade5" => Some(TrackType::Grade5),
+            _ => None,
+        }
+    }
+}
+
+/// Physical usability of a road surface for wheeled vehicles, from the `smoothness=*` tag
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, bincode::Encode, bincode::Decode)]
+enum Smoothness {
+    Excellent,
+    Good,
+    Intermediate,
+    Bad,
+    VeryBad,
+    Horrible,
+    VeryHorrible,
+    Impassable,
+}
+impl Smoothness {
+    fn from_tag(value: &str) -> Option<Self> {
+        match value {
+            "excellent" => Some(Smoothness::Excellent),
+            "good" => Some(Smoothness::Good),
+            "intermediate" => Some(Smoothness::Intermediate),
+            "bad" => Some(Smoothness::Bad),
+            "very_bad" => Some(Smoothness::VeryBad),
+            "horrible" => Some(Smoothness::Horrible),
+            "very_horrible" => Some(Smoothness::VeryHorrible),
+            "impassable" => Some(Smoothness::Impassable),
+            _ => None,
+        }
+    }
+}
+
+/// Presence of sidewalks along a road, from the `sidewalk=*` tag
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+enum Sidewalk {
+    #[default]
+    Unknown,
+    No,
+    Left,
+    Right,
+    Both,
+    /// The sidewalk is mapped as its own footway next to the road
+    Separate,
+}
+impl Sidewalk {
+    fn from_tag(value: &str) -> Self {
+        match value {
+            "no" | "none" => Sidewalk::No,
+            "left" => Sidewalk::Left,
+            "right" => Sidewalk::Right,
+            "both" | "yes" => Sidewalk::Both,
+            "separate" => Sidewalk::Separate,
+            _ => Sidewalk::Unknown,
+        }
+    }
+}
+
+/// A road number split for rendering on a shield, e.g. `E 4` into `E` and `4`
+#[derive(Clone, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+struct RoadRef {
+    /// The letters before the number, usually naming the network, e.g. `E`, `A` or `US`
+    prefix: Option<String>,
+    /// The number and any suffix, e.g. `4` or `20a`
+    number: String,
+    /// From `int_ref`, a number of an international network like the E-roads
+    is_international: bool,
+}
+impl RoadRef {
+    /// Parses a `;` separated list of road numbers
+    fn parse_list(value: &str, is_international: bool) -> Vec<Self> {
+        value
+            .split(';')
+            .map(str::trim)
+            .filter(|road_ref| !road_ref.is_empty())
+            .map(|road_ref| {
+                let split = road_ref
+                    .find(|c: char| c.is_ascii_digit())
+                    .unwrap_or(road_ref.len());
+                let (prefix, number) = road_ref.split_at(split);
+                let prefix = prefix.trim_end_matches([' ', '-']);
+                match (prefix, number) {
+                    // Not a number at all, keep it whole
+                    (_, "") => RoadRef {
+                        prefix: None,
+                        number: road_ref.to_string(),
+                        is_international,
+                    },
+                    _ => RoadRef {
+                        prefix: (!prefix.is_empty()).then(|| prefix.to_string()),
+                        number: number.to_string(),
+                        is_international,
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Default, bincode::Encode, bincode::Decode)]
 struct Way {
     id: WayId,
     name: Option<String>,
+    /// The road number from the `ref` tag, e.g. `E4`
+    road_ref: Option<String>,
+    refs: Vec<RoadRef>,
+    class: RoadClass,
     is_oneway: bool,
+    access: profile::ProfileMask,
+    oneway: profile::ProfileMask,
+    sidewalk: Sidewalk,
+    /// Set on `highway=service` ways
+    service: Option<ServiceClass>,
+    /// Set on `highway=track` ways with a `tracktype`
+    track_type: Option<TrackType>,
+    smoothness: Option<Smoothness>,
+    width: Option<f32>,
+    is_narrow: bool,
+    /// See `Edge::is_toll`
+    is_toll: bool,
+    /// See `Edge::is_ferry`
+    is_ferry: bool,
+    incline: Option<f32>,
+    speed_kmh: u8,
+    /// Set on destination-only ways, see `Edge::destination_zone`
+    destination_zone: Option<WayId>,
+    /// See `Edge::not_before`
+    not_before: Option<u32>,
     nodes: Vec<NodeId>,
-    polyline: String,
 }
-#[derive(Debug, Default, bincode::Encode, bincode::Decode)]
+#[derive(Clone, Debug, Default, bincode::Encode, bincode::Decode)]
 struct Edge {
     from: NodeId,
     to: NodeId,
+    /// The OSM way this edge was split from
+    way_id: WayId,
+    name: Option<String>,
+    /// The road number from the `ref` tag, e.g. `E4`
+    road_ref: Option<String>,
+    /// The numbers of `ref` and `int_ref` split for route shields, national ones first
+    refs: Vec<RoadRef>,
+    class: RoadClass,
+    /// Oneway for the profile the tiles were built for, see `oneway` for the others
     is_oneway: bool,
+    /// The profiles that may use this edge
+    access: profile::ProfileMask,
+    /// The profiles this edge is oneway for
+    oneway: profile::ProfileMask,
+    /// Runs against the direction of its way, a reverse edge from `--reverse-edges`
+    is_reversed: bool,
+    sidewalk: Sidewalk,
+    /// The kind of service road, for costing to penalize e.g. driveways
+    service: Option<ServiceClass>,
+    /// How firm a track is, for off-road profiles to pick usable tracks
+    track_type: Option<TrackType>,
+    /// Surface quality, which profiles turn into a speed factor
+    smoothness: Option<Smoothness>,
+    /// Width of the carriageway in meters, from the `width` tag
+    width: Option<f32>,
+    /// Tagged `narrow=yes`, too narrow for wide vehicles to pass each other
+    is_narrow: bool,
+    /// Tagged `toll=yes`, a fee is charged for using it
+    is_toll: bool,
+    /// A ferry crossing from a `route=ferry` way rather than a road
+    is_ferry: bool,
+    /// Slope from the `incline` tag as rise over run, positive uphill along the way
+    incline: Option<f32>,
+    /// Travel speed of the profile, the tagged `maxspeed` or a default for the class of road
+    speed_kmh: u8,
+    /// The destination-only area this edge belongs to, identified by its smallest way id
+    ///
+    /// Such edges may only be used by routes starting or ending in the same zone,
+    /// so that parking lots and private streets aren't used as shortcuts.
+    destination_zone: Option<WayId>,
+    /// Touches a small strongly connected component, marked with `--islands flag`
+    ///
+    /// Such edges can't be reached from or can't reach the rest of the network,
+    /// so locations shouldn't be snapped onto them.
+    is_island: bool,
+    /// A road under construction that opens on this date, as `YYYYMMDD`
+    not_before: Option<u32>,
+    /// Seconds spent waiting at railway level crossings along the edge
+    level_crossing_delay_s: u16,
+    /// Length in meters along the nodes, for costing without loading the node locations
+    length_m: f32,
+    /// Estimated seconds to traverse the edge for the profile the tiles were built for
+    ///
+    /// The length at `speed_kmh`, slowed down by the surface, plus the waiting at
+    /// level crossings.
+    duration_s: f32,
+    /// The nodes along the edge, from `from` up to and including `to`
     nodes: Vec<NodeId>,
+    /// Geometry resampled at fixed intervals, only stored when requested
+    samples: Vec<geometry::GeometrySample>,
+    /// The shape as a polyline with 6 decimals, only stored when requested
+    polyline: String,
+    /// Sharpest curve and advisory speed, only stored when requested
+    curvature: Option<geometry::Curvature>,
+    /// Driving conditions and CO2 estimate, only stored when requested
+    emissions: Option<emissions::Emissions>,
+    /// ISO 3166-1 code of the country, only stored when boundaries are given
+    country: Option<String>,
+    /// ISO 3166-2 code of the region within the country, e.g. `SE-AB`
+    subdivision: Option<String>,
+}
+impl Edge {
+    /// The nodes along the edge except `to`, whose attributes belong to the edges leaving it
+    fn nodes_before_to(&self) -> &[NodeId] {
+        self.nodes.split_last().map_or(&[], |(_to, nodes)| nodes)
+    }
+}
+
+/// Splits the profiles given on the command line into the first one and the others
+fn split_profiles(profiles: Vec<profile::Profile>) -> (profile::Profile, Vec<profile::Profile>) {
+    let mut profiles = profiles.into_iter();
+    let profile = profiles.next().unwrap_or_default();
+    let mut extra_profiles = Vec::new();
+    for extra_profile in profiles {
+        if extra_profile != profile && !extra_profiles.contains(&extra_profile) {
+            extra_profiles.push(extra_profile);
+        }
+    }
+    (profile, extra_profiles)
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let http_options = http::HttpOptions {
+        timeout: std::time::Duration::from_secs(cli.http_timeout),
+        proxy: cli.http_proxy,
+        workers: cli.http_workers,
+    };
+    // Ctrl-C stops the running command at its next checkpoint instead of killing it
+    let cancel = cancel::CancellationToken::default();
+    {
+        let cancel = cancel.clone();
+        ctrlc::set_handler(move || {
+            println!("WARN: Interrupted, stopping");
+            cancel.cancel();
+        })?;
+    }
     match cli.command {
-        Commands::ParseOsmToBasicTiles { fname, output_dir } => {
+        Commands::ParseOsmToBasicTiles {
+            fname,
+            output_dir,
+            dimacs_prefix,
+            profiles,
+            resample_meters,
+            curvature,
+            polylines,
+            emissions,
+            level_crossing_delay,
+            barrier_crossing_delay,
+            bbox,
+            poly,
+            admin_areas,
+            pois,
+            addresses,
+            node_filter,
+            naming,
+            compression,
+            tile_zoom,
+            overviews,
+            archive,
+            sqlite,
+            flat,
+            protobuf,
+            missing_nodes,
+            reverse_edges,
+            contract_chains,
+            edge_based,
+            islands,
+            min_component_size,
+        } => {
             let start_time = std::time::Instant::now();
-            let fname_tiles = osm_parser::read_osm_pbf(&fname, &output_dir)?;
+            let region = match (bbox, poly) {
+                (Some(bbox), _) => Some(region::Region::from_bbox(&bbox)?),
+                (None, Some(poly)) => Some(region::Region::from_poly_file(&poly)?),
+                (None, None) => None,
+            };
+            let (profile, extra_profiles) = split_profiles(profiles);
+            let options = osm_parser::ParseOptions {
+                dimacs_prefix,
+                profile,
+                extra_profiles,
+                resample_meters,
+                curvature,
+                polylines,
+                region,
+                level_crossing_delay_s: level_crossing_delay,
+                barrier_crossing_delay_s: barrier_crossing_delay,
+                emissions,
+                admin_areas: admin_areas
+                    .map(|dir| admin::AdminAreas::from_dir(&dir))
+                    .transpose()?,
+                pois,
+                addresses,
+                node_filter,
+                naming,
+                compression,
+                tile_zoom,
+                overviews,
+                storage: match (archive, sqlite) {
+                    (true, _) => utils::TileStorage::Archive,
+                    (_, true) => utils::TileStorage::Database,
+                    _ => utils::TileStorage::Files,
+                },
+                flat,
+                protobuf,
+                missing_nodes,
+                reverse_edges,
+                contract_chains,
+                edge_based,
+                islands,
+                min_component_size,
+            };
+            let client = http::HttpClient::new(&http_options)?;
+            let fname = http::fetch_input(&client, &fname, &output_dir)?;
+            osm_parser::read_osm(&fname, &output_dir, &options, &cancel)?;
             println!(
                 "INFO: Finished all parsing in {}ms and produced routing tiles in {}",
                 start_time.elapsed().as_millis(),
@@ -75,9 +1044,645 @@ fn main() -> Result<()> {
             );
             Ok(())
         }
-        Commands::BuildHubLabels {
-            fname: _,
-            directions_endpoint: _,
-        } => Ok(()),
+        Commands::BuildHubLabels { fname } => {
+            let start_time = std::time::Instant::now();
+            let stats = hub_labels::build_hub_labels(&fname, &cancel)?;
+            println!(
+                "INFO: Built hub labels of {} nodes, with {} entries and at most {} hubs per label, into {} files of {} bytes in {}ms",
+                stats.num_nodes,
+                stats.num_entries,
+                stats.max_label_len,
+                stats.num_files,
+                stats.num_bytes,
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::VerifyHubLabels {
+            tiles_dir,
+            samples,
+            seed,
+        } => {
+            let start_time = std::time::Instant::now();
+            let stats = hub_labels::verify_hub_labels(&tiles_dir, samples, seed, &cancel)?;
+            println!(
+                "INFO: Checked {} pairs of nodes in {}ms, {} without a route, with a mean error of {:.4}s and at most {:.4}s ({:.4}%)",
+                stats.num_pairs,
+                start_time.elapsed().as_millis(),
+                stats.num_unreachable,
+                stats.mean_error_s,
+                stats.max_error_s,
+                stats.max_relative_error * 100.0
+            );
+            if stats.num_wrong > 0 {
+                anyhow::bail!(
+                    "The hub labels give wrong travel times for {} of {} pairs",
+                    stats.num_wrong,
+                    stats.num_pairs
+                );
+            }
+            Ok(())
+        }
+        Commands::Partition {
+            tiles_dir,
+            cell_sizes,
+        } => {
+            let start_time = std::time::Instant::now();
+            let stats = partition::build_partition(&tiles_dir, &cell_sizes, &cancel)?;
+            for (level, level_stats) in stats.levels.iter().enumerate() {
+                println!(
+                    "INFO: Level {} of cells of at most {} nodes has {} cells of up to {} nodes, cutting {} of {} arcs",
+                    level,
+                    level_stats.cell_size,
+                    level_stats.num_cells,
+                    level_stats.max_cell_nodes,
+                    level_stats.num_cut_arcs,
+                    stats.num_arcs
+                );
+            }
+            println!(
+                "INFO: Partitioned {} nodes into {} levels in {}ms",
+                stats.num_nodes,
+                stats.levels.len(),
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::Customize {
+            tiles_dir,
+            metric,
+            date: DateArg(date),
+            traffic,
+        } => {
+            let start_time = std::time::Instant::now();
+            let traffic = traffic
+                .map(|traffic| traffic::Traffic::read(&traffic))
+                .transpose()?;
+            if let Some(traffic) = &traffic {
+                println!(
+                    "INFO: Costing by the current speeds of {} edges",
+                    traffic.len()
+                );
+            }
+            let stats = customize::customize(&tiles_dir, metric, date, traffic.as_ref(), &cancel)?;
+            for (level, level_stats) in stats.levels.iter().enumerate() {
+                println!(
+                    "INFO: Level {} has {} cells with {} boundary nodes, at most {} in a cell, and {} costs",
+                    level,
+                    level_stats.num_cells,
+                    level_stats.num_boundary_nodes,
+                    level_stats.max_boundary_nodes,
+                    level_stats.num_entries
+                );
+            }
+            println!(
+                "INFO: Customized the cells of {} nodes in {}ms",
+                stats.num_nodes,
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::ApplyOsmChange {
+            change_file,
+            tiles_dir,
+            source,
+            profiles,
+        } => {
+            let start_time = std::time::Instant::now();
+            let (profile, extra_profiles) = split_profiles(profiles);
+            change::apply_osm_change(
+                &change_file,
+                &tiles_dir,
+                source.as_deref(),
+                profile,
+                &extra_profiles,
+                &cancel,
+            )?;
+            println!(
+                "INFO: Applied {} in {}ms",
+                change_file.display(),
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::LintData {
+            tiles_dir,
+            osm_output,
+            selection,
+        } => {
+            let start_time = std::time::Instant::now();
+            let num_issues = lint::lint_tiles(
+                &tiles_dir,
+                &selection.into_selector()?,
+                osm_output.as_deref(),
+                &cancel,
+            )?;
+            println!(
+                "INFO: Found {} issues in {}ms",
+                num_issues,
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::ExportGeojson {
+            tiles_dir,
+            output,
+            selection,
+        } => {
+            let start_time = std::time::Instant::now();
+            let num_features =
+                geojson::export_geojson(&tiles_dir, &selection.into_selector()?, &output, &cancel)?;
+            println!(
+                "INFO: Wrote {} edges to {} in {}ms",
+                num_features,
+                output.display(),
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::ExportMvt {
+            tiles_dir,
+            output_dir,
+            zoom,
+            selection,
+        } => {
+            let start_time = std::time::Instant::now();
+            let num_tiles = mvt::export_mvt(
+                &tiles_dir,
+                &selection.into_selector()?,
+                &output_dir,
+                zoom,
+                &cancel,
+            )?;
+            println!(
+                "INFO: Wrote {} vector tiles to {} in {}ms",
+                num_tiles,
+                output_dir.display(),
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::TileInfo {
+            tiles_dir,
+            selection,
+        } => {
+            let headers =
+                utils::read_tile_headers(&tiles_dir, &selection.into_selector()?, &cancel)?;
+            println!("quadkey\tzoom\tnodes\tedges\tbounds\tsource_timestamp\tbuilder_version");
+            for (quadkey, header) in &headers {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    quadkey.0,
+                    header.zoom,
+                    header.num_nodes,
+                    header.num_edges,
+                    header.bounds.map_or_else(String::new, |bounds| format!(
+                        "{:.7},{:.7},{:.7},{:.7}",
+                        bounds.0, bounds.1, bounds.2, bounds.3
+                    )),
+                    header
+                        .source_timestamp
+                        .map_or_else(String::new, utils::format_timestamp),
+                    header.builder_version
+                );
+            }
+            let timestamps = headers
+                .iter()
+                .filter_map(|(_quadkey, header)| header.source_timestamp)
+                .collect::<Vec<_>>();
+            if let (Some(oldest), Some(newest)) = (timestamps.iter().min(), timestamps.iter().max())
+            {
+                println!(
+                    "INFO: {} tiles with OSM data from {} to {}",
+                    headers.len(),
+                    utils::format_timestamp(*oldest),
+                    utils::format_timestamp(*newest)
+                );
+            }
+            let overviews = manifest::read_manifest(&tiles_dir)?
+                .map_or_else(Vec::new, |manifest| manifest.overviews);
+            for level in &overviews {
+                println!(
+                    "INFO: Overview level of roads up to {:?} at zoom {} in {}",
+                    level.max_class,
+                    level.zoom,
+                    overview::overview_dir(&tiles_dir, level.zoom).display()
+                );
+            }
+            Ok(())
+        }
+        Commands::ExportParquet {
+            tiles_dir,
+            output,
+            selection,
+        } => {
+            let start_time = std::time::Instant::now();
+            let num_rows = parquet_export::export_parquet(
+                &tiles_dir,
+                &selection.into_selector()?,
+                &output,
+                &cancel,
+            )?;
+            println!(
+                "INFO: Wrote {} edges to {} in {}ms",
+                num_rows,
+                output.display(),
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::MergeTiles {
+            tiles_dirs,
+            output_dir,
+        } => {
+            let start_time = std::time::Instant::now();
+            let num_tiles = merge::merge_tiles(&tiles_dirs, &output_dir, &cancel)?;
+            println!(
+                "INFO: Merged {} directories into {} tiles in {} in {}ms",
+                tiles_dirs.len(),
+                num_tiles,
+                output_dir.display(),
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::FindNode {
+            tiles_dir,
+            node_id,
+            selection,
+        } => {
+            let start_time = std::time::Instant::now();
+            let (quadkeys, num_skipped) = utils::find_node(
+                &tiles_dir,
+                &selection.into_selector()?,
+                NodeId(node_id),
+                &cancel,
+            )?;
+            for quadkey in &quadkeys {
+                println!("{}", quadkey.0);
+            }
+            println!(
+                "INFO: Found node {} in {} tiles, skipping {} tiles by their node filter, in {}ms",
+                node_id,
+                quadkeys.len(),
+                num_skipped,
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::BenchTileCodec {
+            grid_size,
+            nodes_per_block,
+            rounds,
+            compressions,
+        } => {
+            let mut tile = tile_bench::grid_tile(grid_size, nodes_per_block);
+            println!(
+                "INFO: Benchmarking a tile of {} nodes and {} edges over {} rounds",
+                tile.node_locations.len(),
+                tile.edges.len(),
+                rounds
+            );
+            println!("compression\tbytes\tencode_ms\tdecode_ms");
+            for stats in tile_bench::bench_tile_codec(&mut tile, &compressions, rounds, &cancel)? {
+                println!(
+                    "{:?}\t{}\t{:.2}\t{:.2}",
+                    stats.compression,
+                    stats.num_bytes,
+                    stats.encode.as_secs_f64() * 1000.0,
+                    stats.decode.as_secs_f64() * 1000.0
+                );
+            }
+            Ok(())
+        }
+        Commands::SampleCoordinates {
+            tiles_dir,
+            count,
+            seed,
+            selection,
+        } => {
+            let samples = sample::sample_coordinates(
+                &tiles_dir,
+                &selection.into_selector()?,
+                count,
+                seed,
+                &cancel,
+            )?;
+            // Only the coordinates go to stdout, so they can be piped into other tools
+            for (lat, lon) in samples {
+                println!("{:.7},{:.7}", lat, lon);
+            }
+            Ok(())
+        }
+        Commands::AlignEdges {
+            tiles_dir,
+            other,
+            max_distance,
+            output,
+            selection,
+        } => {
+            let start_time = std::time::Instant::now();
+            let selector = selection.into_selector()?;
+            let edges = align::TileSegments {
+                tiles_dir: &tiles_dir,
+                selector: &selector,
+            }
+            .segments(&cancel)?;
+            let others = align::open_source(&other, &selector).segments(&cancel)?;
+            let alignments = align::align(&edges, &others, max_distance, &cancel)?;
+            match output {
+                Some(output) => {
+                    let file = std::fs::File::create(&output)?;
+                    align::write_crosswalk(&mut std::io::BufWriter::new(file), &alignments)?;
+                }
+                None => align::write_crosswalk(&mut std::io::stdout().lock(), &alignments)?,
+            }
+            // Progress goes to stderr, as the crosswalk may be on stdout
+            eprintln!(
+                "INFO: Aligned {} of {} edges with {} segments in {}ms",
+                alignments.len(),
+                edges.len(),
+                others.len(),
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::BuildCh { tiles_dir } => {
+            let start_time = std::time::Instant::now();
+            let stats = hierarchy::build_hierarchy(&tiles_dir, &cancel)?;
+            println!(
+                "INFO: Built a contraction hierarchy of {} nodes, {} arcs and {} shortcuts into {} tiles in {}ms",
+                stats.num_nodes,
+                stats.num_arcs,
+                stats.num_shortcuts,
+                stats.num_tiles,
+                start_time.elapsed().as_millis()
+            );
+            Ok(())
+        }
+        Commands::Route {
+            tiles_dir,
+            from,
+            to,
+            via,
+            algorithm,
+            date: DateArg(date),
+            tile_affinity,
+            turns,
+            alternatives,
+            max_overlap,
+            geometries,
+            avoid,
+            exclude,
+            traffic,
+        } => {
+            let start_time = std::time::Instant::now();
+            let options = routing::RouteOptions {
+                algorithm,
+                date,
+                tile_affinity_s: tile_affinity,
+                turn_costs: turns.into_turn_costs(),
+                alternatives: (alternatives > 0).then_some(routing::Alternatives {
+                    max_alternatives: alternatives,
+                    max_overlap,
+                }),
+                exclude: routing::ExcludeMask::new(&exclude),
+            };
+            let mut graph = routing::TileGraph::open(&tiles_dir)?;
+            if let Some(avoid) = avoid {
+                graph.avoid_areas(region::Region::from_geojson_file(&avoid)?);
+            }
+            if let Some(traffic) = traffic {
+                graph.use_traffic(traffic::Traffic::read(&traffic)?);
+                eprintln!(
+                    "INFO: Routing by the current speeds of {} edges",
+                    graph.num_traffic_speeds()
+                );
+            }
+            let waypoints = std::iter::once(from)
+                .chain(via)
+                .chain(std::iter::once(to))
+                .collect::<Vec<_>>();
+            let route = routing::route(&mut graph, &waypoints, &options, &cancel)?;
+            println!("{}", route.to_json(geometries));
+            // Progress goes to stderr, so the route can be piped into other tools
+            eprintln!(
+                "INFO: Routed over {} edges in {}ms, settling {} nodes, reading {} tiles ({} ahead of the search) and avoiding {}",
+                route
+                    .legs
+                    .iter()
+                    .map(|leg| leg.traversals.len())
+                    .sum::<usize>(),
+                start_time.elapsed().as_millis(),
+                graph.num_settled(),
+                graph.num_loaded(),
+                graph.num_prefetched(),
+                graph.num_avoided()
+            );
+            Ok(())
+        }
+        Commands::Table {
+            tiles_dir,
+            sources,
+            destinations,
+            date: DateArg(date),
+            turns,
+            exclude,
+            traffic,
+        } => {
+            let start_time = std::time::Instant::now();
+            let options = routing::RouteOptions {
+                algorithm: routing::Algorithm::Dijkstra,
+                date,
+                turn_costs: turns.into_turn_costs(),
+                exclude: routing::ExcludeMask::new(&exclude),
+                ..Default::default()
+            };
+            let destinations = if destinations.is_empty() {
+                sources.clone()
+            } else {
+                destinations
+            };
+            let mut graph = routing::TileGraph::open(&tiles_dir)?;
+            if let Some(traffic) = traffic {
+                graph.use_traffic(traffic::Traffic::read(&traffic)?);
+                eprintln!(
+                    "INFO: Routing by the current speeds of {} edges",
+                    graph.num_traffic_speeds()
+                );
+            }
+            let table = table::table(&mut graph, &sources, &destinations, &options, &cancel)?;
+            println!("{}", table.to_json());
+            eprintln!(
+                "INFO: Computed a table of {} sources and {} destinations in {}ms, settling {} nodes and reading {} tiles",
+                sources.len(),
+                destinations.len(),
+                start_time.elapsed().as_millis(),
+                graph.num_settled(),
+                graph.num_loaded()
+            );
+            Ok(())
+        }
+        Commands::Match {
+            tiles_dir,
+            points,
+            gps_accuracy_m,
+            search_radius_m,
+            date: DateArg(date),
+        } => {
+            let start_time = std::time::Instant::now();
+            let options = routing::RouteOptions {
+                algorithm: routing::Algorithm::Dijkstra,
+                date,
+                ..Default::default()
+            };
+            let match_options = matching::MatchOptions {
+                gps_accuracy_m,
+                search_radius_m,
+            };
+            let points = matching::read_points(&points)?;
+            let mut graph = routing::TileGraph::open(&tiles_dir)?;
+            let matched =
+                matching::match_points(&mut graph, &points, &match_options, &options, &cancel)?;
+            println!("{}", matched.to_json());
+            eprintln!(
+                "INFO: Matched {} of {} points into {} matchings in {}ms, settling {} nodes and reading {} tiles",
+                matched.tracepoints.iter().flatten().count(),
+                points.len(),
+                matched.matchings.len(),
+                start_time.elapsed().as_millis(),
+                graph.num_settled(),
+                graph.num_loaded()
+            );
+            Ok(())
+        }
+        Commands::Isochrone {
+            tiles_dir,
+            from,
+            minutes,
+            cell_size_m,
+            date: DateArg(date),
+            turns,
+        } => {
+            let start_time = std::time::Instant::now();
+            let options = routing::RouteOptions {
+                algorithm: routing::Algorithm::Dijkstra,
+                date,
+                turn_costs: turns.into_turn_costs(),
+                ..Default::default()
+            };
+            let budgets_s = minutes
+                .iter()
+                .map(|minutes| minutes * 60.0)
+                .collect::<Vec<_>>();
+            let mut graph = routing::TileGraph::open(&tiles_dir)?;
+            let isochrones = isochrone::isochrones(
+                &mut graph,
+                from,
+                &budgets_s,
+                cell_size_m,
+                &options,
+                &cancel,
+            )?;
+            println!("{}", isochrones.to_geojson());
+            eprintln!(
+                "INFO: Computed {} isochrones in {}ms, settling {} nodes and reading {} tiles",
+                isochrones.isochrones.len(),
+                start_time.elapsed().as_millis(),
+                graph.num_settled(),
+                graph.num_loaded()
+            );
+            Ok(())
+        }
+        Commands::CompareEngines {
+            tiles_dir,
+            endpoint,
+            engine,
+            samples,
+            seed,
+            date: DateArg(date),
+            output,
+            selection,
+        } => {
+            let start_time = std::time::Instant::now();
+            let options = routing::RouteOptions {
+                date,
+                ..Default::default()
+            };
+            let coordinates = sample::sample_coordinates(
+                &tiles_dir,
+                &selection.into_selector()?,
+                2 * samples,
+                seed,
+                &cancel,
+            )?;
+            let locations = coordinates
+                .chunks_exact(2)
+                .map(|pair| {
+                    let coordinate = |(lat, lon)| routing::Coordinate { lat, lon };
+                    (coordinate(pair[0]), coordinate(pair[1]))
+                })
+                .collect::<Vec<_>>();
+            let client = http::HttpClient::new(&http_options)?;
+            let mut graph = routing::TileGraph::open(&tiles_dir)?;
+            let comparisons = compare::compare(
+                &mut graph, &client, engine, &endpoint, &locations, &options, &cancel,
+            )?;
+            if let Some(output) = output {
+                let file = std::fs::File::create(&output)?;
+                compare::write_comparisons(&mut std::io::BufWriter::new(file), &comparisons)?;
+            }
+            print!("{}", compare::report(&comparisons));
+            client.log_metrics();
+            println!(
+                "INFO: Compared {} routes in {}ms, reading {} tiles",
+                comparisons.len(),
+                start_time.elapsed().as_millis(),
+                graph.num_loaded()
+            );
+            Ok(())
+        }
+        Commands::TravelTimes {
+            tiles_dir,
+            sources,
+            output,
+        } => {
+            let start_time = std::time::Instant::now();
+            let phast = phast::Phast::read(&tiles_dir, &cancel)?;
+            eprintln!(
+                "INFO: Read a contraction hierarchy of {} nodes and {} arcs in {}ms",
+                phast.node_ids.len(),
+                phast.num_arcs(),
+                start_time.elapsed().as_millis()
+            );
+            let start_time = std::time::Instant::now();
+            let sources = sources
+                .iter()
+                .map(|source| {
+                    phast
+                        .nearest_node(*source)
+                        .ok_or_else(|| anyhow::anyhow!("No nodes in {}", tiles_dir.display()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let durations = phast.durations_from_all(&sources, &cancel)?;
+            eprintln!(
+                "INFO: Swept {} nodes from {} sources in {}ms",
+                phast.node_ids.len(),
+                sources.len(),
+                start_time.elapsed().as_millis()
+            );
+            match output {
+                Some(output) => {
+                    let file = std::fs::File::create(&output)?;
+                    phast::write_durations(&mut std::io::BufWriter::new(file), &phast, &durations)?;
+                }
+                None => phast::write_durations(
+                    &mut std::io::BufWriter::new(std::io::stdout().lock()),
+                    &phast,
+                    &durations,
+                )?,
+            }
+            Ok(())
+        }
     }
 }
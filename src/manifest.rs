@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::{GladsheimError, IoContext, Result},
+    overview::OverviewLevel,
+    tile_format::TileHeader,
+    utils::{self, Quadkey, TileNaming},
+};
+
+/// Name of the file listing the tiles of a directory
+pub(crate) const MANIFEST_FILE: &str = "manifest.bin";
+
+/// The tiles written into a directory, so readers find and select them without
+/// listing the directory, and tiles left over from earlier builds are ignored
+///
+/// The header of every tile is kept with its quadkey, so the metadata of a
+/// whole deployment is read from a single file.
+#[derive(Debug, bincode::Encode, bincode::Decode)]
+pub(crate) struct Manifest {
+    pub(crate) naming: TileNaming,
+    /// The overview levels built along with the tiles, each in its own
+    /// `overview::overview_dir` with a manifest of its own
+    pub(crate) overviews: Vec<OverviewLevel>,
+    /// The tiles and their headers, sorted by quadkey
+    pub(crate) tiles: Vec<(Quadkey, TileHeader)>,
+}
+
+pub(crate) fn manifest_path(tiles_dir: &Path) -> PathBuf {
+    tiles_dir.join(MANIFEST_FILE)
+}
+
+/// Reads the manifest of a tile directory, `None` if it has none
+pub(crate) fn read_manifest(tiles_dir: &Path) -> Result<Option<Manifest>> {
+    let fname = manifest_path(tiles_dir);
+    if !fname.exists() {
+        return Ok(None);
+    }
+    let bytes =
+        std::fs::read(&fname).io_context(|| format!("Failed reading file {}", fname.display()))?;
+    let (manifest, _len) = bincode::decode_from_slice(&bytes, bincode::config::standard())
+        .map_err(|source| GladsheimError::TileFormat {
+            context: format!("Failed decoding {}", fname.display()),
+            source: Box::new(source),
+        })?;
+    Ok(Some(manifest))
+}
+
+/// Writes the manifest of the tiles of a directory, returning whether it changed
+pub(crate) fn write_manifest(
+    tiles_dir: &Path,
+    naming: TileNaming,
+    overviews: &[OverviewLevel],
+    mut tiles: Vec<(Quadkey, TileHeader)>,
+) -> Result<bool> {
+    tiles.sort_by(|(a, _a_header), (b, _b_header)| a.0.cmp(&b.0));
+    utils::write_encoded(
+        &manifest_path(tiles_dir),
+        &Manifest {
+            naming,
+            overviews: overviews.to_vec(),
+            tiles,
+        },
+    )
+}
+
+/// Removes the manifest of a directory before its tiles are rewritten, so an
+/// interrupted write leaves no manifest vouching for the tiles
+pub(crate) fn remove_manifest(tiles_dir: &Path) -> Result<()> {
+    let fname = manifest_path(tiles_dir);
+    if fname.exists() {
+        std::fs::remove_file(&fname)
+            .io_context(|| format!("Failed removing file {}", fname.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NodeId, RoadClass, osm_parser::Loc, utils::Tile};
+
+    #[test]
+    fn manifests_round_trip_sorted_by_quadkey() {
+        let tiles_dir =
+            std::env::temp_dir().join(format!("gladsheim-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&tiles_dir).unwrap();
+        assert!(read_manifest(&tiles_dir).unwrap().is_none());
+
+        let tile = Tile {
+            node_locations: vec![
+                (
+                    NodeId(1),
+                    Loc {
+                        lat: 59.33,
+                        lon: 18.06,
+                    },
+                ),
+                (
+                    NodeId(2),
+                    Loc {
+                        lat: 59.34,
+                        lon: 18.07,
+                    },
+                ),
+            ],
+            ..Default::default()
+        };
+        let (header, _bytes) = utils::encode_tile(&tile, "of the test").unwrap();
+        let tiles = ["1203", "0231", "1202"]
+            .map(|quadkey| (Quadkey(quadkey.to_string()), header.clone()))
+            .to_vec();
+        let overviews = [OverviewLevel {
+            zoom: 10,
+            max_class: RoadClass::Tertiary,
+        }];
+        assert!(write_manifest(&tiles_dir, TileNaming::Zxy, &overviews, tiles.clone()).unwrap());
+        // Rewriting the same tiles in another order leaves the file alone
+        let mut reversed = tiles;
+        reversed.reverse();
+        assert!(!write_manifest(&tiles_dir, TileNaming::Zxy, &overviews, reversed).unwrap());
+
+        let manifest = read_manifest(&tiles_dir).unwrap().unwrap();
+        remove_manifest(&tiles_dir).unwrap();
+        let removed = read_manifest(&tiles_dir).unwrap();
+        std::fs::remove_dir(&tiles_dir).unwrap();
+
+        assert_eq!(manifest.naming, TileNaming::Zxy);
+        assert_eq!(manifest.overviews, overviews);
+        let quadkeys = manifest
+            .tiles
+            .iter()
+            .map(|(quadkey, _header)| quadkey.0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(quadkeys, ["0231", "1202", "1203"]);
+        let (_quadkey, read_header) = &manifest.tiles[0];
+        assert_eq!(read_header.num_nodes, 2);
+        assert_eq!(read_header.checksum, header.checksum);
+        assert!(removed.is_none());
+    }
+}
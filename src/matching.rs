@@ -0,0 +1,347 @@
+use std::{fmt::Write, path::Path};
+
+use crate::{
+    NodeId, WayId,
+    cancel::CancellationToken,
+    error::{GladsheimError, IoContext, Result},
+    routing::{self, Coordinate, Leg, RouteOptions, Snap, TileGraph},
+    utils,
+};
+
+/// Most roads a point is matched onto, the closest ones
+const MAX_CANDIDATES: usize = 8;
+/// Meters the route between the candidates of consecutive points may differ
+/// from the straight line between the points for the transition to be 1/e as
+/// likely as one along the straight line
+const TRANSITION_SCALE_M: f64 = 20.0;
+/// How many times longer than the straight line between consecutive points,
+/// widened by the search radius at both ends, a route between them may be
+const MAX_DETOUR: f64 = 2.0;
+/// The slowest speed in m/s routes between consecutive points are searched
+/// for, bounding the searches by time along with `MAX_DETOUR`
+const MIN_SPEED_MPS: f64 = 3.0;
+
+/// How far from the roads the points are expected to be and matched
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MatchOptions {
+    /// Standard deviation in meters of the distance from a point to the road it
+    /// was recorded on
+    pub(crate) gps_accuracy_m: f64,
+    /// Roads farther than this from a point aren't matched onto
+    pub(crate) search_radius_m: f64,
+}
+
+/// An edge along a matched route, in the direction travelled
+pub(crate) struct MatchedEdge {
+    pub(crate) way_id: WayId,
+    pub(crate) from: NodeId,
+    pub(crate) to: NodeId,
+}
+
+/// A run of points matched onto one connected route
+pub(crate) struct Matching {
+    /// Probability of the route among all the ways the points could be
+    /// matched, given how far they are from the roads and how the routes
+    /// between them compare to the straight lines
+    pub(crate) confidence: f64,
+    /// The routes between consecutive matched points
+    pub(crate) legs: Vec<Leg>,
+    /// The edges travelled, once each time they're entered
+    pub(crate) edges: Vec<MatchedEdge>,
+}
+
+/// Where a point was matched onto the roads
+pub(crate) struct Tracepoint {
+    pub(crate) snap: Snap,
+    /// The matching the point is in
+    pub(crate) matching: usize,
+    /// The position of the point among those of its matching
+    pub(crate) waypoint: usize,
+}
+
+/// Points matched onto the roads, split into matchings where no route
+/// connects them
+pub(crate) struct Match {
+    /// Every point in the order given, `None` for those without a road nearby
+    pub(crate) tracepoints: Vec<Option<Tracepoint>>,
+    pub(crate) matchings: Vec<Matching>,
+}
+impl Match {
+    /// The match as JSON shaped like an OSRM match response, with the edges of
+    /// every matching added
+    pub(crate) fn to_json(&self) -> String {
+        let mut json = String::from(r#"{"code":"Ok","matchings":["#);
+        for (index, matching) in self.matchings.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                r#"{{"confidence":{:.3},"distance":{:.1},"duration":{:.1},"legs":["#,
+                matching.confidence,
+                matching.legs.iter().map(|leg| leg.distance_m).sum::<f64>(),
+                matching.legs.iter().map(|leg| leg.duration_s).sum::<f64>()
+            );
+            for (index, leg) in matching.legs.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                let _ = write!(
+                    json,
+                    r#"{{"distance":{:.1},"duration":{:.1}}}"#,
+                    leg.distance_m, leg.duration_s
+                );
+            }
+            json.push_str(r#"],"edges":["#);
+            for (index, edge) in matching.edges.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                let _ = write!(
+                    json,
+                    r#"{{"way":{},"from":{},"to":{}}}"#,
+                    edge.way_id.0, edge.from.0, edge.to.0
+                );
+            }
+            json.push_str("]}");
+        }
+        json.push_str(r#"],"tracepoints":["#);
+        for (index, tracepoint) in self.tracepoints.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            match tracepoint {
+                Some(tracepoint) => {
+                    let snap = tracepoint.snap.to_json();
+                    let _ = write!(
+                        json,
+                        r#"{},"matchings_index":{},"waypoint_index":{}}}"#,
+                        &snap[..snap.len() - 1],
+                        tracepoint.matching,
+                        tracepoint.waypoint
+                    );
+                }
+                None => json.push_str("null"),
+            }
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Reads the points of a trace, one `lat,lon` per line, skipping empty lines
+pub(crate) fn read_points(path: &Path) -> Result<Vec<Coordinate>> {
+    let text = std::fs::read_to_string(path)
+        .io_context(|| format!("Failed reading file {}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_index, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            line.parse().map_err(|error| {
+                GladsheimError::parse(format!(
+                    "Line {} of {}: {}",
+                    index + 1,
+                    path.display(),
+                    error
+                ))
+            })
+        })
+        .collect()
+}
+
+/// The candidates of a point, what the most likely way to each of them is and
+/// how likely that is
+struct Layer {
+    /// The point among those given
+    point: usize,
+    candidates: Vec<Snap>,
+    /// Log of the probability of the most likely way to every candidate
+    best: Vec<f64>,
+    /// Log of the probability of all the ways to every candidate
+    total: Vec<f64>,
+    /// The candidate of the layer before the most likely way to every
+    /// candidate comes from, with the route from it
+    previous: Vec<Option<(usize, Leg)>>,
+}
+
+/// Matches a trace of points onto the roads by a hidden Markov model whose
+/// states are the roads near every point
+///
+/// A road is as likely to have been travelled as a normal distribution of the
+/// distance of the point from it, with `gps_accuracy_m` as the deviation, and
+/// moving between the roads of consecutive points gets exponentially less
+/// likely the more the route between them differs from the straight line
+/// between the points. The most likely roads are then found by the Viterbi
+/// algorithm. Where no route connects consecutive points, the trace is split
+/// into a new matching, and points without roads nearby or alone in their
+/// matching are left out.
+pub(crate) fn match_points(
+    graph: &mut TileGraph,
+    points: &[Coordinate],
+    match_options: &MatchOptions,
+    options: &RouteOptions,
+    cancel: &CancellationToken,
+) -> Result<Match> {
+    if points.len() < 2 {
+        return Err(GladsheimError::parse("A match needs at least two points"));
+    }
+    let mut tracepoints = points.iter().map(|_point| None).collect::<Vec<_>>();
+    let mut matchings = Vec::new();
+    let mut layers: Vec<Layer> = Vec::new();
+    for (point, location) in points.iter().enumerate() {
+        cancel.check(|| format!("matching point {} of {}", point, points.len()))?;
+        let mut candidates = graph.candidates(*location, match_options.search_radius_m, options)?;
+        candidates.truncate(MAX_CANDIDATES);
+        if candidates.is_empty() {
+            continue;
+        }
+        let emissions = candidates
+            .iter()
+            .map(|candidate| -0.5 * (candidate.distance_m / match_options.gps_accuracy_m).powi(2))
+            .collect::<Vec<_>>();
+        let mut layer = Layer {
+            point,
+            best: vec![f64::NEG_INFINITY; candidates.len()],
+            total: vec![f64::NEG_INFINITY; candidates.len()],
+            previous: candidates.iter().map(|_candidate| None).collect(),
+            candidates,
+        };
+        if let Some(before) = layers.last() {
+            let location_before = &points[before.point];
+            let straight_m = utils::haversine_distance(
+                location_before.lat,
+                location_before.lon,
+                location.lat,
+                location.lon,
+            );
+            let max_s =
+                MAX_DETOUR * (straight_m + 2.0 * match_options.search_radius_m) / MIN_SPEED_MPS;
+            for (from, from_candidate) in before.candidates.iter().enumerate() {
+                if before.best[from] == f64::NEG_INFINITY {
+                    continue;
+                }
+                let legs = routing::one_to_many(
+                    graph,
+                    from_candidate,
+                    &layer.candidates,
+                    max_s,
+                    options,
+                    cancel,
+                )?;
+                for (to, leg) in legs.into_iter().enumerate() {
+                    let Some(leg) = leg else {
+                        continue;
+                    };
+                    let transition = -(leg.distance_m - straight_m).abs() / TRANSITION_SCALE_M;
+                    let total = before.total[from] + transition;
+                    layer.total[to] = log_add(layer.total[to], total);
+                    if before.best[from] + transition > layer.best[to] {
+                        layer.best[to] = before.best[from] + transition;
+                        layer.previous[to] = Some((from, leg));
+                    }
+                }
+            }
+        }
+        if layer.best.iter().all(|best| *best == f64::NEG_INFINITY) {
+            // Nothing connects the point to those before it, it starts a new matching
+            if layers.len() > 1 {
+                matchings.push(finish_matching(
+                    graph,
+                    std::mem::take(&mut layers),
+                    matchings.len(),
+                    &mut tracepoints,
+                ));
+            }
+            layers.clear();
+            layer.best = emissions.clone();
+            layer.total = emissions;
+        } else {
+            for (index, emission) in emissions.iter().enumerate() {
+                layer.best[index] += emission;
+                layer.total[index] += emission;
+            }
+        }
+        layers.push(layer);
+    }
+    if layers.len() > 1 {
+        matchings.push(finish_matching(
+            graph,
+            layers,
+            matchings.len(),
+            &mut tracepoints,
+        ));
+    }
+    Ok(Match {
+        tracepoints,
+        matchings,
+    })
+}
+
+/// The matching through the most likely candidates of its layers, by
+/// following their previous candidates back from the most likely last one
+fn finish_matching(
+    graph: &TileGraph,
+    mut layers: Vec<Layer>,
+    index: usize,
+    tracepoints: &mut [Option<Tracepoint>],
+) -> Matching {
+    let last = layers.last().expect("a matching has layers");
+    let (mut candidate, best) = last
+        .best
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .expect("a layer has candidates");
+    let total = last.total.iter().copied().fold(f64::NEG_INFINITY, log_add);
+    let mut legs = Vec::new();
+    for (waypoint, layer) in layers.iter_mut().enumerate().rev() {
+        let previous = layer.previous[candidate].take();
+        tracepoints[layer.point] = Some(Tracepoint {
+            snap: layer.candidates.swap_remove(candidate),
+            matching: index,
+            waypoint,
+        });
+        if let Some((previous, leg)) = previous {
+            legs.push(leg);
+            candidate = previous;
+        }
+    }
+    legs.reverse();
+
+    let mut edges: Vec<MatchedEdge> = Vec::new();
+    let mut entered = None;
+    for traversal in legs.iter().flat_map(|leg| &leg.traversals) {
+        let edge = graph.edge(traversal.edge);
+        let forward = traversal.end >= traversal.start;
+        // Legs end and start part way along the same edge
+        if entered == Some((traversal.edge, forward)) {
+            continue;
+        }
+        entered = Some((traversal.edge, forward));
+        let (from, to) = if forward {
+            (edge.from, edge.to)
+        } else {
+            (edge.to, edge.from)
+        };
+        edges.push(MatchedEdge {
+            way_id: edge.way_id,
+            from,
+            to,
+        });
+    }
+    Matching {
+        confidence: (best - total).exp(),
+        legs,
+        edges,
+    }
+}
+
+/// The log of the sum of two probabilities given by their logs
+fn log_add(a: f64, b: f64) -> f64 {
+    let max = a.max(b);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + ((a - max).exp() + (b - max).exp()).ln()
+}
@@ -0,0 +1,216 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    NodeId, WayId,
+    bloom::NodeFilter,
+    boundary,
+    cancel::CancellationToken,
+    change,
+    error::{GladsheimError, IoContext, Result},
+    flat_tile, manifest,
+    osm_parser::{self, ParseOptions},
+    tile_proto,
+    utils::{Quadkey, Tile, TileStore},
+};
+
+/// Combines tile directories built from separate extracts, e.g. of neighbouring
+/// countries, into one directory of tiles
+///
+/// Extracts overlap along their borders, where each holds the ways crossing the
+/// border clipped at its own edge. The edges of all inputs are split wherever
+/// an edge of another input ends or another way joins them midway, after which
+/// the overlapping parts are duplicates that get merged. The boundary nodes are
+/// found anew over the merged tiles. The output takes the naming and format of
+/// the first input. Points of interest, addresses and turn files are not merged.
+/// Returns the number of tiles written.
+pub(crate) fn merge_tiles(
+    inputs: &[PathBuf],
+    output_dir: &Path,
+    cancel: &CancellationToken,
+) -> Result<usize> {
+    let start_time = std::time::Instant::now();
+    let Some(first_dir) = inputs.first() else {
+        return Err(GladsheimError::parse("No tile directories to merge"));
+    };
+    let first_store = TileStore::open(first_dir)?;
+    let naming = first_store.naming();
+    let mut input_tiles = Vec::new();
+    for tiles_dir in inputs {
+        let store = TileStore::open(tiles_dir)?;
+        let tiles = store
+            .iter()
+            .map(|tile| {
+                cancel.check(|| format!("reading the tiles of {}", tiles_dir.display()))?;
+                tile
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        input_tiles.push(tiles);
+    }
+    let all_tiles = || input_tiles.iter().flat_map(HashMap::values);
+    let Some(template) = all_tiles().next() else {
+        return Err(GladsheimError::parse("No tiles to merge"));
+    };
+    if let Some(tile) = all_tiles().find(|tile| {
+        tile.profile != template.profile
+            || tile.extra_profiles != template.extra_profiles
+            || tile.zoom != template.zoom
+            || tile.has_reverse_edges != template.has_reverse_edges
+    }) {
+        return Err(GladsheimError::parse(format!(
+            "Tiles were built with different options, for the profiles {:?} at zoom {} and {:?} at zoom {}",
+            std::iter::once(template.profile)
+                .chain(template.extra_profiles.iter().copied())
+                .collect::<Vec<_>>(),
+            template.zoom,
+            std::iter::once(tile.profile)
+                .chain(tile.extra_profiles.iter().copied())
+                .collect::<Vec<_>>(),
+            tile.zoom
+        )));
+    }
+    if all_tiles().any(|tile| tile.is_contracted) {
+        // Contracted edges no longer end where the ways of other inputs may join them
+        return Err(GladsheimError::parse(
+            "Tiles with contracted chains can't be merged, rebuild them without",
+        ));
+    }
+    if all_tiles().any(|tile| tile.hierarchy.is_some()) {
+        // The hierarchies of the inputs don't know the roads crossing between them
+        return Err(GladsheimError::parse(
+            "Tiles with a contraction hierarchy can't be merged, merge them without and run BuildCh again",
+        ));
+    }
+    if all_tiles().any(|tile| tile.max_class.is_some()) {
+        return Err(GladsheimError::parse(
+            "Overview tiles can't be merged, merge the base tiles and rebuild them instead",
+        ));
+    }
+    let options = ParseOptions {
+        profile: template.profile,
+        extra_profiles: template.extra_profiles.clone(),
+        reverse_edges: template.has_reverse_edges,
+        tile_zoom: template.zoom,
+        compression: template.compression,
+        naming,
+        storage: first_store.storage(),
+        flat: first_store
+            .quadkeys()
+            .any(|quadkey| flat_tile::flat_tile_path(first_dir, quadkey, naming).exists()),
+        protobuf: first_store
+            .quadkeys()
+            .any(|quadkey| tile_proto::proto_tile_path(first_dir, quadkey, naming).exists()),
+        node_filter: all_tiles().any(|tile| tile.node_filter.is_some()),
+        ..Default::default()
+    };
+    // The merged data is only as recent as the oldest of the inputs
+    let source_timestamp = all_tiles().filter_map(|tile| tile.source_timestamp).min();
+    let node_table = change::node_table_from_tiles(all_tiles());
+    println!(
+        "INFO: Read {} tiles of {} directories in {}ms",
+        all_tiles().count(),
+        inputs.len(),
+        start_time.elapsed().as_millis()
+    );
+
+    let start_time = std::time::Instant::now();
+    let edges = input_tiles
+        .iter_mut()
+        .flat_map(|tiles| tiles.values_mut())
+        .flat_map(|tile| std::mem::take(&mut tile.edges))
+        .collect::<Vec<_>>();
+
+    // Edges end at every node where they meet another way, which may now come
+    // from another input, and where any input clipped or split a way, so the
+    // overlapping parts of a way become identical edges
+    let mut ways_at: HashMap<NodeId, HashSet<WayId>> = HashMap::new();
+    for edge in &edges {
+        for node_id in &edge.nodes {
+            ways_at.entry(*node_id).or_default().insert(edge.way_id);
+        }
+    }
+    let intersection_nodes = edges
+        .iter()
+        .flat_map(|edge| [edge.from, edge.to])
+        .chain(
+            ways_at
+                .into_iter()
+                .filter(|(_node_id, ways)| ways.len() > 1)
+                .map(|(node_id, _ways)| node_id),
+        )
+        .collect::<HashSet<_>>();
+    let mut merged: HashMap<Quadkey, Tile> = HashMap::new();
+    let mut num_split = 0;
+    for edge in edges {
+        let is_split = edge
+            .nodes_before_to()
+            .iter()
+            .skip(1)
+            .any(|node_id| intersection_nodes.contains(node_id));
+        let parts = if is_split {
+            num_split += 1;
+            let mut parts = change::split_edge(&edge, &intersection_nodes);
+            for part in &mut parts {
+                osm_parser::annotate_edge(part, &node_table, &options);
+            }
+            parts
+        } else {
+            vec![edge]
+        };
+        for edge in parts {
+            match osm_parser::edge_quadkey(&edge, &node_table, options.tile_zoom) {
+                Ok(quadkey) => merged.entry(quadkey).or_default().edges.push(edge),
+                Err(err) => println!("ERROR: Could not create quadkey: {}", err),
+            }
+        }
+    }
+
+    let mut tiles = merged.into_iter().collect::<Vec<_>>();
+    let mut num_duplicates = 0;
+    for (quadkey, tile) in &mut tiles {
+        num_duplicates += osm_parser::merge_duplicate_edges(&mut tile.edges);
+        tile.profile = options.profile;
+        tile.extra_profiles = options.extra_profiles.clone();
+        tile.compression = options.compression;
+        tile.zoom = options.tile_zoom;
+        tile.source_timestamp = source_timestamp;
+        tile.has_reverse_edges = options.reverse_edges;
+        osm_parser::annotate_tile(tile, &node_table, options.splits_at_crossings());
+        if options.node_filter {
+            tile.node_filter = Some(NodeFilter::from_nodes(
+                tile.node_locations.iter().map(|(node_id, _loc)| node_id),
+            ));
+        }
+        for input in input_tiles.iter().filter_map(|tiles| tiles.get(quadkey)) {
+            for line in &input.transit_lines {
+                if !tile.transit_lines.contains(line) {
+                    tile.transit_lines.push(line.clone());
+                }
+            }
+            for restriction in &input.restrictions {
+                if !tile.restrictions.contains(restriction) {
+                    tile.restrictions.push(restriction.clone());
+                }
+            }
+        }
+    }
+    tiles.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+    boundary::link_tiles(&mut tiles);
+    println!(
+        "INFO: Merged into {} tiles, splitting {} edges and merging {} duplicates, in {}ms",
+        tiles.len(),
+        num_split,
+        num_duplicates,
+        start_time.elapsed().as_millis()
+    );
+
+    cancel.check(|| format!("merging {} tiles, before writing them", tiles.len()))?;
+    std::fs::create_dir_all(output_dir)
+        .io_context(|| format!("Failed creating directory {}", output_dir.display()))?;
+    manifest::remove_manifest(output_dir)?;
+    let headers = osm_parser::write_tiles(output_dir, &tiles, &options, cancel)?;
+    manifest::write_manifest(output_dir, options.naming, &[], headers)?;
+    Ok(tiles.len())
+}
@@ -0,0 +1,297 @@
+use std::{
+    collections::{HashMap, HashSet},
+    f64::consts::PI,
+    path::Path,
+};
+
+use clap::ValueEnum;
+use protobuf::CodedOutputStream;
+
+use crate::{
+    Edge,
+    cancel::CancellationToken,
+    error::{GladsheimError, Result},
+    geojson::value_name,
+    geometry,
+    osm_parser::Node,
+    profile::Profile,
+    tile_proto,
+    utils::{self, Quadkey, TileCoord, TileNaming, TileSelector, TileStore},
+};
+
+/// Name of the layer holding the edges in every vector tile
+const LAYER_NAME: &str = "edges";
+/// Size of a vector tile in its own integer coordinates
+const EXTENT: u32 = 4096;
+/// Version 2.1 of the Mapbox Vector Tile specification
+const MVT_VERSION: u32 = 2;
+/// `GeomType.LINESTRING` of the specification
+const LINESTRING: i32 = 2;
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+
+/// Renders the edges of the selected tiles as Mapbox Vector Tiles, returning the
+/// number of vector tiles written
+///
+/// The vector tiles are written as `<z>/<x>/<y>.mvt` into `output_dir`, at the
+/// zoom of the routing tiles unless a deeper `zoom` is given, so a slippy map can
+/// load the directory with its max zoom set to that. Vector tiles cover the
+/// area of the selected routing tiles, edges crossing between vector tiles are
+/// drawn in each of them so roads don't stop at tile borders.
+pub(crate) fn export_mvt(
+    tiles_dir: &Path,
+    selector: &TileSelector,
+    output_dir: &Path,
+    zoom: Option<u8>,
+    cancel: &CancellationToken,
+) -> Result<usize> {
+    let store = TileStore::open(tiles_dir)?.select(selector);
+    let selected = store.quadkeys().cloned().collect::<HashSet<_>>();
+    let mut layers: HashMap<Quadkey, Layer> = HashMap::new();
+    for (index, tile) in store.iter().enumerate() {
+        cancel.check(|| format!("rendering {} of {} tiles", index, store.len()))?;
+        let (quadkey, tile) = tile?;
+        let tile_zoom = quadkey.0.len() as u8;
+        let zoom = zoom.unwrap_or(tile_zoom);
+        if zoom < tile_zoom {
+            return Err(GladsheimError::parse(format!(
+                "Vector tiles at zoom {} can't be rendered from tiles at zoom {}",
+                zoom, tile_zoom
+            )));
+        }
+        let node_table = tile
+            .node_locations
+            .into_iter()
+            .map(|(node_id, loc)| {
+                (
+                    node_id,
+                    Node {
+                        loc,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        for edge in &tile.edges {
+            let points = geometry::edge_coordinates(edge, &node_table)
+                .into_iter()
+                .map(|(lat, lon)| world_position(lat, lon, zoom))
+                .collect::<Vec<_>>();
+            for tile_coord in touched_tiles(&points, zoom) {
+                let other = Quadkey(utils::tile_coord_to_quadkey(&tile_coord));
+                if !selected.contains(&Quadkey(other.0[..tile_zoom as usize].to_string())) {
+                    continue;
+                }
+                let origin = (tile_coord.x as f64, tile_coord.y as f64);
+                if let Some(geometry) = encode_geometry(&points, origin) {
+                    layers.entry(other).or_default().add(edge, geometry);
+                }
+            }
+        }
+    }
+
+    for (quadkey, layer) in &layers {
+        let fname = utils::tile_path(output_dir, quadkey, TileNaming::Zxy).with_extension("mvt");
+        let bytes = layer
+            .encode()
+            .map_err(|source| GladsheimError::TileFormat {
+                context: format!("Failed encoding vector tile {}", fname.display()),
+                source: Box::new(source),
+            })?;
+        utils::write_bytes(&fname, &bytes)?;
+    }
+    Ok(layers.len())
+}
+
+/// The edges of one vector tile, with the keys and values of their tags interned
+#[derive(Default)]
+struct Layer {
+    keys: Vec<&'static str>,
+    key_indices: HashMap<&'static str, u32>,
+    /// Encoded `Value` messages
+    values: Vec<Vec<u8>>,
+    value_indices: HashMap<Vec<u8>, u32>,
+    /// Encoded `Feature` messages
+    features: Vec<Vec<u8>>,
+}
+impl Layer {
+    fn add(&mut self, edge: &Edge, geometry: Vec<u32>) {
+        let mut tags = Vec::new();
+        for (key, value) in properties(edge) {
+            tags.push(self.key_index(key));
+            tags.push(self.value_index(value));
+        }
+        // Encoding into a Vec can't fail
+        if let Ok(feature) = tile_proto::encode(|output| {
+            output.write_repeated_packed_uint32(2, &tags)?;
+            output.write_enum(3, LINESTRING)?;
+            output.write_repeated_packed_uint32(4, &geometry)
+        }) {
+            self.features.push(feature);
+        }
+    }
+
+    fn key_index(&mut self, key: &'static str) -> u32 {
+        *self.key_indices.entry(key).or_insert_with(|| {
+            self.keys.push(key);
+            self.keys.len() as u32 - 1
+        })
+    }
+
+    fn value_index(&mut self, value: Value) -> u32 {
+        let Ok(encoded) = value.encode() else {
+            return 0;
+        };
+        *self
+            .value_indices
+            .entry(encoded.clone())
+            .or_insert_with(|| {
+                self.values.push(encoded);
+                self.values.len() as u32 - 1
+            })
+    }
+
+    /// Encodes a `Tile` message holding just this layer
+    fn encode(&self) -> protobuf::Result<Vec<u8>> {
+        let layer = tile_proto::encode(|output: &mut CodedOutputStream| {
+            output.write_uint32(15, MVT_VERSION)?;
+            output.write_string(1, LAYER_NAME)?;
+            for feature in &self.features {
+                output.write_bytes(2, feature)?;
+            }
+            for key in &self.keys {
+                output.write_string(3, key)?;
+            }
+            for value in &self.values {
+                output.write_bytes(4, value)?;
+            }
+            output.write_uint32(5, EXTENT)
+        })?;
+        tile_proto::encode(|output| output.write_bytes(3, &layer))
+    }
+}
+
+/// A tag value, vector tiles can't hold lists or missing values
+enum Value {
+    String(String),
+    Uint(u64),
+    Float(f32),
+    Bool(bool),
+}
+impl Value {
+    fn encode(&self) -> protobuf::Result<Vec<u8>> {
+        tile_proto::encode(|output| match self {
+            Value::String(value) => output.write_string(1, value),
+            Value::Float(value) => output.write_float(2, *value),
+            Value::Uint(value) => output.write_uint64(5, *value),
+            Value::Bool(value) => output.write_bool(7, *value),
+        })
+    }
+}
+
+/// The tags of an edge, leaving out those without a value
+///
+/// The first road number is also given as separate shield fields, for styling
+/// the shield by its network.
+fn properties(edge: &Edge) -> Vec<(&'static str, Value)> {
+    let mut properties = vec![
+        ("way_id", Value::Uint(edge.way_id.0 as u64)),
+        ("from", Value::Uint(edge.from.0 as u64)),
+        ("to", Value::Uint(edge.to.0 as u64)),
+        ("class", Value::String(value_name(edge.class))),
+        ("oneway", Value::Bool(edge.is_oneway)),
+        ("is_reversed", Value::Bool(edge.is_reversed)),
+        ("speed_kmh", Value::Uint(edge.speed_kmh.into())),
+        ("length_m", Value::Float(edge.length_m)),
+        ("duration_s", Value::Float(edge.duration_s)),
+    ];
+    let access = Profile::value_variants()
+        .iter()
+        .filter(|profile| edge.access.contains(**profile))
+        .map(|profile| value_name(*profile))
+        .collect::<Vec<_>>();
+    properties.push(("access", Value::String(access.join(","))));
+    if let Some(name) = &edge.name {
+        properties.push(("name", Value::String(name.clone())));
+    }
+    if let Some(road_ref) = &edge.road_ref {
+        properties.push(("ref", Value::String(road_ref.clone())));
+    }
+    if let Some(shield) = edge.refs.first() {
+        if let Some(prefix) = &shield.prefix {
+            properties.push(("shield_prefix", Value::String(prefix.clone())));
+        }
+        properties.push(("shield_number", Value::String(shield.number.clone())));
+        properties.push((
+            "shield_is_international",
+            Value::Bool(shield.is_international),
+        ));
+    }
+    properties
+}
+
+/// Web Mercator position of a coordinate in units of tiles at a zoom level
+fn world_position(lat: f64, lon: f64, zoom: u8) -> (f64, f64) {
+    let n = 2.0f64.powi(zoom as i32);
+    let lat_rad = lat.clamp(-85.05112878, 85.05112878).to_radians();
+    (
+        (lon + 180.0) / 360.0 * n,
+        (1.0 - (lat_rad.tan() + 1.0 / lat_rad.cos()).ln() / PI) / 2.0 * n,
+    )
+}
+
+/// The tiles overlapped by the bounding box of a line
+fn touched_tiles(points: &[(f64, f64)], zoom: u8) -> Vec<TileCoord> {
+    let Some(&(x, y)) = points.first() else {
+        return Vec::new();
+    };
+    let (mut min, mut max) = ((x, y), (x, y));
+    for &(x, y) in points {
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+    }
+    let max_tile = (1u32 << zoom) - 1;
+    let tile = |value: f64| (value.floor().max(0.0) as u32).min(max_tile);
+    let mut tiles = Vec::new();
+    for x in tile(min.0)..=tile(max.0) {
+        for y in tile(min.1)..=tile(max.1) {
+            tiles.push(TileCoord { x, y, zoom });
+        }
+    }
+    tiles
+}
+
+/// Encodes a line as the drawing commands of a feature in the tile at `origin`
+///
+/// Points are relative to the tile and may lie outside of it, renderers clip
+/// them. Points rounding to the same position are dropped, `None` if fewer than
+/// two remain.
+fn encode_geometry(points: &[(f64, f64)], origin: (f64, f64)) -> Option<Vec<u32>> {
+    let mut positions: Vec<(i64, i64)> = Vec::with_capacity(points.len());
+    for &(x, y) in points {
+        let position = (
+            ((x - origin.0) * EXTENT as f64).round() as i64,
+            ((y - origin.1) * EXTENT as f64).round() as i64,
+        );
+        if positions.last() != Some(&position) {
+            positions.push(position);
+        }
+    }
+    if positions.len() < 2 {
+        return None;
+    }
+    let zigzag = |value: i64| ((value << 1) ^ (value >> 63)) as u32;
+    let mut commands = Vec::with_capacity(positions.len() * 2 + 2);
+    let mut cursor = (0, 0);
+    for (index, position) in positions.iter().enumerate() {
+        match index {
+            0 => commands.push(MOVE_TO | (1 << 3)),
+            1 => commands.push(LINE_TO | ((positions.len() as u32 - 1) << 3)),
+            _ => {}
+        }
+        commands.push(zigzag(position.0 - cursor.0));
+        commands.push(zigzag(position.1 - cursor.1));
+        cursor = *position;
+    }
+    Some(commands)
+}
@@ -0,0 +1,74 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{IoContext, Result},
+    utils::{self, Quadkey, Tile, TileNaming},
+};
+
+/// The names and refs in a tile with the edges carrying them, for geocoding
+///
+/// Written next to each tile as `<quadkey>.names`, so names can be searched
+/// without decoding the edges.
+#[derive(Debug, Default, bincode::Encode, bincode::Decode)]
+pub(crate) struct NameIndex {
+    /// Normalized names sorted for prefix search, with indices into the edges of the tile
+    pub(crate) entries: Vec<(String, Vec<u32>)>,
+}
+impl NameIndex {
+    pub(crate) fn from_tile(tile: &Tile) -> Self {
+        let mut entries: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for (index, edge) in tile.edges.iter().enumerate() {
+            for name in [&edge.name, &edge.road_ref].into_iter().flatten() {
+                let key = normalize(name);
+                if !key.is_empty() {
+                    entries.entry(key).or_default().push(index as u32);
+                }
+            }
+        }
+        Self {
+            entries: entries.into_iter().collect(),
+        }
+    }
+}
+
+/// Lowercases a name and collapses whitespace, so lookups ignore case and spacing
+pub(crate) fn normalize(name: &str) -> String {
+    name.split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Path of the name index for a quadkey in a tile directory
+pub(crate) fn name_index_path(tiles_dir: &Path, quadkey: &Quadkey, naming: TileNaming) -> PathBuf {
+    utils::tile_path(tiles_dir, quadkey, naming).with_extension("names")
+}
+
+/// Writes the name index of a tile next to it
+pub(crate) fn write_name_index(
+    tiles_dir: &Path,
+    quadkey: &Quadkey,
+    naming: TileNaming,
+    tile: &Tile,
+) -> Result<()> {
+    let fname = name_index_path(tiles_dir, quadkey, naming);
+    utils::write_encoded(&fname, &NameIndex::from_tile(tile))?;
+    Ok(())
+}
+
+/// Removes the name index of a tile that no longer exists
+pub(crate) fn remove_name_index(
+    tiles_dir: &Path,
+    quadkey: &Quadkey,
+    naming: TileNaming,
+) -> Result<()> {
+    let fname = name_index_path(tiles_dir, quadkey, naming);
+    if fname.exists() {
+        std::fs::remove_file(&fname)
+            .io_context(|| format!("Failed removing file {}", fname.display()))?;
+    }
+    Ok(())
+}
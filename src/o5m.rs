@@ -0,0 +1,533 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use crate::{
+    error::{GladsheimError, IoContext, Result},
+    osm_parser::{MemberType, OwnedElement, OwnedNode, OwnedRelation, OwnedWay},
+};
+
+// https://wiki.openstreetmap.org/wiki/O5m
+const DATASET_NODE: u8 = 0x10;
+const DATASET_WAY: u8 = 0x11;
+const DATASET_RELATION: u8 = 0x12;
+const DATASET_FILE_TIMESTAMP: u8 = 0xdc;
+const DATASET_END_OF_FILE: u8 = 0xfe;
+const DATASET_RESET: u8 = 0xff;
+/// Datasets with a type at or above this carry no length field
+const FIRST_DATASET_WITHOUT_LENGTH: u8 = 0xf0;
+/// Number of string entries the o5m writer may reference back to
+const STRING_TABLE_SIZE: usize = 15_000;
+/// Longer strings are never put in the table
+const MAX_TABLE_STRING_LEN: usize = 250;
+/// Coordinates are stored in units of 100 nanodegrees
+const COORDINATE_SCALE: f64 = 1e-7;
+
+/// Streams the elements of an o5m file in batches
+///
+/// o5m is delta-coded throughout so it has to be decoded sequentially, elements
+/// are handed out in batches that the caller can process on multiple threads.
+pub(crate) fn for_each_batch(
+    fname: &Path,
+    batch_size: usize,
+    mut handle_batch: impl FnMut(Vec<OwnedElement>) -> Result<()>,
+) -> Result<()> {
+    let file = File::open(fname).io_context(|| format!("Failed loading {}", fname.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut decoder = Decoder::default();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut payload = Vec::new();
+
+    loop {
+        let mut dataset_type = [0u8; 1];
+        if reader
+            .read(&mut dataset_type)
+            .io_context(|| format!("Failed reading {}", fname.display()))?
+            == 0
+        {
+            break;
+        }
+        let dataset_type = dataset_type[0];
+        match dataset_type {
+            DATASET_END_OF_FILE => break,
+            DATASET_RESET => {
+                decoder = Decoder::default();
+                continue;
+            }
+            dataset_type if dataset_type >= FIRST_DATASET_WITHOUT_LENGTH => continue,
+            _ => {}
+        }
+
+        let length = read_varint_from(&mut reader)
+            .io_context(|| format!("Truncated o5m dataset in {}", fname.display()))?;
+        payload.resize(length as usize, 0);
+        reader
+            .read_exact(&mut payload)
+            .io_context(|| format!("Truncated o5m dataset in {}", fname.display()))?;
+
+        let mut cursor = Cursor {
+            data: &payload,
+            position: 0,
+        };
+        let element = match dataset_type {
+            DATASET_NODE => decoder.node(&mut cursor)?,
+            DATASET_WAY => decoder.way(&mut cursor)?,
+            DATASET_RELATION => decoder.relation(&mut cursor)?,
+            // Header, bounding box, timestamp and other metadata datasets
+            _ => None,
+        };
+        if let Some(element) = element {
+            batch.push(element);
+            if batch.len() >= batch_size {
+                handle_batch(std::mem::replace(
+                    &mut batch,
+                    Vec::with_capacity(batch_size),
+                ))?;
+            }
+        }
+    }
+    if !batch.is_empty() {
+        handle_batch(batch)?;
+    }
+    Ok(())
+}
+
+/// The time the data of an o5m file is from, in seconds since 1970-01-01
+///
+/// Taken from the file timestamp dataset, which comes before the first element.
+pub(crate) fn source_timestamp(fname: &Path) -> Result<Option<i64>> {
+    let file = File::open(fname).io_context(|| format!("Failed loading {}", fname.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut payload = Vec::new();
+    loop {
+        let mut dataset_type = [0u8; 1];
+        if reader
+            .read(&mut dataset_type)
+            .io_context(|| format!("Failed reading {}", fname.display()))?
+            == 0
+        {
+            return Ok(None);
+        }
+        match dataset_type[0] {
+            DATASET_NODE | DATASET_WAY | DATASET_RELATION | DATASET_END_OF_FILE => {
+                return Ok(None);
+            }
+            dataset_type if dataset_type >= FIRST_DATASET_WITHOUT_LENGTH => continue,
+            _ => {}
+        }
+        let length = read_varint_from(&mut reader)
+            .io_context(|| format!("Truncated o5m dataset in {}", fname.display()))?;
+        payload.resize(length as usize, 0);
+        reader
+            .read_exact(&mut payload)
+            .io_context(|| format!("Truncated o5m dataset in {}", fname.display()))?;
+        if dataset_type[0] == DATASET_FILE_TIMESTAMP {
+            let mut cursor = Cursor {
+                data: &payload,
+                position: 0,
+            };
+            return Ok(Some(cursor.signed()?));
+        }
+    }
+}
+
+/// Delta coding and string table state, cleared by every reset dataset
+#[derive(Default)]
+struct Decoder {
+    id: i64,
+    timestamp: i64,
+    changeset: i64,
+    lon: i64,
+    lat: i64,
+    way_ref: i64,
+    /// Separate deltas for node, way and relation members
+    member_refs: [i64; 3],
+    strings: VecDeque<Vec<u8>>,
+}
+
+impl Decoder {
+    fn node(&mut self, cursor: &mut Cursor) -> Result<Option<OwnedElement>> {
+        self.id += cursor.signed()?;
+        let id = self.id;
+        self.author_info(cursor)?;
+        if cursor.is_empty() {
+            // A deleted node, only used in change files
+            return Ok(None);
+        }
+        self.lon += cursor.signed()?;
+        self.lat += cursor.signed()?;
+        let tags = self.tags(cursor)?;
+        Ok(Some(OwnedElement::Node(OwnedNode {
+            id,
+            lat: self.lat as f64 * COORDINATE_SCALE,
+            lon: self.lon as f64 * COORDINATE_SCALE,
+            tags,
+        })))
+    }
+
+    fn way(&mut self, cursor: &mut Cursor) -> Result<Option<OwnedElement>> {
+        self.id += cursor.signed()?;
+        let id = self.id;
+        self.author_info(cursor)?;
+        if cursor.is_empty() {
+            return Ok(None);
+        }
+        let refs_len = cursor.unsigned()? as usize;
+        let refs_end = cursor.position + refs_len;
+        let mut refs = Vec::new();
+        while cursor.position < refs_end {
+            self.way_ref += cursor.signed()?;
+            refs.push(self.way_ref);
+        }
+        let tags = self.tags(cursor)?;
+        Ok(Some(OwnedElement::Way(OwnedWay { id, refs, tags })))
+    }
+
+    fn relation(&mut self, cursor: &mut Cursor) -> Result<Option<OwnedElement>> {
+        self.id += cursor.signed()?;
+        let id = self.id;
+        self.author_info(cursor)?;
+        if cursor.is_empty() {
+            return Ok(None);
+        }
+        let mut members = Vec::new();
+        let members_len = cursor.unsigned()? as usize;
+        let members_end = cursor.position + members_len;
+        while cursor.position < members_end {
+            let delta = cursor.signed()?;
+            let type_and_role = self.string(cursor, false)?;
+            let (member_type, index) = match type_and_role.first() {
+                Some(b'0') => (MemberType::Node, 0),
+                Some(b'1') => (MemberType::Way, 1),
+                Some(b'2') => (MemberType::Relation, 2),
+                _ => return Err(GladsheimError::parse("Invalid o5m relation member type")),
+            };
+            self.member_refs[index] += delta;
+            let role = String::from_utf8_lossy(&type_and_role[1..]).into_owned();
+            members.push((member_type, self.member_refs[index], role));
+        }
+        let tags = self.tags(cursor)?;
+        Ok(Some(OwnedElement::Relation(OwnedRelation {
+            id,
+            members,
+            tags,
+        })))
+    }
+
+    fn author_info(&mut self, cursor: &mut Cursor) -> Result<()> {
+        let version = cursor.unsigned()?;
+        if version != 0 {
+            self.timestamp += cursor.signed()?;
+            if self.timestamp != 0 {
+                self.changeset += cursor.signed()?;
+                // The uid and user name pair, not used
+                self.string(cursor, true)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn tags(&mut self, cursor: &mut Cursor) -> Result<Vec<(String, String)>> {
+        let mut tags = Vec::new();
+        while !cursor.is_empty() {
+            let pair = self.string(cursor, true)?;
+            let mut parts = pair.splitn(2, |byte| *byte == 0);
+            let key = String::from_utf8_lossy(parts.next().unwrap_or_default()).into_owned();
+            let value = String::from_utf8_lossy(parts.next().unwrap_or_default()).into_owned();
+            tags.push((key, value));
+        }
+        Ok(tags)
+    }
+
+    /// Reads a string, or a pair separated by a zero byte, either inline or from the table
+    fn string(&mut self, cursor: &mut Cursor, is_pair: bool) -> Result<Vec<u8>> {
+        let reference = cursor.unsigned()? as usize;
+        if reference != 0 {
+            return match self.strings.get(reference - 1) {
+                Some(string) => Ok(string.clone()),
+                None => Err(GladsheimError::parse(format!(
+                    "Invalid o5m string reference {}",
+                    reference
+                ))),
+            };
+        }
+        let first = cursor.zero_terminated()?;
+        let mut string = first.to_vec();
+        let mut text_len = first.len();
+        if is_pair {
+            let second = cursor.zero_terminated()?;
+            text_len += second.len();
+            string.push(0);
+            string.extend_from_slice(second);
+        }
+        if text_len <= MAX_TABLE_STRING_LEN {
+            self.strings.push_front(string.clone());
+            self.strings.truncate(STRING_TABLE_SIZE);
+        }
+        Ok(string)
+    }
+}
+
+/// Reading position within the payload of a single dataset
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn unsigned(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let Some(byte) = self.data.get(self.position) else {
+                return Err(GladsheimError::parse("Truncated o5m varint"));
+            };
+            self.position += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift > 63 {
+                return Err(GladsheimError::parse("Invalid o5m varint"));
+            }
+        }
+    }
+
+    /// The sign is stored in the lowest bit
+    fn signed(&mut self) -> Result<i64> {
+        let value = self.unsigned()?;
+        Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+    }
+
+    fn zero_terminated(&mut self) -> Result<&'a [u8]> {
+        let rest = &self.data[self.position.min(self.data.len())..];
+        let Some(len) = rest.iter().position(|byte| *byte == 0) else {
+            return Err(GladsheimError::parse("Unterminated o5m string"));
+        };
+        self.position += len + 1;
+        Ok(&rest[..len])
+    }
+}
+
+fn read_varint_from(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift > 63 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid o5m varint",
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_unsigned(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn push_signed(out: &mut Vec<u8>, value: i64) {
+        push_unsigned(out, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn push_pair(out: &mut Vec<u8>, key: &str, value: &str) {
+        out.push(0);
+        out.extend_from_slice(key.as_bytes());
+        out.push(0);
+        out.extend_from_slice(value.as_bytes());
+        out.push(0);
+    }
+
+    fn push_dataset(out: &mut Vec<u8>, dataset_type: u8, payload: &[u8]) {
+        out.push(dataset_type);
+        push_unsigned(out, payload.len() as u64);
+        out.extend_from_slice(payload);
+    }
+
+    /// Two tagged nodes, a way and a relation, then a reset and a node coded from zero again
+    fn sample_file() -> Vec<u8> {
+        let mut file = vec![DATASET_RESET];
+        push_dataset(&mut file, 0xe0, b"o5m2");
+
+        let mut node = Vec::new();
+        push_signed(&mut node, 100);
+        push_unsigned(&mut node, 0);
+        push_signed(&mut node, 180_600_000);
+        push_signed(&mut node, 593_300_000);
+        push_pair(&mut node, "highway", "traffic_signals");
+        push_dataset(&mut file, DATASET_NODE, &node);
+
+        // Deltas from the node before, with its tag taken from the string table
+        let mut node = Vec::new();
+        push_signed(&mut node, 1);
+        push_unsigned(&mut node, 0);
+        push_signed(&mut node, -15_000);
+        push_signed(&mut node, 20_000);
+        push_unsigned(&mut node, 1);
+        push_dataset(&mut file, DATASET_NODE, &node);
+
+        let mut way = Vec::new();
+        push_signed(&mut way, 5 - 101);
+        push_unsigned(&mut way, 0);
+        let mut refs = Vec::new();
+        push_signed(&mut refs, 100);
+        push_signed(&mut refs, 1);
+        push_unsigned(&mut way, refs.len() as u64);
+        way.extend_from_slice(&refs);
+        push_pair(&mut way, "highway", "residential");
+        push_dataset(&mut file, DATASET_WAY, &way);
+
+        let mut relation = Vec::new();
+        push_signed(&mut relation, 7 - 5);
+        push_unsigned(&mut relation, 0);
+        let mut members = Vec::new();
+        push_signed(&mut members, 5);
+        members.push(0);
+        members.extend_from_slice(b"1outer\0");
+        push_unsigned(&mut relation, members.len() as u64);
+        relation.extend_from_slice(&members);
+        push_pair(&mut relation, "type", "multipolygon");
+        push_dataset(&mut file, DATASET_RELATION, &relation);
+
+        file.push(DATASET_RESET);
+        let mut node = Vec::new();
+        push_signed(&mut node, 42);
+        push_unsigned(&mut node, 0);
+        push_signed(&mut node, 10);
+        push_signed(&mut node, -10);
+        push_dataset(&mut file, DATASET_NODE, &node);
+        file.push(DATASET_END_OF_FILE);
+        file
+    }
+
+    fn write_temp(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let fname =
+            std::env::temp_dir().join(format!("gladsheim-o5m-{}-{}.o5m", name, std::process::id()));
+        std::fs::write(&fname, bytes).unwrap();
+        fname
+    }
+
+    fn read_all(fname: &Path, batch_size: usize) -> Result<Vec<OwnedElement>> {
+        let mut elements = Vec::new();
+        for_each_batch(fname, batch_size, |batch| {
+            assert!(batch.len() <= batch_size);
+            elements.extend(batch);
+            Ok(())
+        })?;
+        Ok(elements)
+    }
+
+    #[test]
+    fn signed_varints_keep_the_sign_in_the_lowest_bit() {
+        let data = [0x00, 0x01, 0x02, 0x03, 0xac, 0x02];
+        let mut cursor = Cursor {
+            data: &data,
+            position: 0,
+        };
+        let values: Vec<i64> = (0..5).map(|_| cursor.signed().unwrap()).collect();
+        assert_eq!(values, [0, -1, 1, -2, 150]);
+        assert!(cursor.is_empty());
+        assert!(cursor.signed().is_err());
+    }
+
+    #[test]
+    fn decodes_delta_coded_elements() {
+        let fname = write_temp("elements", &sample_file());
+        let elements = read_all(&fname, 2).unwrap();
+        std::fs::remove_file(&fname).unwrap();
+
+        assert_eq!(elements.len(), 5);
+        let OwnedElement::Node(first) = &elements[0] else {
+            panic!("expected a node, got {:?}", elements[0]);
+        };
+        assert_eq!(first.id, 100);
+        assert!((first.lat - 59.33).abs() < 1e-9);
+        assert!((first.lon - 18.06).abs() < 1e-9);
+        let signals = vec![("highway".to_string(), "traffic_signals".to_string())];
+        assert_eq!(first.tags, signals);
+
+        let OwnedElement::Node(second) = &elements[1] else {
+            panic!("expected a node, got {:?}", elements[1]);
+        };
+        assert_eq!(second.id, 101);
+        assert!((second.lat - 59.332).abs() < 1e-9);
+        assert!((second.lon - 18.0585).abs() < 1e-9);
+        assert_eq!(second.tags, signals);
+
+        let OwnedElement::Way(way) = &elements[2] else {
+            panic!("expected a way, got {:?}", elements[2]);
+        };
+        assert_eq!(way.id, 5);
+        assert_eq!(way.refs, [100, 101]);
+        assert_eq!(
+            way.tags,
+            [("highway".to_string(), "residential".to_string())]
+        );
+
+        let OwnedElement::Relation(relation) = &elements[3] else {
+            panic!("expected a relation, got {:?}", elements[3]);
+        };
+        assert_eq!(relation.id, 7);
+        assert_eq!(
+            relation.members,
+            [(MemberType::Way, 5, "outer".to_string())]
+        );
+
+        let OwnedElement::Node(after_reset) = &elements[4] else {
+            panic!("expected a node, got {:?}", elements[4]);
+        };
+        assert_eq!(after_reset.id, 42);
+        assert!((after_reset.lat + 1e-6).abs() < 1e-12);
+        assert!(after_reset.tags.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_datasets() {
+        let mut file = sample_file();
+        // Cut the file inside the payload of the last node
+        file.truncate(file.len() - 3);
+        let fname = write_temp("truncated", &file);
+        let result = read_all(&fname, 100);
+        std::fs::remove_file(&fname).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rejects_dangling_string_references() {
+        let mut file = vec![DATASET_RESET];
+        let mut node = Vec::new();
+        push_signed(&mut node, 1);
+        push_unsigned(&mut node, 0);
+        push_signed(&mut node, 0);
+        push_signed(&mut node, 0);
+        push_unsigned(&mut node, 3);
+        push_dataset(&mut file, DATASET_NODE, &node);
+        let fname = write_temp("dangling", &file);
+        let result = read_all(&fname, 100);
+        std::fs::remove_file(&fname).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -1,25 +1,194 @@
 use std::{
     collections::{HashMap, HashSet},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use anyhow::{Context, Result};
-use osmpbf::{Element, ElementReader};
+use osmpbf::{BlobDecode, BlobReader, Element, PrimitiveBlock};
 use rayon::prelude::*;
 
-use crate::{utils, NodeId, Way, WayId};
-use utils::Quadkey;
+use crate::{
+    Edge, NodeId, RoadClass, RoadRef, ServiceClass, Sidewalk, Smoothness, TrackType, Way, WayId,
+    address::{self, Address, AddressWay},
+    admin::AdminAreas,
+    bloom::NodeFilter,
+    boundary,
+    cancel::CancellationToken,
+    components::{self, Islands},
+    contract, dimacs, emissions,
+    error::{GladsheimError, IoContext, ParseContext, Result},
+    flat_tile, geometry, manifest, names, o5m, osm_xml,
+    overview::{self, OverviewLevel},
+    poi::{self, Poi},
+    profile::{Profile, ProfileMask},
+    region::Region,
+    restrictions::{self, TurnRestriction, Via},
+    streets,
+    tile_format::{Compression, TileHeader},
+    tile_proto,
+    transit::{self, TransitLine},
+    turns, utils,
+};
+use utils::{Quadkey, Tile, TileNaming, TileStorage};
 
-#[derive(Clone, Debug, Default, bincode::Encode, bincode::Decode)]
-struct Loc {
-    //nano_lat: i64,
-    //nano_lon: i64,
-    lat: f64,
-    lon: f64,
+/// Zoom level of the quadkeys that edges are tiled by, unless chosen otherwise
+pub(crate) const DEFAULT_TILE_ZOOM: u8 = 7;
+
+/// Number of elements of sequentially decoded formats handed to the worker threads at a time
+const SEQUENTIAL_BATCH_SIZE: usize = 100_000;
+
+/// A coordinate in degrees, stored in tiles as fixed-point, see `tile_format`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Loc {
+    pub(crate) lat: f64,
+    pub(crate) lon: f64,
 }
 #[derive(Clone, Debug, Default, bincode::Encode, bincode::Decode)]
-struct Node {
-    loc: Loc,
+pub(crate) struct Node {
+    pub(crate) loc: Loc,
+    /// Tagged `highway=crossing`, a place where pedestrians cross the road
+    pub(crate) is_crossing: bool,
+    /// Tagged `railway=level_crossing`, where the road crosses a railway
+    pub(crate) level_crossing: Option<LevelCrossing>,
+    /// Tagged `highway=traffic_signals` or `highway=stop`, where vehicles usually stop
+    pub(crate) is_stop: bool,
+    /// Elevation in meters from the `ele` tag
+    pub(crate) ele: Option<f32>,
+}
+
+/// A railway level crossing, by whether it's protected by barriers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) enum LevelCrossing {
+    Open,
+    /// `crossing:barrier=*`, barriers close well before the train passes
+    Barrier,
+}
+
+/// Default waiting time in seconds at a level crossing without barriers
+pub(crate) const DEFAULT_LEVEL_CROSSING_DELAY_S: u16 = 10;
+/// Default waiting time in seconds at a level crossing with barriers
+pub(crate) const DEFAULT_BARRIER_CROSSING_DELAY_S: u16 = 30;
+
+/// Default number of nodes a strongly connected component needs to not be an island
+pub(crate) const DEFAULT_MIN_COMPONENT_SIZE: usize = 20;
+
+/// What to do with ways referencing nodes that are missing, e.g. outside a clipped extract
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum MissingNodes {
+    /// Cut the way into the runs of known nodes
+    #[default]
+    Truncate,
+    /// Leave out the whole way
+    Drop,
+    /// Leave out only the missing nodes, joining the known nodes on either side
+    Skip,
+}
+
+/// Optional outputs and behaviours of the parsing pipeline
+#[derive(Debug)]
+pub(crate) struct ParseOptions {
+    /// Also export the graph in DIMACS format to `<prefix>.gr` and `<prefix>.co`
+    pub(crate) dimacs_prefix: Option<PathBuf>,
+    /// The mode of transport to build the routing graph for
+    pub(crate) profile: Profile,
+    /// Further modes of transport marked in the access masks of the same edges
+    ///
+    /// Their ways are part of the graph too, while speeds and other single valued
+    /// attributes of the edges follow `profile`.
+    pub(crate) extra_profiles: Vec<Profile>,
+    /// Store edge geometry resampled at this interval in meters
+    pub(crate) resample_meters: Option<f64>,
+    /// Store curvature and advisory safe speed on edges
+    pub(crate) curvature: bool,
+    /// Store the shape of edges as polyline6
+    pub(crate) polylines: bool,
+    /// Only keep the parts of ways inside this region
+    pub(crate) region: Option<Region>,
+    /// Seconds added to edges for every level crossing without barriers
+    pub(crate) level_crossing_delay_s: u16,
+    /// Seconds added to edges for every level crossing with barriers
+    pub(crate) barrier_crossing_delay_s: u16,
+    /// Store gradient, stop density and estimated CO2 on edges
+    pub(crate) emissions: bool,
+    /// Boundaries to stamp edges with their country and region
+    pub(crate) admin_areas: Option<AdminAreas>,
+    /// Also extract amenity, shop and tourism nodes into per-tile POI files
+    pub(crate) pois: bool,
+    /// Also extract addresses, with interpolated house numbers, into per-tile files
+    pub(crate) addresses: bool,
+    /// Store a Bloom filter of the node ids in each tile
+    pub(crate) node_filter: bool,
+    /// How the tile files are named in the output directory
+    pub(crate) naming: TileNaming,
+    /// How the tile files are compressed
+    pub(crate) compression: Compression,
+    /// Zoom level of the quadkeys that edges, points of interest and addresses are tiled by
+    pub(crate) tile_zoom: u8,
+    /// Coarser levels of tiles with only the more important roads, each written into
+    /// a directory of its own
+    pub(crate) overviews: Vec<OverviewLevel>,
+    /// Write the tiles into a single archive or database instead of a file per tile
+    pub(crate) storage: TileStorage,
+    /// Also write every tile in the memory-mappable layout of `flat_tile`
+    pub(crate) flat: bool,
+    /// Also write every tile as protobuf, see `tile_proto`
+    pub(crate) protobuf: bool,
+    /// What to do with ways referencing nodes missing from the data
+    pub(crate) missing_nodes: MissingNodes,
+    /// Store two-way roads as an edge in each direction
+    pub(crate) reverse_edges: bool,
+    /// Merge chains of edges through nodes where only two edges meet
+    pub(crate) contract_chains: bool,
+    /// Also write the edge-based graph of `profile` into per-tile turn files
+    pub(crate) edge_based: bool,
+    /// What to do with edges of small strongly connected components
+    pub(crate) islands: Islands,
+    /// Components of fewer nodes than this are islands
+    pub(crate) min_component_size: usize,
+}
+impl ParseOptions {
+    /// The profile and the extra profiles
+    pub(crate) fn profiles(&self) -> impl Iterator<Item = Profile> + '_ {
+        std::iter::once(self.profile).chain(self.extra_profiles.iter().copied())
+    }
+
+    /// Whether crossing nodes are kept as graph nodes for any of the profiles
+    pub(crate) fn splits_at_crossings(&self) -> bool {
+        self.profiles().any(Profile::splits_at_crossings)
+    }
+}
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            dimacs_prefix: None,
+            profile: Profile::default(),
+            extra_profiles: Vec::new(),
+            resample_meters: None,
+            curvature: false,
+            polylines: false,
+            region: None,
+            level_crossing_delay_s: DEFAULT_LEVEL_CROSSING_DELAY_S,
+            barrier_crossing_delay_s: DEFAULT_BARRIER_CROSSING_DELAY_S,
+            emissions: false,
+            admin_areas: None,
+            pois: false,
+            addresses: false,
+            node_filter: false,
+            naming: TileNaming::default(),
+            compression: Compression::default(),
+            tile_zoom: DEFAULT_TILE_ZOOM,
+            overviews: Vec::new(),
+            storage: TileStorage::Files,
+            flat: false,
+            protobuf: false,
+            missing_nodes: MissingNodes::default(),
+            reverse_edges: false,
+            contract_chains: false,
+            edge_based: false,
+            islands: Islands::default(),
+            min_component_size: DEFAULT_MIN_COMPONENT_SIZE,
+        }
+    }
 }
 
 /// Statistics from parsing the OSM data
@@ -44,18 +213,28 @@ impl StatsParsing {
 struct Map {
     ways: Vec<Way>,
     nodes: Vec<(NodeId, Node)>,
+    transit_lines: Vec<TransitLine>,
+    pois: Vec<Poi>,
+    restrictions: Vec<TurnRestriction>,
+    address_nodes: Vec<(NodeId, Address)>,
+    address_ways: Vec<AddressWay>,
 }
 impl Map {
     fn merge(mut self, other: Self) -> Self {
         self.ways.extend(other.ways);
         self.nodes.extend(other.nodes);
+        self.transit_lines.extend(other.transit_lines);
+        self.pois.extend(other.pois);
+        self.restrictions.extend(other.restrictions);
+        self.address_nodes.extend(other.address_nodes);
+        self.address_ways.extend(other.address_ways);
         self
     }
 }
 
 /// Results from parsing the OSM data
 #[derive(Debug, Default)]
-struct PbfReaderResult {
+pub(crate) struct PbfReaderResult {
     stats: StatsParsing,
     map: Map,
 }
@@ -65,15 +244,60 @@ impl PbfReaderResult {
         self.map = self.map.merge(other.map);
         self
     }
+    pub(crate) fn into_ways(self) -> Vec<Way> {
+        self.map.ways
+    }
+    pub(crate) fn into_nodes(self) -> Vec<(NodeId, Node)> {
+        self.map.nodes
+    }
+}
+
+/// A node decoded into owned memory, by the readers of sequentially decoded formats
+#[derive(Debug, Default)]
+pub(crate) struct OwnedNode {
+    pub(crate) id: i64,
+    pub(crate) lat: f64,
+    pub(crate) lon: f64,
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+/// A way decoded into owned memory, by the readers of sequentially decoded formats
+#[derive(Debug, Default)]
+pub(crate) struct OwnedWay {
+    pub(crate) id: i64,
+    pub(crate) refs: Vec<i64>,
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+/// The kind of element that a relation member refers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MemberType {
+    Node,
+    Way,
+    Relation,
+}
+
+/// A relation decoded into owned memory, by the readers of sequentially decoded formats
+#[derive(Debug, Default)]
+pub(crate) struct OwnedRelation {
+    pub(crate) id: i64,
+    pub(crate) members: Vec<(MemberType, i64, String)>,
+    pub(crate) tags: Vec<(String, String)>,
 }
 
-/// A simple trait to abstract away the two OSM node implementations
-trait SimpleNode {
+#[derive(Debug)]
+pub(crate) enum OwnedElement {
+    Node(OwnedNode),
+    Way(OwnedWay),
+    Relation(OwnedRelation),
+}
+
+/// A simple trait to abstract away the OSM node implementations of the input formats
+pub(crate) trait SimpleNode {
     fn lat(&self) -> f64;
     fn lon(&self) -> f64;
-    fn nano_lat(&self) -> i64;
-    fn nano_lon(&self) -> i64;
     fn id(&self) -> i64;
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)>;
 }
 impl SimpleNode for osmpbf::dense::DenseNode<'_> {
     fn lat(&self) -> f64 {
@@ -82,14 +306,27 @@ impl SimpleNode for osmpbf::dense::DenseNode<'_> {
     fn lon(&self) -> f64 {
         self.lon()
     }
-    fn nano_lat(&self) -> i64 {
-        self.nano_lat()
+    fn id(&self) -> i64 {
+        self.id()
+    }
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags()
+    }
+}
+impl SimpleNode for OwnedNode {
+    fn lat(&self) -> f64 {
+        self.lat
     }
-    fn nano_lon(&self) -> i64 {
-        self.nano_lon()
+    fn lon(&self) -> f64 {
+        self.lon
     }
     fn id(&self) -> i64 {
-        self.id()
+        self.id
+    }
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
     }
 }
 impl SimpleNode for osmpbf::elements::Node<'_> {
@@ -97,38 +334,396 @@ impl SimpleNode for osmpbf::elements::Node<'_> {
         self.lat()
     }
     fn lon(&self) -> f64 {
-        self.lat()
+        self.lon()
     }
-    fn nano_lat(&self) -> i64 {
-        self.nano_lat()
+    fn id(&self) -> i64 {
+        self.id()
     }
-    fn nano_lon(&self) -> i64 {
-        self.nano_lat()
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags()
     }
+}
+
+/// A simple trait to abstract away the OSM way implementations of the input formats
+pub(crate) trait SimpleWay {
+    fn id(&self) -> i64;
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)>;
+    fn refs(&self) -> impl Iterator<Item = i64>;
+}
+impl SimpleWay for osmpbf::Way<'_> {
     fn id(&self) -> i64 {
         self.id()
     }
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags()
+    }
+    fn refs(&self) -> impl Iterator<Item = i64> {
+        self.refs()
+    }
+}
+impl SimpleWay for OwnedWay {
+    fn id(&self) -> i64 {
+        self.id
+    }
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+    fn refs(&self) -> impl Iterator<Item = i64> {
+        self.refs.iter().copied()
+    }
+}
+
+/// A simple trait to abstract away the OSM relation implementations of the input formats
+pub(crate) trait SimpleRelation {
+    fn id(&self) -> i64;
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)>;
+    /// The type, id and role of every member
+    fn members(&self) -> impl Iterator<Item = (MemberType, i64, &str)>;
+}
+impl SimpleRelation for osmpbf::Relation<'_> {
+    fn id(&self) -> i64 {
+        self.id()
+    }
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags()
+    }
+    fn members(&self) -> impl Iterator<Item = (MemberType, i64, &str)> {
+        self.members().map(|member| {
+            let member_type = match member.member_type {
+                osmpbf::RelMemberType::Node => MemberType::Node,
+                osmpbf::RelMemberType::Way => MemberType::Way,
+                osmpbf::RelMemberType::Relation => MemberType::Relation,
+            };
+            (member_type, member.member_id, member.role().unwrap_or(""))
+        })
+    }
+}
+impl SimpleRelation for OwnedRelation {
+    fn id(&self) -> i64 {
+        self.id
+    }
+    fn tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+    fn members(&self) -> impl Iterator<Item = (MemberType, i64, &str)> {
+        self.members
+            .iter()
+            .map(|(member_type, id, role)| (*member_type, *id, role.as_str()))
+    }
+}
+
+/// The supported file formats of OpenStreetMap input data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum InputFormat {
+    Pbf,
+    /// OSM XML, optionally bzip2 compressed
+    Xml,
+    /// The delta-coded binary format of osmconvert
+    O5m,
+}
+impl InputFormat {
+    /// Detects the format from the file name, e.g. `.osm.pbf` or `.osm.bz2`
+    pub(crate) fn from_path(fname: &Path) -> Result<Self> {
+        let name = fname
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".pbf") {
+            Ok(InputFormat::Pbf)
+        } else if name.ends_with(".osm") || name.ends_with(".osm.bz2") || name.ends_with(".xml") {
+            Ok(InputFormat::Xml)
+        } else if name.ends_with(".o5m") {
+            Ok(InputFormat::O5m)
+        } else {
+            Err(GladsheimError::parse(format!(
+                "Unknown OSM file format of {}",
+                fname.display()
+            )))
+        }
+    }
+}
+
+/// One pass over the input, mapping each element to a partial result
+///
+/// Implemented once per pass so the same logic runs regardless of input format
+trait ElementPass: Sync {
+    /// Whether a whole PBF block can be skipped without visiting its elements
+    fn skips_block(&self, _block: &PrimitiveBlock) -> bool {
+        false
+    }
+    fn way<W: SimpleWay>(&self, _way: &W) -> PbfReaderResult {
+        PbfReaderResult::default()
+    }
+    fn node<N: SimpleNode>(&self, _node: &N) -> PbfReaderResult {
+        PbfReaderResult::default()
+    }
+    fn relation<R: SimpleRelation>(&self, _relation: &R) -> PbfReaderResult {
+        PbfReaderResult::default()
+    }
+}
+
+/// First pass, reading the Ways and keeping the ones routable in any of the profiles
+struct WayPass<'a> {
+    profile: Profile,
+    extra_profiles: &'a [Profile],
+    /// Also keep the ways carrying addresses
+    addresses: bool,
+}
+impl ElementPass for WayPass<'_> {
+    /// Blocks of only nodes, which make up most of a file, are of no use until the node pass
+    fn skips_block(&self, block: &PrimitiveBlock) -> bool {
+        block
+            .groups()
+            .all(|group| group.ways().next().is_none() && group.relations().next().is_none())
+    }
+    fn way<W: SimpleWay>(&self, way: &W) -> PbfReaderResult {
+        let mut result = parse_way(way, self.profile, self.extra_profiles);
+        if self.addresses {
+            result
+                .map
+                .address_ways
+                .extend(address::parse_address_way(way));
+        }
+        result
+    }
+    fn relation<R: SimpleRelation>(&self, relation: &R) -> PbfReaderResult {
+        PbfReaderResult {
+            map: Map {
+                transit_lines: transit::parse_route_relation(relation)
+                    .into_iter()
+                    .collect(),
+                restrictions: restrictions::parse_restriction_relation(relation)
+                    .filter(|restriction| {
+                        std::iter::once(&self.profile)
+                            .chain(self.extra_profiles)
+                            .any(|profile| restriction.applies_to(*profile))
+                    })
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Second pass, reading the Nodes referenced by the kept Ways
+struct NodePass<'a> {
+    active_nodes: &'a HashSet<NodeId>,
+    region: Option<&'a Region>,
+    /// Bounds of `region` as `(min_lat, min_lon, max_lat, max_lon)`, a cheap check
+    /// before the exact one
+    bounds: Option<(f64, f64, f64, f64)>,
+    /// Also keep the points of interest among all nodes
+    pois: bool,
+    /// Also keep the addresses among all nodes
+    addresses: bool,
+}
+impl NodePass<'_> {
+    fn is_in_bounds(&self, lat: f64, lon: f64) -> bool {
+        self.bounds
+            .is_none_or(|(min_lat, min_lon, max_lat, max_lon)| {
+                (min_lat..=max_lat).contains(&lat) && (min_lon..=max_lon).contains(&lon)
+            })
+    }
+}
+impl ElementPass for NodePass<'_> {
+    /// Blocks without nodes, or with all of them outside the bounds of the region
+    fn skips_block(&self, block: &PrimitiveBlock) -> bool {
+        !block.groups().any(|group| {
+            group
+                .dense_nodes()
+                .any(|node| self.is_in_bounds(node.lat(), node.lon()))
+                || group
+                    .nodes()
+                    .any(|node| self.is_in_bounds(node.lat(), node.lon()))
+        })
+    }
+
+    fn node<N: SimpleNode>(&self, node: &N) -> PbfReaderResult {
+        if !self.is_in_bounds(node.lat(), node.lon())
+            || self
+                .region
+                .is_some_and(|region| !region.contains(node.lat(), node.lon()))
+        {
+            return PbfReaderResult::default();
+        }
+        let mut result = parse_node(node, self.active_nodes);
+        if self.pois {
+            result.map.pois.extend(poi::parse_poi(node));
+        }
+        if self.addresses {
+            result
+                .map
+                .address_nodes
+                .extend(address::parse_address_node(node));
+        }
+        result
+    }
+}
+
+/// Runs a pass over all elements of the input file in parallel
+///
+/// Pbf elements are skipped once cancelled, so the caller has to check the token afterwards.
+fn run_pass(
+    osm_file: &Path,
+    pass: &impl ElementPass,
+    cancel: &CancellationToken,
+) -> Result<PbfReaderResult> {
+    match InputFormat::from_path(osm_file)? {
+        InputFormat::Pbf => {
+            let reader = BlobReader::from_path(osm_file)
+                .parse_context(|| format!("Failed loading {}", osm_file.display()))?;
+            let num_blocks = AtomicUsize::new(0);
+            let num_skipped = AtomicUsize::new(0);
+            let result = reader
+                .par_bridge()
+                .map(|blob| {
+                    let block = match blob?.decode()? {
+                        BlobDecode::OsmData(block) => block,
+                        BlobDecode::OsmHeader(_) | BlobDecode::Unknown(_) => {
+                            return Ok(PbfReaderResult::default());
+                        }
+                    };
+                    num_blocks.fetch_add(1, Ordering::Relaxed);
+                    if cancel.is_cancelled() || pass.skips_block(&block) {
+                        num_skipped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(PbfReaderResult::default());
+                    }
+                    Ok(block
+                        .elements()
+                        .map(|element| match element {
+                            Element::Way(way) => pass.way(&way),
+                            Element::Node(node) => pass.node(&node),
+                            Element::DenseNode(node) => pass.node(&node),
+                            Element::Relation(relation) => pass.relation(&relation),
+                        })
+                        .fold(PbfReaderResult::default(), PbfReaderResult::merge))
+                })
+                .reduce(
+                    || Ok(PbfReaderResult::default()),
+                    |a: osmpbf::Result<_>, b| Ok(a?.merge(b?)),
+                )
+                .parse_context(|| format!("Failed parsing {}", osm_file.display()))?;
+            println!(
+                "INFO: Skipped {} of {} blocks without elements of interest",
+                num_skipped.into_inner(),
+                num_blocks.into_inner()
+            );
+            Ok(result)
+        }
+        format @ (InputFormat::Xml | InputFormat::O5m) => {
+            let mut result = PbfReaderResult::default();
+            let handle_batch = |batch: Vec<OwnedElement>| {
+                cancel.check(|| format!("reading part of {}", osm_file.display()))?;
+                let partial = batch
+                    .par_iter()
+                    .map(|element| match element {
+                        OwnedElement::Way(way) => pass.way(way),
+                        OwnedElement::Node(node) => pass.node(node),
+                        OwnedElement::Relation(relation) => pass.relation(relation),
+                    })
+                    .reduce(PbfReaderResult::default, |a, b| a.merge(b));
+                result = std::mem::take(&mut result).merge(partial);
+                Ok(())
+            };
+            if format == InputFormat::Xml {
+                osm_xml::for_each_batch(osm_file, SEQUENTIAL_BATCH_SIZE, handle_batch)?;
+            } else {
+                o5m::for_each_batch(osm_file, SEQUENTIAL_BATCH_SIZE, handle_batch)?;
+            }
+            Ok(result)
+        }
+    }
+}
+
+/// Reads the nodes with the given ids from an OSM file, e.g. to locate the nodes
+/// of ways an osmChange makes routable without changing the nodes themselves
+pub(crate) fn read_nodes(
+    osm_file: &Path,
+    node_ids: &HashSet<NodeId>,
+    cancel: &CancellationToken,
+) -> Result<HashMap<NodeId, Node>> {
+    let result = run_pass(
+        osm_file,
+        &NodePass {
+            active_nodes: node_ids,
+            region: None,
+            bounds: None,
+            pois: false,
+            addresses: false,
+        },
+        cancel,
+    )?;
+    cancel.check(|| format!("reading {} nodes", node_ids.len()))?;
+    Ok(result.map.nodes.into_iter().collect())
+}
+
+/// The time the data of an OSM file is from in seconds since 1970-01-01, when the
+/// file records it
+///
+/// Pbf files carry the replication timestamp of osmosis and osmium in their header block.
+fn source_timestamp(osm_file: &Path) -> Result<Option<i64>> {
+    match InputFormat::from_path(osm_file)? {
+        InputFormat::Pbf => {
+            let mut reader = BlobReader::from_path(osm_file)
+                .parse_context(|| format!("Failed loading {}", osm_file.display()))?;
+            let Some(blob) = reader.next() else {
+                return Ok(None);
+            };
+            let parse_error = || format!("Failed parsing {}", osm_file.display());
+            match blob
+                .parse_context(parse_error)?
+                .decode()
+                .parse_context(parse_error)?
+            {
+                BlobDecode::OsmHeader(header) => Ok(header.osmosis_replication_timestamp()),
+                BlobDecode::OsmData(_) | BlobDecode::Unknown(_) => Ok(None),
+            }
+        }
+        InputFormat::Xml => osm_xml::source_timestamp(osm_file),
+        InputFormat::O5m => o5m::source_timestamp(osm_file),
+    }
 }
 
 /// Parses an OpenStreetMap dataset
 ///
 /// Focus on being fast and highly multi-threaded
-pub(crate) fn read_osm_pbf(osm_pbf: &Path, output_tile_dir: &Path) -> Result<()> {
+pub(crate) fn read_osm(
+    osm_file: &Path,
+    output_tile_dir: &Path,
+    options: &ParseOptions,
+    cancel: &CancellationToken,
+) -> Result<()> {
     let start_time = std::time::Instant::now();
-    let reader = ElementReader::from_path(osm_pbf)
-        .with_context(|| format!("Failed loading {}", osm_pbf.display()))?;
-
-    let mut parsed_ways = reader.par_map_reduce(
-        // First, just read the Ways, and parse the drivable ones
-        |element| match element {
-            Element::Way(way) => parse_way(&way),
-            Element::Node(_node) => PbfReaderResult::default(),
-            Element::DenseNode(_node) => PbfReaderResult::default(),
-            Element::Relation(_relation) => PbfReaderResult::default(),
+
+    let source_timestamp = source_timestamp(osm_file)?;
+    match source_timestamp {
+        Some(timestamp) => println!(
+            "INFO: Reading OSM data of {}",
+            utils::format_timestamp(timestamp)
+        ),
+        None => println!(
+            "INFO: {} records no timestamp, tiles won't tell which data they hold",
+            osm_file.display()
+        ),
+    }
+
+    // First, just read the Ways, and parse the drivable ones
+    let mut parsed_ways = run_pass(
+        osm_file,
+        &WayPass {
+            profile: options.profile,
+            extra_profiles: &options.extra_profiles,
+            addresses: options.addresses,
         },
-        || PbfReaderResult::default(),
-        |a, b| a.merge(b),
+        cancel,
     )?;
+    cancel.check(|| "starting the first pass over ways".to_string())?;
 
     println!(
         "INFO: Finished first parsing in {}ms",
@@ -141,8 +736,15 @@ pub(crate) fn read_osm_pbf(osm_pbf: &Path, output_tile_dir: &Path) -> Result<()>
         .map
         .ways
         .iter()
-        .map(|way| way.nodes.clone())
-        .flatten()
+        .flat_map(|way| way.nodes.iter().copied())
+        // Addressed buildings and interpolation lines are located by their nodes
+        .chain(
+            parsed_ways
+                .map
+                .address_ways
+                .iter()
+                .flat_map(|address_way| address_way.nodes().iter().copied()),
+        )
         .collect::<HashSet<_>>();
     println!(
         "INFO: Collected active nodes in {}ms",
@@ -150,33 +752,41 @@ pub(crate) fn read_osm_pbf(osm_pbf: &Path, output_tile_dir: &Path) -> Result<()>
     );
 
     let start_time = std::time::Instant::now();
-    let reader = ElementReader::from_path(osm_pbf)
-        .with_context(|| format!("Failed loading {}", osm_pbf.display()))?;
 
     // Now we do the second parsing to parse the active nodes we just derived
-    let parsed_nodes = reader.par_map_reduce(
-        |element| match element {
-            Element::Way(_) => PbfReaderResult::default(),
-            Element::Node(node) => parse_node(node, &active_nodes),
-            Element::DenseNode(node) => parse_node(node, &active_nodes),
-            Element::Relation(_relation) => PbfReaderResult::default(),
+    let mut parsed_nodes = run_pass(
+        osm_file,
+        &NodePass {
+            active_nodes: &active_nodes,
+            region: options.region.as_ref(),
+            bounds: options.region.as_ref().map(Region::bounds),
+            pois: options.pois,
+            addresses: options.addresses,
         },
-        || PbfReaderResult::default(),
-        |a, b| a.merge(b),
+        cancel,
     )?;
+    cancel.check(|| {
+        format!(
+            "parsing {} ways, while reading their nodes",
+            utils::format_count(parsed_ways.map.ways.len())
+        )
+    })?;
     println!(
         "INFO: Finished second parsing in {}ms",
         start_time.elapsed().as_millis()
     );
     println!(
-        "Total number of nodes: {}k",
-        parsed_nodes.stats.num_nodes / 1000
+        "Total number of nodes: {}",
+        utils::format_count(parsed_nodes.stats.num_nodes)
     );
     println!(
-        "Number of parsed nodes: {}k",
-        parsed_nodes.map.nodes.len() / 1000
+        "Number of parsed nodes: {}",
+        utils::format_count(parsed_nodes.map.nodes.len())
     );
 
+    let pois = std::mem::take(&mut parsed_nodes.map.pois);
+    let address_nodes = std::mem::take(&mut parsed_nodes.map.address_nodes);
+
     let node_table = {
         let start_time = std::time::Instant::now();
         let table = parsed_nodes
@@ -192,26 +802,54 @@ pub(crate) fn read_osm_pbf(osm_pbf: &Path, output_tile_dir: &Path) -> Result<()>
         table
     };
 
+    // Ways crossing the region boundary, or the edge of an extract, reference
+    // nodes that are missing
+    let num_ways = parsed_ways.map.ways.len();
+    let mut num_incomplete = 0;
+    parsed_ways.map.ways = std::mem::take(&mut parsed_ways.map.ways)
+        .into_iter()
+        .flat_map(|way| {
+            if way
+                .nodes
+                .iter()
+                .all(|node_id| node_table.contains_key(node_id))
+            {
+                return vec![way];
+            }
+            num_incomplete += 1;
+            clip_way(way, &node_table, options.missing_nodes)
+        })
+        .collect();
+    println!(
+        "INFO: Kept {} of {} ways after handling {} ways with missing nodes by {:?}",
+        utils::format_count(parsed_ways.map.ways.len()),
+        utils::format_count(num_ways),
+        num_incomplete,
+        options.missing_nodes
+    );
+    merge_destination_zones(&mut parsed_ways.map.ways);
+
     let tiles = {
         // Next, time to detect intersections and split ways into edges
         let start_time = std::time::Instant::now();
-        let mut intersection_nodes = HashSet::new();
-        {
-            let mut seen_nodes = HashSet::new();
-            for way in &parsed_ways.map.ways {
-                for node_id in &way.nodes {
-                    if seen_nodes.contains(&node_id) {
-                        intersection_nodes.insert(*node_id);
-                    } else {
-                        seen_nodes.insert(node_id);
-                    }
-                }
-            }
-            println!(
-                "INFO: Calculated intersections in {}ms",
-                start_time.elapsed().as_millis()
+        let mut intersection_nodes = shared_nodes(&parsed_ways.map.ways);
+        if options.splits_at_crossings() {
+            // Crossings must be graph nodes so that pedestrian routes can be
+            // guided over them, even where only the road itself is mapped
+            intersection_nodes.extend(
+                node_table
+                    .iter()
+                    .filter(|(_node_id, node)| node.is_crossing)
+                    .map(|(node_id, _node)| *node_id),
             );
         }
+        // Last, since whether a loop needs splitting depends on the other intersections
+        let num_loops = split_loops(&parsed_ways.map.ways, &mut intersection_nodes);
+        println!(
+            "INFO: Calculated intersections in {}ms, with {} ways looping back on themselves",
+            start_time.elapsed().as_millis(),
+            num_loops
+        );
 
         {
             // Now, use intersections to split ways into edges
@@ -221,51 +859,87 @@ pub(crate) fn read_osm_pbf(osm_pbf: &Path, output_tile_dir: &Path) -> Result<()>
             let edges = parsed_ways
                 .map
                 .ways
-                .par_iter_mut()
-                .flat_map(|way| {
-                    let mut initial_node_index_on_edge = 0;
-                    let mut new_edges = Vec::new();
-                    for (node_index, node_id) in way.nodes.iter().enumerate() {
-                        if node_index==0 {
-                            // Nothing to cut on first index
-                        } else {
-                            if intersection_nodes.contains(node_id) {
-                                // We've reached an intersection and need to create an edge consisting of the
-                                // nodes leading up to this node
-                                let from = way.nodes[initial_node_index_on_edge];
-                                let to = way.nodes[node_index];
-                                let nodes = way.nodes[initial_node_index_on_edge..node_index].to_vec();
-                                if nodes.is_empty() {
-                                    println!("WARN: Produced edge with empty nodes. initial_node {initial_node_index_on_edge}, node_idx {node_index} from {}", way.id.0);
-                                } else {
-                                    new_edges.push(crate::Edge {
-                                        from,
-                                        to,
-                                        nodes,
-                                        is_oneway: way.is_oneway,
-                                    });
-                                }
-                                initial_node_index_on_edge = node_index;
-                            }
-                        }
-                    }
-                    new_edges
+                .par_iter()
+                .flat_map(|way| split_way(way, &intersection_nodes))
+                .collect::<Vec<_>>();
+            let num_split_edges = edges.len();
+            let mut edges = if options.contract_chains {
+                // Nodes and ways referenced by id from the tiles must keep their edges
+                let mut protected_nodes = parsed_ways
+                    .map
+                    .restrictions
+                    .iter()
+                    .filter_map(|restriction| match restriction.via {
+                        Via::Node(node_id) => Some(node_id),
+                        Via::Ways(_) => None,
+                    })
+                    .collect::<HashSet<_>>();
+                if options.splits_at_crossings() {
+                    protected_nodes.extend(
+                        node_table
+                            .iter()
+                            .filter(|(_node_id, node)| node.is_crossing)
+                            .map(|(node_id, _node)| *node_id),
+                    );
+                }
+                let protected_ways = parsed_ways
+                    .map
+                    .restrictions
+                    .iter()
+                    .flat_map(|restriction| {
+                        let via_ways = match &restriction.via {
+                            Via::Node(_) => &[][..],
+                            Via::Ways(ways) => ways,
+                        };
+                        [restriction.from, restriction.to]
+                            .into_iter()
+                            .chain(via_ways.iter().copied())
+                    })
+                    .chain(
+                        parsed_ways
+                            .map
+                            .transit_lines
+                            .iter()
+                            .flat_map(|line| line.ways.iter().copied()),
+                    )
+                    .collect::<HashSet<_>>();
+                let (edges, num_merges) =
+                    contract::contract_chains(edges, &protected_nodes, &protected_ways);
+                println!(
+                    "INFO: Contracted {} of {} edges into chains",
+                    num_merges, num_split_edges
+                );
+                edges
+            } else {
+                edges
+            };
+            if options.islands != Islands::Keep {
+                let num_islands = components::handle_islands(
+                    &mut edges,
+                    options.profile,
+                    options.min_component_size,
+                    options.islands,
+                );
+                println!(
+                    "INFO: Found {} edges in components of fewer than {} nodes",
+                    num_islands, options.min_component_size
+                );
+            }
+            edges
+                .into_par_iter()
+                .flat_map_iter(|mut edge| {
+                    let reverse = options
+                        .reverse_edges
+                        .then(|| reverse_edge(&mut edge))
+                        .flatten();
+                    std::iter::once(edge).chain(reverse)
                 })
-                // Next, while we still have a parallel iterator, lets also do the assignment into Z7
+                // Next, while we still have a parallel iterator, lets also do the assignment into
                 // tiles
-                .for_each(|edge| {
-                    let node_id = edge
-                        .nodes
-                        .first()
-                        // It's invalid to have an edge without nodes so unwrap is ok here
-                        .unwrap();
-                    let node = node_table
-                        .get(node_id)
-                        // Program is invalid if the table misses this node, so unwrap is ok
-                        .unwrap();
-                    match utils::lat_lon_to_quadkey(node.loc.lat, node.loc.lon, 7) {
-                        Ok(s) => {
-                            let quadkey = Quadkey(s);
+                .for_each(|mut edge| {
+                    annotate_edge(&mut edge, &node_table, options);
+                    match edge_quadkey(&edge, &node_table, options.tile_zoom) {
+                        Ok(quadkey) => {
                             collector.insert(quadkey, edge);
                         }
                         Err(err) => {
@@ -274,136 +948,453 @@ pub(crate) fn read_osm_pbf(osm_pbf: &Path, output_tile_dir: &Path) -> Result<()>
                     }
                 });
 
-            let tiles = collector.collect();
+            let mut tiles = collector.collect();
+            let num_duplicates: usize = tiles
+                .par_iter_mut()
+                .map(|(_quadkey, tile)| merge_duplicate_edges(&mut tile.edges))
+                .sum();
+            tiles.par_iter_mut().for_each(|(_quadkey, tile)| {
+                tile.zoom = options.tile_zoom;
+                tile.source_timestamp = source_timestamp;
+                finish_tile(tile, &node_table, &parsed_ways.map, options);
+            });
+            boundary::link_tiles(&mut tiles);
             let num_edges: usize = tiles.iter().map(|(_quadkey, tile)| tile.edges.len()).sum();
 
             println!(
-                "INFO: Split {}k ways into {}k edges and produced {} tiles in {}ms",
-                parsed_ways.map.ways.len() / 1000,
-                num_edges / 1000,
+                "INFO: Split {} ways into {} edges, merging {} duplicates, and produced {} tiles in {}ms",
+                utils::format_count(parsed_ways.map.ways.len()),
+                utils::format_count(num_edges),
+                num_duplicates,
                 tiles.len(),
                 start_time.elapsed().as_millis()
             );
             tiles
         }
     };
+    cancel.check(|| format!("producing {} tiles, before writing them", tiles.len()))?;
 
-    {
-        // Finally write tiles to disk
+    if let Some(prefix) = &options.dimacs_prefix {
         let start_time = std::time::Instant::now();
-        let _results = tiles
-            .par_iter()
-            .map(|(quadkey, tile)| -> Result<()> {
-                let fname = {
-                    let mut fname = output_tile_dir.to_owned();
-                    fname.push(&quadkey.0);
-                    fname.set_extension("grt");
-                    fname
-                };
-                //println!("INFO: Writing to {}", fname.display());
-                let mut file = std::fs::File::create(&fname)
-                    .with_context(|| format!("Failed opening file {}", fname.display()))?;
-                bincode::encode_into_std_write(tile, &mut file, bincode::config::standard())
-                    .with_context(|| format!("Failed writing to file {}", fname.display()))?;
-                Ok(())
-            })
-            .collect::<Vec<_>>();
+        dimacs::write_dimacs(prefix, &tiles, &node_table, options.profile)?;
+        println!(
+            "INFO: Exported DIMACS graph in {}ms",
+            start_time.elapsed().as_millis()
+        );
+    }
+
+    // Finally write tiles to disk
+    manifest::remove_manifest(output_tile_dir)?;
+    let headers = write_tiles(output_tile_dir, &tiles, options, cancel)?;
 
+    for level in &options.overviews {
+        let start_time = std::time::Instant::now();
+        let mut overview_tiles = overview::overview_tiles(&tiles, *level, &node_table);
+        overview_tiles.par_iter_mut().for_each(|(_quadkey, tile)| {
+            tile.zoom = level.zoom;
+            tile.max_class = Some(level.max_class);
+            tile.source_timestamp = source_timestamp;
+            finish_tile(tile, &node_table, &parsed_ways.map, options);
+        });
+        boundary::link_tiles(&mut overview_tiles);
+        let num_edges: usize = overview_tiles
+            .iter()
+            .map(|(_quadkey, tile)| tile.edges.len())
+            .sum();
         println!(
-            "INFO: Finished writing to files in {}ms",
+            "INFO: Gathered {} edges up to {:?} into {} overview tiles at zoom {} in {}ms",
+            utils::format_count(num_edges),
+            level.max_class,
+            overview_tiles.len(),
+            level.zoom,
             start_time.elapsed().as_millis()
         );
+        let overview_dir = overview::overview_dir(output_tile_dir, level.zoom);
+        std::fs::create_dir_all(&overview_dir)
+            .io_context(|| format!("Failed creating directory {}", overview_dir.display()))?;
+        manifest::remove_manifest(&overview_dir)?;
+        let overview_headers = write_tiles(&overview_dir, &overview_tiles, options, cancel)?;
+        manifest::write_manifest(&overview_dir, options.naming, &[], overview_headers)?;
     }
+
+    if options.edge_based {
+        let start_time = std::time::Instant::now();
+        let (turn_tiles, num_skipped) =
+            turns::build_turn_tiles(&tiles, &node_table, options.profile);
+        let num_turns: usize = turn_tiles
+            .iter()
+            .map(|(_quadkey, turn_tile)| turn_tile.turns.len())
+            .sum();
+        let num_files = turns::write_turn_tiles(output_tile_dir, options.naming, &turn_tiles)?;
+        println!(
+            "INFO: Wrote {} turns into {} files, leaving out {} restrictions via ways, in {}ms",
+            num_turns,
+            num_files,
+            num_skipped,
+            start_time.elapsed().as_millis()
+        );
+    }
+
+    if options.pois {
+        let start_time = std::time::Instant::now();
+        let num_pois = pois.len();
+        let num_files = poi::write_pois(output_tile_dir, options.naming, options.tile_zoom, pois)?;
+        println!(
+            "INFO: Wrote {} points of interest into {} files in {}ms",
+            num_pois,
+            num_files,
+            start_time.elapsed().as_millis()
+        );
+    }
+
+    if options.addresses {
+        let start_time = std::time::Instant::now();
+        let addresses =
+            address::resolve_addresses(address_nodes, &parsed_ways.map.address_ways, &node_table);
+        let num_addresses = addresses.len();
+        let num_files = address::write_addresses(
+            output_tile_dir,
+            options.naming,
+            options.tile_zoom,
+            addresses,
+        )?;
+        println!(
+            "INFO: Wrote {} addresses into {} files in {}ms",
+            num_addresses,
+            num_files,
+            start_time.elapsed().as_millis()
+        );
+    }
+    // Last, so readers never see a manifest of tiles that aren't all written
+    manifest::write_manifest(output_tile_dir, options.naming, &options.overviews, headers)?;
     Ok(())
 }
 
-pub(crate) fn parse_way(way: &osmpbf::Way) -> PbfReaderResult {
-    let mut is_drivable = false;
-    let mut name = None;
-    let mut is_oneway = false;
-    for (key, value) in way.tags() {
-        match key {
-            // https://wiki.openstreetmap.org/wiki/Key:highway
-            "highway" => match value {
-                // Main tags
-                "motorway" => {
-                    is_drivable = true;
-                }
-                "trunk" => {
-                    is_drivable = true;
-                }
-                "primary" => {
-                    is_drivable = true;
-                }
-                "secondary" => {
-                    is_drivable = true;
-                }
-                "tertiary" => {
-                    is_drivable = true;
-                }
-                "unclassified" => {
-                    is_drivable = true;
-                }
-                "residential" => {
-                    is_drivable = true;
-                }
-                // Link roads
-                "motorway_link" => {
-                    is_drivable = true;
-                }
-                "trunk_link" => {
-                    is_drivable = true;
-                }
-                "primary_link" => {
-                    is_drivable = true;
+/// Sets the header fields of a tile built from edges and derives the data stored
+/// alongside them
+fn finish_tile(
+    tile: &mut Tile,
+    node_table: &HashMap<NodeId, Node>,
+    parsed: &Map,
+    options: &ParseOptions,
+) {
+    tile.profile = options.profile;
+    tile.extra_profiles = options.extra_profiles.clone();
+    tile.compression = options.compression;
+    tile.has_reverse_edges = options.reverse_edges;
+    tile.is_contracted = options.contract_chains;
+    annotate_tile(tile, node_table, options.splits_at_crossings());
+    if options.node_filter {
+        tile.node_filter = Some(NodeFilter::from_nodes(
+            tile.node_locations.iter().map(|(node_id, _loc)| node_id),
+        ));
+    }
+    tile.transit_lines = transit::lines_in_tile(&parsed.transit_lines, tile);
+    tile.restrictions = restrictions::restrictions_in_tile(&parsed.restrictions, tile);
+}
+
+/// Writes tiles and their name indexes into a directory, as files or into an
+/// archive or database, returning the headers for the manifest listing them
+///
+/// The manifest is left to the caller to write once everything else is written.
+pub(crate) fn write_tiles(
+    output_dir: &Path,
+    tiles: &[(Quadkey, Tile)],
+    options: &ParseOptions,
+    cancel: &CancellationToken,
+) -> Result<Vec<(Quadkey, TileHeader)>> {
+    let headers = if let Some(container_fname) = options.storage.container_fname(output_dir) {
+        let start_time = std::time::Instant::now();
+        let encoded = tiles
+            .par_iter()
+            .filter(|_tile| !cancel.is_cancelled())
+            .map(|(quadkey, tile)| {
+                names::write_name_index(output_dir, quadkey, options.naming, tile)?;
+                if options.flat {
+                    flat_tile::write_flat_tile(output_dir, quadkey, options.naming, tile)?;
                 }
-                "secondary_link" => {
-                    is_drivable = true;
+                if options.protobuf {
+                    tile_proto::write_proto_tile(output_dir, quadkey, options.naming, tile)?;
                 }
-                "tertiary_link" => {
-                    is_drivable = true;
+                let (header, bytes) = utils::encode_tile(
+                    tile,
+                    &format!("{} in {}", quadkey.0, container_fname.display()),
+                )?;
+                Ok(((quadkey.clone(), header), (quadkey.clone(), bytes)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        cancel.check(|| format!("encoding {} of {} tiles", encoded.len(), tiles.len()))?;
+        options.storage.remove_others(output_dir)?;
+        let (headers, encoded): (Vec<_>, Vec<_>) = encoded.into_iter().unzip();
+        let is_written = options.storage.write_container(&container_fname, encoded)?;
+        println!(
+            "INFO: Finished writing {} tiles into {}{}, in {}ms",
+            tiles.len(),
+            container_fname.display(),
+            if is_written {
+                ""
+            } else {
+                ", which was unchanged"
+            },
+            start_time.elapsed().as_millis()
+        );
+        headers
+    } else {
+        let start_time = std::time::Instant::now();
+        // Readers prefer an archive or database over tile files, so an older one
+        // would hide these
+        options.storage.remove_others(output_dir)?;
+        let results = tiles
+            .par_iter()
+            .filter(|_tile| !cancel.is_cancelled())
+            .map(|(quadkey, tile)| {
+                let (header, is_written) =
+                    utils::write_tile(output_dir, quadkey, options.naming, tile)?;
+                names::write_name_index(output_dir, quadkey, options.naming, tile)?;
+                if options.flat {
+                    flat_tile::write_flat_tile(output_dir, quadkey, options.naming, tile)?;
                 }
-                // Special road types
-                "living_street" => {}
-                "service" => {}
-                "pedestrian" => {}
-                "track" => {}
-                "bus_guideway" => {}
-                "escape" => {}
-                "raceway" => {}
-                "road" => {}
-                "busway" => {}
-                _ => {
-                    //println!("Unhandled highway value: {}", value);
+                if options.protobuf {
+                    tile_proto::write_proto_tile(output_dir, quadkey, options.naming, tile)?;
                 }
-            },
-            "name" => {
-                name = Some(value.to_string());
+                Ok(((quadkey.clone(), header), is_written))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        cancel.check(|| format!("writing {} of {} tiles", results.len(), tiles.len()))?;
+        let num_unchanged = results
+            .iter()
+            .filter(|(_header, is_written)| !is_written)
+            .count();
+
+        println!(
+            "INFO: Finished writing to files, leaving {} unchanged tiles as they were, in {}ms",
+            num_unchanged,
+            start_time.elapsed().as_millis()
+        );
+        results
+            .into_iter()
+            .map(|(header, _is_written)| header)
+            .collect()
+    };
+    Ok(headers)
+}
+
+/// The subset of way tags that the profiles make decisions on
+#[derive(Debug, Default)]
+pub(crate) struct WayTags<'a> {
+    pub(crate) highway: Option<&'a str>,
+    pub(crate) name: Option<&'a str>,
+    pub(crate) road_ref: Option<&'a str>,
+    pub(crate) int_ref: Option<&'a str>,
+    pub(crate) oneway: Option<&'a str>,
+    pub(crate) oneway_foot: Option<&'a str>,
+    pub(crate) foot: Option<&'a str>,
+    pub(crate) bus: Option<&'a str>,
+    pub(crate) psv: Option<&'a str>,
+    pub(crate) oneway_bus: Option<&'a str>,
+    pub(crate) oneway_psv: Option<&'a str>,
+    pub(crate) access: Option<&'a str>,
+    pub(crate) vehicle: Option<&'a str>,
+    pub(crate) motor_vehicle: Option<&'a str>,
+    pub(crate) motorcar: Option<&'a str>,
+    pub(crate) service: Option<&'a str>,
+    pub(crate) tracktype: Option<&'a str>,
+    /// The class of road being built on `highway=construction`
+    pub(crate) construction: Option<&'a str>,
+    pub(crate) opening_date: Option<&'a str>,
+    pub(crate) check_date: Option<&'a str>,
+    pub(crate) sidewalk: Sidewalk,
+    pub(crate) smoothness: Option<Smoothness>,
+    pub(crate) width: Option<&'a str>,
+    pub(crate) narrow: Option<&'a str>,
+    pub(crate) shared_space: Option<&'a str>,
+    pub(crate) incline: Option<&'a str>,
+    pub(crate) maxspeed: Option<&'a str>,
+    pub(crate) toll: Option<&'a str>,
+    /// `ferry` on ferry crossings, which have no `highway` tag
+    pub(crate) route: Option<&'a str>,
+}
+impl<'a> WayTags<'a> {
+    fn from_tags(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut way_tags = Self::default();
+        for (key, value) in tags {
+            match key {
+                "highway" => way_tags.highway = Some(value),
+                "name" => way_tags.name = Some(value),
+                "ref" => way_tags.road_ref = Some(value),
+                "int_ref" => way_tags.int_ref = Some(value),
+                "oneway" => way_tags.oneway = Some(value),
+                "oneway:foot" => way_tags.oneway_foot = Some(value),
+                "foot" => way_tags.foot = Some(value),
+                "bus" => way_tags.bus = Some(value),
+                "psv" => way_tags.psv = Some(value),
+                "oneway:bus" => way_tags.oneway_bus = Some(value),
+                "oneway:psv" => way_tags.oneway_psv = Some(value),
+                "access" => way_tags.access = Some(value),
+                "vehicle" => way_tags.vehicle = Some(value),
+                "motor_vehicle" => way_tags.motor_vehicle = Some(value),
+                "motorcar" => way_tags.motorcar = Some(value),
+                "service" => way_tags.service = Some(value),
+                "tracktype" => way_tags.tracktype = Some(value),
+                "construction" => way_tags.construction = Some(value),
+                "opening_date" => way_tags.opening_date = Some(value),
+                "check_date" => way_tags.check_date = Some(value),
+                "sidewalk" | "sidewalk:both" => way_tags.sidewalk = Sidewalk::from_tag(value),
+                "smoothness" => way_tags.smoothness = Smoothness::from_tag(value),
+                "width" => way_tags.width = Some(value),
+                "narrow" => way_tags.narrow = Some(value),
+                "shared_space" => way_tags.shared_space = Some(value),
+                "incline" => way_tags.incline = Some(value),
+                "maxspeed" => way_tags.maxspeed = Some(value),
+                "toll" => way_tags.toll = Some(value),
+                "route" => way_tags.route = Some(value),
+                _ => {}
             }
-            "oneway" => match value {
-                "yes" => is_oneway = true,
-                "no" => {}
-                _ => {
-                    //println!("WARN: Unknown oneway value: {}", value)
-                }
-            },
+        }
+        way_tags
+    }
+}
+
+/// Finds when a road opens, `None` if it's open already
+///
+/// Roads under construction are routed as the class they're being built as, but
+/// only when an opening date is known and no later check found them still closed.
+/// Otherwise they stay `highway=construction`, which no profile routes over.
+fn opening_date(tags: &mut WayTags) -> Option<u32> {
+    let opening_date = tags.opening_date.and_then(utils::parse_date);
+    let not_before = if tags.highway == Some("construction") {
+        let check_date = tags.check_date.and_then(utils::parse_date);
+        let date = opening_date.filter(|date| check_date.is_none_or(|check| check <= *date))?;
+        tags.highway = tags.construction;
+        date
+    } else {
+        opening_date?
+    };
+    (not_before > utils::today()).then_some(not_before)
+}
 
-            _ => {}
+/// Parses a width in meters, optionally with a unit, or in feet and inches like `10'6"`
+fn parse_width(value: &str) -> Option<f32> {
+    let value = value.trim();
+    if let Some((feet, inches)) = value.split_once('\'') {
+        let feet = feet.trim().parse::<f32>().ok()?;
+        let inches = match inches.trim().trim_end_matches('"') {
+            "" => 0.0,
+            inches => inches.trim().parse::<f32>().ok()?,
+        };
+        return Some((feet * 12.0 + inches) * 0.0254);
+    }
+    let meters = value.strip_suffix('m').unwrap_or(value).trim();
+    meters
+        .parse::<f32>()
+        .ok()
+        .filter(|width| width.is_finite() && *width > 0.0)
+}
+
+/// Parses a speed limit in km/h, given in km/h or mph
+///
+/// `none` and implicit limits like `SE:urban` don't give a number and are ignored.
+fn parse_maxspeed(value: &str) -> Option<u8> {
+    let value = value.trim();
+    let (number, factor) = match value.strip_suffix("mph") {
+        Some(mph) => (mph, 1.609_344),
+        None => (value.strip_suffix("km/h").unwrap_or(value), 1.0),
+    };
+    let kmh = number.trim().parse::<f32>().ok()? * factor;
+    (kmh.is_finite() && kmh >= 1.0).then(|| kmh.round().min(f32::from(u8::MAX)) as u8)
+}
+
+/// Parses an incline given in percent or degrees as rise over run
+///
+/// `up` and `down` say nothing about the steepness and are ignored.
+fn parse_incline(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let incline = if let Some(percent) = value.strip_suffix('%') {
+        percent.trim().parse::<f32>().ok()? / 100.0
+    } else if let Some(degrees) = value.strip_suffix('°') {
+        degrees.trim().parse::<f32>().ok()?.to_radians().tan()
+    } else {
+        return None;
+    };
+    incline.is_finite().then_some(incline)
+}
+
+/// Parses a way routable in any of the profiles, with attributes following the first one
+pub(crate) fn parse_way<W: SimpleWay>(
+    way: &W,
+    profile: Profile,
+    extra_profiles: &[Profile],
+) -> PbfReaderResult {
+    let mut tags = WayTags::from_tags(way.tags());
+    let not_before = opening_date(&mut tags);
+    let mut access = ProfileMask::default();
+    let mut oneway = ProfileMask::default();
+    for profile in std::iter::once(profile).chain(extra_profiles.iter().copied()) {
+        if profile.is_routable(&tags) {
+            access.insert(profile);
+            if profile.is_oneway(&tags) {
+                oneway.insert(profile);
+            }
         }
     }
+    let is_drivable = !access.is_empty();
+    let is_oneway = profile.is_oneway(&tags);
 
     let ways = if is_drivable {
-        let nodes = way
-            .refs()
-            .into_iter()
-            .map(|node_id| NodeId(node_id))
-            .collect::<Vec<_>>();
+        let mut nodes = way.refs().map(NodeId).collect::<Vec<_>>();
+        // A node repeated right after itself would become an edge without length
+        nodes.dedup();
+        let id = WayId(way.id());
+        let is_ferry = tags.route == Some("ferry");
+        let class = tags
+            .highway
+            .map(RoadClass::from_highway)
+            .unwrap_or_default();
         vec![Way {
-            id: WayId(way.id()),
-            name,
+            id,
+            name: tags.name.map(|name| name.to_string()),
+            road_ref: tags.road_ref.map(|road_ref| road_ref.to_string()),
+            refs: tags
+                .road_ref
+                .map(|road_ref| RoadRef::parse_list(road_ref, false))
+                .into_iter()
+                .chain(
+                    tags.int_ref
+                        .map(|int_ref| RoadRef::parse_list(int_ref, true)),
+                )
+                .flatten()
+                .collect(),
+            class,
             is_oneway,
+            access,
+            oneway,
+            sidewalk: tags.sidewalk,
+            service: (tags.highway == Some("service"))
+                .then(|| ServiceClass::from_tag(tags.service)),
+            track_type: tags
+                .tracktype
+                .filter(|_| tags.highway == Some("track"))
+                .and_then(TrackType::from_tag),
+            smoothness: tags.smoothness,
+            width: tags.width.and_then(parse_width),
+            is_narrow: tags.narrow == Some("yes"),
+            is_toll: tags.toll == Some("yes"),
+            is_ferry,
+            incline: tags.incline.and_then(parse_incline),
+            speed_kmh: if is_ferry {
+                profile.ferry_speed_kmh()
+            } else {
+                profile.speed_kmh(
+                    class,
+                    tags.shared_space == Some("yes"),
+                    tags.sidewalk,
+                    tags.maxspeed.and_then(parse_maxspeed),
+                )
+            },
+            // Each way starts out as its own zone, merged with its neighbours later
+            destination_zone: profile.is_destination_only(&tags).then_some(id),
+            not_before,
             nodes,
-            polyline: "".into(),
         }]
     } else {
         Vec::with_capacity(0)
@@ -411,7 +1402,7 @@ pub(crate) fn parse_way(way: &osmpbf::Way) -> PbfReaderResult {
 
     PbfReaderResult {
         stats: StatsParsing {
-            num_highways: 1,
+            num_highways: if tags.highway.is_some() { 1 } else { 0 },
             num_drivable: if is_drivable { 1 } else { 0 },
             num_oneways: if is_oneway { 1 } else { 0 },
             num_nodes: 0,
@@ -419,27 +1410,142 @@ pub(crate) fn parse_way(way: &osmpbf::Way) -> PbfReaderResult {
 
         map: Map {
             ways,
-            nodes: Vec::with_capacity(0),
+            ..Default::default()
         },
     }
 }
 
+/// Applies the policy for missing nodes to a way referencing nodes not in the node table
+///
+/// Ways or runs of a single node can't form an edge and are dropped.
+fn clip_way(way: Way, node_table: &HashMap<NodeId, Node>, policy: MissingNodes) -> Vec<Way> {
+    let runs = match policy {
+        MissingNodes::Truncate => way
+            .nodes
+            .split(|node_id| !node_table.contains_key(node_id))
+            .map(|run| run.to_vec())
+            .collect(),
+        MissingNodes::Drop => Vec::new(),
+        MissingNodes::Skip => {
+            let mut nodes = way
+                .nodes
+                .iter()
+                .filter(|node_id| node_table.contains_key(node_id))
+                .copied()
+                .collect::<Vec<_>>();
+            nodes.dedup();
+            vec![nodes]
+        }
+    };
+    runs.into_iter()
+        .filter(|run| run.len() >= 2)
+        .map(|run| Way {
+            id: way.id,
+            name: way.name.clone(),
+            road_ref: way.road_ref.clone(),
+            refs: way.refs.clone(),
+            class: way.class,
+            is_oneway: way.is_oneway,
+            access: way.access,
+            oneway: way.oneway,
+            sidewalk: way.sidewalk,
+            service: way.service,
+            track_type: way.track_type,
+            smoothness: way.smoothness,
+            width: way.width,
+            is_narrow: way.is_narrow,
+            is_toll: way.is_toll,
+            is_ferry: way.is_ferry,
+            incline: way.incline,
+            speed_kmh: way.speed_kmh,
+            destination_zone: way.destination_zone,
+            not_before: way.not_before,
+            nodes: run,
+        })
+        .collect()
+}
+
+/// Merges touching destination-only ways into zones named by their smallest way id
+fn merge_destination_zones(ways: &mut [Way]) {
+    let zone_ways = ways
+        .iter()
+        .enumerate()
+        .filter(|(_index, way)| way.destination_zone.is_some())
+        .map(|(index, _way)| index)
+        .collect::<Vec<_>>();
+
+    // Union-find over the destination-only ways, joined where they share a node
+    let mut parents = (0..zone_ways.len()).collect::<Vec<_>>();
+    fn find(parents: &mut [usize], mut index: usize) -> usize {
+        while parents[index] != index {
+            parents[index] = parents[parents[index]];
+            index = parents[index];
+        }
+        index
+    }
+    let mut node_owner: HashMap<NodeId, usize> = HashMap::new();
+    for (zone_index, way_index) in zone_ways.iter().enumerate() {
+        for node_id in &ways[*way_index].nodes {
+            let owner = *node_owner.entry(*node_id).or_insert(zone_index);
+            let a = find(&mut parents, owner);
+            let b = find(&mut parents, zone_index);
+            parents[a] = b;
+        }
+    }
+
+    let mut zone_ids: HashMap<usize, WayId> = HashMap::new();
+    for (zone_index, way_index) in zone_ways.iter().enumerate() {
+        let root = find(&mut parents, zone_index);
+        let way_id = ways[*way_index].id;
+        zone_ids
+            .entry(root)
+            .and_modify(|zone_id| zone_id.0 = zone_id.0.min(way_id.0))
+            .or_insert(way_id);
+    }
+    for (zone_index, way_index) in zone_ways.iter().enumerate() {
+        let root = find(&mut parents, zone_index);
+        ways[*way_index].destination_zone = zone_ids.get(&root).copied();
+    }
+}
+
 pub(crate) fn parse_node<T: SimpleNode>(
-    node: T,
+    node: &T,
     nodes_of_interest: &HashSet<NodeId>,
 ) -> PbfReaderResult {
     let node_id = NodeId(node.id());
 
     let nodes = if nodes_of_interest.contains(&node_id) {
+        let mut is_crossing = false;
+        let mut is_level_crossing = false;
+        let mut has_barrier = false;
+        let mut is_stop = false;
+        let mut ele = None;
+        for (key, value) in node.tags() {
+            match (key, value) {
+                ("highway", "traffic_signals" | "stop") => is_stop = true,
+                ("ele", value) => ele = value.trim().parse::<f32>().ok(),
+                ("highway", "crossing") => is_crossing = true,
+                ("railway", "level_crossing") => is_level_crossing = true,
+                ("crossing:barrier", value) => has_barrier = value != "no",
+                _ => {}
+            }
+        }
+        let level_crossing = match (is_level_crossing, has_barrier) {
+            (false, _) => None,
+            (true, false) => Some(LevelCrossing::Open),
+            (true, true) => Some(LevelCrossing::Barrier),
+        };
         vec![(
             node_id,
             Node {
                 loc: Loc {
                     lat: node.lat(),
                     lon: node.lon(),
-                    //nano_lat: node.nano_lat(),
-                    //nano_lon: node.nano_lon(),
                 },
+                is_crossing,
+                level_crossing,
+                is_stop,
+                ele,
             },
         )]
     } else {
@@ -459,3 +1565,273 @@ pub(crate) fn parse_node<T: SimpleNode>(
         },
     }
 }
+
+/// The nodes shared by several ways, where they have to be split into edges
+///
+/// A way passing the same node twice doesn't make it shared, see `split_loops`.
+pub(crate) fn shared_nodes<'a>(ways: impl IntoIterator<Item = &'a Way>) -> HashSet<NodeId> {
+    // The first way each node was seen in, by its index since clipped ways share ids
+    let mut first_way = HashMap::new();
+    let mut shared = HashSet::new();
+    for (index, way) in ways.into_iter().enumerate() {
+        for node_id in &way.nodes {
+            if *first_way.entry(*node_id).or_insert(index) != index {
+                shared.insert(*node_id);
+            }
+        }
+    }
+    shared
+}
+
+/// Adds the nodes splitting ways that loop back on themselves, returning the number of such ways
+///
+/// A way returning to a node it already passed, like a circular service road or a
+/// P-shaped street, is split at that node. Unless another intersection lies on the
+/// loop, it's also split halfway around, as an edge can't start and end at one node.
+pub(crate) fn split_loops<'a>(
+    ways: impl IntoIterator<Item = &'a Way>,
+    intersection_nodes: &mut HashSet<NodeId>,
+) -> usize {
+    let mut num_loops = 0;
+    for way in ways {
+        let mut last_index = HashMap::new();
+        let mut is_loop = false;
+        for (index, node_id) in way.nodes.iter().enumerate() {
+            if let Some(previous_index) = last_index.insert(*node_id, index) {
+                is_loop = true;
+                intersection_nodes.insert(*node_id);
+                let around = &way.nodes[previous_index + 1..index];
+                if !around
+                    .iter()
+                    .any(|node_id| intersection_nodes.contains(node_id))
+                {
+                    if let Some(halfway) = around.get(around.len() / 2) {
+                        intersection_nodes.insert(*halfway);
+                    }
+                }
+            }
+        }
+        num_loops += usize::from(is_loop);
+    }
+    num_loops
+}
+
+/// Splits a way into edges at the given intersection nodes
+///
+/// The edges cover the whole way, also where it ends without an intersection.
+pub(crate) fn split_way(way: &Way, intersection_nodes: &HashSet<NodeId>) -> Vec<Edge> {
+    let mut initial_node_index_on_edge = 0;
+    let mut new_edges = Vec::new();
+    for (node_index, node_id) in way.nodes.iter().enumerate().skip(1) {
+        let is_last = node_index == way.nodes.len() - 1;
+        if is_last || intersection_nodes.contains(node_id) {
+            // We've reached an intersection or the end of the way and need to create an
+            // edge consisting of the nodes leading up to, and including, this node
+            new_edges.push(Edge {
+                from: way.nodes[initial_node_index_on_edge],
+                to: *node_id,
+                way_id: way.id,
+                name: way.name.clone(),
+                road_ref: way.road_ref.clone(),
+                refs: way.refs.clone(),
+                class: way.class,
+                nodes: way.nodes[initial_node_index_on_edge..=node_index].to_vec(),
+                is_oneway: way.is_oneway,
+                access: way.access,
+                oneway: way.oneway,
+                is_reversed: false,
+                sidewalk: way.sidewalk,
+                service: way.service,
+                track_type: way.track_type,
+                smoothness: way.smoothness,
+                width: way.width,
+                is_narrow: way.is_narrow,
+                is_toll: way.is_toll,
+                is_ferry: way.is_ferry,
+                incline: way.incline,
+                speed_kmh: way.speed_kmh,
+                destination_zone: way.destination_zone,
+                is_island: false,
+                not_before: way.not_before,
+                level_crossing_delay_s: 0,
+                length_m: 0.0,
+                duration_s: 0.0,
+                samples: Vec::new(),
+                polyline: String::new(),
+                curvature: None,
+                emissions: None,
+                country: None,
+                subdivision: None,
+            });
+            initial_node_index_on_edge = node_index;
+        }
+    }
+    new_edges
+}
+
+/// Makes an edge directed, returning its reverse when any profile may travel against it
+///
+/// Attributes measured along the edge, like the incline, are flipped. The geometry
+/// derived ones are left to `annotate_edge` on both edges.
+pub(crate) fn reverse_edge(edge: &mut Edge) -> Option<Edge> {
+    let reverse_access = edge.access.without(edge.oneway);
+    edge.oneway = edge.access;
+    edge.is_oneway = true;
+    if reverse_access.is_empty() {
+        return None;
+    }
+    let mut reverse = edge.clone();
+    std::mem::swap(&mut reverse.from, &mut reverse.to);
+    reverse.nodes.reverse();
+    reverse.access = reverse_access;
+    reverse.oneway = reverse_access;
+    reverse.is_reversed = !edge.is_reversed;
+    reverse.incline = edge.incline.map(|incline| -incline);
+    Some(reverse)
+}
+
+/// Merges edges along the same nodes in the same direction, returning the number removed
+///
+/// Duplicated ways are a common import artifact. Of each set of duplicates, the edge
+/// of the lowest way id is kept and opened to the profiles of all of them.
+pub(crate) fn merge_duplicate_edges(edges: &mut Vec<Edge>) -> usize {
+    let num_edges = edges.len();
+    // Edges with differing oneway profiles are kept apart, as they aren't redundant
+    let mut kept_index: HashMap<(Vec<NodeId>, ProfileMask), usize> = HashMap::new();
+    for edge in std::mem::take(edges) {
+        let key = (edge.nodes.clone(), edge.oneway);
+        match kept_index.get(&key) {
+            Some(&index) => {
+                let kept = &mut edges[index];
+                let access = kept.access.union(edge.access);
+                if edge.way_id.0 < kept.way_id.0 {
+                    *kept = edge;
+                }
+                kept.access = access;
+            }
+            None => {
+                kept_index.insert(key, edges.len());
+                edges.push(edge);
+            }
+        }
+    }
+    num_edges - edges.len()
+}
+
+/// Fills in the optional, geometry derived, attributes of an edge
+pub(crate) fn annotate_edge(
+    edge: &mut Edge,
+    node_table: &HashMap<NodeId, Node>,
+    options: &ParseOptions,
+) {
+    // Crossings at the `to` node belong to the edges leaving it
+    edge.level_crossing_delay_s = edge
+        .nodes_before_to()
+        .iter()
+        .filter_map(|node_id| node_table.get(node_id)?.level_crossing)
+        .map(|level_crossing| match level_crossing {
+            LevelCrossing::Open => options.level_crossing_delay_s,
+            LevelCrossing::Barrier => options.barrier_crossing_delay_s,
+        })
+        .fold(0, u16::saturating_add);
+    // Like the tile, the area is decided by the first node
+    if let Some(admin_areas) = &options.admin_areas {
+        if let Some(node) = edge
+            .nodes
+            .first()
+            .and_then(|node_id| node_table.get(node_id))
+        {
+            (edge.country, edge.subdivision) = admin_areas.lookup(node.loc.lat, node.loc.lon);
+        }
+    }
+    let coords = geometry::edge_coordinates(edge, node_table);
+    edge.length_m = geometry::length(&coords) as f32;
+    let speed_kmh = f32::from(edge.speed_kmh)
+        * edge
+            .smoothness
+            .and_then(|smoothness| options.profile.smoothness_speed_factor(smoothness))
+            .unwrap_or(1.0);
+    edge.duration_s =
+        edge.length_m / (speed_kmh.max(1.0) / 3.6) + f32::from(edge.level_crossing_delay_s);
+    if let Some(interval) = options.resample_meters {
+        edge.samples = geometry::resample(&coords, interval);
+    }
+    if options.curvature {
+        edge.curvature = geometry::Curvature::from_coords(&coords);
+    }
+    if options.polylines {
+        match geometry::encode_polyline(&coords, 6) {
+            Ok(polyline) => edge.polyline = polyline,
+            Err(err) => println!(
+                "WARN: Failed encoding the shape of an edge of way {}: {}",
+                edge.way_id.0, err
+            ),
+        }
+    }
+    // After the curvature, since slowing down for curves adds to the emissions
+    if options.emissions {
+        edge.emissions = emissions::Emissions::estimate(edge, &coords, node_table, options.profile);
+    }
+}
+
+/// The tile at a zoom level an edge belongs to, decided by its first node
+pub(crate) fn edge_quadkey(
+    edge: &Edge,
+    node_table: &HashMap<NodeId, Node>,
+    zoom: u8,
+) -> Result<Quadkey> {
+    let node_id = edge
+        .nodes
+        .first()
+        // It's invalid to have an edge without nodes so unwrap is ok here
+        .unwrap();
+    let node = node_table.get(node_id).ok_or_else(|| {
+        GladsheimError::parse(format!(
+            "Edge of way {} starts at node {} without known location",
+            edge.way_id.0, node_id.0
+        ))
+    })?;
+    Ok(Quadkey(utils::lat_lon_to_quadkey(
+        node.loc.lat,
+        node.loc.lon,
+        zoom,
+    )?))
+}
+
+/// Stores the per-node data and indices a tile needs alongside its edges
+pub(crate) fn annotate_tile(
+    tile: &mut Tile,
+    node_table: &HashMap<NodeId, Node>,
+    splits_at_crossings: bool,
+) {
+    let node_ids = tile
+        .edges
+        .iter()
+        .flat_map(|edge| &edge.nodes)
+        .copied()
+        .collect::<HashSet<_>>();
+    let mut node_locations = node_ids
+        .iter()
+        .filter_map(|node_id| Some((*node_id, node_table.get(node_id)?.loc.clone())))
+        .collect::<Vec<_>>();
+    node_locations.sort_by_key(|(node_id, _loc)| node_id.0);
+    tile.node_locations = node_locations;
+    let mut level_crossings = node_ids
+        .iter()
+        .filter_map(|node_id| Some((*node_id, node_table.get(node_id)?.level_crossing?)))
+        .collect::<Vec<_>>();
+    level_crossings.sort_by_key(|(node_id, _level_crossing)| node_id.0);
+    tile.level_crossings = level_crossings;
+    tile.streets = streets::group_streets(tile, node_table);
+
+    if splits_at_crossings {
+        tile.crossings = tile
+            .edges
+            .iter()
+            .flat_map(|edge| [edge.from, edge.to])
+            .filter(|node_id| node_table.get(node_id).is_some_and(|node| node.is_crossing))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use quick_xml::events::{BytesStart, Event};
+
+use crate::{
+    error::{GladsheimError, IoContext, ParseContext, Result},
+    osm_parser::{MemberType, OwnedElement, OwnedNode, OwnedRelation, OwnedWay},
+    utils,
+};
+
+/// Opens an OSM XML file, transparently decompressing `.bz2` files
+fn open(fname: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(fname).io_context(|| format!("Failed loading {}", fname.display()))?;
+    if fname.extension().is_some_and(|ext| ext == "bz2") {
+        Ok(Box::new(BufReader::new(bzip2::read::MultiBzDecoder::new(
+            file,
+        ))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// The kind of change applied to the elements of an osmChange section
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ChangeAction {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// Streams the elements of an OSM XML file in batches
+///
+/// The XML format can't be decoded in parallel like pbf blobs, so elements are
+/// handed out in batches that the caller can process on multiple threads.
+pub(crate) fn for_each_batch(
+    fname: &Path,
+    batch_size: usize,
+    mut handle_batch: impl FnMut(Vec<OwnedElement>) -> Result<()>,
+) -> Result<()> {
+    let mut batch = Vec::with_capacity(batch_size);
+    for_each_element(fname, |_action, element| {
+        batch.push(element);
+        if batch.len() >= batch_size {
+            handle_batch(std::mem::replace(
+                &mut batch,
+                Vec::with_capacity(batch_size),
+            ))?;
+        }
+        Ok(())
+    })?;
+    if !batch.is_empty() {
+        handle_batch(batch)?;
+    }
+    Ok(())
+}
+
+/// The time the data of an OSM XML file is from, in seconds since 1970-01-01
+///
+/// Taken from the `timestamp` of `<osm>` or the `osm_base` of Overpass' `<meta>`,
+/// which come before the first element.
+pub(crate) fn source_timestamp(fname: &Path) -> Result<Option<i64>> {
+    let mut reader = quick_xml::Reader::from_reader(open(fname)?);
+    let mut buf = Vec::new();
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .parse_context(|| format!("Failed parsing XML in {}", fname.display()))?;
+        match event {
+            Event::Start(ref start) | Event::Empty(ref start) => {
+                let timestamp = match start.name().as_ref() {
+                    b"osm" => attribute(start, b"timestamp")?,
+                    b"meta" => attribute(start, b"osm_base")?,
+                    b"node" | b"way" | b"relation" => return Ok(None),
+                    _ => None,
+                };
+                if let Some(timestamp) = timestamp.as_deref().and_then(utils::parse_timestamp) {
+                    return Ok(Some(timestamp));
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Reads all elements of an osmChange (.osc) file with their change action
+pub(crate) fn read_change(fname: &Path) -> Result<Vec<(ChangeAction, OwnedElement)>> {
+    let mut changes = Vec::new();
+    for_each_element(fname, |action, element| {
+        let Some(action) = action else {
+            return Err(GladsheimError::parse(format!(
+                "Element outside of create/modify/delete in {}",
+                fname.display()
+            )));
+        };
+        changes.push((action, element));
+        Ok(())
+    })?;
+    Ok(changes)
+}
+
+/// Streams the elements of an OSM XML or osmChange file one at a time
+///
+/// Elements of osmChange files come with the action of their enclosing section.
+fn for_each_element(
+    fname: &Path,
+    mut handle_element: impl FnMut(Option<ChangeAction>, OwnedElement) -> Result<()>,
+) -> Result<()> {
+    let mut reader = quick_xml::Reader::from_reader(open(fname)?);
+    let mut buf = Vec::new();
+    let mut action = None;
+    let mut current: Option<OwnedElement> = None;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .parse_context(|| format!("Failed parsing XML in {}", fname.display()))?;
+        match event {
+            Event::Start(ref start) | Event::Empty(ref start) => {
+                let is_empty = matches!(event, Event::Empty(_));
+                let element = match start.name().as_ref() {
+                    b"create" => {
+                        action = Some(ChangeAction::Create);
+                        None
+                    }
+                    b"modify" => {
+                        action = Some(ChangeAction::Modify);
+                        None
+                    }
+                    b"delete" => {
+                        action = Some(ChangeAction::Delete);
+                        None
+                    }
+                    b"node" => {
+                        // Deleted nodes in change files may come without coordinates
+                        let is_deleted = action == Some(ChangeAction::Delete);
+                        let coordinate = |key| -> Result<f64> {
+                            if is_deleted {
+                                Ok(attribute(start, key)?
+                                    .and_then(|value| value.parse().ok())
+                                    .unwrap_or_default())
+                            } else {
+                                parse_attribute(start, key)
+                            }
+                        };
+                        Some(OwnedElement::Node(OwnedNode {
+                            id: parse_attribute(start, b"id")?,
+                            lat: coordinate(b"lat")?,
+                            lon: coordinate(b"lon")?,
+                            tags: Vec::new(),
+                        }))
+                    }
+                    b"way" => Some(OwnedElement::Way(OwnedWay {
+                        id: parse_attribute(start, b"id")?,
+                        ..Default::default()
+                    })),
+                    b"relation" => Some(OwnedElement::Relation(OwnedRelation {
+                        id: parse_attribute(start, b"id")?,
+                        ..Default::default()
+                    })),
+                    b"member" => {
+                        if let Some(OwnedElement::Relation(relation)) = &mut current {
+                            let member_type = match attribute(start, b"type")?.as_deref() {
+                                Some("node") => MemberType::Node,
+                                Some("way") => MemberType::Way,
+                                Some("relation") => MemberType::Relation,
+                                _ => {
+                                    return Err(GladsheimError::parse(format!(
+                                        "Invalid relation member type in {}",
+                                        fname.display()
+                                    )));
+                                }
+                            };
+                            relation.members.push((
+                                member_type,
+                                parse_attribute(start, b"ref")?,
+                                attribute(start, b"role")?.unwrap_or_default(),
+                            ));
+                        }
+                        None
+                    }
+                    b"nd" => {
+                        if let Some(OwnedElement::Way(way)) = &mut current {
+                            way.refs.push(parse_attribute(start, b"ref")?);
+                        }
+                        None
+                    }
+                    b"tag" => {
+                        let tag = (
+                            attribute(start, b"k")?.unwrap_or_default(),
+                            attribute(start, b"v")?.unwrap_or_default(),
+                        );
+                        match &mut current {
+                            Some(OwnedElement::Node(node)) => node.tags.push(tag),
+                            Some(OwnedElement::Way(way)) => way.tags.push(tag),
+                            Some(OwnedElement::Relation(relation)) => relation.tags.push(tag),
+                            None => {}
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+                if let Some(element) = element {
+                    if is_empty {
+                        handle_element(action, element)?;
+                    } else {
+                        current = Some(element);
+                    }
+                }
+            }
+            Event::End(end) => match end.name().as_ref() {
+                b"node" | b"way" | b"relation" => {
+                    if let Some(element) = current.take() {
+                        handle_element(action, element)?;
+                    }
+                }
+                b"create" | b"modify" | b"delete" => action = None,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+fn attribute(start: &BytesStart, key: &[u8]) -> Result<Option<String>> {
+    for attribute in start.attributes() {
+        let attribute = attribute.parse_context(|| "Invalid XML attribute".to_string())?;
+        if attribute.key.as_ref() == key {
+            return Ok(Some(
+                attribute
+                    .normalized_value(quick_xml::XmlVersion::Implicit1_0)
+                    .parse_context(|| "Invalid XML attribute value".to_string())?
+                    .into_owned(),
+            ));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_attribute<T: std::str::FromStr>(start: &BytesStart, key: &[u8]) -> Result<T> {
+    let Some(value) = attribute(start, key)? else {
+        return Err(GladsheimError::parse(format!(
+            "Missing attribute {} on <{}>",
+            String::from_utf8_lossy(key),
+            String::from_utf8_lossy(start.name().as_ref())
+        )));
+    };
+    match value.parse() {
+        Ok(parsed) => Ok(parsed),
+        Err(_) => Err(GladsheimError::parse(format!(
+            "Invalid value {} for attribute {}",
+            value,
+            String::from_utf8_lossy(key)
+        ))),
+    }
+}
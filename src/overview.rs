@@ -0,0 +1,70 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    NodeId, RoadClass,
+    osm_parser::{self, Node},
+    utils::{self, Quadkey, Tile},
+};
+
+/// A coarser level of tiles holding only the more important roads, so long routes
+/// can cross a country without loading the detailed tiles in between
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) struct OverviewLevel {
+    pub(crate) zoom: u8,
+    /// The least important class of road kept at this level
+    pub(crate) max_class: RoadClass,
+}
+impl std::str::FromStr for OverviewLevel {
+    type Err = String;
+
+    /// Parses `<zoom>:<class>`, e.g. `4:trunk`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let Some((zoom, class)) = value.split_once(':') else {
+            return Err(format!(
+                "Invalid overview level {value}, expected <zoom>:<class>"
+            ));
+        };
+        let zoom = match zoom.parse() {
+            Ok(zoom) if (1..=20).contains(&zoom) => zoom,
+            _ => return Err(format!("Invalid zoom {zoom}, expected 1 to 20")),
+        };
+        let max_class = <RoadClass as clap::ValueEnum>::from_str(class, true)
+            .map_err(|_err| format!("Invalid road class {class}"))?;
+        Ok(Self { zoom, max_class })
+    }
+}
+
+/// The directory the tiles of an overview level are written into, within the
+/// directory of the base tiles
+pub(crate) fn overview_dir(tiles_dir: &Path, zoom: u8) -> PathBuf {
+    tiles_dir.join(format!("overview-{zoom}"))
+}
+
+/// Gathers the edges of the base tiles down to a class of road into the tiles of
+/// an overview level
+///
+/// Only the edges are filled in, the rest of each tile is left for the caller
+/// to annotate like any other tile.
+pub(crate) fn overview_tiles(
+    tiles: &[(Quadkey, Tile)],
+    level: OverviewLevel,
+    node_table: &HashMap<NodeId, Node>,
+) -> Vec<(Quadkey, Tile)> {
+    let collector = utils::ParallelQuadkeyMap::new();
+    tiles
+        .par_iter()
+        .flat_map_iter(|(_quadkey, tile)| &tile.edges)
+        .filter(|edge| edge.class <= level.max_class)
+        .for_each(
+            |edge| match osm_parser::edge_quadkey(edge, node_table, level.zoom) {
+                Ok(quadkey) => collector.insert(quadkey, edge.clone()),
+                Err(err) => println!("ERROR: Could not create quadkey: {}", err),
+            },
+        );
+    collector.collect()
+}
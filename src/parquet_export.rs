@@ -0,0 +1,255 @@
+use std::{collections::HashMap, fs::File, path::Path, sync::Arc};
+
+use clap::ValueEnum;
+use parquet::{
+    basic::{Compression, ZstdLevel},
+    data_type::{BoolType, ByteArray, ByteArrayType, DataType, FloatType, Int32Type, Int64Type},
+    file::{
+        properties::WriterProperties,
+        writer::{SerializedFileWriter, SerializedRowGroupWriter},
+    },
+    format::KeyValue,
+    schema::parser::parse_message_type,
+};
+
+use crate::{
+    Edge,
+    cancel::CancellationToken,
+    error::{GladsheimError, IoContext, Result},
+    geojson::value_name,
+    geometry,
+    osm_parser::Node,
+    profile::Profile,
+    utils::{Quadkey, TileSelector, TileStore},
+};
+
+/// Columns of the edge table, `from` and `to` are renamed as they are SQL keywords
+const SCHEMA: &str = "
+message edge {
+    REQUIRED BYTE_ARRAY quadkey (UTF8);
+    REQUIRED INT64 way_id;
+    REQUIRED INT64 from_node;
+    REQUIRED INT64 to_node;
+    OPTIONAL BYTE_ARRAY name (UTF8);
+    OPTIONAL BYTE_ARRAY ref (UTF8);
+    REQUIRED BYTE_ARRAY class (UTF8);
+    REQUIRED BOOLEAN oneway;
+    REQUIRED BYTE_ARRAY access (UTF8);
+    REQUIRED BOOLEAN is_reversed;
+    REQUIRED INT32 speed_kmh;
+    REQUIRED FLOAT length_m;
+    REQUIRED FLOAT duration_s;
+    REQUIRED BOOLEAN is_island;
+    OPTIONAL BYTE_ARRAY country (UTF8);
+    OPTIONAL BYTE_ARRAY subdivision (UTF8);
+    OPTIONAL BYTE_ARRAY geometry;
+}
+";
+/// GeoParquet metadata, so DuckDB spatial and GeoPandas read `geometry` as WKB
+/// LineStrings in WGS 84 lon/lat
+const GEO_METADATA: &str = r#"{"version":"1.0.0","primary_column":"geometry","columns":{"geometry":{"encoding":"WKB","geometry_types":["LineString"]}}}"#;
+/// Edges buffered before they are written out as a row group
+const ROW_GROUP_EDGES: usize = 128 * 1024;
+
+/// Writes the edges of the selected tiles as a table to a Parquet file, returning
+/// the number of rows
+///
+/// Meant for analytics over the routing graph in DuckDB or Spark, such as data
+/// quality dashboards. Every edge is a row with the attributes that parsing
+/// decides on, the quadkey of its tile and its shape as WKB. Edges whose nodes
+/// aren't located have no geometry.
+pub(crate) fn export_parquet(
+    tiles_dir: &Path,
+    selector: &TileSelector,
+    output: &Path,
+    cancel: &CancellationToken,
+) -> Result<usize> {
+    let store = TileStore::open(tiles_dir)?.select(selector);
+    let write_error = |source| GladsheimError::TileFormat {
+        context: format!("Failed writing Parquet file {}", output.display()),
+        source: Box::new(source),
+    };
+    let schema = parse_message_type(SCHEMA).map_err(write_error)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .set_created_by(format!("gladsheim {}", env!("CARGO_PKG_VERSION")))
+        .build();
+    let file =
+        File::create(output).io_context(|| format!("Failed creating file {}", output.display()))?;
+    let mut writer = SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties))
+        .map_err(write_error)?;
+    writer.append_key_value_metadata(KeyValue::new("geo".to_string(), GEO_METADATA.to_string()));
+
+    let mut columns = Columns::default();
+    let mut num_rows = 0;
+    for (index, tile) in store.iter().enumerate() {
+        cancel.check(|| format!("exporting {} of {} tiles", index, store.len()))?;
+        let (quadkey, tile) = tile?;
+        let node_table = tile
+            .node_locations
+            .into_iter()
+            .map(|(node_id, loc)| {
+                (
+                    node_id,
+                    Node {
+                        loc,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        for edge in &tile.edges {
+            let coords = geometry::edge_coordinates(edge, &node_table);
+            columns.push(edge, &quadkey, &coords);
+            if columns.len() >= ROW_GROUP_EDGES {
+                num_rows += columns.len();
+                std::mem::take(&mut columns)
+                    .write(writer.next_row_group().map_err(write_error)?)
+                    .map_err(write_error)?;
+            }
+        }
+    }
+    if columns.len() > 0 {
+        num_rows += columns.len();
+        columns
+            .write(writer.next_row_group().map_err(write_error)?)
+            .map_err(write_error)?;
+    }
+    writer.close().map_err(write_error)?;
+    Ok(num_rows)
+}
+
+/// The values of a row group, column by column in the order of `SCHEMA`
+///
+/// Optional columns only hold the present values, with a definition level per
+/// row telling which rows have one.
+#[derive(Default)]
+struct Columns {
+    quadkey: Vec<ByteArray>,
+    way_id: Vec<i64>,
+    from_node: Vec<i64>,
+    to_node: Vec<i64>,
+    name: OptionalColumn,
+    road_ref: OptionalColumn,
+    class: Vec<ByteArray>,
+    oneway: Vec<bool>,
+    access: Vec<ByteArray>,
+    is_reversed: Vec<bool>,
+    speed_kmh: Vec<i32>,
+    length_m: Vec<f32>,
+    duration_s: Vec<f32>,
+    is_island: Vec<bool>,
+    country: OptionalColumn,
+    subdivision: OptionalColumn,
+    geometry: OptionalColumn,
+}
+impl Columns {
+    fn len(&self) -> usize {
+        self.way_id.len()
+    }
+
+    /// Adds an edge with its coordinates as `(lat, lon)`
+    fn push(&mut self, edge: &Edge, quadkey: &Quadkey, coords: &[(f64, f64)]) {
+        let string = |value: &str| ByteArray::from(value.as_bytes().to_vec());
+        self.quadkey.push(string(&quadkey.0));
+        self.way_id.push(edge.way_id.0);
+        self.from_node.push(edge.from.0);
+        self.to_node.push(edge.to.0);
+        self.name.push(edge.name.as_deref().map(string));
+        self.road_ref.push(edge.road_ref.as_deref().map(string));
+        self.class.push(string(&value_name(edge.class)));
+        self.oneway.push(edge.is_oneway);
+        let access = Profile::value_variants()
+            .iter()
+            .filter(|profile| edge.access.contains(**profile))
+            .map(|profile| value_name(*profile))
+            .collect::<Vec<_>>();
+        self.access.push(string(&access.join(",")));
+        self.is_reversed.push(edge.is_reversed);
+        self.speed_kmh.push(edge.speed_kmh.into());
+        self.length_m.push(edge.length_m);
+        self.duration_s.push(edge.duration_s);
+        self.is_island.push(edge.is_island);
+        self.country.push(edge.country.as_deref().map(string));
+        self.subdivision
+            .push(edge.subdivision.as_deref().map(string));
+        self.geometry
+            .push((coords.len() >= 2).then(|| ByteArray::from(wkb_line_string(coords))));
+    }
+
+    fn write(
+        self,
+        mut row_group: SerializedRowGroupWriter<'_, File>,
+    ) -> parquet::errors::Result<()> {
+        write_column::<ByteArrayType>(&mut row_group, &self.quadkey, None)?;
+        write_column::<Int64Type>(&mut row_group, &self.way_id, None)?;
+        write_column::<Int64Type>(&mut row_group, &self.from_node, None)?;
+        write_column::<Int64Type>(&mut row_group, &self.to_node, None)?;
+        self.name.write(&mut row_group)?;
+        self.road_ref.write(&mut row_group)?;
+        write_column::<ByteArrayType>(&mut row_group, &self.class, None)?;
+        write_column::<BoolType>(&mut row_group, &self.oneway, None)?;
+        write_column::<ByteArrayType>(&mut row_group, &self.access, None)?;
+        write_column::<BoolType>(&mut row_group, &self.is_reversed, None)?;
+        write_column::<Int32Type>(&mut row_group, &self.speed_kmh, None)?;
+        write_column::<FloatType>(&mut row_group, &self.length_m, None)?;
+        write_column::<FloatType>(&mut row_group, &self.duration_s, None)?;
+        write_column::<BoolType>(&mut row_group, &self.is_island, None)?;
+        self.country.write(&mut row_group)?;
+        self.subdivision.write(&mut row_group)?;
+        self.geometry.write(&mut row_group)?;
+        row_group.close()?;
+        Ok(())
+    }
+}
+
+/// A nullable byte array column
+#[derive(Default)]
+struct OptionalColumn {
+    values: Vec<ByteArray>,
+    /// 1 for rows with a value, 0 for nulls
+    def_levels: Vec<i16>,
+}
+impl OptionalColumn {
+    fn push(&mut self, value: Option<ByteArray>) {
+        self.def_levels.push(value.is_some().into());
+        self.values.extend(value);
+    }
+
+    fn write(
+        &self,
+        row_group: &mut SerializedRowGroupWriter<'_, File>,
+    ) -> parquet::errors::Result<()> {
+        write_column::<ByteArrayType>(row_group, &self.values, Some(&self.def_levels))
+    }
+}
+
+/// Writes the next column of a row group
+fn write_column<T: DataType>(
+    row_group: &mut SerializedRowGroupWriter<'_, File>,
+    values: &[T::T],
+    def_levels: Option<&[i16]>,
+) -> parquet::errors::Result<()> {
+    let Some(mut column) = row_group.next_column()? else {
+        return Err(parquet::errors::ParquetError::General(
+            "More columns written than in the schema".to_string(),
+        ));
+    };
+    column.typed::<T>().write_batch(values, def_levels, None)?;
+    column.close()
+}
+
+/// Encodes coordinates given as `(lat, lon)` as a little endian WKB LineString
+fn wkb_line_string(coords: &[(f64, f64)]) -> Vec<u8> {
+    const LITTLE_ENDIAN: u8 = 1;
+    const LINE_STRING: u32 = 2;
+    let mut wkb = Vec::with_capacity(9 + coords.len() * 16);
+    wkb.push(LITTLE_ENDIAN);
+    wkb.extend_from_slice(&LINE_STRING.to_le_bytes());
+    wkb.extend_from_slice(&(coords.len() as u32).to_le_bytes());
+    for (lat, lon) in coords {
+        wkb.extend_from_slice(&lon.to_le_bytes());
+        wkb.extend_from_slice(&lat.to_le_bytes());
+    }
+    wkb
+}
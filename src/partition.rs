@@ -0,0 +1,330 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    NodeId,
+    cancel::CancellationToken,
+    error::{GladsheimError, IoContext, Result},
+    hierarchy::{self, Graph},
+    osm_parser::Loc,
+    utils,
+};
+
+/// Name of the partition file in a tile directory
+pub(crate) const PARTITION_FILE: &str = "partition.bin";
+
+/// Share of the nodes at each end of a direction that are kept on their side
+/// of a cut, which bounds how unbalanced a bisection can be
+const TERMINAL_SHARE: f64 = 0.25;
+
+/// The nodes of a tile directory split into nested cells on several levels,
+/// the basis of customizable route planning
+///
+/// Written into the tile directory as `partition.bin`.
+#[derive(Debug, bincode::Encode, bincode::Decode)]
+pub(crate) struct Partition {
+    /// Most nodes in a cell of every level, smallest first
+    pub(crate) cell_sizes: Vec<u32>,
+    /// The partitioned nodes, sorted by id
+    pub(crate) node_ids: Vec<NodeId>,
+    /// For every level, the cell of every node, in the order of `node_ids`.
+    /// Every cell lies within a single cell of the level above it
+    pub(crate) cells: Vec<Vec<u32>>,
+}
+
+/// Numbers of one level of a partition, for reporting
+pub(crate) struct LevelStats {
+    pub(crate) cell_size: u32,
+    pub(crate) num_cells: usize,
+    pub(crate) max_cell_nodes: usize,
+    /// Arcs between nodes in different cells
+    pub(crate) num_cut_arcs: usize,
+}
+
+/// Numbers of a built partition, for reporting
+pub(crate) struct PartitionStats {
+    pub(crate) num_nodes: usize,
+    pub(crate) num_arcs: usize,
+    pub(crate) levels: Vec<LevelStats>,
+}
+
+/// Partitions the nodes of a tile directory into cells of at most `cell_sizes`
+/// nodes, one level per size from the smallest, writing `partition.bin` into it
+///
+/// Cells are bisected by inertial flow, top level first: the nodes are sorted
+/// along a few directions, and for each a minimum cut separates a share of
+/// them at either end, the smallest of those cuts splitting the cell. The
+/// cells of a level are split further into the cells of the level below. The
+/// partition is of the roads the profile may use on any day, so it remains
+/// valid as roads open, while destination-only zones are left out as in the
+/// contraction hierarchy.
+pub(crate) fn build_partition(
+    tiles_dir: &Path,
+    cell_sizes: &[u32],
+    cancel: &CancellationToken,
+) -> Result<PartitionStats> {
+    if cell_sizes.is_empty()
+        || cell_sizes[0] == 0
+        || cell_sizes.windows(2).any(|sizes| sizes[0] >= sizes[1])
+    {
+        return Err(GladsheimError::parse(format!(
+            "Invalid cell sizes {:?}, expected increasing positive sizes",
+            cell_sizes
+        )));
+    }
+    let start_time = std::time::Instant::now();
+    let (_store, tiles) = hierarchy::read_base_tiles(tiles_dir, cancel)?;
+    let graph = Graph::of_tiles(&tiles, u32::MAX);
+    let neighbours = undirected_neighbours(&graph);
+    println!(
+        "INFO: Read {} tiles with {} nodes and {} arcs in {}ms",
+        tiles.len(),
+        graph.node_ids.len(),
+        graph.num_arcs(),
+        start_time.elapsed().as_millis()
+    );
+
+    let mut order = (0..graph.node_ids.len()).collect::<Vec<_>>();
+    order.sort_by_key(|node| graph.node_ids[*node].0);
+    let mut partition = Partition {
+        cell_sizes: cell_sizes.to_vec(),
+        node_ids: order.iter().map(|node| graph.node_ids[*node]).collect(),
+        cells: vec![Vec::new(); cell_sizes.len()],
+    };
+    let mut stats = PartitionStats {
+        num_nodes: graph.node_ids.len(),
+        num_arcs: graph.num_arcs(),
+        levels: Vec::new(),
+    };
+    let mut cells = vec![(0..graph.node_ids.len() as u32).collect::<Vec<_>>()];
+    for (level, cell_size) in cell_sizes.iter().enumerate().rev() {
+        let start_time = std::time::Instant::now();
+        let mut split = Vec::new();
+        for cell in cells {
+            split.extend(split_cell(
+                &neighbours,
+                &graph.locations,
+                cell,
+                *cell_size as usize,
+                cancel,
+            )?);
+        }
+        cells = split;
+        let mut cell_of = vec![0; graph.node_ids.len()];
+        for (index, cell) in cells.iter().enumerate() {
+            for node in cell {
+                cell_of[*node as usize] = index as u32;
+            }
+        }
+        let num_cut_arcs = graph
+            .outgoing
+            .iter()
+            .enumerate()
+            .flat_map(|(from, arcs)| arcs.iter().map(move |(to, _duration_s)| (from, *to)))
+            .filter(|(from, to)| cell_of[*from] != cell_of[*to as usize])
+            .count();
+        println!(
+            "INFO: Split level {} into {} cells in {}ms",
+            level,
+            cells.len(),
+            start_time.elapsed().as_millis()
+        );
+        partition.cells[level] = order.iter().map(|node| cell_of[*node]).collect();
+        stats.levels.push(LevelStats {
+            cell_size: *cell_size,
+            num_cells: cells.len(),
+            max_cell_nodes: cells.iter().map(Vec::len).max().unwrap_or_default(),
+            num_cut_arcs,
+        });
+    }
+    stats.levels.reverse();
+    utils::write_encoded(&tiles_dir.join(PARTITION_FILE), &partition)?;
+    Ok(stats)
+}
+
+/// Reads the partition written into a tile directory by `build_partition`
+pub(crate) fn read_partition(tiles_dir: &Path) -> Result<Partition> {
+    let fname = tiles_dir.join(PARTITION_FILE);
+    if !fname.exists() {
+        return Err(GladsheimError::parse(format!(
+            "No partition in {}, run Partition first",
+            tiles_dir.display()
+        )));
+    }
+    let bytes =
+        std::fs::read(&fname).io_context(|| format!("Failed reading file {}", fname.display()))?;
+    let (partition, _len) = bincode::decode_from_slice(&bytes, bincode::config::standard())
+        .map_err(|source| GladsheimError::TileFormat {
+            context: format!("Failed decoding {}", fname.display()),
+            source: Box::new(source),
+        })?;
+    Ok(partition)
+}
+
+/// The nodes adjacent to every node in either direction, without repetitions
+fn undirected_neighbours(graph: &Graph) -> Vec<Vec<u32>> {
+    graph
+        .outgoing
+        .iter()
+        .zip(&graph.incoming)
+        .enumerate()
+        .map(|(node, (outgoing, incoming))| {
+            let mut neighbours = outgoing
+                .iter()
+                .chain(incoming)
+                .map(|(other, _duration_s)| *other)
+                .filter(|other| *other as usize != node)
+                .collect::<Vec<_>>();
+            neighbours.sort_unstable();
+            neighbours.dedup();
+            neighbours
+        })
+        .collect()
+}
+
+/// Bisects a cell until none of its parts has more than `max_size` nodes,
+/// returning the parts with neighbouring ones next to each other
+fn split_cell(
+    neighbours: &[Vec<u32>],
+    locations: &[Loc],
+    cell: Vec<u32>,
+    max_size: usize,
+    cancel: &CancellationToken,
+) -> Result<Vec<Vec<u32>>> {
+    let mut parts = Vec::new();
+    let mut pending = vec![cell];
+    while let Some(cell) = pending.pop() {
+        if cell.len() <= max_size {
+            parts.push(cell);
+            continue;
+        }
+        cancel.check(|| format!("bisecting a cell of {} nodes", cell.len()))?;
+        let (first, second) = bisect(neighbours, locations, &cell);
+        pending.push(second);
+        pending.push(first);
+    }
+    Ok(parts)
+}
+
+/// Splits a cell of at least two nodes in two by inertial flow, keeping the
+/// smallest cut of those along each direction and the most balanced of equal ones
+fn bisect(neighbours: &[Vec<u32>], locations: &[Loc], cell: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let local = cell
+        .iter()
+        .enumerate()
+        .map(|(index, node)| (*node, index as u32))
+        .collect::<HashMap<_, _>>();
+    let mut edges = Vec::new();
+    let mut adjacent = vec![Vec::new(); cell.len()];
+    for (index, node) in cell.iter().enumerate() {
+        for neighbour in &neighbours[*node as usize] {
+            let Some(other) = local.get(neighbour).map(|other| *other as usize) else {
+                continue;
+            };
+            if other > index {
+                adjacent[index].push((other as u32, edges.len() as u32, 1));
+                adjacent[other].push((index as u32, edges.len() as u32, -1));
+                edges.push((index as u32, other as u32));
+            }
+        }
+    }
+
+    let mean_lat = cell
+        .iter()
+        .map(|node| locations[*node as usize].lat)
+        .sum::<f64>()
+        / cell.len() as f64;
+    let lon_scale = mean_lat.to_radians().cos();
+    let num_terminals = ((cell.len() as f64 * TERMINAL_SHARE) as usize).max(1);
+    let mut best: Option<(usize, usize, Vec<bool>)> = None;
+    for (lat_weight, lon_weight) in [(1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, -1.0)] {
+        let projection = |node: u32| {
+            let loc = &locations[node as usize];
+            lat_weight * loc.lat + lon_weight * lon_scale * loc.lon
+        };
+        let mut order = (0..cell.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| projection(cell[*a]).total_cmp(&projection(cell[*b])));
+        let mut terminals = vec![Terminal::None; cell.len()];
+        for index in &order[..num_terminals] {
+            terminals[*index] = Terminal::Source;
+        }
+        for index in &order[order.len() - num_terminals..] {
+            terminals[*index] = Terminal::Sink;
+        }
+        let (cut, source_side) = min_cut(&adjacent, edges.len(), &terminals);
+        let num_source_side = source_side.iter().filter(|is_source| **is_source).count();
+        let imbalance = num_source_side.abs_diff(cell.len() - num_source_side);
+        if best.as_ref().is_none_or(|(best_cut, best_imbalance, _)| {
+            (cut, imbalance) < (*best_cut, *best_imbalance)
+        }) {
+            best = Some((cut, imbalance, source_side));
+        }
+    }
+
+    let (_cut, _imbalance, source_side) = best.expect("a direction was tried");
+    let (mut first, mut second) = (Vec::new(), Vec::new());
+    for (node, is_source) in cell.iter().zip(source_side) {
+        if is_source {
+            first.push(*node);
+        } else {
+            second.push(*node);
+        }
+    }
+    (first, second)
+}
+
+/// Which end of a direction a node of a cell is kept at while cutting
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Terminal {
+    None,
+    Source,
+    Sink,
+}
+
+/// The fewest edges separating the sources from the sinks, each edge carrying
+/// one unit of flow in either direction, and the nodes on the side of the sources
+///
+/// Augments along shortest paths in the residual graph until no sink can be
+/// reached, the nodes still reachable then being the side of the sources.
+fn min_cut(
+    adjacent: &[Vec<(u32, u32, i8)>],
+    num_edges: usize,
+    terminals: &[Terminal],
+) -> (usize, Vec<bool>) {
+    // Flow along every edge from its first node to its second one
+    let mut flow = vec![0i8; num_edges];
+    let mut cut = 0;
+    loop {
+        let mut reached = terminals
+            .iter()
+            .map(|terminal| *terminal == Terminal::Source)
+            .collect::<Vec<_>>();
+        let mut parent = vec![(u32::MAX, 0, 0); adjacent.len()];
+        let mut queue = (0..adjacent.len() as u32)
+            .filter(|node| reached[*node as usize])
+            .collect::<std::collections::VecDeque<_>>();
+        let mut sink = None;
+        'search: while let Some(node) = queue.pop_front() {
+            for (other, edge, direction) in &adjacent[node as usize] {
+                if reached[*other as usize] || direction * flow[*edge as usize] >= 1 {
+                    continue;
+                }
+                reached[*other as usize] = true;
+                parent[*other as usize] = (node, *edge, *direction);
+                if terminals[*other as usize] == Terminal::Sink {
+                    sink = Some(*other);
+                    break 'search;
+                }
+                queue.push_back(*other);
+            }
+        }
+        let Some(mut node) = sink else {
+            return (cut, reached);
+        };
+        while terminals[node as usize] != Terminal::Source {
+            let (previous, edge, direction) = parent[node as usize];
+            flow[edge as usize] += direction;
+            node = previous;
+        }
+        cut += 1;
+    }
+}
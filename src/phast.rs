@@ -0,0 +1,372 @@
+use std::{
+    collections::{BinaryHeap, HashMap},
+    io::Write,
+    path::Path,
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    NodeId,
+    cancel::CancellationToken,
+    error::{GladsheimError, IoContext, Result},
+    hierarchy::{self, Graph, GraphEntry},
+    osm_parser::Loc,
+    routing::Coordinate,
+    utils,
+};
+
+/// A contraction hierarchy laid out for one-to-all searches by PHAST
+///
+/// The nodes are numbered by descending rank, so the sweep down the hierarchy
+/// runs over the nodes and their arcs in the order they're stored in.
+pub(crate) struct Phast {
+    /// The nodes, highest ranked first
+    pub(crate) node_ids: Vec<NodeId>,
+    pub(crate) locations: Vec<Loc>,
+    /// Where the arcs of every node start in `up_arcs`, with one more entry for
+    /// where those of the last node end
+    up_first: Vec<u32>,
+    /// Arcs from every node up to higher ranked ones, with their head and duration
+    up_arcs: Vec<(u32, f64)>,
+    /// Where the arcs of every node start in `down_arcs`, like `up_first`
+    down_first: Vec<u32>,
+    /// Arcs into every node down from higher ranked ones, with their tail and duration
+    down_arcs: Vec<(u32, f64)>,
+}
+impl Phast {
+    /// Reads the edges and the contraction hierarchy of all tiles of a directory
+    ///
+    /// Like the hierarchy, the arcs cover the roads the profile may use on the
+    /// day it was built, except those of destination-only zones.
+    pub(crate) fn read(tiles_dir: &Path, cancel: &CancellationToken) -> Result<Self> {
+        let (_store, tiles) = hierarchy::read_base_tiles(tiles_dir, cancel)?;
+        let Some(built_on) = tiles
+            .iter()
+            .find_map(|(_quadkey, tile)| Some(tile.hierarchy.as_ref()?.built_on))
+        else {
+            return Err(GladsheimError::parse(format!(
+                "No contraction hierarchy in {}, build one with BuildCh",
+                tiles_dir.display()
+            )));
+        };
+        let graph = Graph::of_tiles(&tiles, built_on);
+        let ranks = tiles
+            .iter()
+            .flat_map(|(_quadkey, tile)| &tile.hierarchy)
+            .flat_map(|hierarchy| hierarchy.ranks.iter().copied())
+            .collect::<HashMap<_, _>>();
+        let mut order = (0..graph.node_ids.len() as u32).collect::<Vec<_>>();
+        order.sort_by_key(|node| {
+            std::cmp::Reverse(ranks.get(&graph.node_ids[*node as usize]).copied())
+        });
+        let mut positions = vec![0u32; order.len()];
+        for (position, node) in order.iter().enumerate() {
+            positions[*node as usize] = position as u32;
+        }
+        let position_of = graph
+            .node_ids
+            .iter()
+            .zip(&positions)
+            .map(|(node_id, position)| (*node_id, *position))
+            .collect::<HashMap<_, _>>();
+
+        let mut up: Vec<Vec<(u32, f64)>> = vec![Vec::new(); order.len()];
+        let mut down: Vec<Vec<(u32, f64)>> = vec![Vec::new(); order.len()];
+        let mut add_arc = |from: u32, to: u32, duration_s: f64| {
+            // Higher ranked nodes come first
+            if to < from {
+                up[from as usize].push((to, duration_s));
+            } else {
+                down[to as usize].push((from, duration_s));
+            }
+        };
+        for (node, arcs) in graph.outgoing.iter().enumerate() {
+            for (head, duration_s) in arcs {
+                add_arc(positions[node], positions[*head as usize], *duration_s);
+            }
+        }
+        // Shortcuts are stored in the tiles of both their ends
+        let mut shortcuts = HashMap::new();
+        for shortcut in tiles
+            .iter()
+            .flat_map(|(_quadkey, tile)| &tile.hierarchy)
+            .flat_map(|hierarchy| &hierarchy.shortcuts)
+        {
+            let (Some(from), Some(to)) = (
+                position_of.get(&shortcut.from),
+                position_of.get(&shortcut.to),
+            ) else {
+                continue;
+            };
+            shortcuts.insert((*from, *to), f64::from(shortcut.duration_s));
+        }
+        for ((from, to), duration_s) in shortcuts {
+            add_arc(from, to, duration_s);
+        }
+
+        let (up_first, up_arcs) = compressed(up);
+        let (down_first, down_arcs) = compressed(down);
+        Ok(Self {
+            node_ids: order
+                .iter()
+                .map(|node| graph.node_ids[*node as usize])
+                .collect(),
+            locations: order
+                .iter()
+                .map(|node| graph.locations[*node as usize].clone())
+                .collect(),
+            up_first,
+            up_arcs,
+            down_first,
+            down_arcs,
+        })
+    }
+
+    pub(crate) fn num_arcs(&self) -> usize {
+        self.up_arcs.len() + self.down_arcs.len()
+    }
+
+    /// The node closest to a location, `None` when there are no nodes
+    pub(crate) fn nearest_node(&self, location: Coordinate) -> Option<u32> {
+        self.locations
+            .iter()
+            .map(|loc| utils::haversine_distance(location.lat, location.lon, loc.lat, loc.lon))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(node, _distance)| node as u32)
+    }
+
+    /// Seconds from a node to every node, infinite for those without a way there
+    ///
+    /// A Dijkstra search up the hierarchy from the node finds the fastest ways
+    /// to the nodes ranked above it, and a single sweep over all nodes from the
+    /// highest ranked down then takes every arc down into a node from the
+    /// nodes above it, whose durations are final by then.
+    pub(crate) fn durations_from(&self, source: u32) -> Vec<f64> {
+        let mut durations = vec![f64::INFINITY; self.node_ids.len()];
+        durations[source as usize] = 0.0;
+        let mut queue = BinaryHeap::new();
+        queue.push(GraphEntry {
+            duration: 0.0,
+            node: source,
+        });
+        while let Some(GraphEntry { duration, node }) = queue.pop() {
+            if duration > durations[node as usize] {
+                continue;
+            }
+            for &(head, arc_duration) in self.arcs(&self.up_first, &self.up_arcs, node) {
+                let duration = duration + arc_duration;
+                if duration < durations[head as usize] {
+                    durations[head as usize] = duration;
+                    queue.push(GraphEntry {
+                        duration,
+                        node: head,
+                    });
+                }
+            }
+        }
+        for node in 0..self.node_ids.len() as u32 {
+            let mut duration = durations[node as usize];
+            for &(tail, arc_duration) in self.arcs(&self.down_first, &self.down_arcs, node) {
+                duration = duration.min(durations[tail as usize] + arc_duration);
+            }
+            durations[node as usize] = duration;
+        }
+        durations
+    }
+
+    /// Seconds from every source node to every node, a sweep per source on the thread pool
+    pub(crate) fn durations_from_all(
+        &self,
+        sources: &[u32],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Vec<f64>>> {
+        sources
+            .par_iter()
+            .map(|source| {
+                cancel.check(|| format!("sweeping from {} sources", sources.len()))?;
+                Ok(self.durations_from(*source))
+            })
+            .collect()
+    }
+
+    fn arcs<'a>(&self, first: &[u32], arcs: &'a [(u32, f64)], node: u32) -> &'a [(u32, f64)] {
+        &arcs[first[node as usize] as usize..first[node as usize + 1] as usize]
+    }
+}
+
+/// The arcs of every node one after the other, with where those of every node start
+fn compressed(arcs: Vec<Vec<(u32, f64)>>) -> (Vec<u32>, Vec<(u32, f64)>) {
+    let mut first = Vec::with_capacity(arcs.len() + 1);
+    first.push(0);
+    let mut flat = Vec::new();
+    for node_arcs in arcs {
+        flat.extend(node_arcs);
+        first.push(flat.len() as u32);
+    }
+    (first, flat)
+}
+
+/// Writes the seconds from every source to every node as tab separated values,
+/// a line per node with its id and location, leaving out the nodes no source
+/// reaches and leaving a field empty where a source doesn't reach the node
+pub(crate) fn write_durations(
+    writer: &mut impl Write,
+    phast: &Phast,
+    durations: &[Vec<f64>],
+) -> Result<()> {
+    let context = || "Failed writing the travel times".to_string();
+    write!(writer, "node_id\tlat\tlon").io_context(context)?;
+    for index in 0..durations.len() {
+        write!(writer, "\tseconds_{}", index).io_context(context)?;
+    }
+    writeln!(writer).io_context(context)?;
+    for (node, (node_id, loc)) in phast.node_ids.iter().zip(&phast.locations).enumerate() {
+        if durations
+            .iter()
+            .all(|durations| durations[node].is_infinite())
+        {
+            continue;
+        }
+        write!(writer, "{}\t{:.7}\t{:.7}", node_id.0, loc.lat, loc.lon).io_context(context)?;
+        for durations in durations {
+            if durations[node].is_finite() {
+                write!(writer, "\t{:.1}", durations[node]).io_context(context)?;
+            } else {
+                write!(writer, "\t").io_context(context)?;
+            }
+        }
+        writeln!(writer).io_context(context)?;
+    }
+    writer.flush().io_context(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Edge,
+        adjacency::Adjacency,
+        osm_parser::{self, ParseOptions},
+        profile::{Profile, ProfileMask},
+        utils::{Quadkey, Tile},
+    };
+
+    const ZOOM: u8 = 10;
+
+    /// A tile of a four by four grid of streets with different durations,
+    /// every other avenue oneway
+    fn grid_tile() -> Tile {
+        let node_locations = (0..16)
+            .map(|id| {
+                let (row, col) = (id / 4, id % 4);
+                let loc = Loc {
+                    lat: 59.33 + row as f64 * 0.002,
+                    lon: 18.06 + col as f64 * 0.004,
+                };
+                (NodeId(id + 1), loc)
+            })
+            .collect::<Vec<_>>();
+        let mut car = ProfileMask::default();
+        car.insert(Profile::Car);
+        let edge = |from: i64, to: i64, duration_s: f32, is_oneway: bool| Edge {
+            from: NodeId(from + 1),
+            to: NodeId(to + 1),
+            nodes: vec![NodeId(from + 1), NodeId(to + 1)],
+            access: car,
+            oneway: if is_oneway {
+                car
+            } else {
+                ProfileMask::default()
+            },
+            is_oneway,
+            duration_s,
+            ..Default::default()
+        };
+        let mut edges = Vec::new();
+        for row in 0..4 {
+            for col in 0..3 {
+                let duration_s = [10.0, 30.0, 15.0, 40.0][row as usize];
+                edges.push(edge(row * 4 + col, row * 4 + col + 1, duration_s, false));
+            }
+        }
+        for col in 0..4 {
+            for row in 0..3 {
+                let (from, to) = (row * 4 + col, (row + 1) * 4 + col);
+                let (from, to) = if col == 3 { (to, from) } else { (from, to) };
+                edges.push(edge(from, to, 20.0 + col as f32, col % 2 == 1));
+            }
+        }
+        Tile {
+            zoom: ZOOM,
+            adjacency: Adjacency::of_edges(&node_locations, &edges),
+            node_locations,
+            edges,
+            ..Default::default()
+        }
+    }
+
+    /// The fastest durations from a node to all others, by plain Dijkstra
+    fn durations_from(graph: &Graph, start: u32) -> Vec<f64> {
+        let mut durations = vec![f64::INFINITY; graph.node_ids.len()];
+        let mut queue = BinaryHeap::new();
+        durations[start as usize] = 0.0;
+        queue.push(GraphEntry {
+            duration: 0.0,
+            node: start,
+        });
+        while let Some(GraphEntry { duration, node }) = queue.pop() {
+            if duration > durations[node as usize] {
+                continue;
+            }
+            for &(head, arc_duration) in &graph.outgoing[node as usize] {
+                if duration + arc_duration < durations[head as usize] {
+                    durations[head as usize] = duration + arc_duration;
+                    queue.push(GraphEntry {
+                        duration: duration + arc_duration,
+                        node: head,
+                    });
+                }
+            }
+        }
+        durations
+    }
+
+    #[test]
+    fn sweeps_match_dijkstra() {
+        let tiles_dir =
+            std::env::temp_dir().join(format!("gladsheim-phast-{}", std::process::id()));
+        let tile = grid_tile();
+        let quadkey = utils::lat_lon_to_quadkey(59.33, 18.06, ZOOM).unwrap();
+        let cancel = CancellationToken::default();
+        let graph = Graph::of_tiles(&[(Quadkey(quadkey.clone()), grid_tile())], 20260101);
+        osm_parser::write_tiles(
+            &tiles_dir,
+            &[(Quadkey(quadkey), tile)],
+            &ParseOptions::default(),
+            &cancel,
+        )
+        .unwrap();
+        let stats = hierarchy::build_hierarchy(&tiles_dir, &cancel).unwrap();
+        let phast = Phast::read(&tiles_dir, &cancel);
+        std::fs::remove_dir_all(&tiles_dir).unwrap();
+        let phast = phast.unwrap();
+        assert!(stats.num_shortcuts > 0);
+        assert_eq!(phast.node_ids.len(), graph.node_ids.len());
+
+        let node_of = |node_id: NodeId| graph.node_ids.iter().position(|id| *id == node_id);
+        let sources = (0..phast.node_ids.len() as u32).collect::<Vec<_>>();
+        let all_durations = phast.durations_from_all(&sources, &cancel).unwrap();
+        for (source, durations) in sources.iter().zip(&all_durations) {
+            let expected = durations_from(
+                &graph,
+                node_of(phast.node_ids[*source as usize]).unwrap() as u32,
+            );
+            for (node, duration) in durations.iter().enumerate() {
+                let expected = expected[node_of(phast.node_ids[node]).unwrap()];
+                assert_eq!(*duration, expected, "{source} to {node}");
+            }
+        }
+    }
+}
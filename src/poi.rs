@@ -0,0 +1,83 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    NodeId,
+    error::Result,
+    osm_parser::SimpleNode,
+    utils::{self, Quadkey, TileNaming},
+};
+
+/// The top level tag a point of interest was found by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) enum PoiCategory {
+    Amenity,
+    Shop,
+    Tourism,
+}
+
+/// A point of interest, for nearby search
+#[derive(Clone, Debug, bincode::Encode, bincode::Decode)]
+pub(crate) struct Poi {
+    pub(crate) id: NodeId,
+    pub(crate) lat: f64,
+    pub(crate) lon: f64,
+    pub(crate) category: PoiCategory,
+    /// The value of the category tag, e.g. `restaurant` for `amenity=restaurant`
+    pub(crate) kind: String,
+    pub(crate) name: Option<String>,
+}
+
+/// The points of interest within a tile, written as `<quadkey>.pois`
+#[derive(Debug, Default, bincode::Encode, bincode::Decode)]
+pub(crate) struct PoiTile {
+    pub(crate) pois: Vec<Poi>,
+}
+
+/// Reads a node as a point of interest, `None` if it has no category tag
+pub(crate) fn parse_poi<N: SimpleNode>(node: &N) -> Option<Poi> {
+    let mut category = None;
+    let mut name = None;
+    for (key, value) in node.tags() {
+        match key {
+            // Amenities take precedence over shops, which take precedence over tourism
+            "amenity" => category = Some((PoiCategory::Amenity, value)),
+            "shop" if category.is_none_or(|(category, _)| category == PoiCategory::Tourism) => {
+                category = Some((PoiCategory::Shop, value))
+            }
+            "tourism" if category.is_none() => category = Some((PoiCategory::Tourism, value)),
+            "name" => name = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    let (category, kind) = category?;
+    Some(Poi {
+        id: NodeId(node.id()),
+        lat: node.lat(),
+        lon: node.lon(),
+        category,
+        kind: kind.to_string(),
+        name,
+    })
+}
+
+/// Writes points of interest into per-tile files at a zoom level, returning the number of files
+pub(crate) fn write_pois(
+    output_dir: &Path,
+    naming: TileNaming,
+    zoom: u8,
+    pois: Vec<Poi>,
+) -> Result<usize> {
+    let mut tiles: HashMap<Quadkey, PoiTile> = HashMap::new();
+    for poi in pois {
+        match utils::lat_lon_to_quadkey(poi.lat, poi.lon, zoom) {
+            Ok(quadkey) => tiles.entry(Quadkey(quadkey)).or_default().pois.push(poi),
+            Err(err) => println!("WARN: Skipping point of interest {}: {}", poi.id.0, err),
+        }
+    }
+    for (quadkey, tile) in &mut tiles {
+        tile.pois.sort_by_key(|poi| poi.id.0);
+        let fname = utils::tile_path(output_dir, quadkey, naming).with_extension("pois");
+        utils::write_encoded(&fname, tile)?;
+    }
+    Ok(tiles.len())
+}
@@ -0,0 +1,279 @@
+use clap::ValueEnum;
+
+use crate::{RoadClass, Sidewalk, Smoothness, osm_parser::WayTags};
+
+/// The mode of transport that the routing tiles are built for
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, bincode::Encode, bincode::Decode,
+)]
+pub(crate) enum Profile {
+    #[default]
+    Car,
+    Foot,
+    /// Buses and other public-service vehicles
+    Bus,
+}
+
+/// Speed in km/h buses keep to on roads with higher limits
+const BUS_MAX_SPEED_KMH: u8 = 90;
+/// Speed in km/h cars keep to on roads with higher limits, the highest limit
+/// commonly tagged on roads
+const CAR_MAX_SPEED_KMH: u8 = 140;
+/// Speed in km/h pedestrians are routed at on roads without a sidewalk of
+/// their own, so routes prefer roads with one and footways
+const FOOT_ROADWAY_SPEED_KMH: u8 = 4;
+/// Typical speed in km/h of ferries, including the time spent docking
+const FERRY_SPEED_KMH: u8 = 20;
+
+/// A set of profiles, e.g. those that may use an edge
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
+pub(crate) struct ProfileMask(u8);
+impl ProfileMask {
+    pub(crate) fn contains(self, profile: Profile) -> bool {
+        self.0 & profile.bit() != 0
+    }
+
+    pub(crate) fn insert(&mut self, profile: Profile) {
+        self.0 |= profile.bit();
+    }
+
+    pub(crate) fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub(crate) fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub(crate) fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// The profiles as one bit each, for layouts of fixed size
+    pub(crate) fn bits(self) -> u8 {
+        self.0
+    }
+}
+
+impl Profile {
+    fn bit(self) -> u8 {
+        match self {
+            Profile::Car => 1,
+            Profile::Foot => 1 << 1,
+            Profile::Bus => 1 << 2,
+        }
+    }
+
+    /// Decides whether a way is part of the routing graph for this profile
+    pub(crate) fn is_routable(self, tags: &WayTags) -> bool {
+        if tags.route == Some("ferry") {
+            return self.is_ferry_routable(tags);
+        }
+        let Some(highway) = tags.highway else {
+            return false;
+        };
+        if tags
+            .smoothness
+            .is_some_and(|smoothness| self.smoothness_speed_factor(smoothness).is_none())
+        {
+            return false;
+        }
+        match self {
+            Profile::Car => is_car_highway(highway) || tags.service == Some("parking_aisle"),
+            Profile::Foot => match tags.foot {
+                Some("no") | Some("private") => false,
+                Some("yes") | Some("designated") | Some("permissive") => true,
+                _ => is_foot_highway(highway, tags.sidewalk),
+            },
+            Profile::Bus => match (tags.bus.or(tags.psv), highway) {
+                (Some("no"), _) => false,
+                (Some("yes") | Some("designated"), _) => true,
+                (_, "busway" | "bus_guideway") => true,
+                _ => is_car_highway(highway),
+            },
+        }
+    }
+
+    /// Whether a ferry takes this mode of transport, pedestrians unless told
+    /// otherwise but vehicles only when tagged
+    fn is_ferry_routable(self, tags: &WayTags) -> bool {
+        match self {
+            Profile::Car => tags.motorcar.or(tags.motor_vehicle) == Some("yes"),
+            Profile::Foot => !matches!(tags.foot, Some("no") | Some("private")),
+            Profile::Bus => matches!(
+                tags.bus.or(tags.psv).or(tags.motor_vehicle),
+                Some("yes") | Some("designated")
+            ),
+        }
+    }
+
+    /// Whether the oneway tag applies to this profile
+    pub(crate) fn is_oneway(self, tags: &WayTags) -> bool {
+        match self {
+            Profile::Car => tags.oneway == Some("yes"),
+            // Pedestrians may walk against the traffic direction unless explicitly told not to
+            Profile::Foot => tags.oneway_foot == Some("yes"),
+            // Contraflow bus lanes lift the oneway restriction for buses only
+            Profile::Bus => match tags.oneway_bus.or(tags.oneway_psv) {
+                Some(value) => value == "yes",
+                None => tags.oneway == Some("yes"),
+            },
+        }
+    }
+
+    /// Whether a way may only be used to reach destinations along it
+    pub(crate) fn is_destination_only(self, tags: &WayTags) -> bool {
+        // The most specific access tag for the mode of transport decides
+        let access = match self {
+            Profile::Car => tags.motor_vehicle.or(tags.vehicle).or(tags.access),
+            Profile::Foot => tags.foot.or(tags.access),
+            Profile::Bus => tags
+                .bus
+                .or(tags.psv)
+                .or(tags.motor_vehicle)
+                .or(tags.vehicle)
+                .or(tags.access),
+        };
+        match access {
+            Some(value) => value == "destination",
+            // Parking aisles only lead to parking spaces, they're not for driving through
+            None => self != Profile::Foot && tags.service == Some("parking_aisle"),
+        }
+    }
+
+    /// How much a road surface slows this mode of transport down, `None` if it's unusable
+    pub(crate) fn smoothness_speed_factor(self, smoothness: Smoothness) -> Option<f32> {
+        match (self, smoothness) {
+            (_, Smoothness::Excellent | Smoothness::Good) => Some(1.0),
+            (Profile::Car, Smoothness::Intermediate) => Some(0.9),
+            (Profile::Car, Smoothness::Bad) => Some(0.6),
+            // Only passable with high-clearance vehicles
+            (Profile::Car, Smoothness::VeryBad) => Some(0.4),
+            (Profile::Bus, Smoothness::Intermediate) => Some(0.85),
+            (Profile::Bus, Smoothness::Bad) => Some(0.5),
+            (Profile::Foot, Smoothness::Intermediate) => Some(1.0),
+            (Profile::Foot, Smoothness::Bad) => Some(0.9),
+            (Profile::Foot, Smoothness::VeryBad) => Some(0.8),
+            (Profile::Foot, Smoothness::Horrible) => Some(0.6),
+            (Profile::Foot, Smoothness::VeryHorrible) => Some(0.5),
+            _ => None,
+        }
+    }
+
+    /// Travel speed in km/h on a road, following its speed limit where one is tagged
+    ///
+    /// Cars and buses keep to `max_speed_kmh` on roads tagged with higher limits,
+    /// and pedestrians walk at their own pace whatever the limit, slower in the
+    /// roadway of roads lacking a sidewalk.
+    pub(crate) fn speed_kmh(
+        self,
+        class: RoadClass,
+        is_shared_space: bool,
+        sidewalk: Sidewalk,
+        maxspeed: Option<u8>,
+    ) -> u8 {
+        let default_speed_kmh = self.default_speed_kmh(class, is_shared_space);
+        match (self, maxspeed) {
+            (Profile::Foot, _) if is_in_roadway(class, is_shared_space, sidewalk) => {
+                FOOT_ROADWAY_SPEED_KMH
+            }
+            (Profile::Foot, _) | (_, None) => default_speed_kmh,
+            (Profile::Bus, Some(maxspeed)) => maxspeed.min(BUS_MAX_SPEED_KMH),
+            (Profile::Car, Some(maxspeed)) => maxspeed.min(CAR_MAX_SPEED_KMH),
+        }
+    }
+
+    /// Typical travel speed in km/h on a class of road, before any tagged limits
+    ///
+    /// Living streets and shared spaces are driven at walking pace, as the law
+    /// requires in many countries.
+    pub(crate) fn default_speed_kmh(self, class: RoadClass, is_shared_space: bool) -> u8 {
+        if self == Profile::Foot {
+            return 5;
+        }
+        if is_shared_space {
+            return 7;
+        }
+        match (self, class) {
+            (Profile::Bus, RoadClass::Motorway) => BUS_MAX_SPEED_KMH,
+            (_, RoadClass::Motorway) => 110,
+            (_, RoadClass::Trunk) => 90,
+            (_, RoadClass::Primary) => 70,
+            (_, RoadClass::Secondary) => 60,
+            (_, RoadClass::Tertiary) => 50,
+            (_, RoadClass::Unclassified) => 40,
+            (_, RoadClass::Residential) => 30,
+            (_, RoadClass::Service | RoadClass::Track) => 15,
+            (_, RoadClass::LivingStreet) => 7,
+            (_, RoadClass::Busway) => 50,
+            (_, RoadClass::Footway) => 5,
+            (_, RoadClass::Other) => 30,
+        }
+    }
+
+    /// Travel speed in km/h on a ferry, no faster than the heuristics assume
+    /// this mode of transport ever goes
+    pub(crate) fn ferry_speed_kmh(self) -> u8 {
+        FERRY_SPEED_KMH.min(self.max_speed_kmh())
+    }
+
+    /// The fastest speed in km/h an edge is travelled at, for search heuristics
+    /// that must not overestimate travel times
+    pub(crate) fn max_speed_kmh(self) -> u8 {
+        match self {
+            Profile::Car => CAR_MAX_SPEED_KMH,
+            Profile::Foot => 5,
+            Profile::Bus => BUS_MAX_SPEED_KMH,
+        }
+    }
+
+    /// Whether crossing nodes should be kept as graph nodes
+    pub(crate) fn splits_at_crossings(self) -> bool {
+        self == Profile::Foot
+    }
+}
+
+// https://wiki.openstreetmap.org/wiki/Key:highway
+fn is_car_highway(highway: &str) -> bool {
+    match highway {
+        // Main tags
+        "motorway" | "trunk" | "primary" | "secondary" | "tertiary" | "unclassified"
+        | "residential" => true,
+        // Drivable, but only at walking pace
+        "living_street" => true,
+        // Link roads
+        "motorway_link" | "trunk_link" | "primary_link" | "secondary_link" | "tertiary_link" => {
+            true
+        }
+        // Special road types
+        "service" | "pedestrian" | "track" | "bus_guideway" | "escape" | "raceway" | "road"
+        | "busway" => false,
+        _ => false,
+    }
+}
+
+/// Whether pedestrians walk in the roadway among the traffic, on roads without a
+/// sidewalk or with one mapped as a separate footway
+fn is_in_roadway(class: RoadClass, is_shared_space: bool, sidewalk: Sidewalk) -> bool {
+    matches!(sidewalk, Sidewalk::No | Sidewalk::Separate)
+        && !is_shared_space
+        && !matches!(
+            class,
+            RoadClass::LivingStreet | RoadClass::Track | RoadClass::Footway
+        )
+}
+
+fn is_foot_highway(highway: &str, sidewalk: Sidewalk) -> bool {
+    match highway {
+        // Paths dedicated to, or shared with, pedestrians
+        "footway" | "pedestrian" | "path" | "steps" | "living_street" | "track" => true,
+        // Low-traffic roads are walkable with or without a sidewalk
+        "residential" | "unclassified" | "service" | "road" => true,
+        // Busier roads are only walkable when they are not explicitly lacking a sidewalk
+        "primary" | "secondary" | "tertiary" | "primary_link" | "secondary_link"
+        | "tertiary_link" => sidewalk != Sidewalk::No,
+        // Pedestrians are banned from motorways and usually from trunk roads
+        "motorway" | "motorway_link" | "trunk" | "trunk_link" => false,
+        _ => false,
+    }
+}
@@ -0,0 +1,280 @@
+use std::path::Path;
+
+use crate::{
+    error::{GladsheimError, IoContext, ParseContext, Result},
+    json::{self, Value},
+};
+
+/// An area to restrict parsing or routing to, or to keep out of
+#[derive(Debug)]
+pub(crate) enum Region {
+    BoundingBox {
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    },
+    /// Rings of `(lat, lon)`, holes included, combined with the even-odd rule
+    Polygon { rings: Vec<Vec<(f64, f64)>> },
+}
+impl Region {
+    /// Parses a bounding box given as `min_lon,min_lat,max_lon,max_lat`, as osmium does
+    pub(crate) fn from_bbox(bbox: &str) -> Result<Self> {
+        let values = bbox
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .parse_context(|| format!("Invalid bounding box '{}'", bbox))?;
+        let [min_lon, min_lat, max_lon, max_lat] = values[..] else {
+            return Err(GladsheimError::parse(format!(
+                "Bounding box '{}' should be min_lon,min_lat,max_lon,max_lat",
+                bbox
+            )));
+        };
+        if min_lat > max_lat || min_lon > max_lon {
+            return Err(GladsheimError::parse(format!(
+                "Bounding box '{}' has its corners swapped",
+                bbox
+            )));
+        }
+        Ok(Region::BoundingBox {
+            min_lat,
+            min_lon,
+            max_lat,
+            max_lon,
+        })
+    }
+
+    /// Reads a polygon in the Osmosis `.poly` format
+    ///
+    /// https://wiki.openstreetmap.org/wiki/Osmosis/Polygon_Filter_File_Format
+    pub(crate) fn from_poly_file(fname: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(fname)
+            .io_context(|| format!("Failed loading {}", fname.display()))?;
+        let mut lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        // The first line is the name of the polygon
+        lines.next();
+
+        let mut rings = Vec::new();
+        let mut ring: Option<Vec<(f64, f64)>> = None;
+        for line in lines {
+            match (&mut ring, line) {
+                (Some(_), "END") => rings.extend(ring.take()),
+                (None, "END") => break,
+                // The name of a ring, `!`-prefixed for holes which even-odd handles as is
+                (None, _) => ring = Some(Vec::new()),
+                (Some(points), _) => {
+                    let mut values = line.split_whitespace().map(str::parse::<f64>);
+                    match (values.next(), values.next()) {
+                        (Some(Ok(lon)), Some(Ok(lat))) => points.push((lat, lon)),
+                        _ => {
+                            return Err(GladsheimError::parse(format!(
+                                "Invalid coordinate '{}' in {}",
+                                line,
+                                fname.display()
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+        if ring.is_some() {
+            return Err(GladsheimError::parse(format!(
+                "Unterminated ring in {}",
+                fname.display()
+            )));
+        }
+        if rings.iter().all(|ring| ring.len() < 3) {
+            return Err(GladsheimError::parse(format!(
+                "No polygon found in {}",
+                fname.display()
+            )));
+        }
+        Ok(Region::Polygon { rings })
+    }
+
+    /// Reads the polygons of a GeoJSON file, a region for every Polygon and
+    /// every polygon of a MultiPolygon, whether given as geometries, features
+    /// or collections of them
+    pub(crate) fn from_geojson_file(fname: &Path) -> Result<Vec<Self>> {
+        let content = std::fs::read_to_string(fname)
+            .io_context(|| format!("Failed loading {}", fname.display()))?;
+        let document = json::parse(&content).map_err(|message| {
+            GladsheimError::parse(format!("{} in {}", message, fname.display()))
+        })?;
+        let mut regions = Vec::new();
+        collect_polygons(&document, &mut regions).map_err(|message| {
+            GladsheimError::parse(format!("{} in {}", message, fname.display()))
+        })?;
+        if regions.is_empty() {
+            return Err(GladsheimError::parse(format!(
+                "No polygon found in {}",
+                fname.display()
+            )));
+        }
+        Ok(regions)
+    }
+
+    /// The bounding box of the region as `(min_lat, min_lon, max_lat, max_lon)`
+    pub(crate) fn bounds(&self) -> (f64, f64, f64, f64) {
+        match self {
+            Region::BoundingBox {
+                min_lat,
+                min_lon,
+                max_lat,
+                max_lon,
+            } => (*min_lat, *min_lon, *max_lat, *max_lon),
+            Region::Polygon { rings } => rings.iter().flatten().fold(
+                (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+                |(min_lat, min_lon, max_lat, max_lon), (lat, lon)| {
+                    (
+                        min_lat.min(*lat),
+                        min_lon.min(*lon),
+                        max_lat.max(*lat),
+                        max_lon.max(*lon),
+                    )
+                },
+            ),
+        }
+    }
+
+    pub(crate) fn contains(&self, lat: f64, lon: f64) -> bool {
+        match self {
+            Region::BoundingBox {
+                min_lat,
+                min_lon,
+                max_lat,
+                max_lon,
+            } => (*min_lat..=*max_lat).contains(&lat) && (*min_lon..=*max_lon).contains(&lon),
+            Region::Polygon { rings } => {
+                // Ray casting towards increasing longitude, counting edge crossings
+                let mut is_inside = false;
+                for ring in rings {
+                    let closing = ring.last().into_iter().chain(ring.iter());
+                    for (a, b) in closing.zip(ring.iter()) {
+                        if (a.0 > lat) != (b.0 > lat)
+                            && lon < a.1 + (lat - a.0) / (b.0 - a.0) * (b.1 - a.1)
+                        {
+                            is_inside = !is_inside;
+                        }
+                    }
+                }
+                is_inside
+            }
+        }
+    }
+
+    /// Whether a line through coordinates given as `(lat, lon)` has a point in
+    /// the region or crosses its border
+    pub(crate) fn intersects_line(&self, coords: &[(f64, f64)]) -> bool {
+        if coords.iter().any(|(lat, lon)| self.contains(*lat, *lon)) {
+            return true;
+        }
+        let borders = match self {
+            Region::BoundingBox {
+                min_lat,
+                min_lon,
+                max_lat,
+                max_lon,
+            } => vec![vec![
+                (*min_lat, *min_lon),
+                (*min_lat, *max_lon),
+                (*max_lat, *max_lon),
+                (*max_lat, *min_lon),
+            ]],
+            Region::Polygon { rings } => rings.clone(),
+        };
+        coords.windows(2).any(|pair| {
+            borders.iter().any(|ring| {
+                let closing = ring.last().into_iter().chain(ring.iter());
+                closing
+                    .zip(ring.iter())
+                    .any(|(a, b)| segments_cross((pair[0], pair[1]), (*a, *b)))
+            })
+        })
+    }
+}
+
+/// Adds the polygons of a GeoJSON object and the objects it holds
+fn collect_polygons(value: &Value, regions: &mut Vec<Region>) -> std::result::Result<(), String> {
+    let members = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_array)
+            .ok_or_else(|| format!("Expected an array of {}", key))
+    };
+    match value.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            for feature in members("features")? {
+                collect_polygons(feature, regions)?;
+            }
+        }
+        Some("GeometryCollection") => {
+            for geometry in members("geometries")? {
+                collect_polygons(geometry, regions)?;
+            }
+        }
+        Some("Feature") => {
+            if let Some(geometry) = value.get("geometry") {
+                collect_polygons(geometry, regions)?;
+            }
+        }
+        Some("Polygon") => {
+            regions.push(polygon(value.get("coordinates").unwrap_or(&Value::Null))?);
+        }
+        Some("MultiPolygon") => {
+            for coordinates in members("coordinates")? {
+                regions.push(polygon(coordinates)?);
+            }
+        }
+        // Points and lines don't enclose an area
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The polygon of the coordinates of a GeoJSON Polygon, rings of `[lon, lat]`
+fn polygon(coordinates: &Value) -> std::result::Result<Region, String> {
+    let invalid = || "Expected the rings of a polygon as arrays of [lon, lat]".to_string();
+    let rings = coordinates
+        .as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|ring| {
+            ring.as_array()
+                .ok_or_else(invalid)?
+                .iter()
+                .map(|position| match position.as_array() {
+                    Some([lon, lat, ..]) => Ok((
+                        lat.as_f64().ok_or_else(invalid)?,
+                        lon.as_f64().ok_or_else(invalid)?,
+                    )),
+                    _ => Err(invalid()),
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if rings.first().is_none_or(|ring| ring.len() < 3) {
+        return Err(invalid());
+    }
+    Ok(Region::Polygon { rings })
+}
+
+/// Whether two segments between coordinates have a point in common, taking
+/// the coordinates as planar over the short distances of roads
+fn segments_cross((p1, p2): ((f64, f64), (f64, f64)), (q1, q2): ((f64, f64), (f64, f64))) -> bool {
+    let side = |o: (f64, f64), a: (f64, f64), b: (f64, f64)| {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+    // Collinear segments only meet where their boxes overlap
+    let boxes_overlap = p1.0.min(p2.0) <= q1.0.max(q2.0)
+        && q1.0.min(q2.0) <= p1.0.max(p2.0)
+        && p1.1.min(p2.1) <= q1.1.max(q2.1)
+        && q1.1.min(q2.1) <= p1.1.max(p2.1);
+    boxes_overlap
+        && side(q1, q2, p1) * side(q1, q2, p2) <= 0.0
+        && side(p1, p2, q1) * side(p1, p2, q2) <= 0.0
+}
@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+
+use crate::{
+    NodeId, WayId,
+    osm_parser::{MemberType, SimpleRelation},
+    profile::Profile,
+    utils::Tile,
+};
+
+/// The vehicle types a turn restriction applies to, as a set of flags
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) struct VehicleModes(u8);
+impl VehicleModes {
+    pub(crate) const CAR: Self = Self(1);
+    /// Buses and other public-service vehicles
+    pub(crate) const BUS: Self = Self(1 << 1);
+    pub(crate) const BICYCLE: Self = Self(1 << 2);
+    pub(crate) const EMERGENCY: Self = Self(1 << 3);
+    /// Heavy goods vehicles
+    pub(crate) const HGV: Self = Self(1 << 4);
+    pub(crate) const ALL: Self = Self(0b1_1111);
+
+    /// Reads a vehicle type of the access tag hierarchy, e.g. from `except=psv`
+    fn from_tag(value: &str) -> Option<Self> {
+        match value.trim() {
+            "motorcar" | "motor_vehicle" => Some(Self::CAR),
+            "psv" | "bus" => Some(Self::BUS),
+            "bicycle" => Some(Self::BICYCLE),
+            "emergency" => Some(Self::EMERGENCY),
+            "hgv" | "goods" => Some(Self::HGV),
+            _ => None,
+        }
+    }
+
+    /// The vehicle type a profile routes for, pedestrians ignore turn restrictions
+    fn of(profile: Profile) -> Self {
+        match profile {
+            Profile::Car => Self::CAR,
+            Profile::Bus => Self::BUS,
+            Profile::Foot => Self::default(),
+        }
+    }
+
+    pub(crate) fn contains(self, other: Self) -> bool {
+        other.0 != 0 && self.0 & other.0 == other.0
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+/// Whether a restriction forbids a turn or makes it the only one allowed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) enum RestrictionKind {
+    /// `no_left_turn`, `no_u_turn`, ... forbid turning from `from` into `to`
+    No,
+    /// `only_straight_on`, ... forbid turning from `from` into anything but `to`
+    Only,
+}
+impl RestrictionKind {
+    fn from_tag(value: &str) -> Option<Self> {
+        if value.starts_with("no_") {
+            Some(RestrictionKind::No)
+        } else if value.starts_with("only_") {
+            Some(RestrictionKind::Only)
+        } else {
+            None
+        }
+    }
+}
+
+/// Where a restricted turn takes place
+#[derive(Clone, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) enum Via {
+    Node(NodeId),
+    /// The ways to pass in between, in the order of the relation
+    Ways(Vec<WayId>),
+}
+
+/// A turn restriction, from a `type=restriction` relation
+#[derive(Clone, Debug, PartialEq, bincode::Encode, bincode::Decode)]
+pub(crate) struct TurnRestriction {
+    pub(crate) relation_id: i64,
+    pub(crate) kind: RestrictionKind,
+    pub(crate) from: WayId,
+    pub(crate) via: Via,
+    pub(crate) to: WayId,
+    /// The vehicles that must obey it, after `restriction:<mode>` and `except=*`
+    pub(crate) modes: VehicleModes,
+}
+impl TurnRestriction {
+    pub(crate) fn applies_to(&self, profile: Profile) -> bool {
+        self.modes.contains(VehicleModes::of(profile))
+    }
+
+    /// Whether it forbids turning from a way into another at its via node
+    pub(crate) fn forbids(&self, from: WayId, to: WayId) -> bool {
+        if self.from != from {
+            return false;
+        }
+        match self.kind {
+            RestrictionKind::No => self.to == to,
+            RestrictionKind::Only => self.to != to,
+        }
+    }
+}
+
+/// Extracts a turn restriction, `None` for other relations or incomplete restrictions
+pub(crate) fn parse_restriction_relation<R: SimpleRelation>(
+    relation: &R,
+) -> Option<TurnRestriction> {
+    let mut is_restriction = false;
+    let mut kind = None;
+    // Restrictions limited to some vehicles with `restriction:<mode>=*`
+    let mut mode_kind = None;
+    let mut only_modes = VehicleModes::default();
+    let mut except = VehicleModes::default();
+    for (key, value) in relation.tags() {
+        match key {
+            "type" => is_restriction = value == "restriction",
+            "restriction" => kind = RestrictionKind::from_tag(value),
+            "except" => {
+                except = value
+                    .split(';')
+                    .filter_map(VehicleModes::from_tag)
+                    .fold(except, VehicleModes::union)
+            }
+            _ => {
+                if let Some(modes) = key
+                    .strip_prefix("restriction:")
+                    .and_then(VehicleModes::from_tag)
+                {
+                    only_modes = only_modes.union(modes);
+                    mode_kind = mode_kind.or(RestrictionKind::from_tag(value));
+                }
+            }
+        }
+    }
+    if !is_restriction {
+        return None;
+    }
+    let (kind, modes) = match (kind, mode_kind) {
+        (Some(kind), _) => (kind, VehicleModes::ALL),
+        (None, Some(kind)) => (kind, only_modes),
+        (None, None) => return None,
+    };
+    let modes = modes.without(except);
+
+    let mut from = None;
+    let mut to = None;
+    let mut via_node = None;
+    let mut via_ways = Vec::new();
+    for (member_type, id, role) in relation.members() {
+        match (member_type, role) {
+            (MemberType::Way, "from") => from = Some(WayId(id)),
+            (MemberType::Way, "to") => to = Some(WayId(id)),
+            (MemberType::Node, "via") => via_node = Some(NodeId(id)),
+            (MemberType::Way, "via") => via_ways.push(WayId(id)),
+            _ => {}
+        }
+    }
+    let via = match via_node {
+        Some(node_id) => Via::Node(node_id),
+        None if !via_ways.is_empty() => Via::Ways(via_ways),
+        None => return None,
+    };
+    Some(TurnRestriction {
+        relation_id: relation.id(),
+        kind,
+        from: from?,
+        via,
+        to: to?,
+        modes,
+    })
+}
+
+/// The restrictions taking place at nodes or ways of a tile
+pub(crate) fn restrictions_in_tile(
+    restrictions: &[TurnRestriction],
+    tile: &Tile,
+) -> Vec<TurnRestriction> {
+    let nodes_in_tile = tile
+        .edges
+        .iter()
+        .flat_map(|edge| [edge.from, edge.to])
+        .collect::<HashSet<_>>();
+    let ways_in_tile = tile
+        .edges
+        .iter()
+        .map(|edge| edge.way_id)
+        .collect::<HashSet<_>>();
+    restrictions
+        .iter()
+        .filter(|restriction| match &restriction.via {
+            Via::Node(node_id) => nodes_in_tile.contains(node_id),
+            Via::Ways(ways) => ways.iter().any(|way_id| ways_in_tile.contains(way_id)),
+        })
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,2950 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fmt::Write,
+    path::Path,
+    sync::{Arc, mpsc},
+};
+
+use crate::{
+    Edge, NodeId, RoadClass, WayId,
+    cancel::CancellationToken,
+    error::{GladsheimError, Result},
+    geojson::json_string,
+    geometry::{self, LineProjection},
+    hierarchy::Shortcut,
+    profile::{Profile, ProfileMask},
+    region::Region,
+    restrictions::{TurnRestriction, Via},
+    rtree::{BoundingBox, RTree},
+    traffic::Traffic,
+    turns,
+    utils::{self, Quadkey, Tile, TileCoord, TileStore},
+};
+
+/// Locations farther than this from any usable road can't be routed from or to
+const MAX_SNAP_DISTANCE_M: f64 = 1_000.0;
+/// Roads this close to the nearest one are snapped onto as well, such as the
+/// reverse edge of a two-way road
+const SNAP_TOLERANCE_M: f64 = 0.01;
+/// Meters around a location searched for the closest road, wider only when
+/// there's none that close
+const SNAP_RADII_M: [f64; 3] = [50.0, 250.0, MAX_SNAP_DISTANCE_M];
+/// Nodes settled between checks for cancellation
+const CANCEL_CHECK_INTERVAL: usize = 1024;
+/// Turns of at most this many degrees go straight on and cost nothing
+const STRAIGHT_ANGLE_DEG: f64 = 30.0;
+/// Alternative routes take at most this share longer than the fastest one
+const MAX_STRETCH: f64 = 0.25;
+/// Share of the stretch of an alternative route off the fastest one that has
+/// to be a plateau, so it's a route a driver would choose rather than a detour
+const MIN_PLATEAU_SHARE: f64 = 0.25;
+
+/// A location given as `lat,lon`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Coordinate {
+    pub(crate) lat: f64,
+    pub(crate) lon: f64,
+}
+impl std::str::FromStr for Coordinate {
+    type Err = String;
+
+    /// Parses `<lat>,<lon>`, e.g. `59.3293,18.0686`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parsed = value.split_once(',').and_then(|(lat, lon)| {
+            Some(Self {
+                lat: lat.trim().parse().ok()?,
+                lon: lon.trim().parse().ok()?,
+            })
+        });
+        match parsed {
+            Some(coordinate)
+                if (-90.0..=90.0).contains(&coordinate.lat)
+                    && (-180.0..=180.0).contains(&coordinate.lon) =>
+            {
+                Ok(coordinate)
+            }
+            _ => Err(format!("Invalid coordinate {value}, expected <lat>,<lon>")),
+        }
+    }
+}
+impl std::fmt::Display for Coordinate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.7},{:.7}", self.lat, self.lon)
+    }
+}
+
+/// How the fastest route of a leg is searched for, all of them find it
+///
+/// Legs over tiles with a contraction hierarchy are searched over it instead,
+/// see `route`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Algorithm {
+    /// Settles nodes by their travel time from the origin, in every direction
+    Dijkstra,
+    /// Settles nodes by travel time plus a lower bound of the time left to the
+    /// destination, from the great-circle distance at the top speed of the
+    /// profile, so the search heads towards the destination
+    #[default]
+    AStar,
+    /// Dijkstra from both ends at once until the searches meet, settling nodes
+    /// within about half the travel time around each end
+    Bidirectional,
+}
+
+/// How the shape of a route is given in its JSON, named as in OSRM requests
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Geometries {
+    /// A polyline with 5 decimals
+    #[default]
+    Polyline,
+    /// A polyline with 6 decimals
+    Polyline6,
+    /// A GeoJSON LineString
+    Geojson,
+}
+
+/// What a route may use and how it's searched for
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RouteOptions {
+    pub(crate) algorithm: Algorithm,
+    /// The day of travel as `YYYYMMDD`, roads opening after it aren't used
+    pub(crate) date: u32,
+    /// Seconds added to the key of nodes whose edges continue in tiles that
+    /// aren't loaded yet, 0 to only go by travel time
+    ///
+    /// A positive affinity keeps the search within the loaded tiles for longer, so
+    /// fewer tiles are read, at the price of routes up to that much slower than the
+    /// fastest one. Bidirectional searches only add it going forwards, which
+    /// keeps them within the same bound.
+    pub(crate) tile_affinity_s: f64,
+    /// What turning at nodes costs, `None` to only go by the duration of the edges
+    pub(crate) turn_costs: Option<TurnCosts>,
+    /// How many slower routes to suggest besides the fastest one, `None` for
+    /// the fastest one alone
+    pub(crate) alternatives: Option<Alternatives>,
+    /// Kinds of roads that aren't used, nor snapped onto
+    pub(crate) exclude: ExcludeMask,
+}
+impl Default for RouteOptions {
+    /// The fastest route today by A*, without turn costs or alternatives
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::default(),
+            date: utils::today(),
+            tile_affinity_s: 0.0,
+            turn_costs: None,
+            alternatives: None,
+            exclude: ExcludeMask::default(),
+        }
+    }
+}
+
+/// A kind of road routes can be kept off
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Exclude {
+    /// Roads tagged `toll=yes`
+    Toll,
+    /// Ferry crossings
+    Ferry,
+    /// Motorways and their links
+    Motorway,
+}
+impl Exclude {
+    fn bit(self) -> u8 {
+        match self {
+            Exclude::Toll => 1,
+            Exclude::Ferry => 1 << 1,
+            Exclude::Motorway => 1 << 2,
+        }
+    }
+}
+
+/// A set of kinds of roads routes are kept off
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ExcludeMask(u8);
+impl ExcludeMask {
+    pub(crate) fn new(excludes: &[Exclude]) -> Self {
+        Self(
+            excludes
+                .iter()
+                .fold(0, |bits, exclude| bits | exclude.bit()),
+        )
+    }
+
+    pub(crate) fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    fn contains(self, exclude: Exclude) -> bool {
+        self.0 & exclude.bit() != 0
+    }
+
+    /// Whether an edge is of a kind in the set
+    fn excludes(self, edge: &Edge) -> bool {
+        (edge.is_toll && self.contains(Exclude::Toll))
+            || (edge.is_ferry && self.contains(Exclude::Ferry))
+            || (edge.class == RoadClass::Motorway && self.contains(Exclude::Motorway))
+    }
+}
+
+/// Which alternative routes are suggested besides the fastest one
+///
+/// Alternatives go through a plateau, a stretch that is on both the fastest
+/// way from the origin and the fastest way to the destination, so they're
+/// the fastest routes through it without detours. They take at most
+/// `MAX_STRETCH` longer than the fastest route.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Alternatives {
+    /// Most alternatives suggested, fewer when there aren't that many distinct ones
+    pub(crate) max_alternatives: usize,
+    /// Largest share of the distance of an alternative that may be along the
+    /// fastest route or an alternative suggested before it
+    pub(crate) max_overlap: f64,
+}
+
+/// Seconds added to a route for the turns it takes at nodes
+///
+/// Searching with turn costs keeps track of the edge each node was reached
+/// over, which also lets it obey the turn restrictions via nodes of the tiles.
+/// Turns at junctions are costed by their angle, straight on within
+/// `STRAIGHT_ANGLE_DEG` is free and so are the bends of roads that don't branch.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TurnCosts {
+    /// Seconds for a left turn of 90 degrees, sharper turns cost more and
+    /// gentler ones less
+    pub(crate) left_s: f64,
+    /// Seconds for a right turn of 90 degrees, scaled like `left_s`
+    pub(crate) right_s: f64,
+    /// Seconds for turning back along the way arrived on
+    pub(crate) u_turn_s: f64,
+}
+
+/// An edge of a loaded tile
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct EdgeRef {
+    tile: u32,
+    edge: u32,
+}
+
+/// A shortcut of the contraction hierarchy of a loaded tile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ShortcutRef {
+    tile: u32,
+    shortcut: u32,
+}
+
+/// What a search travels from one node to the next
+#[derive(Clone, Copy, Debug)]
+enum Link {
+    /// An edge, from `from` to `to` when `forward` and the other way around otherwise
+    Edge { edge: EdgeRef, forward: bool },
+    /// A shortcut, standing in for the edges it unpacks into
+    Shortcut(ShortcutRef),
+}
+
+impl Link {
+    /// The edge travelled, `None` for a shortcut
+    fn edge(self) -> Option<(EdgeRef, bool)> {
+        match self {
+            Link::Edge { edge, forward } => Some((edge, forward)),
+            Link::Shortcut(_) => None,
+        }
+    }
+}
+
+/// A location snapped onto the road network
+#[derive(Clone, Debug)]
+pub(crate) struct Snap {
+    /// The location as given
+    pub(crate) location: Coordinate,
+    /// The closest point on a road as `(lat, lon)`
+    pub(crate) point: (f64, f64),
+    /// Meters from the location to `point`
+    pub(crate) distance_m: f64,
+    /// The name of the road snapped onto
+    pub(crate) name: Option<String>,
+    /// Every edge through `point`, with the share of the edge from `from` to it
+    edges: Vec<(EdgeRef, f64)>,
+}
+impl Snap {
+    /// The snapped location as JSON shaped like an OSRM waypoint
+    pub(crate) fn to_json(&self) -> String {
+        format!(
+            r#"{{"name":{},"location":[{:.7},{:.7}],"distance":{:.1}}}"#,
+            json_string(self.name.as_deref().unwrap_or_default()),
+            self.point.1,
+            self.point.0,
+            self.distance_m
+        )
+    }
+}
+
+/// A stretch of an edge along a route
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Traversal {
+    pub(crate) edge: EdgeRef,
+    /// Shares of the edge from `from` where the stretch starts and ends, the
+    /// edge is travelled backwards when `end < start`
+    pub(crate) start: f64,
+    pub(crate) end: f64,
+}
+impl Traversal {
+    /// All of an edge, travelled from `from` to `to` when `forward`
+    fn whole(edge: EdgeRef, forward: bool) -> Self {
+        let (start, end) = if forward { (0.0, 1.0) } else { (1.0, 0.0) };
+        Self { edge, start, end }
+    }
+
+    fn share(&self) -> f64 {
+        (self.end - self.start).abs()
+    }
+}
+
+/// The part of a route between two consecutive waypoints
+#[derive(Clone, Debug)]
+pub(crate) struct Leg {
+    pub(crate) distance_m: f64,
+    pub(crate) duration_s: f64,
+    /// Grams of CO2 along the leg, when the tiles were built with emissions
+    pub(crate) co2_g: Option<f64>,
+    /// The names of the two longest streets of the leg, in the order driven
+    pub(crate) summary: Vec<String>,
+    pub(crate) traversals: Vec<Traversal>,
+    /// The coordinates along the leg as `(lat, lon)`, from the shapes of its edges
+    pub(crate) geometry: Vec<(f64, f64)>,
+}
+
+/// A route through waypoints, with a leg between each consecutive pair of them
+#[derive(Clone, Debug)]
+pub(crate) struct Route {
+    pub(crate) waypoints: Vec<Snap>,
+    pub(crate) legs: Vec<Leg>,
+    /// Slower routes from the origin to the destination when asked for, fastest first
+    pub(crate) alternatives: Vec<Leg>,
+}
+impl Route {
+    /// The route as JSON shaped like an OSRM response, so existing clients can
+    /// read it, with the alternatives after the fastest route
+    pub(crate) fn to_json(&self, geometries: Geometries) -> String {
+        let mut json = String::from(r#"{"code":"Ok","routes":["#);
+        write_route(&mut json, &self.legs, geometries);
+        for alternative in &self.alternatives {
+            json.push(',');
+            write_route(&mut json, std::slice::from_ref(alternative), geometries);
+        }
+        json.push_str(r#"],"waypoints":["#);
+        for (index, waypoint) in self.waypoints.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            json.push_str(&waypoint.to_json());
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Writes a route through its legs as JSON shaped like an OSRM route
+fn write_route(json: &mut String, legs: &[Leg], geometries: Geometries) {
+    let mut coordinates: Vec<(f64, f64)> = Vec::new();
+    for leg in legs {
+        // Every leg starts where the one before it ends
+        let skip =
+            usize::from(coordinates.last().is_some() && coordinates.last() == leg.geometry.first());
+        coordinates.extend(&leg.geometry[skip..]);
+    }
+    let _ = write!(
+        json,
+        r#"{{"distance":{:.1},"duration":{:.1},"geometry":"#,
+        legs.iter().map(|leg| leg.distance_m).sum::<f64>(),
+        legs.iter().map(|leg| leg.duration_s).sum::<f64>()
+    );
+    match geometries {
+        Geometries::Polyline | Geometries::Polyline6 => {
+            let precision = if geometries == Geometries::Polyline {
+                5
+            } else {
+                6
+            };
+            // Coordinates from the tiles are always in range
+            let polyline = geometry::encode_polyline(&coordinates, precision).unwrap_or_default();
+            json.push_str(&json_string(&polyline));
+        }
+        Geometries::Geojson => {
+            json.push_str(r#"{"type":"LineString","coordinates":["#);
+            for (index, (lat, lon)) in coordinates.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                let _ = write!(json, "[{:.7},{:.7}]", lon, lat);
+            }
+            json.push_str("]}");
+        }
+    }
+    json.push_str(r#","legs":["#);
+    for (index, leg) in legs.iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            r#"{{"distance":{:.1},"duration":{:.1},"summary":{}"#,
+            leg.distance_m,
+            leg.duration_s,
+            json_string(&leg.summary.join(", "))
+        );
+        if let Some(co2_g) = leg.co2_g {
+            let _ = write!(json, r#","co2_g":{:.1}"#, co2_g);
+        }
+        json.push('}');
+    }
+    json.push_str("]}");
+}
+
+/// Reads tiles for a search, ahead of time in the background when asked to
+struct TileLoader {
+    store: Arc<TileStore>,
+    prefetching: HashMap<Quadkey, mpsc::Receiver<Result<Option<Tile>>>>,
+}
+impl TileLoader {
+    /// Starts reading a tile on the thread pool, returning whether it wasn't already
+    fn prefetch(&mut self, quadkey: &Quadkey) -> bool {
+        if self.prefetching.contains_key(quadkey) {
+            return false;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let store = Arc::clone(&self.store);
+        let key = quadkey.clone();
+        rayon::spawn(move || {
+            // The search may have finished without waiting for the tile
+            let _ = sender.send(store.load(&key));
+        });
+        self.prefetching.insert(quadkey.clone(), receiver);
+        true
+    }
+
+    fn load(&mut self, quadkey: &Quadkey) -> Result<Option<Tile>> {
+        match self.prefetching.remove(quadkey) {
+            Some(receiver) => receiver
+                .recv()
+                .unwrap_or_else(|_disconnected| self.store.load(quadkey)),
+            None => self.store.load(quadkey),
+        }
+    }
+}
+
+/// The road network of a tile directory, read tile by tile as a search reaches them
+///
+/// Only the tiles around the waypoints are read up front. Expanding a boundary
+/// node reads the tiles its edges continue in, so a route only reads the tiles
+/// its search reaches.
+pub(crate) struct TileGraph {
+    loader: TileLoader,
+    profile: Profile,
+    zoom: u8,
+    tiles: Vec<Tile>,
+    /// R-trees over the boxes of the edges of the first tiles, built when a
+    /// location is snapped, for all the tiles read by then
+    edge_trees: Vec<RTree>,
+    /// Every tile asked for, with its index in `tiles` unless it holds no edges
+    requested: HashMap<Quadkey, Option<u32>>,
+    /// Where the nodes with edges are in `tiles`, as tile and node index
+    nodes: HashMap<NodeId, Vec<(u32, u32)>>,
+    /// The tiles that edges of boundary nodes continue in and that aren't read yet
+    pending: HashMap<NodeId, Vec<Quadkey>>,
+    /// The day the contraction hierarchy of the tiles was built, when they have one
+    built_on: Option<u32>,
+    /// The contraction hierarchy ranks of the nodes of the loaded tiles
+    ranks: HashMap<NodeId, u32>,
+    /// The shortcuts starting or ending at every node of the loaded tiles
+    shortcuts: HashMap<NodeId, Vec<ShortcutRef>>,
+    /// The turn restrictions of the profile via every node of the loaded tiles
+    restrictions: HashMap<NodeId, Vec<TurnRestriction>>,
+    /// Areas whose roads aren't used, each with its bounds
+    avoided_areas: Vec<(BoundingBox, Region)>,
+    /// Current speeds of edges overriding the durations of the tiles
+    traffic: Option<Traffic>,
+    /// Durations at the current speeds of the edges of the loaded tiles that have one
+    traffic_durations_s: HashMap<EdgeRef, f32>,
+    num_prefetched: usize,
+    /// Nodes settled by all searches so far
+    num_settled: usize,
+}
+impl TileGraph {
+    /// Opens the base tiles of a directory, taking the profile and zoom from the first
+    pub(crate) fn open(tiles_dir: &Path) -> Result<Self> {
+        let store = TileStore::open(tiles_dir)?;
+        let header = match store.quadkeys().next() {
+            Some(quadkey) => store.load_header(quadkey)?,
+            None => None,
+        };
+        let Some(header) = header else {
+            return Err(GladsheimError::parse(format!(
+                "No tiles in {}",
+                tiles_dir.display()
+            )));
+        };
+        if header.max_class.is_some() {
+            return Err(GladsheimError::parse(format!(
+                "{} holds overview tiles, route over the base tiles instead",
+                tiles_dir.display()
+            )));
+        }
+        Ok(Self {
+            loader: TileLoader {
+                store: Arc::new(store),
+                prefetching: HashMap::new(),
+            },
+            profile: header.profile,
+            zoom: header.zoom,
+            tiles: Vec::new(),
+            edge_trees: Vec::new(),
+            requested: HashMap::new(),
+            nodes: HashMap::new(),
+            pending: HashMap::new(),
+            built_on: None,
+            ranks: HashMap::new(),
+            shortcuts: HashMap::new(),
+            restrictions: HashMap::new(),
+            avoided_areas: Vec::new(),
+            traffic: None,
+            traffic_durations_s: HashMap::new(),
+            num_prefetched: 0,
+            num_settled: 0,
+        })
+    }
+
+    /// The profile the tiles were built for
+    pub(crate) fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    /// Keeps routes out of areas, closing every edge with a point in one or
+    /// crossing its border as the tiles are read
+    ///
+    /// Only the copies of the tiles in memory change, so the areas can differ
+    /// from one query to the next.
+    pub(crate) fn avoid_areas(&mut self, areas: Vec<Region>) {
+        self.avoided_areas.extend(areas.into_iter().map(|area| {
+            let (min_lat, min_lon, max_lat, max_lon) = area.bounds();
+            let bounds = BoundingBox {
+                min: (min_lat, min_lon),
+                max: (max_lat, max_lon),
+            };
+            (bounds, area)
+        }));
+        let mut tiles = std::mem::take(&mut self.tiles);
+        for tile in &mut tiles {
+            self.close_avoided(tile);
+        }
+        self.tiles = tiles;
+    }
+
+    /// Closes the edges of a tile that go into the areas to avoid
+    fn close_avoided(&self, tile: &mut Tile) {
+        if self.avoided_areas.is_empty() {
+            return;
+        }
+        for index in 0..tile.edges.len() {
+            let coords = tile.edges[index]
+                .nodes
+                .iter()
+                .filter_map(|node_id| tile.node_index(*node_id))
+                .map(|node| {
+                    let loc = &tile.node_locations[node].1;
+                    (loc.lat, loc.lon)
+                })
+                .collect::<Vec<_>>();
+            let Some(bounds) = BoundingBox::of(&coords) else {
+                continue;
+            };
+            if self.avoided_areas.iter().any(|(area_bounds, area)| {
+                area_bounds.intersects(&bounds) && area.intersects_line(&coords)
+            }) {
+                tile.edges[index].access = ProfileMask::default();
+            }
+        }
+    }
+
+    /// Routes by the current speeds of edges instead of the durations the tiles
+    /// were built with, for the edges that have one
+    ///
+    /// The tiles stay as they are, and the speeds can be read again with
+    /// `reload_traffic` whenever they change.
+    pub(crate) fn use_traffic(&mut self, traffic: Traffic) {
+        self.traffic = Some(traffic);
+        self.apply_traffic();
+    }
+
+    /// Reads the current speeds again when their file changed since it was
+    /// last read, so the next searches route by them
+    pub(crate) fn reload_traffic(&mut self) -> Result<()> {
+        if let Some(traffic) = &mut self.traffic {
+            if traffic.reload()? {
+                self.apply_traffic();
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes the durations at the current speeds for all loaded tiles
+    fn apply_traffic(&mut self) {
+        self.traffic_durations_s.clear();
+        for tile in 0..self.tiles.len() as u32 {
+            self.apply_traffic_to(tile);
+        }
+    }
+
+    /// Computes the durations at the current speeds for the edges of a tile
+    ///
+    /// Speeds are capped at the fastest the profile goes, which A* assumes
+    /// nothing is faster than.
+    fn apply_traffic_to(&mut self, tile: u32) {
+        let Some(traffic) = &self.traffic else {
+            return;
+        };
+        let max_speed_kmh = f32::from(self.profile.max_speed_kmh());
+        for (edge, edge_data) in self.tiles[tile as usize].edges.iter().enumerate() {
+            if let Some(speed_kmh) = traffic.speed_kmh(edge_data) {
+                let speed_mps = speed_kmh.min(max_speed_kmh) / 3.6;
+                self.traffic_durations_s.insert(
+                    EdgeRef {
+                        tile,
+                        edge: edge as u32,
+                    },
+                    edge_data.length_m / speed_mps,
+                );
+            }
+        }
+    }
+
+    /// Number of edges with a current speed, whether or not their tiles are read
+    pub(crate) fn num_traffic_speeds(&self) -> usize {
+        self.traffic.as_ref().map_or(0, Traffic::len)
+    }
+
+    /// Number of tiles with edges read so far
+    pub(crate) fn num_loaded(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// Number of tiles read in the background ahead of the search
+    pub(crate) fn num_prefetched(&self) -> usize {
+        self.num_prefetched
+    }
+
+    /// Number of nodes the searches settled, which is what their running time grows with
+    pub(crate) fn num_settled(&self) -> usize {
+        self.num_settled
+    }
+
+    /// Number of tiles the search reached the border of without reading them
+    pub(crate) fn num_avoided(&self) -> usize {
+        self.pending
+            .values()
+            .flatten()
+            .filter(|quadkey| !self.requested.contains_key(*quadkey))
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    pub(crate) fn edge(&self, edge: EdgeRef) -> &Edge {
+        &self.tiles[edge.tile as usize].edges[edge.edge as usize]
+    }
+
+    fn shortcut(&self, shortcut: ShortcutRef) -> &Shortcut {
+        let hierarchy = self.tiles[shortcut.tile as usize].hierarchy.as_ref();
+        &hierarchy
+            .expect("shortcut of a tile without hierarchy")
+            .shortcuts[shortcut.shortcut as usize]
+    }
+
+    /// Seconds to traverse an edge, at its current speed when there is one
+    fn edge_duration_s(&self, edge: EdgeRef) -> f64 {
+        f64::from(
+            self.traffic_durations_s
+                .get(&edge)
+                .copied()
+                .unwrap_or(self.edge(edge).duration_s),
+        )
+    }
+
+    fn duration_s(&self, link: Link) -> f64 {
+        match link {
+            Link::Edge { edge, .. } => self.edge_duration_s(edge),
+            Link::Shortcut(shortcut) => f64::from(self.shortcut(shortcut).duration_s),
+        }
+    }
+
+    /// The coordinates of a node with edges as `(lat, lon)`
+    fn node_location(&self, node_id: NodeId) -> Option<(f64, f64)> {
+        let &(tile, node) = self.nodes.get(&node_id)?.first()?;
+        let loc = &self.tiles[tile as usize].node_locations[node as usize].1;
+        Some((loc.lat, loc.lon))
+    }
+
+    /// The coordinates along an edge as `(lat, lon)`, leaving out unlocated nodes
+    pub(crate) fn edge_coordinates(&self, edge: EdgeRef) -> Vec<(f64, f64)> {
+        let tile = &self.tiles[edge.tile as usize];
+        tile.edges[edge.edge as usize]
+            .nodes
+            .iter()
+            .filter_map(|node_id| tile.node_index(*node_id))
+            .map(|index| {
+                let loc = &tile.node_locations[index].1;
+                (loc.lat, loc.lon)
+            })
+            .collect()
+    }
+
+    /// Whether an edge may be travelled from `to` to `from`, which tiles with
+    /// reverse edges hold a separate edge for
+    fn can_reverse(&self, edge: EdgeRef) -> bool {
+        !self.tiles[edge.tile as usize].has_reverse_edges
+            && !self.edge(edge).oneway.contains(self.profile)
+    }
+
+    /// The node an edge is left at when travelled from `from` to `to` when
+    /// `forward`, or the other way around
+    fn end_node(&self, (edge, forward): (EdgeRef, bool)) -> NodeId {
+        let edge = self.edge(edge);
+        if forward { edge.to } else { edge.from }
+    }
+
+    /// The compass bearings an edge is travelled with at its start and end,
+    /// from `from` to `to` when `forward` or the other way around
+    fn bearings(&self, (edge, forward): (EdgeRef, bool)) -> Option<(f64, f64)> {
+        let (start, end) = turns::end_bearings(&self.edge_coordinates(edge))?;
+        Some(if forward {
+            (start, end)
+        } else {
+            (
+                (end + 180.0).rem_euclid(360.0),
+                (start + 180.0).rem_euclid(360.0),
+            )
+        })
+    }
+
+    /// Whether roads the profile may use lead from a node to more than two
+    /// others, so turning there is a choice rather than following a bend
+    fn is_junction(&self, node_id: NodeId) -> bool {
+        let mut neighbours = HashSet::new();
+        for &(tile_index, node) in self.nodes.get(&node_id).into_iter().flatten() {
+            let tile = &self.tiles[tile_index as usize];
+            let adjacency = &tile.adjacency;
+            for edge in adjacency
+                .outgoing
+                .edges(node as usize)
+                .chain(adjacency.incoming.edges(node as usize))
+            {
+                let edge = &tile.edges[edge];
+                if edge.access.contains(self.profile) {
+                    neighbours.insert(if edge.from == node_id {
+                        edge.to
+                    } else {
+                        edge.from
+                    });
+                }
+            }
+        }
+        neighbours.len() > 2
+    }
+
+    /// Seconds for turning from one edge into another at the node between
+    /// them, `None` when a restriction forbids it
+    fn turn_cost(
+        &self,
+        costs: &TurnCosts,
+        from: (EdgeRef, bool),
+        to: (EdgeRef, bool),
+    ) -> Option<f64> {
+        let node_id = self.end_node(from);
+        let (from_way, to_way) = (self.edge(from.0).way_id, self.edge(to.0).way_id);
+        if self
+            .restrictions
+            .get(&node_id)
+            .into_iter()
+            .flatten()
+            .any(|restriction| restriction.forbids(from_way, to_way))
+        {
+            return None;
+        }
+        // Back along the same way to the node arrived from
+        if from_way == to_way && self.end_node(to) == self.end_node((from.0, !from.1)) {
+            return Some(costs.u_turn_s);
+        }
+        if !self.is_junction(node_id) {
+            return Some(0.0);
+        }
+        let (Some((_, arrival)), Some((departure, _))) = (self.bearings(from), self.bearings(to))
+        else {
+            return Some(0.0);
+        };
+        // Degrees in `[-180, 180)`, positive to the right
+        let angle = (departure - arrival + 540.0).rem_euclid(360.0) - 180.0;
+        Some(if angle.abs() <= STRAIGHT_ANGLE_DEG {
+            0.0
+        } else if angle > 0.0 {
+            costs.right_s * angle / 90.0
+        } else {
+            costs.left_s * -angle / 90.0
+        })
+    }
+
+    /// Whether the profile may use an edge on a day as `YYYYMMDD`
+    fn is_open(&self, edge: &Edge, date: u32) -> bool {
+        edge.access.contains(self.profile) && edge.not_before.is_none_or(|opens| opens <= date)
+    }
+
+    fn load_tile(&mut self, quadkey: &Quadkey) -> Result<()> {
+        if self.requested.contains_key(quadkey) {
+            return Ok(());
+        }
+        let Some(mut tile) = self.loader.load(quadkey)? else {
+            self.requested.insert(quadkey.clone(), None);
+            return Ok(());
+        };
+        self.close_avoided(&mut tile);
+        let tile_index = self.tiles.len() as u32;
+        self.requested.insert(quadkey.clone(), Some(tile_index));
+        for (node, (node_id, _loc)) in tile.node_locations.iter().enumerate() {
+            let has_edges = tile.adjacency.outgoing.edges(node).next().is_some()
+                || tile.adjacency.incoming.edges(node).next().is_some();
+            if has_edges {
+                self.nodes
+                    .entry(*node_id)
+                    .or_default()
+                    .push((tile_index, node as u32));
+            }
+        }
+        for boundary_node in &tile.boundary_nodes {
+            let unread = boundary_node
+                .tiles
+                .iter()
+                .filter(|other| !self.requested.contains_key(*other))
+                .cloned();
+            self.pending
+                .entry(boundary_node.node_id)
+                .or_default()
+                .extend(unread);
+        }
+        // Restrictions are stored in every tile they touch
+        for restriction in tile
+            .restrictions
+            .iter()
+            .filter(|restriction| restriction.applies_to(self.profile))
+        {
+            let Via::Node(node_id) = restriction.via else {
+                continue;
+            };
+            let restrictions = self.restrictions.entry(node_id).or_default();
+            if !restrictions
+                .iter()
+                .any(|other| other.relation_id == restriction.relation_id)
+            {
+                restrictions.push(restriction.clone());
+            }
+        }
+        if let Some(hierarchy) = &tile.hierarchy {
+            self.built_on = Some(hierarchy.built_on);
+            self.ranks.extend(hierarchy.ranks.iter().copied());
+            for (index, shortcut) in hierarchy.shortcuts.iter().enumerate() {
+                let shortcut_ref = ShortcutRef {
+                    tile: tile_index,
+                    shortcut: index as u32,
+                };
+                for node_id in [shortcut.from, shortcut.to] {
+                    self.shortcuts
+                        .entry(node_id)
+                        .or_default()
+                        .push(shortcut_ref);
+                }
+            }
+            for boundary_node in &hierarchy.boundary_nodes {
+                let unread = boundary_node
+                    .tiles
+                    .iter()
+                    .filter(|other| !self.requested.contains_key(*other))
+                    .cloned();
+                self.pending
+                    .entry(boundary_node.node_id)
+                    .or_default()
+                    .extend(unread);
+            }
+        }
+        self.tiles.push(tile);
+        self.apply_traffic_to(tile_index);
+        Ok(())
+    }
+
+    /// Reads the tiles the edges and shortcuts of a node continue in, before
+    /// expanding it
+    fn load_pending(&mut self, node_id: NodeId) -> Result<()> {
+        // The hierarchy of a tile read may list more tiles of the node
+        while let Some(quadkeys) = self.pending.remove(&node_id) {
+            for quadkey in &quadkeys {
+                self.load_tile(quadkey)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts reading the unread tiles of a node in the background, returning
+    /// whether there are any
+    fn prefetch_pending(&mut self, node_id: NodeId) -> bool {
+        let Some(quadkeys) = self.pending.get(&node_id) else {
+            return false;
+        };
+        let mut has_unread = false;
+        for quadkey in quadkeys {
+            if !self.requested.contains_key(quadkey) {
+                has_unread = true;
+                if self.loader.prefetch(quadkey) {
+                    self.num_prefetched += 1;
+                }
+            }
+        }
+        has_unread
+    }
+
+    /// The edges that can be travelled away from a node, or into it when searching
+    /// backwards, with the node at their other end
+    fn steps(
+        &self,
+        node_id: NodeId,
+        direction: Direction,
+        usable: impl Fn(&Edge) -> bool,
+    ) -> Vec<Step> {
+        let mut steps = Vec::new();
+        for &(tile_index, node) in self.nodes.get(&node_id).into_iter().flatten() {
+            let tile = &self.tiles[tile_index as usize];
+            let edge_ref = |edge: usize| EdgeRef {
+                tile: tile_index,
+                edge: edge as u32,
+            };
+            // Edges away from the node in their own direction, and against it into the node
+            let (along, against) = match direction {
+                Direction::Forward => (&tile.adjacency.outgoing, &tile.adjacency.incoming),
+                Direction::Backward => (&tile.adjacency.incoming, &tile.adjacency.outgoing),
+            };
+            for edge in along.edges(node as usize) {
+                if usable(&tile.edges[edge]) {
+                    steps.push(Step {
+                        link: Link::Edge {
+                            edge: edge_ref(edge),
+                            forward: true,
+                        },
+                        head: direction.head(&tile.edges[edge], true),
+                    });
+                }
+            }
+            for edge in against.edges(node as usize) {
+                if usable(&tile.edges[edge]) && self.can_reverse(edge_ref(edge)) {
+                    steps.push(Step {
+                        link: Link::Edge {
+                            edge: edge_ref(edge),
+                            forward: false,
+                        },
+                        head: direction.head(&tile.edges[edge], false),
+                    });
+                }
+            }
+        }
+        steps
+    }
+
+    /// The shortcuts away from a node, or into it when searching backwards,
+    /// with the node at their other end
+    fn shortcut_steps(&self, node_id: NodeId, direction: Direction) -> Vec<Step> {
+        let mut steps = Vec::new();
+        for &shortcut_ref in self.shortcuts.get(&node_id).into_iter().flatten() {
+            let shortcut = self.shortcut(shortcut_ref);
+            let (tail, head) = match direction {
+                Direction::Forward => (shortcut.from, shortcut.to),
+                Direction::Backward => (shortcut.to, shortcut.from),
+            };
+            // Tiles at both ends hold the shortcut, only one of them is needed
+            let is_listed = steps.iter().any(|step: &Step| match step.link {
+                Link::Shortcut(other) => {
+                    let other = self.shortcut(other);
+                    (other.from, other.to) == (shortcut.from, shortcut.to)
+                }
+                Link::Edge { .. } => false,
+            });
+            if tail == node_id && !is_listed {
+                steps.push(Step {
+                    link: Link::Shortcut(shortcut_ref),
+                    head,
+                });
+            }
+        }
+        steps
+    }
+
+    /// The fastest edge or shortcut the hierarchy has from one node to another
+    fn fastest_link(&mut self, from: NodeId, to: NodeId, built_on: u32) -> Result<Link> {
+        self.load_pending(from)?;
+        let mut steps = self.steps(from, Direction::Forward, |edge| {
+            self.is_open(edge, built_on) && edge.destination_zone.is_none()
+        });
+        steps.extend(self.shortcut_steps(from, Direction::Forward));
+        steps
+            .into_iter()
+            .filter(|step| step.head == to)
+            .map(|step| step.link)
+            .min_by(|a, b| self.duration_s(*a).total_cmp(&self.duration_s(*b)))
+            .ok_or_else(|| {
+                GladsheimError::routing(format!(
+                    "No way from node {} to {} to unpack a shortcut over, run BuildCh again",
+                    from.0, to.0
+                ))
+            })
+    }
+
+    /// The edges a shortcut stands for, in the order travelled
+    fn unpack(&mut self, shortcut: ShortcutRef, built_on: u32) -> Result<Vec<Traversal>> {
+        let mut traversals = Vec::new();
+        let mut links = vec![Link::Shortcut(shortcut)];
+        while let Some(link) = links.pop() {
+            match link {
+                Link::Edge { edge, forward } => traversals.push(Traversal::whole(edge, forward)),
+                Link::Shortcut(shortcut) => {
+                    let &Shortcut { from, to, via, .. } = self.shortcut(shortcut);
+                    // The link to `via` is read first, reading the tiles of `via` along with it
+                    let first = self.fastest_link(from, via, built_on)?;
+                    let second = self.fastest_link(via, to, built_on)?;
+                    links.push(second);
+                    links.push(first);
+                }
+            }
+        }
+        Ok(traversals)
+    }
+
+    /// Snaps a location onto the closest road the profile may use, reading the
+    /// tile of the location and those around it
+    ///
+    /// Roads of islands are left out, as few routes could start or end on them.
+    pub(crate) fn snap(&mut self, location: Coordinate, options: &RouteOptions) -> Result<Snap> {
+        self.load_around(location)?;
+        let mut closest: Option<Snap> = None;
+        for radius_m in SNAP_RADII_M {
+            closest = None;
+            for (edge_ref, projection, fraction) in self.projections(location, radius_m, options) {
+                match &mut closest {
+                    Some(snap) if projection.distance_m > snap.distance_m + SNAP_TOLERANCE_M => {}
+                    Some(snap) if projection.distance_m >= snap.distance_m - SNAP_TOLERANCE_M => {
+                        snap.edges.push((edge_ref, fraction));
+                    }
+                    _ => closest = Some(self.snap_onto(location, edge_ref, projection, fraction)),
+                }
+            }
+            // Roads as close as the closest one are all within the radius
+            if closest
+                .as_ref()
+                .is_some_and(|snap| snap.distance_m + SNAP_TOLERANCE_M <= radius_m)
+            {
+                break;
+            }
+        }
+        closest.ok_or_else(|| {
+            GladsheimError::routing(format!(
+                "No road for {:?} within {}m of {}",
+                self.profile, MAX_SNAP_DISTANCE_M, location
+            ))
+        })
+    }
+
+    /// Snaps a location onto every road the profile may use within `radius_m`
+    /// of it, closest first, leaving out islands like `snap`
+    ///
+    /// Roads through the same point, such as both edges of a two-way road, are
+    /// snapped onto together.
+    pub(crate) fn candidates(
+        &mut self,
+        location: Coordinate,
+        radius_m: f64,
+        options: &RouteOptions,
+    ) -> Result<Vec<Snap>> {
+        self.load_around(location)?;
+        let mut projections = self.projections(location, radius_m, options);
+        projections.sort_by(|(_, a, _), (_, b, _)| a.distance_m.total_cmp(&b.distance_m));
+        let mut candidates: Vec<Snap> = Vec::new();
+        for (edge_ref, projection, fraction) in projections {
+            let through = candidates.iter_mut().find(|snap| {
+                utils::haversine_distance(
+                    snap.point.0,
+                    snap.point.1,
+                    projection.point.0,
+                    projection.point.1,
+                ) <= SNAP_TOLERANCE_M
+            });
+            match through {
+                Some(snap) => snap.edges.push((edge_ref, fraction)),
+                None => candidates.push(self.snap_onto(location, edge_ref, projection, fraction)),
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Reads the tile of a location and those around it, indexing the edges of
+    /// all the tiles read
+    fn load_around(&mut self, location: Coordinate) -> Result<()> {
+        let center = utils::lat_lon_to_tile_coord(location.lat, location.lon, self.zoom)?;
+        let max_tile = (1i64 << self.zoom) - 1;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let (x, y) = (center.x as i64 + dx, center.y as i64 + dy);
+                if (0..=max_tile).contains(&x) && (0..=max_tile).contains(&y) {
+                    let tile = TileCoord {
+                        x: x as u32,
+                        y: y as u32,
+                        zoom: self.zoom,
+                    };
+                    self.load_tile(&Quadkey(utils::tile_coord_to_quadkey(&tile)))?;
+                }
+            }
+        }
+        for tile_index in self.edge_trees.len()..self.tiles.len() {
+            let boxes = (0..self.tiles[tile_index].edges.len())
+                .filter_map(|edge_index| {
+                    let coords = self.edge_coordinates(EdgeRef {
+                        tile: tile_index as u32,
+                        edge: edge_index as u32,
+                    });
+                    Some((BoundingBox::of(&coords)?, edge_index as u32))
+                })
+                .collect();
+            self.edge_trees.push(RTree::new(boxes));
+        }
+        Ok(())
+    }
+
+    /// Projects a location onto the indexed edges the profile may use that
+    /// come within `max_distance_m` of it, with the share of the edge from
+    /// `from` to the closest point
+    fn projections(
+        &self,
+        location: Coordinate,
+        max_distance_m: f64,
+        options: &RouteOptions,
+    ) -> Vec<(EdgeRef, LineProjection, f64)> {
+        let query = BoundingBox::around((location.lat, location.lon), max_distance_m);
+        let mut projections = Vec::new();
+        for (tile_index, tree) in self.edge_trees.iter().enumerate() {
+            let tile = &self.tiles[tile_index];
+            for edge_index in tree.search(&query) {
+                let edge = &tile.edges[edge_index as usize];
+                if edge.is_island
+                    || !self.is_open(edge, options.date)
+                    || options.exclude.excludes(edge)
+                {
+                    continue;
+                }
+                let edge_ref = EdgeRef {
+                    tile: tile_index as u32,
+                    edge: edge_index,
+                };
+                let coords = self.edge_coordinates(edge_ref);
+                let Some(projection) =
+                    geometry::project_onto_line(&coords, location.lat, location.lon)
+                else {
+                    continue;
+                };
+                if projection.distance_m > max_distance_m {
+                    continue;
+                }
+                let length = geometry::length(&coords);
+                let fraction = if length > 0.0 {
+                    (projection.along_m / length).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                projections.push((edge_ref, projection, fraction));
+            }
+        }
+        projections
+    }
+
+    fn snap_onto(
+        &self,
+        location: Coordinate,
+        edge: EdgeRef,
+        projection: LineProjection,
+        fraction: f64,
+    ) -> Snap {
+        Snap {
+            location,
+            point: projection.point,
+            distance_m: projection.distance_m,
+            name: self.edge(edge).name.clone(),
+            edges: vec![(edge, fraction)],
+        }
+    }
+}
+
+/// Which way a search travels the edges
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    /// From the origin, along the direction of travel
+    Forward,
+    /// From the destination, against the direction of travel
+    Backward,
+}
+impl Direction {
+    /// The node a search reaches over an edge travelled from `from` to `to`, or
+    /// the other way around when not `forward`
+    fn head(self, edge: &Edge, forward: bool) -> NodeId {
+        if forward == (self == Direction::Forward) {
+            edge.to
+        } else {
+            edge.from
+        }
+    }
+}
+
+/// An edge or shortcut a search takes from a node
+struct Step {
+    link: Link,
+    /// The node the search reaches over the link
+    head: NodeId,
+}
+
+/// Where a search is, a node together with the edge it reached the node over
+/// when turns cost
+///
+/// The edge is the one into the node when searching forwards and the one out
+/// of it when searching backwards, in the direction of travel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct State {
+    node_id: NodeId,
+    edge: Option<(EdgeRef, bool)>,
+}
+
+/// A state waiting to be settled, the heap pops the smallest key first
+#[derive(Clone, Copy)]
+struct QueueEntry {
+    key: f64,
+    cost: f64,
+    state: State,
+}
+impl PartialEq for QueueEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for QueueEntry {}
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.total_cmp(&self.key)
+    }
+}
+
+/// The edge or shortcut a search reached a state over, for walking the route
+/// back to where the search started
+#[derive(Clone, Copy)]
+struct Parent {
+    /// The state at the other end of the link, `None` when the search started
+    /// part way along its edge
+    state: Option<State>,
+    /// The share of the edge from `from` the search started at, when it did
+    fraction: f64,
+    link: Link,
+}
+
+/// How the fastest route found so far gets from the origin to the destination
+#[derive(Clone, Copy)]
+enum Meeting {
+    /// Along a single edge that both the origin and the destination are on
+    Direct(Traversal),
+    /// Through a node both searches reached, in the states of the forward and
+    /// the backward search
+    Via(State, State),
+}
+
+/// What the searches of a leg have in common
+struct LegContext<'a> {
+    options: &'a RouteOptions,
+    /// The destination-only zones the leg starts or ends in
+    zones: HashSet<WayId>,
+    destination: (f64, f64),
+    max_speed_mps: f64,
+    /// The day the contraction hierarchy searched was built, `None` when
+    /// searching over the edges alone
+    hierarchy: Option<u32>,
+}
+impl LegContext<'_> {
+    fn is_usable(&self, graph: &TileGraph, edge: &Edge) -> bool {
+        // The hierarchy holds the roads open when it was built
+        graph.is_open(edge, self.hierarchy.unwrap_or(self.options.date))
+            && !self.options.exclude.excludes(edge)
+            && edge
+                .destination_zone
+                .is_none_or(|zone| self.zones.contains(&zone))
+    }
+
+    /// The state a search is in after taking a link to a node, which only
+    /// keeps the link when turns cost
+    fn state(&self, node_id: NodeId, link: Link) -> State {
+        State {
+            node_id,
+            edge: self.options.turn_costs.and(link.edge()),
+        }
+    }
+
+    /// Seconds for turning from the edge of one state into that of another,
+    /// `None` when it's forbidden
+    fn turn_cost(&self, graph: &TileGraph, from: State, to: State) -> Option<f64> {
+        match (self.options.turn_costs, from.edge, to.edge) {
+            (Some(costs), Some(from), Some(to)) => graph.turn_cost(&costs, from, to),
+            _ => Some(0.0),
+        }
+    }
+
+    /// The key a node is queued with, never more than the travel time of the
+    /// fastest route through it unless there is a tile affinity
+    fn key(&self, graph: &mut TileGraph, direction: Direction, node_id: NodeId, cost: f64) -> f64 {
+        // Upward searches stop by travel time alone
+        if self.hierarchy.is_some() {
+            return cost;
+        }
+        let mut key = cost;
+        if direction == Direction::Forward && self.options.algorithm == Algorithm::AStar {
+            key += graph.node_location(node_id).map_or(0.0, |(lat, lon)| {
+                utils::haversine_distance(lat, lon, self.destination.0, self.destination.1)
+                    / self.max_speed_mps
+            });
+        }
+        // Only forwards, as the stop test of bidirectional searches adds up the
+        // keys of both directions and would count it twice
+        if direction == Direction::Forward
+            && self.options.tile_affinity_s > 0.0
+            && graph.prefetch_pending(node_id)
+        {
+            key += self.options.tile_affinity_s;
+        }
+        key
+    }
+}
+
+/// One direction of the search of a leg, from the origin forwards or from the
+/// destination backwards
+struct Search {
+    direction: Direction,
+    /// The fastest known travel time between every reached state and where the
+    /// search started
+    costs: HashMap<State, f64>,
+    parents: HashMap<State, Parent>,
+    /// The reached states of every node
+    states: HashMap<NodeId, Vec<State>>,
+    queue: BinaryHeap<QueueEntry>,
+    settled: HashSet<State>,
+}
+impl Search {
+    /// Starts a search from the ends of the edges a waypoint is snapped onto
+    fn new(graph: &mut TileGraph, context: &LegContext, direction: Direction, snap: &Snap) -> Self {
+        let mut search = Self {
+            direction,
+            costs: HashMap::new(),
+            parents: HashMap::new(),
+            states: HashMap::new(),
+            queue: BinaryHeap::new(),
+            settled: HashSet::new(),
+        };
+        for &(edge, fraction) in &snap.edges {
+            let duration_s = graph.edge_duration_s(edge);
+            // Towards `to` leaving the origin, or from `from` into the destination
+            let along = match direction {
+                Direction::Forward => 1.0 - fraction,
+                Direction::Backward => fraction,
+            };
+            let mut seeds = vec![(true, along * duration_s)];
+            if graph.can_reverse(edge) {
+                seeds.push((false, (1.0 - along) * duration_s));
+            }
+            for (forward, cost) in seeds {
+                let link = Link::Edge { edge, forward };
+                let state = context.state(direction.head(graph.edge(edge), forward), link);
+                let parent = Parent {
+                    state: None,
+                    fraction,
+                    link,
+                };
+                search.reach(graph, context, state, cost, parent);
+            }
+        }
+        search
+    }
+
+    /// Queues a state unless it's already known to be reached faster
+    fn reach(
+        &mut self,
+        graph: &mut TileGraph,
+        context: &LegContext,
+        state: State,
+        cost: f64,
+        parent: Parent,
+    ) -> bool {
+        if self.settled.contains(&state)
+            || self.costs.get(&state).is_some_and(|known| cost >= *known)
+        {
+            return false;
+        }
+        if self.costs.insert(state, cost).is_none() {
+            self.states.entry(state.node_id).or_default().push(state);
+        }
+        self.parents.insert(state, parent);
+        let key = context.key(graph, self.direction, state.node_id, cost);
+        self.queue.push(QueueEntry { key, cost, state });
+        true
+    }
+
+    /// The smallest key in the queue, skipping states already settled
+    fn min_key(&mut self) -> f64 {
+        while let Some(entry) = self.queue.peek() {
+            if !self.settled.contains(&entry.state) {
+                return entry.key;
+            }
+            self.queue.pop();
+        }
+        f64::INFINITY
+    }
+
+    /// Settles the state with the smallest key and reaches the states next to
+    /// it, recording where this search meets the other one when that's faster
+    fn settle_next(
+        &mut self,
+        graph: &mut TileGraph,
+        context: &LegContext,
+        other: &Search,
+        best: &mut Option<(f64, Meeting)>,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        for state in self.expand(graph, context, cancel)? {
+            self.meet(graph, context, other, state, best);
+        }
+        Ok(())
+    }
+
+    /// Settles the state with the smallest key and reaches the states next to
+    /// it, returning those reached faster than before
+    fn expand(
+        &mut self,
+        graph: &mut TileGraph,
+        context: &LegContext,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<State>> {
+        let mut reached = Vec::new();
+        if self.min_key().is_infinite() {
+            return Ok(reached);
+        }
+        let Some(entry) = self.queue.pop() else {
+            return Ok(reached);
+        };
+        self.settled.insert(entry.state);
+        graph.num_settled += 1;
+        if graph.num_settled % CANCEL_CHECK_INTERVAL == 0 {
+            cancel.check(|| {
+                format!(
+                    "settling {} nodes over {} tiles",
+                    graph.num_settled,
+                    graph.num_loaded()
+                )
+            })?;
+        }
+
+        let node_id = entry.state.node_id;
+        graph.load_pending(node_id)?;
+        let mut steps = graph.steps(node_id, self.direction, |edge| {
+            context.is_usable(graph, edge)
+        });
+        if context.hierarchy.is_some() {
+            // Only up the hierarchy, the searches meet at the highest ranked node of the route
+            steps.extend(graph.shortcut_steps(node_id, self.direction));
+            let rank = graph.ranks.get(&node_id).copied();
+            steps.retain(|step| {
+                graph
+                    .ranks
+                    .get(&step.head)
+                    .is_some_and(|head| rank.is_none_or(|rank| *head > rank))
+            });
+        }
+        for step in steps {
+            let state = context.state(step.head, step.link);
+            let turn_s = match self.direction {
+                Direction::Forward => context.turn_cost(graph, entry.state, state),
+                Direction::Backward => context.turn_cost(graph, state, entry.state),
+            };
+            let Some(turn_s) = turn_s else {
+                continue;
+            };
+            let cost = entry.cost + turn_s + graph.duration_s(step.link);
+            let parent = Parent {
+                state: Some(entry.state),
+                fraction: 0.0,
+                link: step.link,
+            };
+            if self.reach(graph, context, state, cost, parent) {
+                reached.push(state);
+            }
+        }
+        Ok(reached)
+    }
+
+    /// Records a route through a state when the other search reached its node
+    /// too and it's faster than the best route so far
+    fn meet(
+        &self,
+        graph: &TileGraph,
+        context: &LegContext,
+        other: &Search,
+        state: State,
+        best: &mut Option<(f64, Meeting)>,
+    ) {
+        let Some(cost) = self.costs.get(&state) else {
+            return;
+        };
+        for other_state in other.states.get(&state.node_id).into_iter().flatten() {
+            let (forward, backward) = match self.direction {
+                Direction::Forward => (state, *other_state),
+                Direction::Backward => (*other_state, state),
+            };
+            let Some(turn_s) = context.turn_cost(graph, forward, backward) else {
+                continue;
+            };
+            let total = cost + other.costs[other_state] + turn_s;
+            if best.is_none_or(|(best_cost, _meeting)| total < best_cost) {
+                *best = Some((total, Meeting::Via(forward, backward)));
+            }
+        }
+    }
+
+    /// The stretches of edges between where the search started and a state it
+    /// reached, in the direction of travel, unpacking the shortcuts taken
+    fn traversals(
+        &self,
+        graph: &mut TileGraph,
+        context: &LegContext,
+        mut state: State,
+    ) -> Result<Vec<Traversal>> {
+        let mut traversals = Vec::new();
+        while let Some(parent) = self.parents.get(&state) {
+            match parent.link {
+                Link::Edge { edge, forward } => {
+                    let whole = Traversal::whole(edge, forward);
+                    let started = parent.state.is_none().then_some(parent.fraction);
+                    traversals.push(match self.direction {
+                        Direction::Forward => Traversal {
+                            start: started.unwrap_or(whole.start),
+                            ..whole
+                        },
+                        Direction::Backward => Traversal {
+                            end: started.unwrap_or(whole.end),
+                            ..whole
+                        },
+                    });
+                }
+                Link::Shortcut(shortcut) => {
+                    let built_on = context.hierarchy.unwrap_or(context.options.date);
+                    let mut unpacked = graph.unpack(shortcut, built_on)?;
+                    // Collected against the direction of travel when searching forwards
+                    if self.direction == Direction::Forward {
+                        unpacked.reverse();
+                    }
+                    traversals.extend(unpacked);
+                }
+            }
+            match parent.state {
+                Some(previous) => state = previous,
+                None => break,
+            }
+        }
+        if self.direction == Direction::Forward {
+            traversals.reverse();
+        }
+        Ok(traversals)
+    }
+}
+
+/// Finds the fastest route through waypoints, one leg after the other
+///
+/// Every leg is a search over the duration of the edges, starting from the
+/// edges the origin is snapped onto, part way along them. Roads of
+/// destination-only zones are only used by legs starting or ending in the zone.
+/// With turn costs, the search goes by the edge every node is reached over,
+/// adding the cost of the turns and leaving out restricted ones.
+///
+/// Over tiles with a contraction hierarchy, from `BuildCh`, a leg is instead
+/// searched from both ends up the hierarchy, taking shortcuts, which settles a
+/// few hundred nodes on any distance. That's done unless the leg starts or ends
+/// in a destination-only zone or on a road the hierarchy doesn't have, turns
+/// cost, areas or kinds of roads are avoided, current speeds of traffic are
+/// used, or the day of travel is before the hierarchy was built. Roads opening
+/// after it was built aren't used then, until it's built again.
+///
+/// Current speeds are read again first when their file changed.
+///
+/// Alternatives, when asked for, are searched for over the edges alone.
+pub(crate) fn route(
+    graph: &mut TileGraph,
+    waypoints: &[Coordinate],
+    options: &RouteOptions,
+    cancel: &CancellationToken,
+) -> Result<Route> {
+    if waypoints.len() < 2 {
+        return Err(GladsheimError::parse(
+            "A route needs at least an origin and a destination",
+        ));
+    }
+    graph.reload_traffic()?;
+    let snaps = waypoints
+        .iter()
+        .map(|waypoint| graph.snap(*waypoint, options))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(alternatives) = &options.alternatives {
+        let [origin, destination] = snaps.as_slice() else {
+            return Err(GladsheimError::parse(
+                "Alternatives are only suggested for routes without intermediate waypoints",
+            ));
+        };
+        let mut legs = alternative_legs(graph, origin, destination, options, alternatives, cancel)?;
+        let alternatives = legs.split_off(1);
+        return Ok(Route {
+            waypoints: snaps,
+            legs,
+            alternatives,
+        });
+    }
+    let legs = snaps
+        .windows(2)
+        .map(|pair| search_leg(graph, &pair[0], &pair[1], options, cancel))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Route {
+        waypoints: snaps,
+        legs,
+        alternatives: Vec::new(),
+    })
+}
+
+fn search_leg(
+    graph: &mut TileGraph,
+    origin: &Snap,
+    destination: &Snap,
+    options: &RouteOptions,
+    cancel: &CancellationToken,
+) -> Result<Leg> {
+    let zones = zones_of(graph, [origin, destination].into_iter());
+    let hierarchy = graph.built_on.filter(|built_on| {
+        *built_on <= options.date
+            && zones.is_empty()
+            && options.turn_costs.is_none()
+            && graph.avoided_areas.is_empty()
+            && options.exclude.is_empty()
+            && graph.traffic.is_none()
+            && origin
+                .edges
+                .iter()
+                .chain(&destination.edges)
+                .all(|(edge, _fraction)| graph.is_open(graph.edge(*edge), *built_on))
+    });
+    let context = LegContext {
+        options,
+        zones,
+        destination: destination.point,
+        max_speed_mps: f64::from(graph.profile.max_speed_kmh()) / 3.6,
+        hierarchy,
+    };
+    let mut forward = Search::new(graph, &context, Direction::Forward, origin);
+    let mut backward = Search::new(graph, &context, Direction::Backward, destination);
+
+    let mut best = direct_meeting(graph, origin, destination);
+    for state in forward.costs.keys() {
+        forward.meet(graph, &context, &backward, *state, &mut best);
+    }
+
+    let best_cost = |best: &Option<(f64, Meeting)>| best.map_or(f64::INFINITY, |(cost, _)| cost);
+    if context.hierarchy.is_some() {
+        // Going only up, neither search can find a faster route once its keys reach the best
+        loop {
+            let (forward_key, backward_key) = (forward.min_key(), backward.min_key());
+            if forward_key.min(backward_key) >= best_cost(&best) {
+                break;
+            }
+            if forward_key <= backward_key {
+                forward.settle_next(graph, &context, &backward, &mut best, cancel)?;
+            } else {
+                backward.settle_next(graph, &context, &forward, &mut best, cancel)?;
+            }
+        }
+    } else if options.algorithm == Algorithm::Bidirectional {
+        // Every route still to be found takes at least the smallest keys of both searches
+        loop {
+            let (forward_key, backward_key) = (forward.min_key(), backward.min_key());
+            if forward_key + backward_key >= best_cost(&best) {
+                break;
+            }
+            if forward_key <= backward_key {
+                forward.settle_next(graph, &context, &backward, &mut best, cancel)?;
+            } else {
+                backward.settle_next(graph, &context, &forward, &mut best, cancel)?;
+            }
+        }
+    } else {
+        // The destination is only reached from the ends of its edges
+        while forward.min_key() < best_cost(&best) {
+            forward.settle_next(graph, &context, &backward, &mut best, cancel)?;
+        }
+    }
+
+    let Some(best) = best else {
+        return Err(GladsheimError::routing(format!(
+            "No route from {} to {}",
+            origin.location, destination.location
+        )));
+    };
+    leg_of_meeting(graph, &context, &forward, &backward, best)
+}
+
+/// Finds the fastest leg between two waypoints followed by the alternatives to
+/// it, see `Alternatives`
+///
+/// Both searches go over the edges, without the contraction hierarchy, and
+/// once they've met keep on until they've settled every node an alternative
+/// could go through. Each of the nodes both reached is on a plateau, and the
+/// fastest route through every plateau is tried as an alternative, the
+/// fastest first.
+fn alternative_legs(
+    graph: &mut TileGraph,
+    origin: &Snap,
+    destination: &Snap,
+    options: &RouteOptions,
+    alternatives: &Alternatives,
+    cancel: &CancellationToken,
+) -> Result<Vec<Leg>> {
+    // Keys by travel time alone, so both searches settle all the nodes within the stretch
+    let options = RouteOptions {
+        algorithm: Algorithm::Dijkstra,
+        tile_affinity_s: 0.0,
+        ..*options
+    };
+    let context = LegContext {
+        options: &options,
+        zones: zones_of(graph, [origin, destination].into_iter()),
+        destination: destination.point,
+        max_speed_mps: f64::from(graph.profile.max_speed_kmh()) / 3.6,
+        hierarchy: None,
+    };
+    let mut forward = Search::new(graph, &context, Direction::Forward, origin);
+    let mut backward = Search::new(graph, &context, Direction::Backward, destination);
+
+    let mut best = direct_meeting(graph, origin, destination);
+    for state in forward.costs.keys() {
+        forward.meet(graph, &context, &backward, *state, &mut best);
+    }
+    loop {
+        let (forward_key, backward_key) = (forward.min_key(), backward.min_key());
+        if forward_key + backward_key >= best.map_or(f64::INFINITY, |(cost, _)| cost) {
+            break;
+        }
+        if forward_key <= backward_key {
+            forward.settle_next(graph, &context, &backward, &mut best, cancel)?;
+        } else {
+            backward.settle_next(graph, &context, &forward, &mut best, cancel)?;
+        }
+    }
+    let Some(fastest) = best else {
+        return Err(GladsheimError::routing(format!(
+            "No route from {} to {}",
+            origin.location, destination.location
+        )));
+    };
+    let max_cost = fastest.0 * (1.0 + MAX_STRETCH);
+    while forward.min_key() <= max_cost {
+        forward.expand(graph, &context, cancel)?;
+    }
+    while backward.min_key() <= max_cost {
+        backward.expand(graph, &context, cancel)?;
+    }
+
+    let mut candidates = Vec::new();
+    for state in &forward.settled {
+        let mut via = None;
+        forward.meet(graph, &context, &backward, *state, &mut via);
+        if let Some((cost, Meeting::Via(forward_state, backward_state))) = via {
+            if cost <= max_cost {
+                candidates.push((cost, forward_state, backward_state));
+            }
+        }
+    }
+    candidates.sort_by(|(cost, state, _), (other_cost, other_state, _)| {
+        cost.total_cmp(other_cost)
+            .then(state.node_id.0.cmp(&other_state.node_id.0))
+    });
+
+    let mut legs = vec![leg_of_meeting(
+        graph, &context, &forward, &backward, fastest,
+    )?];
+    // Nodes of the plateaus tried, the route through any of them being the same
+    let mut tried = HashSet::new();
+    if let (_cost, Meeting::Via(forward_state, backward_state)) = fastest {
+        let (nodes, _length_m) = plateau(
+            graph,
+            &context,
+            &forward,
+            &backward,
+            forward_state,
+            backward_state,
+        );
+        tried.extend(nodes);
+    }
+    for (cost, forward_state, backward_state) in candidates {
+        if legs.len() > alternatives.max_alternatives {
+            break;
+        }
+        if tried.contains(&forward_state.node_id) {
+            continue;
+        }
+        let (nodes, plateau_m) = plateau(
+            graph,
+            &context,
+            &forward,
+            &backward,
+            forward_state,
+            backward_state,
+        );
+        tried.extend(nodes);
+        let leg = leg_of_meeting(
+            graph,
+            &context,
+            &forward,
+            &backward,
+            (cost, Meeting::Via(forward_state, backward_state)),
+        )?;
+        let detour_m = leg.distance_m - shared_m(graph, &leg, &legs[0]);
+        if plateau_m < MIN_PLATEAU_SHARE * detour_m
+            || legs.iter().any(|other| {
+                shared_m(graph, &leg, other) > alternatives.max_overlap * leg.distance_m
+            })
+        {
+            continue;
+        }
+        legs.push(leg);
+    }
+    Ok(legs)
+}
+
+/// The nodes and meters of the plateau through the node where the states of
+/// both searches meet, the stretch around it where the way there from the
+/// origin and the way on to the destination are both the fastest
+fn plateau(
+    graph: &TileGraph,
+    context: &LegContext,
+    forward: &Search,
+    backward: &Search,
+    forward_state: State,
+    backward_state: State,
+) -> (Vec<NodeId>, f64) {
+    let mut nodes = vec![forward_state.node_id];
+    let mut length_m = 0.0;
+    // Towards the origin along the forward search, then towards the destination
+    // along the backward one, while the other search came the same way
+    for (search, other, mut state, mut other_state) in [
+        (forward, backward, forward_state, backward_state),
+        (backward, forward, backward_state, forward_state),
+    ] {
+        while let Some(Parent {
+            state: Some(previous),
+            link,
+            ..
+        }) = search.parents.get(&state).copied()
+        {
+            let mirrored = context.state(previous.node_id, link);
+            let is_shared = other.parents.get(&mirrored).is_some_and(|parent| {
+                parent.state == Some(other_state) && parent.link.edge() == link.edge()
+            });
+            if !is_shared {
+                break;
+            }
+            length_m += link
+                .edge()
+                .map_or(0.0, |(edge, _forward)| f64::from(graph.edge(edge).length_m));
+            nodes.push(previous.node_id);
+            (state, other_state) = (previous, mirrored);
+        }
+    }
+    (nodes, length_m)
+}
+
+/// Meters of a leg along the edges of another one
+fn shared_m(graph: &TileGraph, leg: &Leg, other: &Leg) -> f64 {
+    let edges = other
+        .traversals
+        .iter()
+        .map(|traversal| traversal.edge)
+        .collect::<HashSet<_>>();
+    leg.traversals
+        .iter()
+        .filter(|traversal| edges.contains(&traversal.edge))
+        .map(|traversal| f64::from(graph.edge(traversal.edge).length_m) * traversal.share())
+        .sum()
+}
+
+/// Finds the fastest routes from an origin to several destinations at once,
+/// by a single search from the origin that runs until it has settled all of
+/// them, with `None` for the destinations without a route or farther than
+/// `max_s` seconds away
+///
+/// Much like a leg of `route` searched by `Algorithm::Dijkstra`, that isn't
+/// steered towards any of the destinations and doesn't use the contraction
+/// hierarchy, whose upward searches only meet those from the other end. The
+/// roads of destination-only zones the origin or any destination is in may be
+/// used by all of the routes.
+pub(crate) fn one_to_many(
+    graph: &mut TileGraph,
+    origin: &Snap,
+    destinations: &[Snap],
+    max_s: f64,
+    options: &RouteOptions,
+    cancel: &CancellationToken,
+) -> Result<Vec<Option<Leg>>> {
+    let options = RouteOptions {
+        algorithm: Algorithm::Dijkstra,
+        ..*options
+    };
+    let context = LegContext {
+        options: &options,
+        zones: zones_of(graph, std::iter::once(origin).chain(destinations)),
+        destination: origin.point,
+        max_speed_mps: f64::from(graph.profile.max_speed_kmh()) / 3.6,
+        hierarchy: None,
+    };
+    let targets = destinations
+        .iter()
+        .map(|destination| Search::new(graph, &context, Direction::Backward, destination))
+        .collect::<Vec<_>>();
+    // The destinations reached from the ends of the edges at every node
+    let mut targets_at: HashMap<NodeId, Vec<usize>> = HashMap::new();
+    for (index, target) in targets.iter().enumerate() {
+        for node_id in target.states.keys() {
+            targets_at.entry(*node_id).or_default().push(index);
+        }
+    }
+
+    let mut best = destinations
+        .iter()
+        .map(|destination| direct_meeting(graph, origin, destination))
+        .collect::<Vec<_>>();
+    let forward = sweep(
+        graph,
+        &context,
+        origin,
+        cancel,
+        |graph, forward, reached| {
+            for state in reached {
+                for index in targets_at.get(&state.node_id).into_iter().flatten() {
+                    forward.meet(graph, &context, &targets[*index], state, &mut best[*index]);
+                }
+            }
+            // Routes still to be found take at least the smallest key
+            best.iter()
+                .map(|best| best.map_or(f64::INFINITY, |(cost, _meeting)| cost))
+                .fold(0.0, f64::max)
+                .min(max_s)
+        },
+    )?;
+
+    best.into_iter()
+        .zip(&targets)
+        .map(|(best, target)| {
+            best.filter(|(cost, _meeting)| *cost <= max_s)
+                .map(|best| leg_of_meeting(graph, &context, &forward, target, best))
+                .transpose()
+        })
+        .collect()
+}
+
+/// Searches forwards from an origin without steering towards anywhere, so
+/// states are settled in the order of their travel time, until the smallest
+/// key is at least the bound `visit` gives after seeing the states reached
+///
+/// The states reached from the origin itself are visited first, then those
+/// reached by settling each state.
+fn sweep(
+    graph: &mut TileGraph,
+    context: &LegContext,
+    origin: &Snap,
+    cancel: &CancellationToken,
+    mut visit: impl FnMut(&TileGraph, &Search, Vec<State>) -> f64,
+) -> Result<Search> {
+    let mut forward = Search::new(graph, context, Direction::Forward, origin);
+    let mut reached = forward.costs.keys().copied().collect::<Vec<_>>();
+    loop {
+        let bound = visit(graph, &forward, reached);
+        if forward.min_key() >= bound {
+            return Ok(forward);
+        }
+        reached = forward.expand(graph, context, cancel)?;
+    }
+}
+
+/// A stretch of an edge reached from an origin within a time budget
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Reached {
+    pub(crate) traversal: Traversal,
+    /// Seconds from the origin to where the stretch starts
+    pub(crate) start_s: f64,
+    /// Seconds from the origin to where the stretch ends
+    pub(crate) end_s: f64,
+}
+
+/// Finds the roads reachable from an origin within `max_s` seconds, by the
+/// sweep of `one_to_many` stopping at the budget
+///
+/// Every edge leaving a settled node is reached up to where the budget runs
+/// out along it, so the stretches may overlap where an edge is reached from
+/// both ends. Like `one_to_many` the search isn't steered and doesn't use the
+/// contraction hierarchy, and the roads of a destination-only zone the origin
+/// is in may be used.
+pub(crate) fn reachable(
+    graph: &mut TileGraph,
+    origin: &Snap,
+    max_s: f64,
+    options: &RouteOptions,
+    cancel: &CancellationToken,
+) -> Result<Vec<Reached>> {
+    let options = RouteOptions {
+        algorithm: Algorithm::Dijkstra,
+        ..*options
+    };
+    let context = LegContext {
+        options: &options,
+        zones: zones_of(graph, std::iter::once(origin)),
+        destination: origin.point,
+        max_speed_mps: f64::from(graph.profile.max_speed_kmh()) / 3.6,
+        hierarchy: None,
+    };
+    let forward = sweep(
+        graph,
+        &context,
+        origin,
+        cancel,
+        |_graph, _forward, _reached| max_s,
+    )?;
+
+    // Stretches from the origin along its own edges, then onwards from every settled state
+    let mut reached = Vec::new();
+    let mut stretch = |traversal: Traversal, start_s: f64, duration_s: f64| {
+        let share = traversal.share();
+        let within = if duration_s * share > 0.0 {
+            ((max_s - start_s) / (duration_s * share)).min(1.0)
+        } else {
+            1.0
+        };
+        if within > 0.0 {
+            reached.push(Reached {
+                traversal: Traversal {
+                    end: traversal.start + (traversal.end - traversal.start) * within,
+                    ..traversal
+                },
+                start_s,
+                end_s: start_s + duration_s * share * within,
+            });
+        }
+    };
+    for &(edge, fraction) in &origin.edges {
+        let duration_s = graph.edge_duration_s(edge);
+        stretch(
+            Traversal {
+                edge,
+                start: fraction,
+                end: 1.0,
+            },
+            0.0,
+            duration_s,
+        );
+        if graph.can_reverse(edge) {
+            stretch(
+                Traversal {
+                    edge,
+                    start: fraction,
+                    end: 0.0,
+                },
+                0.0,
+                duration_s,
+            );
+        }
+    }
+    for state in &forward.settled {
+        let cost = forward.costs[state];
+        let steps = graph.steps(state.node_id, Direction::Forward, |edge| {
+            context.is_usable(graph, edge)
+        });
+        for step in steps {
+            let Some((edge, is_forward)) = step.link.edge() else {
+                continue;
+            };
+            let Some(turn_s) =
+                context.turn_cost(graph, *state, context.state(step.head, step.link))
+            else {
+                continue;
+            };
+            stretch(
+                Traversal::whole(edge, is_forward),
+                cost + turn_s,
+                graph.edge_duration_s(edge),
+            );
+        }
+    }
+    Ok(reached)
+}
+
+/// The destination-only zones of the edges waypoints are snapped onto
+fn zones_of<'a>(graph: &TileGraph, snaps: impl Iterator<Item = &'a Snap>) -> HashSet<WayId> {
+    snaps
+        .flat_map(|snap| &snap.edges)
+        .filter_map(|(edge, _fraction)| graph.edge(*edge).destination_zone)
+        .collect()
+}
+
+/// The route along a single edge when both ends are on it, which may be
+/// faster than leaving it
+fn direct_meeting(graph: &TileGraph, origin: &Snap, destination: &Snap) -> Option<(f64, Meeting)> {
+    let mut best: Option<(f64, Meeting)> = None;
+    for &(edge, start) in &origin.edges {
+        for &(other, end) in &destination.edges {
+            if other == edge && (end >= start || graph.can_reverse(edge)) {
+                let cost = (end - start).abs() * graph.edge_duration_s(edge);
+                if best.is_none_or(|(best_cost, _meeting)| cost < best_cost) {
+                    best = Some((cost, Meeting::Direct(Traversal { edge, start, end })));
+                }
+            }
+        }
+    }
+    best
+}
+
+/// The leg where the searches from both ends met for the fastest route
+fn leg_of_meeting(
+    graph: &mut TileGraph,
+    context: &LegContext,
+    forward: &Search,
+    backward: &Search,
+    (cost, meeting): (f64, Meeting),
+) -> Result<Leg> {
+    let traversals = match meeting {
+        Meeting::Direct(traversal) => vec![traversal],
+        Meeting::Via(forward_state, backward_state) => {
+            let mut traversals = forward.traversals(graph, context, forward_state)?;
+            traversals.extend(backward.traversals(graph, context, backward_state)?);
+            traversals
+        }
+    };
+    let mut leg = leg_of(graph, traversals);
+    if context.options.turn_costs.is_some() {
+        // The turns take time besides the edges
+        leg.duration_s = cost;
+    }
+    Ok(leg)
+}
+
+/// Sums up the stretches of edges of a leg
+fn leg_of(graph: &TileGraph, traversals: Vec<Traversal>) -> Leg {
+    let mut distance_m = 0.0;
+    let mut duration_s = 0.0;
+    let mut co2_g = None;
+    // The distance along every street name, in the order they're driven
+    let mut streets: Vec<(&str, f64)> = Vec::new();
+    for traversal in &traversals {
+        let edge = graph.edge(traversal.edge);
+        let share = traversal.share();
+        distance_m += f64::from(edge.length_m) * share;
+        duration_s += graph.edge_duration_s(traversal.edge) * share;
+        if let Some(emissions) = &edge.emissions {
+            let co2 = if traversal.end >= traversal.start {
+                emissions.co2_g
+            } else {
+                emissions.co2_reverse_g
+            };
+            *co2_g.get_or_insert(0.0) += f64::from(co2) * share;
+        }
+        if let Some(name) = &edge.name {
+            match streets.iter_mut().find(|(street, _length)| street == name) {
+                Some((_street, length)) => *length += f64::from(edge.length_m) * share,
+                None => streets.push((name, f64::from(edge.length_m) * share)),
+            }
+        }
+    }
+    let mut coordinates: Vec<(f64, f64)> = Vec::new();
+    for traversal in &traversals {
+        let edge_coordinates = graph.edge_coordinates(traversal.edge);
+        for point in geometry::sub_line(&edge_coordinates, traversal.start, traversal.end) {
+            if coordinates.last() != Some(&point) {
+                coordinates.push(point);
+            }
+        }
+    }
+    let mut longest = streets.iter().enumerate().collect::<Vec<_>>();
+    longest.sort_by(|(_, a), (_, b)| b.1.total_cmp(&a.1));
+    longest.truncate(2);
+    longest.sort_by_key(|(order, _)| *order);
+    Leg {
+        distance_m,
+        duration_s,
+        co2_g,
+        summary: longest
+            .into_iter()
+            .map(|(_order, (name, _length))| name.to_string())
+            .collect(),
+        traversals,
+        geometry: coordinates,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::osm_parser::{self, ParseOptions};
+
+    /// A node of a test network as id, lat and lon
+    type TestNode = (i64, f64, f64);
+    /// A way of a test network as id, node ids and tags
+    type TestWay = (i64, Vec<i64>, Vec<(&'static str, &'static str)>);
+    /// A relation of a test network as id, members as type, id and role, and tags
+    type TestRelation = (
+        i64,
+        Vec<(&'static str, i64, &'static str)>,
+        Vec<(&'static str, &'static str)>,
+    );
+
+    /// Parses a network written as OSM XML into tiles in a temporary directory
+    fn write_tiles(name: &str, nodes: &[TestNode], ways: &[TestWay]) -> PathBuf {
+        write_tiles_with(name, nodes, ways, &[], &ParseOptions::default())
+    }
+
+    /// Like `write_tiles`, with relations and parsed with options
+    fn write_tiles_with(
+        name: &str,
+        nodes: &[TestNode],
+        ways: &[TestWay],
+        relations: &[TestRelation],
+        options: &ParseOptions,
+    ) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("gladsheim-routing-{}-{}", name, std::process::id()));
+        let tiles_dir = dir.join("tiles");
+        std::fs::create_dir_all(&tiles_dir).unwrap();
+        let mut xml = String::from("<?xml version=\"1.0\"?>\n<osm version=\"0.6\">\n");
+        for (id, lat, lon) in nodes {
+            let _ = writeln!(xml, r#"<node id="{id}" lat="{lat}" lon="{lon}"/>"#);
+        }
+        for (id, refs, tags) in ways {
+            let _ = writeln!(xml, r#"<way id="{id}">"#);
+            for node_id in refs {
+                let _ = writeln!(xml, r#"<nd ref="{node_id}"/>"#);
+            }
+            for (key, value) in tags {
+                let _ = writeln!(xml, r#"<tag k="{key}" v="{value}"/>"#);
+            }
+            xml.push_str("</way>\n");
+        }
+        for (id, members, tags) in relations {
+            let _ = writeln!(xml, r#"<relation id="{id}">"#);
+            for (member_type, id, role) in members {
+                let _ = writeln!(
+                    xml,
+                    r#"<member type="{member_type}" ref="{id}" role="{role}"/>"#
+                );
+            }
+            for (key, value) in tags {
+                let _ = writeln!(xml, r#"<tag k="{key}" v="{value}"/>"#);
+            }
+            xml.push_str("</relation>\n");
+        }
+        xml.push_str("</osm>\n");
+        let fname = dir.join("network.osm");
+        std::fs::write(&fname, xml).unwrap();
+        osm_parser::read_osm(&fname, &tiles_dir, options, &CancellationToken::default()).unwrap();
+        tiles_dir
+    }
+
+    fn remove_tiles(tiles_dir: &Path) {
+        std::fs::remove_dir_all(tiles_dir.parent().unwrap()).unwrap();
+    }
+
+    /// A slow street from node 1 east to node 2, and a fast oneway road from
+    /// node 1 to node 2 bending north through node 3
+    fn two_roads() -> (Vec<TestNode>, Vec<TestWay>) {
+        let nodes = vec![(1, 59.33, 18.06), (2, 59.33, 18.07), (3, 59.332, 18.065)];
+        let ways = vec![
+            (
+                10,
+                vec![1, 2],
+                vec![
+                    ("highway", "residential"),
+                    ("maxspeed", "10"),
+                    ("name", "Slow street"),
+                ],
+            ),
+            (
+                11,
+                vec![1, 3, 2],
+                vec![
+                    ("highway", "primary"),
+                    ("maxspeed", "70"),
+                    ("oneway", "yes"),
+                    ("name", "Fast road"),
+                ],
+            ),
+        ];
+        (nodes, ways)
+    }
+
+    fn options() -> RouteOptions {
+        RouteOptions {
+            date: 20260101,
+            ..Default::default()
+        }
+    }
+
+    /// A grid of `size` by `size` intersections about 220 m apart, on streets of
+    /// different speeds with every other avenue oneway
+    fn street_grid(size: i64) -> (Vec<TestNode>, Vec<TestWay>) {
+        let id = |row: i64, col: i64| 1 + row * size + col;
+        let mut nodes = Vec::new();
+        for row in 0..size {
+            for col in 0..size {
+                let (lat, lon) = (59.33 + row as f64 * 0.002, 18.06 + col as f64 * 0.004);
+                nodes.push((id(row, col), lat, lon));
+            }
+        }
+        let mut ways = Vec::new();
+        for row in 0..size {
+            let highway = ["primary", "residential", "secondary"][row as usize % 3];
+            let refs = (0..size).map(|col| id(row, col)).collect();
+            ways.push((100 + row, refs, vec![("highway", highway)]));
+        }
+        for col in 0..size {
+            let mut tags = vec![("highway", "tertiary")];
+            if col % 2 == 1 {
+                tags.push(("oneway", "yes"));
+            }
+            let refs = (0..size).map(|row| id(row, col)).collect();
+            ways.push((200 + col, refs, tags));
+        }
+        (nodes, ways)
+    }
+
+    /// The intersections of a network and points halfway along its first ways
+    fn grid_locations(nodes: &[TestNode]) -> Vec<Coordinate> {
+        let mut locations = nodes
+            .iter()
+            .map(|(_id, lat, lon)| Coordinate {
+                lat: *lat,
+                lon: *lon,
+            })
+            .collect::<Vec<_>>();
+        for pair in nodes.windows(2).take(3) {
+            locations.push(Coordinate {
+                lat: (pair[0].1 + pair[1].1) / 2.0,
+                lon: (pair[0].2 + pair[1].2) / 2.0,
+            });
+        }
+        locations
+    }
+
+    /// The durations between every pair of locations with an algorithm
+    fn all_durations(
+        graph: &mut TileGraph,
+        locations: &[Coordinate],
+        options: &RouteOptions,
+    ) -> Vec<f64> {
+        let mut durations = Vec::new();
+        for from in locations {
+            for to in locations {
+                let route = route(graph, &[*from, *to], options, &CancellationToken::default());
+                durations.push(duration_s(&route.unwrap()));
+            }
+        }
+        durations
+    }
+
+    fn assert_same_durations(durations: &[f64], expected: &[f64]) {
+        assert_eq!(durations.len(), expected.len());
+        for (duration, expected) in durations.iter().zip(expected) {
+            assert!(
+                (duration - expected).abs() < 1e-6,
+                "{duration} != {expected}"
+            );
+        }
+    }
+
+    fn distance_m(route: &Route) -> f64 {
+        route.legs.iter().map(|leg| leg.distance_m).sum()
+    }
+
+    fn duration_s(route: &Route) -> f64 {
+        route.legs.iter().map(|leg| leg.duration_s).sum()
+    }
+
+    fn street_names(graph: &TileGraph, route: &Route) -> Vec<String> {
+        route
+            .legs
+            .iter()
+            .flat_map(|leg| &leg.traversals)
+            .filter_map(|traversal| graph.edge(traversal.edge).name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn takes_the_faster_road_where_it_may() {
+        let (nodes, ways) = two_roads();
+        let tiles_dir = write_tiles("faster", &nodes, &ways);
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let cancel = CancellationToken::default();
+        let west = Coordinate {
+            lat: 59.33,
+            lon: 18.06,
+        };
+        let east = Coordinate {
+            lat: 59.33,
+            lon: 18.07,
+        };
+        let there = route(&mut graph, &[west, east], &options(), &cancel).unwrap();
+        let back = route(&mut graph, &[east, west], &options(), &cancel).unwrap();
+        remove_tiles(&tiles_dir);
+
+        // The fast road is longer but takes a fraction of the time, one way only
+        assert_eq!(street_names(&graph, &there), ["Fast road"]);
+        assert_eq!(street_names(&graph, &back), ["Slow street"]);
+        assert_eq!(there.legs[0].summary, ["Fast road"]);
+        assert!(distance_m(&there) > distance_m(&back));
+        assert!(duration_s(&there) < duration_s(&back) / 2.0);
+    }
+
+    #[test]
+    fn routes_along_part_of_an_edge() {
+        let (nodes, ways) = two_roads();
+        let tiles_dir = write_tiles("part", &nodes, &ways);
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let from = Coordinate {
+            lat: 59.33,
+            lon: 18.0625,
+        };
+        let to = Coordinate {
+            lat: 59.33,
+            lon: 18.0675,
+        };
+        let route = route(
+            &mut graph,
+            &[from, to],
+            &options(),
+            &CancellationToken::default(),
+        )
+        .unwrap();
+        remove_tiles(&tiles_dir);
+
+        // From a quarter to three quarters along the slow street
+        assert_eq!(route.legs.len(), 1);
+        let leg = &route.legs[0];
+        assert_eq!(leg.traversals.len(), 1);
+        let traversal = leg.traversals[0];
+        let edge = graph.edge(traversal.edge);
+        assert_eq!(edge.name.as_deref(), Some("Slow street"));
+        assert!((traversal.share() - 0.5).abs() < 0.01);
+        assert!((leg.duration_s - f64::from(edge.duration_s) / 2.0).abs() < 1.0);
+        assert!((leg.distance_m - f64::from(edge.length_m) / 2.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn locations_far_from_roads_are_rejected() {
+        let (nodes, ways) = two_roads();
+        let tiles_dir = write_tiles("far", &nodes, &ways);
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let from = Coordinate {
+            lat: 59.33,
+            lon: 18.06,
+        };
+        let far = Coordinate {
+            lat: 59.38,
+            lon: 18.06,
+        };
+        let result = route(
+            &mut graph,
+            &[from, far],
+            &options(),
+            &CancellationToken::default(),
+        );
+        remove_tiles(&tiles_dir);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn a_star_matches_dijkstra() {
+        let (nodes, ways) = street_grid(5);
+        let tiles_dir = write_tiles("astar", &nodes, &ways);
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let locations = grid_locations(&nodes);
+        let dijkstra = RouteOptions {
+            algorithm: Algorithm::Dijkstra,
+            ..options()
+        };
+        let a_star = RouteOptions {
+            algorithm: Algorithm::AStar,
+            ..options()
+        };
+        let expected = all_durations(&mut graph, &locations, &dijkstra);
+        let durations = all_durations(&mut graph, &locations, &a_star);
+        remove_tiles(&tiles_dir);
+        assert_same_durations(&durations, &expected);
+        // The oneway avenues make some routes take a detour one way only
+        let n = locations.len();
+        let detour = |i: usize, j: usize| expected[i * n + j] - expected[j * n + i] > 1.0;
+        assert!((0..n).any(|i| (0..n).any(|j| detour(i, j))));
+    }
+
+    #[test]
+    fn bidirectional_dijkstra_matches_dijkstra() {
+        let (nodes, ways) = street_grid(5);
+        let tiles_dir = write_tiles("bidirectional", &nodes, &ways);
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let locations = grid_locations(&nodes);
+        let dijkstra = RouteOptions {
+            algorithm: Algorithm::Dijkstra,
+            ..options()
+        };
+        let bidirectional = RouteOptions {
+            algorithm: Algorithm::Bidirectional,
+            ..options()
+        };
+        let expected = all_durations(&mut graph, &locations, &dijkstra);
+        let durations = all_durations(&mut graph, &locations, &bidirectional);
+        remove_tiles(&tiles_dir);
+        assert_same_durations(&durations, &expected);
+    }
+
+    #[test]
+    fn tile_affinity_slows_routes_by_at_most_itself() {
+        let (nodes, ways) = street_grid(5);
+        // Small tiles, so the grid spans four of them
+        let parse_options = ParseOptions {
+            tile_zoom: 14,
+            ..Default::default()
+        };
+        let tiles_dir = write_tiles_with("affinity", &nodes, &ways, &[], &parse_options);
+        let locations = grid_locations(&nodes);
+        let dijkstra = RouteOptions {
+            algorithm: Algorithm::Dijkstra,
+            ..options()
+        };
+        let expected = all_durations(
+            &mut TileGraph::open(&tiles_dir).unwrap(),
+            &locations,
+            &dijkstra,
+        );
+        let affinity_s = 30.0;
+        for algorithm in [Algorithm::AStar, Algorithm::Bidirectional] {
+            let options = RouteOptions {
+                algorithm,
+                tile_affinity_s: affinity_s,
+                ..options()
+            };
+            let mut graph = TileGraph::open(&tiles_dir).unwrap();
+            let durations = all_durations(&mut graph, &locations, &options);
+            for (duration, expected) in durations.iter().zip(&expected) {
+                assert!(*duration >= expected - 1e-6);
+                assert!(
+                    *duration <= expected + affinity_s,
+                    "{duration} > {expected} + {affinity_s}"
+                );
+            }
+        }
+        remove_tiles(&tiles_dir);
+    }
+
+    #[test]
+    fn one_to_many_and_reachable_match_routes() {
+        let (nodes, ways) = street_grid(4);
+        let tiles_dir = write_tiles("sweep", &nodes, &ways);
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let cancel = CancellationToken::default();
+        let locations = grid_locations(&nodes);
+        let dijkstra = RouteOptions {
+            algorithm: Algorithm::Dijkstra,
+            ..options()
+        };
+        let expected = locations
+            .iter()
+            .map(|to| {
+                duration_s(&route(&mut graph, &[locations[0], *to], &dijkstra, &cancel).unwrap())
+            })
+            .collect::<Vec<_>>();
+        let origin = graph.snap(locations[0], &options()).unwrap();
+        let destinations = locations
+            .iter()
+            .map(|location| graph.snap(*location, &options()).unwrap())
+            .collect::<Vec<_>>();
+        let legs = one_to_many(
+            &mut graph,
+            &origin,
+            &destinations,
+            f64::INFINITY,
+            &options(),
+            &cancel,
+        )
+        .unwrap();
+        let max_s = expected.iter().copied().fold(0.0, f64::max) / 2.0;
+        let within = one_to_many(
+            &mut graph,
+            &origin,
+            &destinations,
+            max_s,
+            &options(),
+            &cancel,
+        )
+        .unwrap();
+        let reached = reachable(&mut graph, &origin, max_s, &options(), &cancel).unwrap();
+        remove_tiles(&tiles_dir);
+
+        for ((leg, within), expected) in legs.iter().zip(&within).zip(&expected) {
+            assert!((leg.as_ref().unwrap().duration_s - expected).abs() < 1e-6);
+            assert_eq!(within.is_some(), *expected <= max_s);
+        }
+        // The roads leaving every intersection within the budget are reached from it
+        for expected in expected
+            .iter()
+            .take(nodes.len())
+            .filter(|expected| **expected < max_s)
+        {
+            assert!(
+                reached
+                    .iter()
+                    .any(|reached| (reached.start_s - expected).abs() < 1e-6)
+            );
+        }
+        for reached in &reached {
+            assert!(reached.start_s <= reached.end_s && reached.end_s <= max_s + 1e-6);
+        }
+    }
+
+    #[test]
+    fn the_hierarchy_gives_the_same_routes() {
+        let (nodes, ways) = street_grid(5);
+        let tiles_dir = write_tiles("hierarchy", &nodes, &ways);
+        let locations = grid_locations(&nodes);
+        let cancel = CancellationToken::default();
+        // The hierarchy is only searched on days from the one it was built on
+        let options = RouteOptions {
+            date: utils::today(),
+            ..options()
+        };
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let expected = all_durations(&mut graph, &locations, &options);
+        assert!(graph.built_on.is_none());
+
+        let stats = crate::hierarchy::build_hierarchy(&tiles_dir, &cancel).unwrap();
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let durations = all_durations(&mut graph, &locations, &options);
+        remove_tiles(&tiles_dir);
+        assert!(stats.num_shortcuts > 0);
+        assert!(graph.built_on.is_some());
+        assert_same_durations(&durations, &expected);
+    }
+
+    /// A street from west to east crossing another going north at node 2, and
+    /// a back road from the east end of the first to the north end of the other
+    fn junction() -> (Vec<TestNode>, Vec<TestWay>) {
+        let nodes = vec![
+            (1, 59.33, 18.05),
+            (2, 59.33, 18.06),
+            (3, 59.33, 18.07),
+            (4, 59.335, 18.06),
+            (6, 59.335, 18.07),
+        ];
+        let street = |name| vec![("highway", "residential"), ("name", name)];
+        let ways = vec![
+            (20, vec![1, 2, 3], street("Main street")),
+            (21, vec![2, 4], street("North street")),
+            (22, vec![3, 6, 4], street("Back road")),
+        ];
+        (nodes, ways)
+    }
+
+    #[test]
+    fn turns_cost_and_restrictions_are_obeyed() {
+        let (nodes, ways) = junction();
+        let no_left_turn = (
+            30,
+            vec![("way", 20, "from"), ("node", 2, "via"), ("way", 21, "to")],
+            vec![("type", "restriction"), ("restriction", "no_left_turn")],
+        );
+        let plain_dir = write_tiles("turns", &nodes, &ways);
+        let restricted_dir = write_tiles_with(
+            "restricted",
+            &nodes,
+            &ways,
+            &[no_left_turn],
+            &ParseOptions::default(),
+        );
+        let cancel = CancellationToken::default();
+        let west = Coordinate {
+            lat: 59.33,
+            lon: 18.055,
+        };
+        let north = Coordinate {
+            lat: 59.3325,
+            lon: 18.06,
+        };
+        let with_turns = RouteOptions {
+            turn_costs: Some(TurnCosts {
+                left_s: 8.0,
+                right_s: 3.0,
+                u_turn_s: 30.0,
+            }),
+            ..options()
+        };
+        let mut plain = TileGraph::open(&plain_dir).unwrap();
+        let mut restricted = TileGraph::open(&restricted_dir).unwrap();
+        let free = route(&mut plain, &[west, north], &options(), &cancel).unwrap();
+        let turning = route(&mut plain, &[west, north], &with_turns, &cancel).unwrap();
+        let ignored = route(&mut restricted, &[west, north], &options(), &cancel).unwrap();
+        let obeyed = route(&mut restricted, &[west, north], &with_turns, &cancel).unwrap();
+        remove_tiles(&plain_dir);
+        remove_tiles(&restricted_dir);
+
+        // Turning left by about 90 degrees at node 2
+        assert_eq!(
+            street_names(&plain, &turning),
+            ["Main street", "North street"]
+        );
+        let turn_s = duration_s(&turning) - duration_s(&free);
+        assert!((7.0..9.0).contains(&turn_s), "{turn_s}");
+        // Restrictions are only known to searches keeping track of the edges
+        assert_eq!(
+            street_names(&restricted, &ignored),
+            ["Main street", "North street"]
+        );
+        assert_eq!(
+            street_names(&restricted, &obeyed),
+            ["Main street", "Main street", "Back road", "North street"]
+        );
+        assert!(duration_s(&obeyed) > duration_s(&turning));
+    }
+
+    #[test]
+    fn suggests_alternatives_not_much_slower() {
+        let nodes = vec![
+            (1, 59.33, 18.06),
+            (2, 59.33, 18.08),
+            (3, 59.332, 18.07),
+            (4, 59.328, 18.065),
+            (5, 59.345, 18.07),
+            (6, 59.328, 18.075),
+            (7, 59.326, 18.065),
+            (8, 59.326, 18.075),
+        ];
+        let road = |name, maxspeed| {
+            vec![
+                ("highway", "primary"),
+                ("maxspeed", maxspeed),
+                ("name", name),
+            ]
+        };
+        let ways = vec![
+            (40, vec![1, 3, 2], road("North road", "50")),
+            (41, vec![1, 4, 6, 2], road("South road", "45")),
+            (42, vec![1, 5, 2], road("Long way", "50")),
+            // Side streets make junctions of the south road, between which it's a plateau
+            (43, vec![4, 7], vec![("highway", "residential")]),
+            (44, vec![6, 8], vec![("highway", "residential")]),
+        ];
+        let tiles_dir = write_tiles("alternatives", &nodes, &ways);
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let cancel = CancellationToken::default();
+        let west = Coordinate {
+            lat: 59.33,
+            lon: 18.06,
+        };
+        let east = Coordinate {
+            lat: 59.33,
+            lon: 18.08,
+        };
+        let with_alternatives = RouteOptions {
+            alternatives: Some(Alternatives {
+                max_alternatives: 2,
+                max_overlap: 0.5,
+            }),
+            ..options()
+        };
+        let fastest = route(&mut graph, &[west, east], &options(), &cancel).unwrap();
+        let route = route(&mut graph, &[west, east], &with_alternatives, &cancel).unwrap();
+        remove_tiles(&tiles_dir);
+
+        assert!(fastest.alternatives.is_empty());
+        assert_eq!(fastest.legs[0].summary, ["North road"]);
+        assert_eq!(route.legs[0].summary, ["North road"]);
+        assert!((duration_s(&route) - duration_s(&fastest)).abs() < 1e-6);
+        // The long way takes far more than a quarter longer
+        assert_eq!(route.alternatives.len(), 1);
+        let alternative = &route.alternatives[0];
+        assert_eq!(alternative.summary, ["South road"]);
+        assert!(alternative.duration_s > duration_s(&fastest));
+        assert!(alternative.duration_s < duration_s(&fastest) * (1.0 + MAX_STRETCH));
+    }
+
+    #[test]
+    fn passes_through_via_points() {
+        let (nodes, ways) = two_roads();
+        let tiles_dir = write_tiles("via", &nodes, &ways);
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let cancel = CancellationToken::default();
+        let west = Coordinate {
+            lat: 59.33,
+            lon: 18.06,
+        };
+        let east = Coordinate {
+            lat: 59.33,
+            lon: 18.07,
+        };
+        // Half way along the slow street
+        let via = Coordinate {
+            lat: 59.33,
+            lon: 18.065,
+        };
+        let direct = route(&mut graph, &[west, east], &options(), &cancel).unwrap();
+        let through = route(&mut graph, &[west, via, east], &options(), &cancel).unwrap();
+        remove_tiles(&tiles_dir);
+
+        assert_eq!(direct.legs.len(), 1);
+        assert_eq!(through.legs.len(), 2);
+        assert_eq!(through.waypoints.len(), 3);
+        for leg in &through.legs {
+            assert_eq!(leg.summary, ["Slow street"]);
+        }
+        let (first, second) = (&through.legs[0], &through.legs[1]);
+        assert!((first.duration_s - second.duration_s).abs() < 1.0);
+        assert!(duration_s(&through) > duration_s(&direct));
+    }
+
+    #[test]
+    fn routes_around_avoided_areas() {
+        let (nodes, ways) = two_roads();
+        let tiles_dir = write_tiles("avoid", &nodes, &ways);
+        // A closure around the bend of the fast road at node 3
+        let fname = tiles_dir.with_file_name("closure.geojson");
+        std::fs::write(
+            &fname,
+            r#"{"type": "Feature", "properties": {}, "geometry": {"type": "Polygon",
+                "coordinates": [[[18.063, 59.331], [18.067, 59.331], [18.067, 59.333],
+                [18.063, 59.333], [18.063, 59.331]]]}}"#,
+        )
+        .unwrap();
+        let areas = Region::from_geojson_file(&fname).unwrap();
+        let cancel = CancellationToken::default();
+        let west = Coordinate {
+            lat: 59.33,
+            lon: 18.06,
+        };
+        let east = Coordinate {
+            lat: 59.33,
+            lon: 18.07,
+        };
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let open = route(&mut graph, &[west, east], &options(), &cancel).unwrap();
+        let mut avoiding = TileGraph::open(&tiles_dir).unwrap();
+        avoiding.avoid_areas(areas);
+        let around = route(&mut avoiding, &[west, east], &options(), &cancel).unwrap();
+        remove_tiles(&tiles_dir);
+
+        assert_eq!(street_names(&graph, &open), ["Fast road"]);
+        assert_eq!(street_names(&avoiding, &around), ["Slow street"]);
+    }
+
+    #[test]
+    fn keeps_off_excluded_roads() {
+        let (nodes, mut ways) = two_roads();
+        for (id, _refs, tags) in &mut ways {
+            if *id == 11 {
+                tags.push(("toll", "yes"));
+            }
+        }
+        let tiles_dir = write_tiles("exclude", &nodes, &ways);
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let cancel = CancellationToken::default();
+        let west = Coordinate {
+            lat: 59.33,
+            lon: 18.06,
+        };
+        let east = Coordinate {
+            lat: 59.33,
+            lon: 18.07,
+        };
+        let without = |excludes: &[Exclude]| RouteOptions {
+            exclude: ExcludeMask::new(excludes),
+            ..options()
+        };
+        let tolled = route(&mut graph, &[west, east], &options(), &cancel).unwrap();
+        let ferries = route(
+            &mut graph,
+            &[west, east],
+            &without(&[Exclude::Ferry]),
+            &cancel,
+        );
+        let free = route(
+            &mut graph,
+            &[west, east],
+            &without(&[Exclude::Toll]),
+            &cancel,
+        );
+        remove_tiles(&tiles_dir);
+
+        assert_eq!(street_names(&graph, &tolled), ["Fast road"]);
+        assert_eq!(street_names(&graph, &ferries.unwrap()), ["Fast road"]);
+        assert_eq!(street_names(&graph, &free.unwrap()), ["Slow street"]);
+    }
+
+    #[test]
+    fn routes_by_current_speeds() {
+        let (nodes, ways) = two_roads();
+        let tiles_dir = write_tiles("traffic", &nodes, &ways);
+        let fname = tiles_dir.with_file_name("speeds.tsv");
+        // A jam on the fast road, which runs from node 1 to node 2
+        let write_speed = |speed_kmh: u32, modified_s: u64| {
+            std::fs::write(&fname, format!("edge_id\tspeed_kmh\n11:1:2\t{speed_kmh}\n")).unwrap();
+            let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified_s);
+            let file = std::fs::File::options().write(true).open(&fname).unwrap();
+            file.set_modified(modified).unwrap();
+        };
+        write_speed(5, 1_000_000);
+        let cancel = CancellationToken::default();
+        let west = Coordinate {
+            lat: 59.33,
+            lon: 18.06,
+        };
+        let east = Coordinate {
+            lat: 59.33,
+            lon: 18.07,
+        };
+        let mut graph = TileGraph::open(&tiles_dir).unwrap();
+        let free_flow = route(&mut graph, &[west, east], &options(), &cancel).unwrap();
+        graph.use_traffic(Traffic::read(&fname).unwrap());
+        let jammed = route(&mut graph, &[west, east], &options(), &cancel).unwrap();
+        // Queries read the speeds again once the file changes
+        write_speed(60, 2_000_000);
+        let cleared = route(&mut graph, &[west, east], &options(), &cancel).unwrap();
+        remove_tiles(&tiles_dir);
+
+        assert_eq!(graph.num_traffic_speeds(), 1);
+        assert_eq!(street_names(&graph, &free_flow), ["Fast road"]);
+        assert_eq!(street_names(&graph, &jammed), ["Slow street"]);
+        assert_eq!(street_names(&graph, &cleared), ["Fast road"]);
+        assert!(duration_s(&cleared) > duration_s(&free_flow));
+    }
+}
@@ -0,0 +1,137 @@
+/// Entries of every node of an R-tree
+const NODE_CAPACITY: usize = 16;
+
+/// A box of coordinates, from its south-west corner to its north-east one,
+/// both as `(lat, lon)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct BoundingBox {
+    pub(crate) min: (f64, f64),
+    pub(crate) max: (f64, f64),
+}
+impl BoundingBox {
+    /// The smallest box holding coordinates given as `(lat, lon)`, `None` when
+    /// there are none
+    pub(crate) fn of(coords: &[(f64, f64)]) -> Option<Self> {
+        let (first, rest) = coords.split_first()?;
+        Some(rest.iter().fold(
+            Self {
+                min: *first,
+                max: *first,
+            },
+            |bbox, (lat, lon)| Self {
+                min: (bbox.min.0.min(*lat), bbox.min.1.min(*lon)),
+                max: (bbox.max.0.max(*lat), bbox.max.1.max(*lon)),
+            },
+        ))
+    }
+
+    /// The box reaching at least `radius_m` from a point in every direction
+    pub(crate) fn around((lat, lon): (f64, f64), radius_m: f64) -> Self {
+        // Meters along a degree of latitude, and of longitude at the equator
+        const METERS_PER_DEGREE: f64 = 111_195.0;
+        let lat_radius = radius_m / METERS_PER_DEGREE;
+        // The longitude of the pole side of the box, where degrees are shortest
+        let lon_scale = (lat.abs() + lat_radius).min(89.0).to_radians().cos();
+        let lon_radius = (radius_m / (METERS_PER_DEGREE * lon_scale)).min(180.0);
+        Self {
+            min: (lat - lat_radius, lon - lon_radius),
+            max: (lat + lat_radius, lon + lon_radius),
+        }
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        Self {
+            min: (self.min.0.min(other.min.0), self.min.1.min(other.min.1)),
+            max: (self.max.0.max(other.max.0), self.max.1.max(other.max.1)),
+        }
+    }
+
+    pub(crate) fn intersects(&self, other: &Self) -> bool {
+        self.min.0 <= other.max.0
+            && other.min.0 <= self.max.0
+            && self.min.1 <= other.max.1
+            && other.min.1 <= self.max.1
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (
+            (self.min.0 + self.max.0) / 2.0,
+            (self.min.1 + self.max.1) / 2.0,
+        )
+    }
+}
+
+/// A static R-tree over the boxes of numbered items, packed by
+/// sort-tile-recursive so every node is full but the last of its level
+///
+/// The nodes aren't linked, every level is a list of boxes where node `i`
+/// covers the entries `i * NODE_CAPACITY..(i + 1) * NODE_CAPACITY` of the
+/// level below it, the lowest level covering the items.
+pub(crate) struct RTree {
+    /// The items in the order of the leaves, each with its box
+    items: Vec<(BoundingBox, u32)>,
+    /// The boxes of the nodes of every level, from the leaves up to the root
+    levels: Vec<Vec<BoundingBox>>,
+}
+impl RTree {
+    /// Packs the items into a tree, the ones close to each other into the same leaves
+    ///
+    /// The items are sorted by longitude into vertical slices, each holding as
+    /// many leaves as there are slices, and every slice by latitude into leaves.
+    pub(crate) fn new(mut items: Vec<(BoundingBox, u32)>) -> Self {
+        let num_leaves = items.len().div_ceil(NODE_CAPACITY);
+        let num_slices = (num_leaves as f64).sqrt().ceil() as usize;
+        let slice_len = num_leaves.div_ceil(num_slices.max(1)) * NODE_CAPACITY;
+        items.sort_by(|(a, _), (b, _)| a.center().1.total_cmp(&b.center().1));
+        for slice in items.chunks_mut(slice_len.max(1)) {
+            slice.sort_by(|(a, _), (b, _)| a.center().0.total_cmp(&b.center().0));
+        }
+
+        let mut levels = Vec::new();
+        let mut boxes = items.iter().map(|(bbox, _item)| *bbox).collect::<Vec<_>>();
+        while boxes.len() > 1 || (levels.is_empty() && !boxes.is_empty()) {
+            boxes = boxes
+                .chunks(NODE_CAPACITY)
+                .map(|chunk| {
+                    chunk[1..]
+                        .iter()
+                        .fold(chunk[0], |bbox, other| bbox.union(other))
+                })
+                .collect();
+            levels.push(boxes.clone());
+        }
+        Self { items, levels }
+    }
+
+    /// The items whose boxes intersect a box, in ascending order
+    pub(crate) fn search(&self, query: &BoundingBox) -> Vec<u32> {
+        let mut found = Vec::new();
+        let Some(root) = self.levels.last() else {
+            return found;
+        };
+        let mut pending = (0..root.len())
+            .map(|node| (self.levels.len() - 1, node))
+            .collect::<Vec<_>>();
+        while let Some((level, node)) = pending.pop() {
+            if !self.levels[level][node].intersects(query) {
+                continue;
+            }
+            let entries = node * NODE_CAPACITY..((node + 1) * NODE_CAPACITY);
+            if level == 0 {
+                found.extend(
+                    self.items[entries.start..entries.end.min(self.items.len())]
+                        .iter()
+                        .filter(|(bbox, _item)| bbox.intersects(query))
+                        .map(|(_bbox, item)| *item),
+                );
+            } else {
+                let below = self.levels[level - 1].len();
+                pending.extend(
+                    (entries.start..entries.end.min(below)).map(|child| (level - 1, child)),
+                );
+            }
+        }
+        found.sort_unstable();
+        found
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    RoadClass,
+    cancel::CancellationToken,
+    error::{GladsheimError, Result},
+    geometry,
+    osm_parser::Node,
+    utils::{self, TileSelector, TileStore},
+};
+
+/// How much more often a class of road is sampled per meter, roughly following traffic volumes
+fn class_weight(class: RoadClass) -> f64 {
+    match class {
+        RoadClass::Motorway | RoadClass::Trunk => 4.0,
+        RoadClass::Primary => 3.0,
+        RoadClass::Secondary | RoadClass::Tertiary => 2.0,
+        RoadClass::Unclassified | RoadClass::Residential => 1.0,
+        RoadClass::Service | RoadClass::LivingStreet | RoadClass::Footway | RoadClass::Other => 0.5,
+        RoadClass::Track | RoadClass::Busway => 0.2,
+    }
+}
+
+/// The SplitMix64 generator, so a seed gives the same samples on every platform and version
+pub(crate) struct SplitMix64(pub(crate) u64);
+impl SplitMix64 {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in `[0, 1)`
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Picks random coordinates on the edges of the selected tiles as `(lat, lon)`
+///
+/// Edges are picked by their length weighted by road class, so samples spread
+/// over the network like real queries rather than clustering in dense areas.
+pub(crate) fn sample_coordinates(
+    tiles_dir: &Path,
+    selector: &TileSelector,
+    count: usize,
+    seed: u64,
+    cancel: &CancellationToken,
+) -> Result<Vec<(f64, f64)>> {
+    let tiles = TileStore::open(tiles_dir)?.select(selector);
+    // Line geometry of every edge and the running total of weights up to it
+    let mut lines = Vec::new();
+    let mut cumulative_weights = Vec::new();
+    let mut total_weight = 0.0;
+    for (index, tile) in tiles.iter().enumerate() {
+        cancel.check(|| format!("reading {} of {} tiles", index, tiles.len()))?;
+        let (_quadkey, tile) = tile?;
+        let node_table = tile
+            .node_locations
+            .into_iter()
+            .map(|(node_id, loc)| {
+                (
+                    node_id,
+                    Node {
+                        loc,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        for edge in &tile.edges {
+            let coords = geometry::edge_coordinates(edge, &node_table);
+            let weight = geometry::length(&coords) * class_weight(edge.class);
+            if weight > 0.0 {
+                total_weight += weight;
+                cumulative_weights.push(total_weight);
+                lines.push(coords);
+            }
+        }
+    }
+    if lines.is_empty() {
+        return Err(GladsheimError::parse(format!(
+            "No edges to sample in {}",
+            tiles_dir.display()
+        )));
+    }
+
+    let mut rng = SplitMix64(seed);
+    let samples = (0..count)
+        .map(|_| {
+            let target = rng.next_f64() * total_weight;
+            let index = cumulative_weights
+                .partition_point(|weight| *weight <= target)
+                .min(lines.len() - 1);
+            let line = &lines[index];
+            let distance = rng.next_f64() * geometry::length(line);
+            point_along(line, distance)
+        })
+        .collect();
+    Ok(samples)
+}
+
+/// The coordinate at a distance in meters along a line
+fn point_along(coords: &[(f64, f64)], mut distance: f64) -> (f64, f64) {
+    for pair in coords.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let length = utils::haversine_distance(from.0, from.1, to.0, to.1);
+        if distance <= length && length > 0.0 {
+            let fraction = distance / length;
+            return (
+                from.0 + (to.0 - from.0) * fraction,
+                from.1 + (to.1 - from.1) * fraction,
+            );
+        }
+        distance -= length;
+    }
+    // Only reached through rounding at the very end of the line
+    coords.last().copied().unwrap_or_default()
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use crate::{Edge, NodeId, geometry, osm_parser::Node, utils::Tile};
+
+/// A logical street, a chain of consecutive edges sharing the same name and ref
+///
+/// Streets are grouped per tile, so a street crossing a tile boundary appears
+/// once in each tile.
+#[derive(Clone, Debug, Default, PartialEq, bincode::Encode, bincode::Decode)]
+pub(crate) struct Street {
+    pub(crate) name: Option<String>,
+    pub(crate) road_ref: Option<String>,
+    /// Indices into the edges of the tile, in driving order along the street
+    pub(crate) edges: Vec<usize>,
+    /// The merged geometry of all edges as `(lat, lon)`
+    pub(crate) geometry: Vec<(f64, f64)>,
+    /// Length of the street in meters
+    pub(crate) length: f64,
+}
+
+/// Groups the named edges of a tile into streets
+///
+/// A street continues through nodes where exactly two of its edges meet, and is
+/// broken at forks and at nodes where other edges with the same name join.
+pub(crate) fn group_streets(tile: &Tile, node_table: &HashMap<NodeId, Node>) -> Vec<Street> {
+    let mut groups: HashMap<(&Option<String>, &Option<String>), Vec<usize>> = HashMap::new();
+    for (index, edge) in tile.edges.iter().enumerate() {
+        // Reverse edges would make every node of a two-way street a fork
+        if !edge.is_reversed && (edge.name.is_some() || edge.road_ref.is_some()) {
+            groups
+                .entry((&edge.name, &edge.road_ref))
+                .or_default()
+                .push(index);
+        }
+    }
+
+    let mut streets = Vec::new();
+    for ((name, road_ref), indices) in groups {
+        let mut node_edges: HashMap<NodeId, Vec<usize>> = HashMap::new();
+        for index in &indices {
+            let edge = &tile.edges[*index];
+            node_edges.entry(edge.from).or_default().push(*index);
+            node_edges.entry(edge.to).or_default().push(*index);
+        }
+        let continues_through = |node_id: &NodeId| node_edges[node_id].len() == 2;
+
+        let mut visited = vec![false; tile.edges.len()];
+        // Start at street ends first, whatever is left after that are closed loops
+        let ends = indices.iter().flat_map(|index| {
+            let edge = &tile.edges[*index];
+            [(*index, edge.from), (*index, edge.to)]
+                .into_iter()
+                .filter(|(_index, node_id)| !continues_through(node_id))
+        });
+        let loops = indices
+            .iter()
+            .map(|index| (*index, tile.edges[*index].from));
+        for (start, start_node) in ends.chain(loops) {
+            if visited[start] {
+                continue;
+            }
+            let mut street = Street {
+                name: name.clone(),
+                road_ref: road_ref.clone(),
+                ..Default::default()
+            };
+            let mut index = start;
+            let mut node_id = start_node;
+            loop {
+                visited[index] = true;
+                let edge = &tile.edges[index];
+                let is_forward = edge.from == node_id;
+                append_geometry(&mut street.geometry, edge, is_forward, node_table);
+                street.edges.push(index);
+                node_id = if is_forward { edge.to } else { edge.from };
+                if !continues_through(&node_id) {
+                    break;
+                }
+                match node_edges[&node_id].iter().find(|next| !visited[**next]) {
+                    Some(next) => index = *next,
+                    None => break,
+                }
+            }
+            street.length = geometry::length(&street.geometry);
+            streets.push(street);
+        }
+    }
+    streets.sort_by(|a, b| a.edges.cmp(&b.edges));
+    streets
+}
+
+/// Appends the coordinates of an edge in travel direction, skipping the shared node
+fn append_geometry(
+    geometry: &mut Vec<(f64, f64)>,
+    edge: &Edge,
+    is_forward: bool,
+    node_table: &HashMap<NodeId, Node>,
+) {
+    let mut coords = geometry::edge_coordinates(edge, node_table);
+    if !is_forward {
+        coords.reverse();
+    }
+    let skip = usize::from(!geometry.is_empty());
+    geometry.extend(coords.into_iter().skip(skip));
+}
@@ -0,0 +1,117 @@
+use std::fmt::Write;
+
+use crate::{
+    cancel::CancellationToken,
+    error::{GladsheimError, Result},
+    routing::{self, Coordinate, RouteOptions, Snap, TileGraph},
+};
+
+/// The travel times and distances from every source to every destination
+pub(crate) struct Table {
+    pub(crate) sources: Vec<Snap>,
+    pub(crate) destinations: Vec<Snap>,
+    /// Seconds from every source to every destination, a row per source,
+    /// `None` when there's no route
+    pub(crate) durations_s: Vec<Vec<Option<f64>>>,
+    /// Meters along the fastest routes, like `durations_s`
+    pub(crate) distances_m: Vec<Vec<Option<f64>>>,
+}
+impl Table {
+    /// The table as JSON shaped like an OSRM table response, with `null` where
+    /// there's no route
+    pub(crate) fn to_json(&self) -> String {
+        let mut json = String::from(r#"{"code":"Ok","durations":"#);
+        write_matrix(&mut json, &self.durations_s);
+        json.push_str(r#","distances":"#);
+        write_matrix(&mut json, &self.distances_m);
+        for (key, snaps) in [
+            ("sources", &self.sources),
+            ("destinations", &self.destinations),
+        ] {
+            let _ = write!(json, r#","{}":["#, key);
+            for (index, snap) in snaps.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                json.push_str(&snap.to_json());
+            }
+            json.push(']');
+        }
+        json.push('}');
+        json
+    }
+}
+
+fn write_matrix(json: &mut String, rows: &[Vec<Option<f64>>]) {
+    json.push('[');
+    for (index, row) in rows.iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        json.push('[');
+        for (index, value) in row.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            match value {
+                Some(value) => {
+                    let _ = write!(json, "{:.1}", value);
+                }
+                None => json.push_str("null"),
+            }
+        }
+        json.push(']');
+    }
+    json.push(']');
+}
+
+/// Computes the fastest routes from every source to every destination, by a
+/// single search from each source that runs until it has reached all the
+/// destinations, see `routing::one_to_many`
+///
+/// Every location is snapped once, however many routes start or end at it,
+/// and current speeds are read again first when their file changed.
+pub(crate) fn table(
+    graph: &mut TileGraph,
+    sources: &[Coordinate],
+    destinations: &[Coordinate],
+    options: &RouteOptions,
+    cancel: &CancellationToken,
+) -> Result<Table> {
+    if sources.is_empty() || destinations.is_empty() {
+        return Err(GladsheimError::parse(
+            "A table needs at least a source and a destination",
+        ));
+    }
+    graph.reload_traffic()?;
+    let sources = sources
+        .iter()
+        .map(|source| graph.snap(*source, options))
+        .collect::<Result<Vec<_>>>()?;
+    let destinations = destinations
+        .iter()
+        .map(|destination| graph.snap(*destination, options))
+        .collect::<Result<Vec<_>>>()?;
+    let mut durations_s = Vec::with_capacity(sources.len());
+    let mut distances_m = Vec::with_capacity(sources.len());
+    for source in &sources {
+        let legs =
+            routing::one_to_many(graph, source, &destinations, f64::INFINITY, options, cancel)?;
+        durations_s.push(
+            legs.iter()
+                .map(|leg| leg.as_ref().map(|leg| leg.duration_s))
+                .collect(),
+        );
+        distances_m.push(
+            legs.iter()
+                .map(|leg| leg.as_ref().map(|leg| leg.distance_m))
+                .collect(),
+        );
+    }
+    Ok(Table {
+        sources,
+        destinations,
+        durations_s,
+        distances_m,
+    })
+}
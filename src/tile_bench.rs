@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    Edge, NodeId, RoadClass, WayId,
+    cancel::CancellationToken,
+    error::Result,
+    osm_parser::Loc,
+    profile::{Profile, ProfileMask},
+    tile_format::Compression,
+    utils::{self, Tile},
+};
+
+/// Degrees between neighbouring intersections of the grid, about 170m north to south
+const BLOCK_DEG: f64 = 0.0015;
+/// The south-west corner of the grid
+const ORIGIN: (f64, f64) = (59.33, 18.06);
+
+/// How large a tile is and how long it takes to encode and decode with a compression
+pub(crate) struct CodecStats {
+    pub(crate) compression: Compression,
+    pub(crate) num_bytes: usize,
+    /// Fastest of the rounds, the least disturbed by other work on the machine
+    pub(crate) encode: Duration,
+    pub(crate) decode: Duration,
+}
+
+/// A tile of `size` by `size` intersections joined by two-way residential streets,
+/// with `nodes_per_block` nodes along the street between neighbouring intersections
+///
+/// Every row and column is a way of its own, with the nodes between its
+/// intersections numbered one after the other as OSM numbers newly drawn ways.
+/// The same arguments give the same tile, so it's a fixture for comparing layouts.
+pub(crate) fn grid_tile(size: usize, nodes_per_block: usize) -> Tile {
+    let intersection = |row: usize, column: usize| NodeId((1_000_000 + row * size + column) as i64);
+    let location = |row: f64, column: f64| Loc {
+        lat: ORIGIN.0 + row * BLOCK_DEG,
+        lon: ORIGIN.1 + column * BLOCK_DEG,
+    };
+    let mut access = ProfileMask::default();
+    access.insert(Profile::Car);
+    let mut node_locations = Vec::new();
+    for row in 0..size {
+        for column in 0..size {
+            node_locations.push((
+                intersection(row, column),
+                location(row as f64, column as f64),
+            ));
+        }
+    }
+    let mut edges = Vec::new();
+    let mut next_node_id = 10_000_000;
+    for way in 0..2 * size {
+        // Rows first, then columns
+        let (line, is_row) = (way % size, way < size);
+        let at = |step: f64| {
+            if is_row {
+                location(line as f64, step)
+            } else {
+                location(step, line as f64)
+            }
+        };
+        for block in 0..size.saturating_sub(1) {
+            let (from, to) = if is_row {
+                (intersection(line, block), intersection(line, block + 1))
+            } else {
+                (intersection(block, line), intersection(block + 1, line))
+            };
+            let mut nodes = vec![from];
+            for step in 1..=nodes_per_block {
+                let node_id = NodeId(next_node_id);
+                next_node_id += 1;
+                let offset = step as f64 / (nodes_per_block + 1) as f64;
+                node_locations.push((node_id, at(block as f64 + offset)));
+                nodes.push(node_id);
+            }
+            nodes.push(to);
+            let (start, end) = (at(block as f64), at(block as f64 + 1.0));
+            let length_m = utils::haversine_distance(start.lat, start.lon, end.lat, end.lon) as f32;
+            let speed_kmh = Profile::Car.default_speed_kmh(RoadClass::Residential, false);
+            edges.push(Edge {
+                from,
+                to,
+                way_id: WayId(way as i64 + 1),
+                name: Some(format!("Street {}", way + 1)),
+                class: RoadClass::Residential,
+                access,
+                speed_kmh,
+                length_m,
+                duration_s: length_m / (f32::from(speed_kmh) / 3.6),
+                nodes,
+                ..Edge::default()
+            });
+        }
+    }
+    node_locations.sort_unstable_by_key(|(node_id, _loc)| node_id.0);
+    Tile {
+        zoom: 14,
+        edges,
+        node_locations,
+        ..Tile::default()
+    }
+}
+
+/// Encodes and decodes a tile `rounds` times with every compression
+///
+/// `cargo run --release -- bench-tile-codec` runs it over `grid_tile(120, 2)`,
+/// to compare the sizes and times of a change to `tile_format` with those before.
+pub(crate) fn bench_tile_codec(
+    tile: &mut Tile,
+    compressions: &[Compression],
+    rounds: usize,
+    cancel: &CancellationToken,
+) -> Result<Vec<CodecStats>> {
+    let mut all_stats = Vec::new();
+    for &compression in compressions {
+        tile.compression = compression;
+        let mut stats = CodecStats {
+            compression,
+            num_bytes: 0,
+            encode: Duration::MAX,
+            decode: Duration::MAX,
+        };
+        for round in 0..rounds.max(1) {
+            cancel.check(|| format!("round {} of {:?}", round + 1, compression))?;
+            let start_time = Instant::now();
+            let (_header, bytes) = utils::encode_tile(tile, "of the benchmark")?;
+            stats.encode = stats.encode.min(start_time.elapsed());
+            let start_time = Instant::now();
+            utils::decode_tile(&bytes[..], "of the benchmark")?;
+            stats.decode = stats.decode.min(start_time.elapsed());
+            stats.num_bytes = bytes.len();
+        }
+        all_stats.push(stats);
+    }
+    Ok(all_stats)
+}
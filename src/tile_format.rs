@@ -0,0 +1,727 @@
+use std::collections::HashMap;
+
+use bincode::{
+    Decode, Encode,
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+};
+
+use crate::{
+    Edge, NodeId, RoadClass,
+    adjacency::Adjacency,
+    boundary::BoundaryNode,
+    hierarchy::{Shortcut, TileHierarchy},
+    osm_parser::{LevelCrossing, Loc},
+    profile::Profile,
+    utils::{Quadkey, Tile},
+};
+
+/// Start of every tile file, telling tiles apart from other files
+const MAGIC: [u8; 4] = *b"GRT\x00";
+/// Incremented whenever the layout of tiles changes
+pub(crate) const FORMAT_VERSION: u16 = 13;
+/// Coordinates are stored as integer multiples of 1e-7 degrees, the precision of OSM itself
+const COORDINATE_SCALE: f64 = 1e7;
+
+/// How the tile data following the header is compressed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub(crate) enum Compression {
+    #[default]
+    None,
+    /// Zstandard at a level from 1 to 22, the level is kept for rewriting the tile
+    Zstd { level: i32 },
+}
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    /// Parses `none`, `zstd` or `zstd:<level>`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "none" => Ok(Compression::None),
+            None if value == "zstd" => Ok(Compression::Zstd {
+                level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            }),
+            Some(("zstd", level)) => match level.parse() {
+                Ok(level) if (1..=22).contains(&level) => Ok(Compression::Zstd { level }),
+                _ => Err(format!("Invalid zstd level {level}, expected 1 to 22")),
+            },
+            _ => Err(format!(
+                "Invalid compression {value}, expected none, zstd or zstd:<level>"
+            )),
+        }
+    }
+}
+
+/// Coordinates are stored as `i32` fixed-point rather than `f64`
+///
+/// That's the precision OSM data comes with, so nothing is lost, while a
+/// coordinate takes at most 10 bytes instead of 16. Rounding to a fixed grid
+/// also makes the bytes of a tile the same however its coordinates were computed.
+impl Encode for Loc {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        let fixed = |degrees: f64| (degrees * COORDINATE_SCALE).round() as i32;
+        fixed(self.lat).encode(encoder)?;
+        fixed(self.lon).encode(encoder)
+    }
+}
+impl<Context> Decode<Context> for Loc {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let lat = i32::decode(decoder)?;
+        let lon = i32::decode(decoder)?;
+        Ok(Loc {
+            lat: f64::from(lat) / COORDINATE_SCALE,
+            lon: f64::from(lon) / COORDINATE_SCALE,
+        })
+    }
+}
+bincode::impl_borrow_decode!(Loc);
+
+/// What a tile file starts with, so readers can reject other files and formats
+///
+/// Decoding fails unless the magic bytes and format version match this build,
+/// rather than misinterpreting the bytes that follow. The header itself is never
+/// compressed, so the metadata describing where the tile came from and what it
+/// holds can be read without decoding the rest.
+#[derive(Clone, Debug)]
+pub(crate) struct TileHeader {
+    pub(crate) profile: Profile,
+    pub(crate) extra_profiles: Vec<Profile>,
+    pub(crate) compression: Compression,
+    pub(crate) zoom: u8,
+    pub(crate) max_class: Option<RoadClass>,
+    /// When the OSM data the tile was built from is from, see `Tile::source_timestamp`
+    pub(crate) source_timestamp: Option<i64>,
+    /// Version of Gladsheim that wrote the tile
+    pub(crate) builder_version: String,
+    /// Bounding box of the nodes as `(min_lat, min_lon, max_lat, max_lon)`, `None`
+    /// for tiles without nodes
+    pub(crate) bounds: Option<(f64, f64, f64, f64)>,
+    pub(crate) num_nodes: u64,
+    pub(crate) num_edges: u64,
+    /// Number of bytes following the header, as stored
+    pub(crate) payload_len: u64,
+    /// CRC-32 of the bytes following the header, as stored
+    pub(crate) checksum: u32,
+}
+impl TileHeader {
+    /// The header of a tile, with the `payload` that follows it already encoded
+    pub(crate) fn of_tile(tile: &Tile, payload: &[u8]) -> Self {
+        Self {
+            profile: tile.profile,
+            extra_profiles: tile.extra_profiles.clone(),
+            compression: tile.compression,
+            zoom: tile.zoom,
+            max_class: tile.max_class,
+            source_timestamp: tile.source_timestamp,
+            builder_version: env!("CARGO_PKG_VERSION").to_string(),
+            bounds: tile
+                .node_locations
+                .iter()
+                .fold(None, |bounds, (_node_id, loc)| {
+                    let (min_lat, min_lon, max_lat, max_lon) =
+                        bounds.unwrap_or((loc.lat, loc.lon, loc.lat, loc.lon));
+                    Some((
+                        min_lat.min(loc.lat),
+                        min_lon.min(loc.lon),
+                        max_lat.max(loc.lat),
+                        max_lon.max(loc.lon),
+                    ))
+                }),
+            num_nodes: tile.node_locations.len() as u64,
+            num_edges: tile.edges.len() as u64,
+            payload_len: payload.len() as u64,
+            checksum: crc32fast::hash(payload),
+        }
+    }
+
+    /// Checks the bytes read after the header, so truncated files from interrupted
+    /// builds and damaged files fail with a clear error instead of decoding garbage
+    pub(crate) fn verify(&self, payload: &[u8]) -> Result<(), DecodeError> {
+        if payload.len() as u64 != self.payload_len {
+            return Err(DecodeError::OtherString(format!(
+                "tile is truncated, {} of {} bytes present",
+                payload.len(),
+                self.payload_len
+            )));
+        }
+        if crc32fast::hash(payload) != self.checksum {
+            return Err(DecodeError::Other("tile is corrupt, checksum mismatch"));
+        }
+        Ok(())
+    }
+}
+impl Encode for TileHeader {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        MAGIC.encode(encoder)?;
+        FORMAT_VERSION.encode(encoder)?;
+        self.profile.encode(encoder)?;
+        self.extra_profiles.encode(encoder)?;
+        self.compression.encode(encoder)?;
+        self.zoom.encode(encoder)?;
+        self.max_class.encode(encoder)?;
+        self.source_timestamp.encode(encoder)?;
+        self.builder_version.encode(encoder)?;
+        self.bounds.encode(encoder)?;
+        self.num_nodes.encode(encoder)?;
+        self.num_edges.encode(encoder)?;
+        self.payload_len.encode(encoder)?;
+        self.checksum.encode(encoder)
+    }
+}
+impl<Context> Decode<Context> for TileHeader {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let magic: [u8; 4] = Decode::decode(decoder)?;
+        if magic != MAGIC {
+            return Err(DecodeError::Other("not a Gladsheim tile"));
+        }
+        let version: u16 = Decode::decode(decoder)?;
+        if version != FORMAT_VERSION {
+            return Err(DecodeError::OtherString(format!(
+                "tile format version {version}, while this build reads version {FORMAT_VERSION}"
+            )));
+        }
+        Ok(Self {
+            profile: Decode::decode(decoder)?,
+            extra_profiles: Decode::decode(decoder)?,
+            compression: Decode::decode(decoder)?,
+            zoom: Decode::decode(decoder)?,
+            max_class: Decode::decode(decoder)?,
+            source_timestamp: Decode::decode(decoder)?,
+            builder_version: Decode::decode(decoder)?,
+            bounds: Decode::decode(decoder)?,
+            num_nodes: Decode::decode(decoder)?,
+            num_edges: Decode::decode(decoder)?,
+            payload_len: Decode::decode(decoder)?,
+            checksum: Decode::decode(decoder)?,
+        })
+    }
+}
+bincode::impl_borrow_decode!(TileHeader);
+
+/// Dense per-tile indices of the nodes a tile refers to
+///
+/// The nodes of `node_locations` come first in their sorted order, followed by
+/// any other referenced nodes, so the OSM ids form a mapping table that is mostly
+/// sorted and can be stored as small differences.
+struct NodeIndex {
+    node_ids: Vec<NodeId>,
+    indices: HashMap<NodeId, u32>,
+}
+impl NodeIndex {
+    fn of_tile(tile: &Tile) -> Self {
+        let mut node_ids = tile
+            .node_locations
+            .iter()
+            .map(|(node_id, _loc)| *node_id)
+            .collect::<Vec<_>>();
+        let mut indices = node_ids
+            .iter()
+            .enumerate()
+            .map(|(index, node_id)| (*node_id, index as u32))
+            .collect::<HashMap<_, _>>();
+        let mut unlocated = tile
+            .edges
+            .iter()
+            .flat_map(|edge| edge.nodes.iter().chain([&edge.from, &edge.to]))
+            .chain(&tile.crossings)
+            .chain(tile.level_crossings.iter().map(|(node_id, _)| node_id))
+            .chain(
+                tile.hierarchy
+                    .iter()
+                    .flat_map(|hierarchy| &hierarchy.shortcuts)
+                    .flat_map(|shortcut| [&shortcut.from, &shortcut.to, &shortcut.via]),
+            )
+            .chain(
+                tile.hierarchy
+                    .iter()
+                    .flat_map(|hierarchy| &hierarchy.boundary_nodes)
+                    .map(|node| &node.node_id),
+            )
+            .filter(|node_id| !indices.contains_key(node_id))
+            .copied()
+            .collect::<Vec<_>>();
+        unlocated.sort_by_key(|node_id| node_id.0);
+        unlocated.dedup();
+        for node_id in unlocated {
+            indices.insert(node_id, node_ids.len() as u32);
+            node_ids.push(node_id);
+        }
+        Self { node_ids, indices }
+    }
+
+    fn index(&self, node_id: NodeId) -> u32 {
+        self.indices[&node_id]
+    }
+
+    /// The edge with its nodes referred to by index, in place of the OSM ids
+    ///
+    /// The nodes along the edge are stored as the difference to the index of the
+    /// previous node, starting from `from`. Nodes of a way were mostly numbered
+    /// one after the other, so most differences fit a single byte however large
+    /// the tile. The name is left out, it's stored in the string table of the tile.
+    fn indexed_edge(&self, edge: &Edge) -> Edge {
+        let from = i64::from(self.index(edge.from));
+        let mut previous = from;
+        Edge {
+            name: None,
+            from: NodeId(from),
+            to: NodeId(self.index(edge.to).into()),
+            nodes: edge
+                .nodes
+                .iter()
+                .map(|node_id| {
+                    let index = i64::from(self.index(*node_id));
+                    let delta = index - previous;
+                    previous = index;
+                    NodeId(delta)
+                })
+                .collect(),
+            ..edge.clone()
+        }
+    }
+}
+
+/// The distinct names of the edges in order of first appearance, and the index of
+/// every edge's name among them
+fn name_table(edges: &[Edge]) -> (Vec<&str>, Vec<Option<u32>>) {
+    let mut names = Vec::new();
+    let mut indices: HashMap<&str, u32> = HashMap::new();
+    let name_indices = edges
+        .iter()
+        .map(|edge| {
+            let name = edge.name.as_deref()?;
+            Some(*indices.entry(name).or_insert_with(|| {
+                names.push(name);
+                names.len() as u32 - 1
+            }))
+        })
+        .collect();
+    (names, name_indices)
+}
+
+/// The distinct neighbouring tiles of the boundary nodes sorted by quadkey, and the
+/// indices of each node's tiles among them
+fn neighbour_table(boundary_nodes: &[BoundaryNode]) -> (Vec<&str>, Vec<Vec<u32>>) {
+    let mut neighbours = boundary_nodes
+        .iter()
+        .flat_map(|node| &node.tiles)
+        .map(|quadkey| quadkey.0.as_str())
+        .collect::<Vec<_>>();
+    neighbours.sort();
+    neighbours.dedup();
+    let indices = neighbours
+        .iter()
+        .enumerate()
+        .map(|(index, quadkey)| (*quadkey, index as u32))
+        .collect::<HashMap<_, _>>();
+    let tile_indices = boundary_nodes
+        .iter()
+        .map(|node| {
+            node.tiles
+                .iter()
+                .map(|quadkey| indices[quadkey.0.as_str()])
+                .collect()
+        })
+        .collect();
+    (neighbours, tile_indices)
+}
+
+/// Encodes boundary nodes as a table of their tiles and the indices into it
+fn encode_boundary_nodes<E: Encoder>(
+    boundary_nodes: &[BoundaryNode],
+    index: &NodeIndex,
+    encoder: &mut E,
+) -> Result<(), EncodeError> {
+    let (neighbours, tile_indices) = neighbour_table(boundary_nodes);
+    neighbours.encode(encoder)?;
+    let boundary_nodes = boundary_nodes
+        .iter()
+        .zip(tile_indices)
+        .map(|(node, tile_indices)| (index.index(node.node_id), tile_indices))
+        .collect::<Vec<_>>();
+    boundary_nodes.encode(encoder)
+}
+
+fn decode_boundary_nodes<Context, D: Decoder<Context = Context>>(
+    node_ids: &[NodeId],
+    decoder: &mut D,
+) -> Result<Vec<BoundaryNode>, DecodeError> {
+    let neighbours: Vec<String> = Decode::decode(decoder)?;
+    Vec::<(u32, Vec<u32>)>::decode(decoder)?
+        .into_iter()
+        .map(|(index, tile_indices)| {
+            let tiles = tile_indices
+                .iter()
+                .map(|index| {
+                    neighbours
+                        .get(*index as usize)
+                        .map(|quadkey| Quadkey(quadkey.clone()))
+                        .ok_or(DecodeError::Other("neighbour index outside the tile"))
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(BoundaryNode {
+                node_id: node_id(node_ids, index)?,
+                tiles,
+            })
+        })
+        .collect()
+}
+
+/// Looks up the OSM id of an index read from a tile
+fn node_id(node_ids: &[NodeId], index: impl TryInto<usize>) -> Result<NodeId, DecodeError> {
+    index
+        .try_into()
+        .ok()
+        .and_then(|index| node_ids.get(index))
+        .copied()
+        .ok_or(DecodeError::Other("node index outside the tile"))
+}
+
+/// Tiles are stored with nodes and names referred to by dense per-tile indices
+///
+/// This is the data after the `TileHeader`, which `utils::write_tile` puts in
+/// front of it. Repeating 64-bit OSM ids for every node of every edge
+/// dominated the tile size, so the ids are stored once, as the differences
+/// between them, and edges, crossings and level crossings refer to nodes by
+/// their index among them, the nodes along an edge by the differences between
+/// indices. Popular names repeat just as often, so every distinct name of the
+/// edges is stored once in a string table, as are the neighbouring tiles of
+/// the boundary nodes.
+///
+/// In order, a tile holds:
+/// - the node filter, first so `utils::read_node_filter` can stop after it
+/// - the node ids and the locations of the nodes
+/// - the edges, the name table and the index of the name of every edge
+/// - the crossings, level crossings, transit lines, streets and restrictions
+/// - whether the tile has reverse edges and whether it's contracted
+/// - the boundary nodes
+/// - the edges at every node, so routers don't have to group them themselves
+/// - the contraction hierarchy, with the boundary nodes of its shortcuts
+impl Encode for Tile {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        let index = NodeIndex::of_tile(self);
+        self.node_filter.encode(encoder)?;
+        let mut previous = 0;
+        let id_deltas = index
+            .node_ids
+            .iter()
+            .map(|node_id| {
+                let delta = node_id.0 - previous;
+                previous = node_id.0;
+                delta
+            })
+            .collect::<Vec<_>>();
+        id_deltas.encode(encoder)?;
+        let locations = self
+            .node_locations
+            .iter()
+            .map(|(_node_id, loc)| loc)
+            .collect::<Vec<_>>();
+        locations.encode(encoder)?;
+        let edges = self
+            .edges
+            .iter()
+            .map(|edge| index.indexed_edge(edge))
+            .collect::<Vec<_>>();
+        edges.encode(encoder)?;
+        let (names, name_indices) = name_table(&self.edges);
+        names.encode(encoder)?;
+        name_indices.encode(encoder)?;
+        let crossings = self
+            .crossings
+            .iter()
+            .map(|node_id| index.index(*node_id))
+            .collect::<Vec<_>>();
+        crossings.encode(encoder)?;
+        let level_crossings = self
+            .level_crossings
+            .iter()
+            .map(|(node_id, level_crossing)| (index.index(*node_id), level_crossing))
+            .collect::<Vec<_>>();
+        level_crossings.encode(encoder)?;
+        self.transit_lines.encode(encoder)?;
+        self.streets.encode(encoder)?;
+        self.restrictions.encode(encoder)?;
+        self.has_reverse_edges.encode(encoder)?;
+        self.is_contracted.encode(encoder)?;
+        encode_boundary_nodes(&self.boundary_nodes, &index, encoder)?;
+        Adjacency::of_edges(&self.node_locations, &self.edges).encode(encoder)?;
+        let hierarchy = self.hierarchy.as_ref().map(|hierarchy| {
+            let ranks = hierarchy
+                .ranks
+                .iter()
+                .map(|(node_id, rank)| (index.index(*node_id), *rank))
+                .collect::<Vec<_>>();
+            let shortcuts = hierarchy
+                .shortcuts
+                .iter()
+                .map(|shortcut| {
+                    (
+                        index.index(shortcut.from),
+                        index.index(shortcut.to),
+                        index.index(shortcut.via),
+                        shortcut.duration_s,
+                    )
+                })
+                .collect::<Vec<_>>();
+            (hierarchy.built_on, ranks, shortcuts)
+        });
+        hierarchy.encode(encoder)?;
+        if let Some(hierarchy) = &self.hierarchy {
+            encode_boundary_nodes(&hierarchy.boundary_nodes, &index, encoder)?;
+        }
+        Ok(())
+    }
+}
+
+impl<Context> Decode<Context> for Tile {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let node_filter = Decode::decode(decoder)?;
+        let id_deltas: Vec<i64> = Decode::decode(decoder)?;
+        let node_ids = id_deltas
+            .iter()
+            .scan(0, |previous, delta| {
+                *previous += delta;
+                Some(NodeId(*previous))
+            })
+            .collect::<Vec<_>>();
+        let locations: Vec<Loc> = Decode::decode(decoder)?;
+        if locations.len() > node_ids.len() {
+            return Err(DecodeError::Other("more node locations than nodes"));
+        }
+        let node_locations: Vec<_> = node_ids.iter().copied().zip(locations).collect();
+        let edges = Vec::<Edge>::decode(decoder)?
+            .into_iter()
+            .map(|edge| {
+                Ok(Edge {
+                    from: node_id(&node_ids, edge.from.0)?,
+                    to: node_id(&node_ids, edge.to.0)?,
+                    nodes: edge
+                        .nodes
+                        .iter()
+                        .scan(edge.from.0, |previous, delta| {
+                            *previous += delta.0;
+                            Some(node_id(&node_ids, *previous))
+                        })
+                        .collect::<Result<_, _>>()?,
+                    ..edge
+                })
+            })
+            .collect::<Result<Vec<_>, DecodeError>>()?;
+        let names: Vec<String> = Decode::decode(decoder)?;
+        let name_indices: Vec<Option<u32>> = Decode::decode(decoder)?;
+        if name_indices.len() != edges.len() {
+            return Err(DecodeError::Other("name indices not matching the edges"));
+        }
+        let edges: Vec<_> = edges
+            .into_iter()
+            .zip(name_indices)
+            .map(|(edge, name_index)| {
+                let name = name_index
+                    .map(|index| {
+                        names
+                            .get(index as usize)
+                            .cloned()
+                            .ok_or(DecodeError::Other("name index outside the tile"))
+                    })
+                    .transpose()?;
+                Ok(Edge { name, ..edge })
+            })
+            .collect::<Result<_, DecodeError>>()?;
+        let crossings = Vec::<u32>::decode(decoder)?
+            .into_iter()
+            .map(|index| node_id(&node_ids, index))
+            .collect::<Result<_, _>>()?;
+        let level_crossings = Vec::<(u32, LevelCrossing)>::decode(decoder)?
+            .into_iter()
+            .map(|(index, level_crossing)| Ok((node_id(&node_ids, index)?, level_crossing)))
+            .collect::<Result<_, DecodeError>>()?;
+        let transit_lines = Decode::decode(decoder)?;
+        let streets = Decode::decode(decoder)?;
+        let restrictions = Decode::decode(decoder)?;
+        let has_reverse_edges = Decode::decode(decoder)?;
+        let is_contracted = Decode::decode(decoder)?;
+        let boundary_nodes = decode_boundary_nodes(&node_ids, decoder)?;
+        let adjacency: Adjacency = Decode::decode(decoder)?;
+        if !adjacency.is_valid(node_locations.len(), edges.len()) {
+            return Err(DecodeError::Other(
+                "adjacency not matching the nodes and edges",
+            ));
+        }
+        type EncodedHierarchy = (u32, Vec<(u32, u32)>, Vec<(u32, u32, u32, f32)>);
+        let hierarchy = Option::<EncodedHierarchy>::decode(decoder)?
+            .map(|(built_on, ranks, shortcuts)| {
+                let ranks = ranks
+                    .into_iter()
+                    .map(|(index, rank)| Ok((node_id(&node_ids, index)?, rank)))
+                    .collect::<Result<_, DecodeError>>()?;
+                let shortcuts = shortcuts
+                    .into_iter()
+                    .map(|(from, to, via, duration_s)| {
+                        Ok(Shortcut {
+                            from: node_id(&node_ids, from)?,
+                            to: node_id(&node_ids, to)?,
+                            via: node_id(&node_ids, via)?,
+                            duration_s,
+                        })
+                    })
+                    .collect::<Result<_, DecodeError>>()?;
+                Ok(TileHierarchy {
+                    built_on,
+                    ranks,
+                    shortcuts,
+                    boundary_nodes: decode_boundary_nodes(&node_ids, decoder)?,
+                })
+            })
+            .transpose()?;
+        Ok(Tile {
+            node_filter,
+            edges,
+            crossings,
+            node_locations,
+            level_crossings,
+            transit_lines,
+            streets,
+            restrictions,
+            has_reverse_edges,
+            is_contracted,
+            boundary_nodes,
+            adjacency,
+            hierarchy,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    /// Three nodes in a row joined by two edges
+    fn small_tile() -> Tile {
+        let node_locations = [(1, 59.33, 18.06), (2, 59.331, 18.061), (3, 59.332, 18.062)]
+            .map(|(id, lat, lon)| (NodeId(id), Loc { lat, lon }))
+            .to_vec();
+        let edges = vec![
+            Edge {
+                from: NodeId(1),
+                to: NodeId(2),
+                nodes: vec![NodeId(1), NodeId(2)],
+                name: Some("Drottninggatan".to_string()),
+                ..Default::default()
+            },
+            Edge {
+                from: NodeId(2),
+                to: NodeId(3),
+                nodes: vec![NodeId(2), NodeId(3)],
+                ..Default::default()
+            },
+        ];
+        Tile {
+            adjacency: Adjacency::of_edges(&node_locations, &edges),
+            node_locations,
+            edges,
+            ..Default::default()
+        }
+    }
+
+    /// The message of the error that made decoding a tile fail
+    fn decode_failure(bytes: &[u8]) -> String {
+        let error = utils::decode_tile(bytes, "the test").unwrap_err();
+        let source = std::error::Error::source(&error).expect("a decoding error");
+        source.to_string()
+    }
+
+    #[test]
+    fn tiles_round_trip_with_every_compression() {
+        for compression in [Compression::None, Compression::Zstd { level: 3 }] {
+            let mut tile = small_tile();
+            tile.compression = compression;
+            // Runs back over the nodes, so the deltas between them are negative
+            tile.edges.push(Edge {
+                from: NodeId(3),
+                to: NodeId(1),
+                nodes: vec![NodeId(3), NodeId(2), NodeId(1)],
+                ..Default::default()
+            });
+            tile.adjacency = Adjacency::of_edges(&tile.node_locations, &tile.edges);
+            let (_header, bytes) = utils::encode_tile(&tile, "of the test").unwrap();
+
+            let decoded = utils::decode_tile(&bytes[..], "the test").unwrap();
+            assert_eq!(decoded.compression, compression);
+            assert_eq!(decoded.node_locations.len(), tile.node_locations.len());
+            for ((node_id, loc), (decoded_id, decoded_loc)) in
+                tile.node_locations.iter().zip(&decoded.node_locations)
+            {
+                assert_eq!(node_id, decoded_id);
+                assert!((loc.lat - decoded_loc.lat).abs() < 1e-7);
+                assert!((loc.lon - decoded_loc.lon).abs() < 1e-7);
+            }
+            assert_eq!(decoded.edges.len(), tile.edges.len());
+            for (edge, decoded_edge) in tile.edges.iter().zip(&decoded.edges) {
+                assert_eq!(edge.from, decoded_edge.from);
+                assert_eq!(edge.to, decoded_edge.to);
+                assert_eq!(edge.nodes, decoded_edge.nodes);
+                assert_eq!(edge.name, decoded_edge.name);
+            }
+
+            // The same tile always gives the same bytes
+            let (_header, again) = utils::encode_tile(&decoded, "of the test").unwrap();
+            assert_eq!(again, bytes);
+        }
+    }
+
+    #[test]
+    fn corrupt_and_truncated_tiles_are_rejected() {
+        let (_header, bytes) = utils::encode_tile(&small_tile(), "of the test").unwrap();
+        let decoded = utils::decode_tile(&bytes[..], "the test").unwrap();
+        assert_eq!(decoded.edges.len(), 2);
+
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0x01;
+        assert!(decode_failure(&corrupt).contains("checksum mismatch"));
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(decode_failure(truncated).contains("truncated"));
+
+        let mut not_a_tile = bytes.clone();
+        not_a_tile[0] = b'X';
+        assert!(decode_failure(&not_a_tile).contains("not a Gladsheim tile"));
+    }
+
+    fn encode_loc(loc: &Loc) -> Vec<u8> {
+        bincode::encode_to_vec(loc, bincode::config::standard()).unwrap()
+    }
+
+    fn decode_loc(bytes: &[u8]) -> Loc {
+        let (loc, _len) = bincode::decode_from_slice(bytes, bincode::config::standard()).unwrap();
+        loc
+    }
+
+    #[test]
+    fn locations_round_to_the_fixed_point_grid() {
+        let locations = [
+            (59.329_323_456_7, 18.068_580_812_3),
+            (-33.868_800_049_9, 151.209_299_950_1),
+            (90.0, 180.0),
+            (-90.0, -180.0),
+            (0.0, 0.0),
+        ];
+        for (lat, lon) in locations {
+            let decoded = decode_loc(&encode_loc(&Loc { lat, lon }));
+            assert!((decoded.lat - lat).abs() <= 0.5 / COORDINATE_SCALE, "{lat}");
+            assert!((decoded.lon - lon).abs() <= 0.5 / COORDINATE_SCALE, "{lon}");
+            // Decoded locations lie on the grid, so they encode to the same bytes again
+            assert_eq!(encode_loc(&decoded), encode_loc(&Loc { lat, lon }));
+        }
+        let decoded = decode_loc(&encode_loc(&Loc {
+            lat: 59.329_323_46,
+            lon: -0.000_000_04,
+        }));
+        assert_eq!(decoded.lat, 593_293_235.0 / COORDINATE_SCALE);
+        assert_eq!(decoded.lon, 0.0);
+    }
+}
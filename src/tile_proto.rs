@@ -0,0 +1,141 @@
+use std::path::{Path, PathBuf};
+
+use protobuf::CodedOutputStream;
+
+use crate::{
+    Edge,
+    boundary::BoundaryNode,
+    error::{GladsheimError, IoContext, Result},
+    osm_parser::Loc,
+    utils::{self, Quadkey, Tile, TileNaming},
+};
+
+/// Path of the protobuf encoding of the tile for a quadkey in a tile directory
+pub(crate) fn proto_tile_path(tiles_dir: &Path, quadkey: &Quadkey, naming: TileNaming) -> PathBuf {
+    utils::tile_path(tiles_dir, quadkey, naming).with_extension("pb")
+}
+
+/// Writes a tile as protobuf next to it, returning whether the file changed
+pub(crate) fn write_proto_tile(
+    tiles_dir: &Path,
+    quadkey: &Quadkey,
+    naming: TileNaming,
+    tile: &Tile,
+) -> Result<bool> {
+    let fname = proto_tile_path(tiles_dir, quadkey, naming);
+    let bytes = encode_tile(quadkey, tile).map_err(|source| GladsheimError::TileFormat {
+        context: format!("Failed encoding tile {}", fname.display()),
+        source: Box::new(source),
+    })?;
+    utils::write_bytes(&fname, &bytes)
+}
+
+/// Removes the protobuf encoding of a tile that no longer exists
+pub(crate) fn remove_proto_tile(
+    tiles_dir: &Path,
+    quadkey: &Quadkey,
+    naming: TileNaming,
+) -> Result<()> {
+    let fname = proto_tile_path(tiles_dir, quadkey, naming);
+    if fname.exists() {
+        std::fs::remove_file(&fname)
+            .io_context(|| format!("Failed removing file {}", fname.display()))?;
+    }
+    Ok(())
+}
+
+/// Encodes a tile as the `Tile` message of `proto/tile.proto`
+///
+/// Only the routing graph itself is included, the optional attributes of edges
+/// such as geometry samples and emissions stay in the `.grt` tiles.
+fn encode_tile(quadkey: &Quadkey, tile: &Tile) -> protobuf::Result<Vec<u8>> {
+    encode(|output| {
+        output.write_string(1, &quadkey.0)?;
+        output.write_uint32(2, tile.zoom.into())?;
+        output.write_enum(3, tile.profile as i32)?;
+        let extra_profiles = tile
+            .extra_profiles
+            .iter()
+            .map(|profile| *profile as i32)
+            .collect::<Vec<_>>();
+        output.write_repeated_packed_int32(4, &extra_profiles)?;
+        for (node_id, loc) in &tile.node_locations {
+            output.write_bytes(5, &encode_node(node_id.0, loc)?)?;
+        }
+        for edge in &tile.edges {
+            output.write_bytes(6, &encode_edge(edge)?)?;
+        }
+        for boundary_node in &tile.boundary_nodes {
+            output.write_bytes(7, &encode_boundary_node(boundary_node)?)?;
+        }
+        output.write_bool(8, tile.has_reverse_edges)?;
+        output.write_bool(9, tile.is_contracted)?;
+        if let Some(max_class) = tile.max_class {
+            output.write_enum(10, max_class as i32)?;
+        }
+        Ok(())
+    })
+}
+
+fn encode_node(node_id: i64, loc: &Loc) -> protobuf::Result<Vec<u8>> {
+    encode(|output| {
+        output.write_int64(1, node_id)?;
+        output.write_double(2, loc.lat)?;
+        output.write_double(3, loc.lon)
+    })
+}
+
+fn encode_edge(edge: &Edge) -> protobuf::Result<Vec<u8>> {
+    encode(|output| {
+        output.write_int64(1, edge.from.0)?;
+        output.write_int64(2, edge.to.0)?;
+        output.write_int64(3, edge.way_id.0)?;
+        if let Some(name) = &edge.name {
+            output.write_string(4, name)?;
+        }
+        if let Some(road_ref) = &edge.road_ref {
+            output.write_string(5, road_ref)?;
+        }
+        output.write_enum(6, edge.class as i32)?;
+        output.write_uint32(7, edge.access.bits().into())?;
+        output.write_uint32(8, edge.oneway.bits().into())?;
+        output.write_bool(9, edge.is_reversed)?;
+        output.write_uint32(10, edge.speed_kmh.into())?;
+        output.write_float(11, edge.length_m)?;
+        output.write_float(12, edge.duration_s)?;
+        let nodes = edge
+            .nodes
+            .iter()
+            .map(|node_id| node_id.0)
+            .collect::<Vec<_>>();
+        output.write_repeated_packed_int64(13, &nodes)?;
+        output.write_bool(14, edge.is_island)?;
+        if let Some(zone) = edge.destination_zone {
+            output.write_int64(15, zone.0)?;
+        }
+        Ok(())
+    })
+}
+
+fn encode_boundary_node(boundary_node: &BoundaryNode) -> protobuf::Result<Vec<u8>> {
+    encode(|output| {
+        output.write_int64(1, boundary_node.node_id.0)?;
+        for quadkey in &boundary_node.tiles {
+            output.write_string(2, &quadkey.0)?;
+        }
+        Ok(())
+    })
+}
+
+/// Encodes a message, for embedding it as a length delimited field of another
+pub(crate) fn encode(
+    fields: impl FnOnce(&mut CodedOutputStream) -> protobuf::Result<()>,
+) -> protobuf::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    {
+        let mut output = CodedOutputStream::vec(&mut bytes);
+        fields(&mut output)?;
+        output.flush()?;
+    }
+    Ok(bytes)
+}
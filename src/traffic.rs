@@ -0,0 +1,101 @@
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    Edge, NodeId, WayId,
+    error::{GladsheimError, IoContext, Result},
+};
+
+/// Current speeds of edges from a file, e.g. kept up to date from a live
+/// traffic feed, overriding the durations the tiles were built with
+pub(crate) struct Traffic {
+    fname: PathBuf,
+    /// When the file was last modified as it was read
+    modified: Option<SystemTime>,
+    /// Speed in km/h by the way and the nodes an edge runs from and to
+    speeds_kmh: HashMap<(WayId, NodeId, NodeId), f32>,
+}
+impl Traffic {
+    /// Reads the speeds from a tab separated file with an edge id and a speed in km/h per line
+    ///
+    /// Edge ids are `way:from:to`, as in the crosswalk from `AlignEdges`, so a
+    /// feed keyed by the segments of another network can be translated through
+    /// it. A speed applies to the edge in both directions it may be travelled,
+    /// tiles built with `--reverse-edges` have an edge per direction. Lines
+    /// starting with `#` and a header line starting with `edge_id` are skipped.
+    pub(crate) fn read(fname: &Path) -> Result<Self> {
+        Ok(Self {
+            fname: fname.to_path_buf(),
+            modified: modified(fname),
+            speeds_kmh: read_speeds(fname)?,
+        })
+    }
+
+    /// Reads the file again when it was modified since it was last read,
+    /// returning whether it was
+    pub(crate) fn reload(&mut self) -> Result<bool> {
+        let modified = modified(&self.fname);
+        if modified.is_some() && modified == self.modified {
+            return Ok(false);
+        }
+        self.speeds_kmh = read_speeds(&self.fname)?;
+        self.modified = modified;
+        Ok(true)
+    }
+
+    /// The current speed in km/h on an edge, `None` when it's not known
+    pub(crate) fn speed_kmh(&self, edge: &Edge) -> Option<f32> {
+        self.speeds_kmh
+            .get(&(edge.way_id, edge.from, edge.to))
+            .copied()
+    }
+
+    /// Number of edges with a current speed
+    pub(crate) fn len(&self) -> usize {
+        self.speeds_kmh.len()
+    }
+}
+
+fn modified(fname: &Path) -> Option<SystemTime> {
+    std::fs::metadata(fname)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn read_speeds(fname: &Path) -> Result<HashMap<(WayId, NodeId, NodeId), f32>> {
+    let file = std::fs::File::open(fname)
+        .io_context(|| format!("Failed opening file {}", fname.display()))?;
+    let mut speeds_kmh = HashMap::new();
+    for (line_index, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.io_context(|| format!("Failed reading file {}", fname.display()))?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("edge_id") {
+            continue;
+        }
+        let invalid = || {
+            GladsheimError::parse(format!(
+                "Invalid speed on line {} of {}, expected a way:from:to edge id and a positive speed in km/h separated by a tab",
+                line_index + 1,
+                fname.display()
+            ))
+        };
+        let (id, speed_kmh) = line.split_once('\t').ok_or_else(invalid)?;
+        let mut ids = id.trim().split(':').map(|id| id.parse::<i64>());
+        let (Some(Ok(way_id)), Some(Ok(from)), Some(Ok(to)), None) =
+            (ids.next(), ids.next(), ids.next(), ids.next())
+        else {
+            return Err(invalid());
+        };
+        let speed_kmh = speed_kmh
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .filter(|speed_kmh| speed_kmh.is_finite() && *speed_kmh > 0.0)
+            .ok_or_else(invalid)?;
+        speeds_kmh.insert((WayId(way_id), NodeId(from), NodeId(to)), speed_kmh);
+    }
+    Ok(speeds_kmh)
+}
@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+
+use crate::{
+    WayId,
+    osm_parser::{MemberType, SimpleRelation},
+    utils::Tile,
+};
+
+/// The public transport modes whose route relations are extracted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) enum TransitMode {
+    Bus,
+    Trolleybus,
+    Tram,
+}
+
+/// A public transport line, from a `type=route` relation
+///
+/// Stored per tile with only the member ways that have edges in that tile, the
+/// edges a line runs along are the ones whose `way_id` is in `ways`.
+#[derive(Clone, Debug, PartialEq, bincode::Encode, bincode::Decode)]
+pub(crate) struct TransitLine {
+    pub(crate) relation_id: i64,
+    pub(crate) mode: TransitMode,
+    /// The line number, e.g. `4` or `N91`
+    pub(crate) line_ref: Option<String>,
+    pub(crate) name: Option<String>,
+    pub(crate) ways: Vec<WayId>,
+}
+
+/// Extracts a transit line from a bus, trolleybus or tram route relation
+pub(crate) fn parse_route_relation<R: SimpleRelation>(relation: &R) -> Option<TransitLine> {
+    let mut is_route = false;
+    let mut mode = None;
+    let mut line_ref = None;
+    let mut name = None;
+    for (key, value) in relation.tags() {
+        match key {
+            "type" => is_route = value == "route",
+            "route" => {
+                mode = match value {
+                    "bus" => Some(TransitMode::Bus),
+                    "trolleybus" => Some(TransitMode::Trolleybus),
+                    "tram" => Some(TransitMode::Tram),
+                    _ => None,
+                }
+            }
+            "ref" => line_ref = Some(value.to_string()),
+            "name" => name = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    if !is_route {
+        return None;
+    }
+
+    // Stops and platforms are members too, only the ways making up the path are kept
+    let ways = relation
+        .members()
+        .filter(|(member_type, _id, role)| {
+            *member_type == MemberType::Way && matches!(*role, "" | "forward" | "backward")
+        })
+        .map(|(_member_type, id, _role)| WayId(id))
+        .collect::<Vec<_>>();
+    Some(TransitLine {
+        relation_id: relation.id(),
+        mode: mode?,
+        line_ref,
+        name,
+        ways,
+    })
+}
+
+/// The transit lines running along edges of a tile, restricted to the ways in the tile
+pub(crate) fn lines_in_tile(lines: &[TransitLine], tile: &Tile) -> Vec<TransitLine> {
+    let ways_in_tile = tile
+        .edges
+        .iter()
+        .map(|edge| edge.way_id)
+        .collect::<HashSet<_>>();
+    lines
+        .iter()
+        .filter_map(|line| {
+            let ways = line
+                .ways
+                .iter()
+                .filter(|way_id| ways_in_tile.contains(way_id))
+                .copied()
+                .collect::<Vec<_>>();
+            if ways.is_empty() {
+                None
+            } else {
+                Some(TransitLine {
+                    ways,
+                    ..line.clone()
+                })
+            }
+        })
+        .collect()
+}
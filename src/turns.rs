@@ -0,0 +1,178 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    NodeId, WayId,
+    error::Result,
+    geometry,
+    osm_parser::Node,
+    profile::Profile,
+    restrictions::{TurnRestriction, Via},
+    utils::{self, Quadkey, Tile, TileNaming},
+};
+
+/// An edge in one direction of travel, a node of the edge-based graph
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
+pub(crate) struct DirectedEdge {
+    pub(crate) way_id: WayId,
+    pub(crate) from: NodeId,
+    pub(crate) to: NodeId,
+}
+
+/// An allowed turn from one directed edge onto another at the node joining them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub(crate) struct Turn {
+    pub(crate) from: DirectedEdge,
+    pub(crate) to: DirectedEdge,
+    /// Degrees in `[-180, 180)` to turn, positive to the right, for deriving turn costs
+    pub(crate) angle: i16,
+}
+
+/// The edge-based graph of a tile, written as `<quadkey>.turns`
+///
+/// Holds the directed edges of the edges in the tile and the turns leaving them,
+/// which may lead onto directed edges of neighbouring tiles.
+#[derive(Debug, Default, bincode::Encode, bincode::Decode)]
+pub(crate) struct TurnTile {
+    pub(crate) edges: Vec<DirectedEdge>,
+    pub(crate) turns: Vec<Turn>,
+}
+
+/// A directed edge together with its bearing when entering it
+struct Bearings {
+    edge: DirectedEdge,
+    start: f64,
+}
+
+/// Builds the edge-based graph of the tiles for a profile
+///
+/// Turns obey the restrictions stored in the tiles that apply to the profile and
+/// U-turns are only allowed at dead ends. Restrictions via ways would need more
+/// than a single directed edge of history and are left out, their number is
+/// returned with the tiles.
+pub(crate) fn build_turn_tiles(
+    tiles: &[(Quadkey, Tile)],
+    node_table: &HashMap<NodeId, Node>,
+    profile: Profile,
+) -> (Vec<(Quadkey, TurnTile)>, usize) {
+    // Directed edges by the node they leave, and the bearing they arrive with
+    let mut outgoing: HashMap<NodeId, Vec<Bearings>> = HashMap::new();
+    let mut arrival_bearings: HashMap<DirectedEdge, f64> = HashMap::new();
+    let mut turn_tiles = Vec::with_capacity(tiles.len());
+    for (quadkey, tile) in tiles {
+        let mut turn_tile = TurnTile::default();
+        for edge in tile
+            .edges
+            .iter()
+            .filter(|edge| edge.access.contains(profile))
+        {
+            let coords = geometry::edge_coordinates(edge, node_table);
+            let Some((start, end)) = end_bearings(&coords) else {
+                continue;
+            };
+            let forward = DirectedEdge {
+                way_id: edge.way_id,
+                from: edge.from,
+                to: edge.to,
+            };
+            turn_tile.edges.push(forward);
+            arrival_bearings.insert(forward, end);
+            outgoing.entry(edge.from).or_default().push(Bearings {
+                edge: forward,
+                start,
+            });
+            if !edge.oneway.contains(profile) {
+                let backward = DirectedEdge {
+                    way_id: edge.way_id,
+                    from: edge.to,
+                    to: edge.from,
+                };
+                turn_tile.edges.push(backward);
+                arrival_bearings.insert(backward, (start + 180.0).rem_euclid(360.0));
+                outgoing.entry(edge.to).or_default().push(Bearings {
+                    edge: backward,
+                    start: (end + 180.0).rem_euclid(360.0),
+                });
+            }
+        }
+        turn_tiles.push((quadkey.clone(), turn_tile));
+    }
+
+    // Restrictions are stored in every tile they touch
+    let mut seen_restrictions = HashSet::new();
+    let mut restrictions_at: HashMap<NodeId, Vec<&TurnRestriction>> = HashMap::new();
+    let mut num_skipped = 0;
+    for restriction in tiles
+        .iter()
+        .flat_map(|(_quadkey, tile)| &tile.restrictions)
+        .filter(|restriction| restriction.applies_to(profile))
+        .filter(|restriction| seen_restrictions.insert(restriction.relation_id))
+    {
+        match restriction.via {
+            Via::Node(node_id) => restrictions_at
+                .entry(node_id)
+                .or_default()
+                .push(restriction),
+            Via::Ways(_) => num_skipped += 1,
+        }
+    }
+
+    for (_quadkey, turn_tile) in &mut turn_tiles {
+        for from in &turn_tile.edges {
+            let Some(candidates) = outgoing.get(&from.to) else {
+                continue;
+            };
+            let from_end = arrival_bearings[from];
+            let is_dead_end = candidates.iter().all(|to| is_u_turn(from, &to.edge));
+            let restrictions = restrictions_at.get(&from.to).map_or(&[][..], Vec::as_slice);
+            for to in candidates {
+                if is_u_turn(from, &to.edge) && !is_dead_end {
+                    continue;
+                }
+                if restrictions
+                    .iter()
+                    .any(|restriction| restriction.forbids(from.way_id, to.edge.way_id))
+                {
+                    continue;
+                }
+                let angle = (to.start - from_end + 540.0).rem_euclid(360.0) - 180.0;
+                turn_tile.turns.push(Turn {
+                    from: *from,
+                    to: to.edge,
+                    angle: angle.round() as i16,
+                });
+            }
+        }
+    }
+    (turn_tiles, num_skipped)
+}
+
+/// Bearings when entering and leaving a line, `None` if it has no length
+pub(crate) fn end_bearings(coords: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let first = coords.windows(2).find(|pair| pair[0] != pair[1])?;
+    let last = coords.windows(2).rev().find(|pair| pair[0] != pair[1])?;
+    Some((
+        geometry::bearing(first[0].0, first[0].1, first[1].0, first[1].1),
+        geometry::bearing(last[0].0, last[0].1, last[1].0, last[1].1),
+    ))
+}
+
+/// Whether a turn goes straight back along the same edge
+fn is_u_turn(from: &DirectedEdge, to: &DirectedEdge) -> bool {
+    from.way_id == to.way_id && from.from == to.to && from.to == to.from
+}
+
+/// Writes the edge-based graph into per-tile files, returning the number of files
+pub(crate) fn write_turn_tiles(
+    output_dir: &Path,
+    naming: TileNaming,
+    turn_tiles: &[(Quadkey, TurnTile)],
+) -> Result<usize> {
+    for (quadkey, turn_tile) in turn_tiles {
+        let fname = utils::tile_path(output_dir, quadkey, naming).with_extension("turns");
+        utils::write_encoded(&fname, turn_tile)?;
+    }
+    Ok(turn_tiles.len())
+}
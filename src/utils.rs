@@ -1,21 +1,95 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     f64::consts::PI,
     hash::{DefaultHasher, Hash, Hasher},
+    io::{Read, Write},
+    path::{Path, PathBuf},
     sync::Mutex,
 };
 
-use anyhow::{Result, bail};
-use bincode::Encode;
+use bincode::{Decode, Encode};
 
-use crate::{Edge, NodeId, Way, WayId, utils};
+use crate::{
+    Edge, NodeId, RoadClass,
+    adjacency::Adjacency,
+    archive::{self, TileArchive},
+    bloom::NodeFilter,
+    boundary::BoundaryNode,
+    cancel::CancellationToken,
+    database::{self, TileDatabase},
+    error::{GladsheimError, IoContext, Result},
+    flat_tile::{self, FlatTile},
+    hierarchy::TileHierarchy,
+    manifest,
+    osm_parser::{LevelCrossing, Loc},
+    overview::OverviewLevel,
+    profile::Profile,
+    region::Region,
+    restrictions::TurnRestriction,
+    streets::Street,
+    tile_format::{Compression, TileHeader},
+    transit::TransitLine,
+};
 
-#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+#[derive(Clone, Debug, Hash, Eq, PartialEq, bincode::Encode, bincode::Decode)]
 pub(crate) struct Quadkey(pub(crate) String);
 
-#[derive(Debug, Default, Encode)]
+/// The routing data of a tile, see `tile_format` for how it's stored
+#[derive(Debug, Default)]
 pub(crate) struct Tile {
+    /// The mode of transport the tile was built for, stored in the file header
+    pub(crate) profile: Profile,
+    /// Further modes of transport in the access masks of the edges
+    pub(crate) extra_profiles: Vec<Profile>,
+    /// How the tile is compressed on disk, stored in the file header
+    pub(crate) compression: Compression,
+    /// Zoom level of the quadkey of the tile, stored in the file header
+    pub(crate) zoom: u8,
+    /// The least important class of road in an overview tile, `None` for the base tiles
+    /// holding all roads, stored in the file header
+    pub(crate) max_class: Option<RoadClass>,
+    /// Replication timestamp of the OSM data the tile was built from in seconds since
+    /// 1970-01-01, when the input file records one, stored in the file header
+    pub(crate) source_timestamp: Option<i64>,
+    /// Filter over the ids in `node_locations`, only stored when requested
+    ///
+    /// Kept first after the header so it can be read without decoding the rest, see
+    /// `read_node_filter`.
+    pub(crate) node_filter: Option<NodeFilter>,
     pub(crate) edges: Vec<Edge>,
+    /// Graph nodes of this tile that are pedestrian crossings
+    pub(crate) crossings: Vec<NodeId>,
+    /// Coordinates of every node along the edges, sorted by node id
+    pub(crate) node_locations: Vec<(NodeId, Loc)>,
+    /// Railway level crossings along the edges, sorted by node id
+    pub(crate) level_crossings: Vec<(NodeId, LevelCrossing)>,
+    /// Public transport lines running along the edges
+    pub(crate) transit_lines: Vec<TransitLine>,
+    /// Edges grouped into named streets, for display, search and guidance
+    pub(crate) streets: Vec<Street>,
+    /// Turn restrictions for the profile at the nodes and ways of the edges
+    pub(crate) restrictions: Vec<TurnRestriction>,
+    /// Every edge is directed, with two-way roads stored as an edge in each direction
+    pub(crate) has_reverse_edges: bool,
+    /// Chains of edges were merged, so edges no longer end at every way they touch
+    pub(crate) is_contracted: bool,
+    /// Graph nodes shared with other tiles, for stitching the tiles together, sorted by node id
+    pub(crate) boundary_nodes: Vec<BoundaryNode>,
+    /// The edges at every node, as stored in the tile file
+    ///
+    /// Derived from `edges` whenever a tile is written, so it's only filled in for
+    /// tiles read from disk and is not kept up to date while changing their edges.
+    pub(crate) adjacency: Adjacency,
+    /// Node ranks and shortcuts of a contraction hierarchy, from `BuildCh`
+    pub(crate) hierarchy: Option<TileHierarchy>,
+}
+impl Tile {
+    /// The index of a node in `node_locations`, `None` if it's not in the tile
+    pub(crate) fn node_index(&self, node_id: NodeId) -> Option<usize> {
+        self.node_locations
+            .binary_search_by_key(&node_id.0, |(node_id, _loc)| node_id.0)
+            .ok()
+    }
 }
 #[derive(Debug)]
 pub(crate) struct TileCoord {
@@ -24,11 +98,17 @@ pub(crate) struct TileCoord {
     pub zoom: u8,
 }
 pub(crate) fn lat_lon_to_tile_coord(lat: f64, lon: f64, zoom: u8) -> Result<TileCoord> {
-    if lat < -85.05112878 || lat > 85.05112878 {
-        bail!("Latitude {} out of valid range", lat);
+    if !(-85.05112878..=85.05112878).contains(&lat) {
+        return Err(GladsheimError::parse(format!(
+            "Latitude {} out of valid range",
+            lat
+        )));
     }
-    if lon < -180.0 || lon > 180.0 {
-        bail!("Longitude {} out of valid range", lat);
+    if !(-180.0..=180.0).contains(&lon) {
+        return Err(GladsheimError::parse(format!(
+            "Longitude {} out of valid range",
+            lon
+        )));
     }
 
     let n = 2.0f64.powi(zoom as i32);
@@ -72,6 +152,813 @@ pub(crate) fn lat_lon_to_quadkey(lat: f64, lon: f64, zoom: u8) -> Result<String>
     Ok(tile_coord_to_quadkey(&tile))
 }
 
+pub(crate) fn quadkey_to_tile_coord(quadkey: &Quadkey) -> Option<TileCoord> {
+    let mut tile = TileCoord {
+        x: 0,
+        y: 0,
+        zoom: quadkey.0.len() as u8,
+    };
+    for digit in quadkey.0.chars() {
+        let digit = digit.to_digit(4)?;
+        tile.x = (tile.x << 1) | (digit & 1);
+        tile.y = (tile.y << 1) | (digit >> 1);
+    }
+    Some(tile)
+}
+
+/// The area covered by a tile as `(min_lat, min_lon, max_lat, max_lon)`
+pub(crate) fn tile_coord_bounds(tile: &TileCoord) -> (f64, f64, f64, f64) {
+    let n = 2.0f64.powi(tile.zoom as i32);
+    let lon = |x: u32| x as f64 / n * 360.0 - 180.0;
+    let lat = |y: u32| (PI * (1.0 - 2.0 * y as f64 / n)).sinh().atan().to_degrees();
+    (lat(tile.y + 1), lon(tile.x), lat(tile.y), lon(tile.x + 1))
+}
+
+/// Picks tiles by quadkey and area, a selector without criteria picks all tiles
+#[derive(Debug, Default)]
+pub(crate) struct TileSelector {
+    /// Quadkey prefixes, or globs with `*` and `?` matched against the whole quadkey
+    pub(crate) patterns: Vec<String>,
+    /// Tiles overlapping the bounds of this region
+    pub(crate) region: Option<Region>,
+}
+impl TileSelector {
+    pub(crate) fn matches(&self, quadkey: &Quadkey) -> bool {
+        let matches_pattern = self.patterns.is_empty()
+            || self.patterns.iter().any(|pattern| {
+                if pattern.contains(['*', '?']) {
+                    glob_matches(pattern.as_bytes(), quadkey.0.as_bytes())
+                } else {
+                    quadkey.0.starts_with(pattern.as_str())
+                }
+            });
+        let matches_region = self.region.as_ref().is_none_or(|region| {
+            let Some(tile) = quadkey_to_tile_coord(quadkey) else {
+                return false;
+            };
+            let (min_lat, min_lon, max_lat, max_lon) = tile_coord_bounds(&tile);
+            let bounds = region.bounds();
+            min_lat <= bounds.2 && bounds.0 <= max_lat && min_lon <= bounds.3 && bounds.1 <= max_lon
+        });
+        matches_pattern && matches_region
+    }
+}
+
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_matches(&pattern[1..], text)
+                || (!text.is_empty() && glob_matches(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob_matches(&pattern[1..], &text[1..]),
+        (Some(a), Some(b)) if a == b => glob_matches(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+/// How tile files are named within a tile directory
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, bincode::Encode, bincode::Decode,
+)]
+pub(crate) enum TileNaming {
+    /// `<quadkey>.grt`
+    #[default]
+    Quadkey,
+    /// `<z>/<x>/<y>.grt`, so tiles can be served by static file servers and CDNs
+    Zxy,
+    /// `<first 3 digits of the quadkey>/<quadkey>.grt`, so no directory holds more than
+    /// a few hundred tiles, as ext4 and NFS slow down on huge directories
+    Sharded,
+}
+impl TileNaming {
+    /// The naming of tiles listed by `list_tiles`, by how deeply the files are nested
+    pub(crate) fn of_tiles(tiles_dir: &Path, tiles: &[(Quadkey, PathBuf)]) -> Self {
+        let depth = |fname: &Path| {
+            fname
+                .strip_prefix(tiles_dir)
+                .map_or(1, |relative| relative.components().count())
+        };
+        match tiles.iter().map(|(_quadkey, fname)| depth(fname)).max() {
+            Some(3) => TileNaming::Zxy,
+            Some(2) => TileNaming::Sharded,
+            _ => TileNaming::Quadkey,
+        }
+    }
+}
+
+/// Number of leading quadkey digits naming the directory of a tile with `TileNaming::Sharded`
+const SHARD_DIGITS: usize = 3;
+
+/// Whether a directory name is the leading digits of quadkeys, see `TileNaming::Sharded`
+fn is_shard_name(name: &str) -> bool {
+    (1..=SHARD_DIGITS).contains(&name.len())
+        && name.bytes().all(|digit| (b'0'..=b'3').contains(&digit))
+}
+
+/// Lists the `.grt` tiles in a directory together with their quadkeys
+///
+/// Finds tiles of any `TileNaming`. Names of shards and zoom levels can look the
+/// same, e.g. `12`, but shards hold tiles and zoom levels hold directories.
+pub(crate) fn list_tiles(tiles_dir: &Path) -> Result<Vec<(Quadkey, PathBuf)>> {
+    let mut tiles = list_quadkey_tiles(tiles_dir)?;
+    for path in list_dir(tiles_dir)? {
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|_name| path.is_dir())
+        else {
+            continue;
+        };
+        if let Ok(zoom) = name.parse::<u8>() {
+            tiles.extend(list_zxy_tiles(&path, zoom)?);
+        }
+        if is_shard_name(name) {
+            tiles.extend(list_quadkey_tiles(&path)?);
+        }
+    }
+    tiles.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+    Ok(tiles)
+}
+
+/// Lists the `<quadkey>.grt` tiles directly in a directory
+fn list_quadkey_tiles(dir: &Path) -> Result<Vec<(Quadkey, PathBuf)>> {
+    let mut tiles = Vec::new();
+    for path in list_dir(dir)? {
+        if path.extension().is_some_and(|ext| ext == "grt") {
+            if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                tiles.push((Quadkey(stem.to_string()), path.clone()));
+            }
+        }
+    }
+    Ok(tiles)
+}
+
+/// Lists the `<x>/<y>.grt` tiles in the directory of a zoom level
+fn list_zxy_tiles(zoom_dir: &Path, zoom: u8) -> Result<Vec<(Quadkey, PathBuf)>> {
+    let number = |path: &Path| path.file_stem()?.to_str()?.parse::<u32>().ok();
+    let mut tiles = Vec::new();
+    for x_dir in list_dir(zoom_dir)? {
+        let Some(x) = number(&x_dir).filter(|_x| x_dir.is_dir()) else {
+            continue;
+        };
+        for path in list_dir(&x_dir)? {
+            if let Some(y) =
+                number(&path).filter(|_y| path.extension().is_some_and(|ext| ext == "grt"))
+            {
+                let quadkey = tile_coord_to_quadkey(&TileCoord { x, y, zoom });
+                tiles.push((Quadkey(quadkey), path));
+            }
+        }
+    }
+    Ok(tiles)
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    std::fs::read_dir(dir)
+        .io_context(|| format!("Failed listing directory {}", dir.display()))?
+        .map(|entry| {
+            Ok(entry
+                .io_context(|| format!("Failed listing directory {}", dir.display()))?
+                .path())
+        })
+        .collect()
+}
+
+/// Reads a single tile previously written by the parser
+pub(crate) fn read_tile(fname: &Path) -> Result<Tile> {
+    decode_tile(open_tile_file(fname)?, &fname.display().to_string())
+}
+
+/// Decodes a tile as written by `encode_tile`, naming its `source` in errors
+///
+/// The whole tile is checked against the length and checksum in its header first.
+pub(crate) fn decode_tile(mut reader: impl Read, source: &str) -> Result<Tile> {
+    let decode_error = |error| GladsheimError::TileFormat {
+        context: format!("Failed decoding tile of {}", source),
+        source: Box::new(error),
+    };
+    let header = read_tile_header(&mut reader, source, "tile")?;
+    let mut payload = Vec::new();
+    reader
+        .take(header.payload_len)
+        .read_to_end(&mut payload)
+        .io_context(|| format!("Failed reading {}", source))?;
+    header.verify(&payload).map_err(decode_error)?;
+    let mut tile: Tile = decode_payload(&header, &payload[..], source, "tile")?;
+    tile.profile = header.profile;
+    tile.extra_profiles = header.extra_profiles;
+    tile.compression = header.compression;
+    tile.zoom = header.zoom;
+    tile.max_class = header.max_class;
+    tile.source_timestamp = header.source_timestamp;
+    Ok(tile)
+}
+
+/// Reads only the node filter at the start of a tile, `None` if it was built without one
+pub(crate) fn read_node_filter(fname: &Path) -> Result<Option<NodeFilter>> {
+    decode_node_filter(open_tile_file(fname)?, &fname.display().to_string())
+}
+
+/// Decodes the node filter without reading the rest of the tile, so unlike
+/// `decode_tile` this doesn't check the checksum
+fn decode_node_filter(mut reader: impl Read, source: &str) -> Result<Option<NodeFilter>> {
+    let header = read_tile_header(&mut reader, source, "node filter")?;
+    decode_payload(&header, reader, source, "node filter")
+}
+
+fn open_tile_file(fname: &Path) -> Result<impl Read> {
+    Ok(std::io::BufReader::new(
+        std::fs::File::open(fname)
+            .io_context(|| format!("Failed opening file {}", fname.display()))?,
+    ))
+}
+
+/// Reads the header of a tile, naming `what` is being read from `source` in errors
+fn read_tile_header(reader: &mut impl Read, source: &str, what: &str) -> Result<TileHeader> {
+    bincode::decode_from_std_read(reader, bincode::config::standard()).map_err(|error| {
+        GladsheimError::TileFormat {
+            context: format!("Failed decoding {} of {}", what, source),
+            source: Box::new(error),
+        }
+    })
+}
+
+/// Decodes the start of the data following the header, decompressing as much as needed
+fn decode_payload<T: Decode<()>>(
+    header: &TileHeader,
+    mut reader: impl Read,
+    source: &str,
+    what: &str,
+) -> Result<T> {
+    match header.compression {
+        Compression::None => {
+            bincode::decode_from_std_read(&mut reader, bincode::config::standard())
+        }
+        Compression::Zstd { .. } => {
+            let mut decoder = zstd::Decoder::new(reader)
+                .io_context(|| format!("Failed decompressing {}", source))?;
+            bincode::decode_from_std_read(&mut decoder, bincode::config::standard())
+        }
+    }
+    .map_err(|error| GladsheimError::TileFormat {
+        context: format!("Failed decoding {} of {}", what, source),
+        source: Box::new(error),
+    })
+}
+
+/// How the tiles of a directory are stored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum TileStorage {
+    /// A file per tile, named by `TileNaming`
+    #[default]
+    Files,
+    /// A `tiles.grta` archive, see `TileArchive`
+    Archive,
+    /// A `tiles.sqlite` database, see `TileDatabase`
+    Database,
+}
+impl TileStorage {
+    /// The file holding all tiles, `None` for a file per tile
+    pub(crate) fn container_fname(self, tiles_dir: &Path) -> Option<PathBuf> {
+        match self {
+            TileStorage::Files => None,
+            TileStorage::Archive => Some(tiles_dir.join(archive::ARCHIVE_FILE_NAME)),
+            TileStorage::Database => Some(tiles_dir.join(database::DATABASE_FILE_NAME)),
+        }
+    }
+
+    /// Writes encoded tiles into the single file of the storage at `fname`,
+    /// returning whether it changed, tile files are written one by one instead
+    pub(crate) fn write_container(
+        self,
+        fname: &Path,
+        tiles: Vec<(Quadkey, Vec<u8>)>,
+    ) -> Result<bool> {
+        match self {
+            TileStorage::Files => Ok(false),
+            TileStorage::Archive => archive::write_archive(fname, tiles),
+            TileStorage::Database => database::write_database(fname, tiles),
+        }
+    }
+
+    /// Removes the files of the other storages, which readers would prefer over
+    /// or mix up with the tiles just written
+    pub(crate) fn remove_others(self, tiles_dir: &Path) -> Result<()> {
+        for other in [TileStorage::Archive, TileStorage::Database] {
+            if other == self {
+                continue;
+            }
+            if let Some(fname) = other.container_fname(tiles_dir) {
+                if fname.exists() {
+                    std::fs::remove_file(&fname)
+                        .io_context(|| format!("Failed removing file {}", fname.display()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single file holding the encoded tiles of a directory, read tile by tile
+pub(crate) trait TileContainer: Send + Sync {
+    fn fname(&self) -> &Path;
+
+    /// The quadkeys of the tiles held, sorted
+    fn quadkeys(&self) -> Vec<Quadkey>;
+
+    /// Reads the encoded tile of a quadkey, `None` if the container doesn't hold it
+    fn read(&self, quadkey: &Quadkey) -> Result<Option<Vec<u8>>>;
+}
+
+/// Where the tiles of a `TileStore` are read from
+enum TileSource {
+    /// A file per tile, sorted by quadkey
+    Files(Vec<(Quadkey, PathBuf)>),
+    Container(Box<dyn TileContainer>),
+}
+
+/// A directory of tiles, which are read as they're needed
+///
+/// Reads a `tiles.grta` archive or `tiles.sqlite` database when the directory
+/// has one, otherwise the tile files listed in its manifest, or of any
+/// `TileNaming` in directories built without one.
+pub(crate) struct TileStore {
+    tiles_dir: PathBuf,
+    naming: TileNaming,
+    storage: TileStorage,
+    source: TileSource,
+    /// The headers of the tile files from the manifest, in the order of
+    /// `TileSource::Files`, empty without a manifest
+    headers: Vec<TileHeader>,
+    /// The overview levels listed in the manifest
+    overviews: Vec<OverviewLevel>,
+    /// The tiles to read in order, all of them unless narrowed down by `select`
+    quadkeys: Vec<Quadkey>,
+}
+impl TileStore {
+    /// Lists the tiles in a directory, without reading any of them yet
+    pub(crate) fn open(tiles_dir: &Path) -> Result<Self> {
+        let manifest = manifest::read_manifest(tiles_dir)?;
+        for storage in [TileStorage::Archive, TileStorage::Database] {
+            let Some(fname) = storage.container_fname(tiles_dir) else {
+                continue;
+            };
+            if !fname.exists() {
+                continue;
+            }
+            let container: Box<dyn TileContainer> = match storage {
+                TileStorage::Files => continue,
+                TileStorage::Archive => Box::new(TileArchive::open(&fname)?),
+                TileStorage::Database => Box::new(TileDatabase::open(&fname)?),
+            };
+            return Ok(Self {
+                tiles_dir: tiles_dir.to_path_buf(),
+                naming: TileNaming::default(),
+                storage,
+                headers: Vec::new(),
+                overviews: manifest.map_or_else(Vec::new, |manifest| manifest.overviews),
+                quadkeys: container.quadkeys(),
+                source: TileSource::Container(container),
+            });
+        }
+        let (naming, tiles, headers, overviews) = match manifest {
+            Some(manifest) => {
+                let (tiles, headers) = manifest
+                    .tiles
+                    .into_iter()
+                    .map(|(quadkey, header)| {
+                        let fname = tile_path(tiles_dir, &quadkey, manifest.naming);
+                        ((quadkey, fname), header)
+                    })
+                    .unzip();
+                (manifest.naming, tiles, headers, manifest.overviews)
+            }
+            None => {
+                let tiles = list_tiles(tiles_dir)?;
+                if !tiles.is_empty() {
+                    println!(
+                        "WARN: No {} in {}, the tiles may be left from an interrupted build",
+                        manifest::MANIFEST_FILE,
+                        tiles_dir.display()
+                    );
+                }
+                let naming = TileNaming::of_tiles(tiles_dir, &tiles);
+                (naming, tiles, Vec::new(), Vec::new())
+            }
+        };
+        Ok(Self {
+            tiles_dir: tiles_dir.to_path_buf(),
+            naming,
+            headers,
+            overviews,
+            storage: TileStorage::Files,
+            quadkeys: tiles
+                .iter()
+                .map(|(quadkey, _fname)| quadkey.clone())
+                .collect(),
+            source: TileSource::Files(tiles),
+        })
+    }
+
+    /// Narrows the tiles down to those picked by a selector
+    pub(crate) fn select(mut self, selector: &TileSelector) -> Self {
+        self.quadkeys.retain(|quadkey| selector.matches(quadkey));
+        self
+    }
+
+    pub(crate) fn naming(&self) -> TileNaming {
+        self.naming
+    }
+
+    pub(crate) fn storage(&self) -> TileStorage {
+        self.storage
+    }
+
+    pub(crate) fn overviews(&self) -> &[OverviewLevel] {
+        &self.overviews
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.quadkeys.len()
+    }
+
+    /// The quadkeys of the selected tiles, in order
+    pub(crate) fn quadkeys(&self) -> impl Iterator<Item = &Quadkey> {
+        self.quadkeys.iter()
+    }
+
+    /// Reads the tile of a quadkey, `None` when there are no edges in it
+    pub(crate) fn load(&self, quadkey: &Quadkey) -> Result<Option<Tile>> {
+        match &self.source {
+            TileSource::Files(tiles) => tiles
+                .binary_search_by(|(other, _fname)| other.0.cmp(&quadkey.0))
+                .ok()
+                .map(|index| read_tile(&tiles[index].1))
+                .transpose(),
+            TileSource::Container(container) => container
+                .read(quadkey)?
+                .map(|bytes| {
+                    decode_tile(&bytes[..], &container_source(container.as_ref(), quadkey))
+                })
+                .transpose(),
+        }
+    }
+
+    /// Reads only the node filter of the tile of a quadkey, see `read_node_filter`
+    pub(crate) fn load_node_filter(&self, quadkey: &Quadkey) -> Result<Option<NodeFilter>> {
+        match &self.source {
+            TileSource::Files(tiles) => {
+                match tiles.binary_search_by(|(other, _fname)| other.0.cmp(&quadkey.0)) {
+                    Ok(index) => read_node_filter(&tiles[index].1),
+                    Err(_) => Ok(None),
+                }
+            }
+            TileSource::Container(container) => match container.read(quadkey)? {
+                Some(bytes) => {
+                    decode_node_filter(&bytes[..], &container_source(container.as_ref(), quadkey))
+                }
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Reads only the header of the tile of a quadkey, with the metadata of the tile
+    ///
+    /// Tile files listed in a manifest aren't opened, their headers are kept there.
+    pub(crate) fn load_header(&self, quadkey: &Quadkey) -> Result<Option<TileHeader>> {
+        match &self.source {
+            TileSource::Files(tiles) => {
+                match tiles.binary_search_by(|(other, _fname)| other.0.cmp(&quadkey.0)) {
+                    Ok(index) if !self.headers.is_empty() => Ok(Some(self.headers[index].clone())),
+                    Ok(index) => {
+                        let fname = &tiles[index].1;
+                        read_tile_header(
+                            &mut open_tile_file(fname)?,
+                            &fname.display().to_string(),
+                            "header",
+                        )
+                        .map(Some)
+                    }
+                    Err(_) => Ok(None),
+                }
+            }
+            TileSource::Container(container) => match container.read(quadkey)? {
+                Some(bytes) => read_tile_header(
+                    &mut &bytes[..],
+                    &container_source(container.as_ref(), quadkey),
+                    "header",
+                )
+                .map(Some),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Reads the tile of a quadkey as stored, without decoding it, e.g. to copy it unchanged
+    pub(crate) fn load_encoded(&self, quadkey: &Quadkey) -> Result<Option<Vec<u8>>> {
+        match &self.source {
+            TileSource::Files(tiles) => {
+                match tiles.binary_search_by(|(other, _fname)| other.0.cmp(&quadkey.0)) {
+                    Ok(index) => {
+                        let fname = &tiles[index].1;
+                        std::fs::read(fname)
+                            .io_context(|| format!("Failed reading file {}", fname.display()))
+                            .map(Some)
+                    }
+                    Err(_) => Ok(None),
+                }
+            }
+            TileSource::Container(container) => container.read(quadkey),
+        }
+    }
+
+    /// Maps the flat layout of the tile of a quadkey, `None` when it wasn't written
+    pub(crate) fn load_flat(&self, quadkey: &Quadkey) -> Result<Option<FlatTile>> {
+        let fname = flat_tile::flat_tile_path(&self.tiles_dir, quadkey, self.naming);
+        if !fname.exists() {
+            return Ok(None);
+        }
+        FlatTile::open(&fname).map(Some)
+    }
+
+    /// Reads every selected tile in order of quadkey
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Result<(Quadkey, Tile)>> + '_ {
+        self.quadkeys().filter_map(|quadkey| {
+            self.load(quadkey)
+                .transpose()
+                .map(|tile| Ok((quadkey.clone(), tile?)))
+        })
+    }
+}
+
+/// Names a tile within an archive or database in errors
+fn container_source(container: &dyn TileContainer, quadkey: &Quadkey) -> String {
+    format!("tile {} in {}", quadkey.0, container.fname().display())
+}
+
+/// Reads the headers of the selected tiles, without decoding the tiles themselves
+pub(crate) fn read_tile_headers(
+    tiles_dir: &Path,
+    selector: &TileSelector,
+    cancel: &CancellationToken,
+) -> Result<Vec<(Quadkey, TileHeader)>> {
+    let store = TileStore::open(tiles_dir)?.select(selector);
+    let mut headers = Vec::new();
+    for quadkey in store.quadkeys() {
+        cancel.check(|| format!("reading headers up to tile {}", quadkey.0))?;
+        if let Some(header) = store.load_header(quadkey)? {
+            headers.push((quadkey.clone(), header));
+        }
+    }
+    Ok(headers)
+}
+
+/// Finds the tiles with edges through a node, returning them and how many were skipped
+///
+/// Tiles whose node filter rules the node out are not decoded.
+pub(crate) fn find_node(
+    tiles_dir: &Path,
+    selector: &TileSelector,
+    node_id: NodeId,
+    cancel: &CancellationToken,
+) -> Result<(Vec<Quadkey>, usize)> {
+    let store = TileStore::open(tiles_dir)?.select(selector);
+    let mut found = Vec::new();
+    let mut num_skipped = 0;
+    for quadkey in store.quadkeys() {
+        cancel.check(|| format!("searching up to tile {}", quadkey.0))?;
+        if let Some(flat_tile) = store.load_flat(quadkey)? {
+            if flat_tile.node_index(node_id).is_some() {
+                found.push(quadkey.clone());
+            }
+            continue;
+        }
+        if store
+            .load_node_filter(quadkey)?
+            .is_some_and(|filter| !filter.may_contain(node_id))
+        {
+            num_skipped += 1;
+            continue;
+        }
+        if store
+            .load(quadkey)?
+            .is_some_and(|tile| tile.node_index(node_id).is_some())
+        {
+            found.push(quadkey.clone());
+        }
+    }
+    Ok((found, num_skipped))
+}
+
+/// Path of the tile file for a quadkey in a tile directory
+///
+/// Files belonging to the tile, like its name index, swap the extension.
+pub(crate) fn tile_path(tiles_dir: &Path, quadkey: &Quadkey, naming: TileNaming) -> PathBuf {
+    let mut fname = tiles_dir.to_owned();
+    match (naming, quadkey_to_tile_coord(quadkey)) {
+        (TileNaming::Zxy, Some(tile)) => {
+            fname.push(tile.zoom.to_string());
+            fname.push(tile.x.to_string());
+            fname.push(tile.y.to_string());
+        }
+        (TileNaming::Sharded, _) => {
+            fname.push(&quadkey.0[..quadkey.0.len().min(SHARD_DIGITS)]);
+            fname.push(&quadkey.0);
+        }
+        _ => fname.push(&quadkey.0),
+    }
+    fname.set_extension("grt");
+    fname
+}
+
+/// Writes a tile into a tile directory, returning its header and whether the file changed
+pub(crate) fn write_tile(
+    tiles_dir: &Path,
+    quadkey: &Quadkey,
+    naming: TileNaming,
+    tile: &Tile,
+) -> Result<(TileHeader, bool)> {
+    let fname = tile_path(tiles_dir, quadkey, naming);
+    let (header, bytes) = encode_tile(tile, &fname.display().to_string())?;
+    Ok((header, write_bytes(&fname, &bytes)?))
+}
+
+/// Encodes a tile with its header and compression, naming its `destination` in errors
+///
+/// The header is returned as well, for the manifest of the tile directory.
+pub(crate) fn encode_tile(tile: &Tile, destination: &str) -> Result<(TileHeader, Vec<u8>)> {
+    let encode_error = |source| GladsheimError::TileFormat {
+        context: format!("Failed encoding tile {}", destination),
+        source: Box::new(source),
+    };
+    let data = bincode::encode_to_vec(tile, bincode::config::standard()).map_err(encode_error)?;
+    let payload = match tile.compression {
+        Compression::None => data,
+        Compression::Zstd { level } => zstd::encode_all(&data[..], level)
+            .io_context(|| format!("Failed compressing tile {}", destination))?,
+    };
+    let header = TileHeader::of_tile(tile, &payload);
+    let mut bytes =
+        bincode::encode_to_vec(&header, bincode::config::standard()).map_err(encode_error)?;
+    bytes.extend(payload);
+    Ok((header, bytes))
+}
+
+/// Writes a value into a file with the bincode configuration used for tiles
+///
+/// Missing parent directories are created, as `TileNaming::Zxy` nests files. A file
+/// already holding the same bytes is left untouched, so rebuilding unchanged data
+/// keeps modification times and only changed files need to be deployed. Returns
+/// whether the file was written.
+pub(crate) fn write_encoded<T: Encode>(fname: &Path, value: &T) -> Result<bool> {
+    let bytes = bincode::encode_to_vec(value, bincode::config::standard()).map_err(|source| {
+        GladsheimError::TileFormat {
+            context: format!("Failed encoding file {}", fname.display()),
+            source: Box::new(source),
+        }
+    })?;
+    write_bytes(fname, &bytes)
+}
+
+/// Writes bytes into a file unless it already holds them, see `write_encoded`
+///
+/// The bytes go into a temporary file next to it that is renamed into place, so
+/// an interrupted build leaves either the old or the new file, never a partly
+/// written one.
+pub(crate) fn write_bytes(fname: &Path, bytes: &[u8]) -> Result<bool> {
+    // Only read back files of the same size, most changes alter the size anyway
+    let is_unchanged = std::fs::metadata(fname)
+        .is_ok_and(|metadata| metadata.len() == bytes.len() as u64)
+        && std::fs::read(fname).is_ok_and(|existing| existing == bytes);
+    if is_unchanged {
+        return Ok(false);
+    }
+    if let Some(dir) = fname.parent() {
+        std::fs::create_dir_all(dir)
+            .io_context(|| format!("Failed creating directory {}", dir.display()))?;
+    }
+    let mut tmp_name = fname.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_fname = PathBuf::from(tmp_name);
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp_fname)?;
+        file.write_all(bytes)?;
+        // Flushed before the rename, or a crash could leave the renamed file empty
+        file.sync_all()?;
+        std::fs::rename(&tmp_fname, fname)
+    };
+    if let Err(error) = write() {
+        let _ = std::fs::remove_file(&tmp_fname);
+        return Err(error).io_context(|| format!("Failed writing to file {}", fname.display()));
+    }
+    Ok(true)
+}
+
+/// A count for logs, in thousands as `12k` once it's large, exact below that
+pub(crate) fn format_count(count: usize) -> String {
+    if count < 1000 {
+        count.to_string()
+    } else {
+        format!("{}k", count / 1000)
+    }
+}
+
+/// Great-circle distance in meters between two coordinates
+pub(crate) fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const EARTH_RADIUS_M: f64 = 6_371_008.8;
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Parses an OSM date (`YYYY`, `YYYY-MM` or `YYYY-MM-DD`) into a `YYYYMMDD` number
+///
+/// Missing month or day count as the first, so the result is the earliest date meant.
+pub(crate) fn parse_date(value: &str) -> Option<u32> {
+    let mut parts = value.trim().splitn(3, '-');
+    let year = parts.next()?.parse::<u32>().ok()?;
+    let month = parts
+        .next()
+        .map_or(Some(1), |month| month.parse::<u32>().ok())?;
+    let day = parts
+        .next()
+        .map_or(Some(1), |day| day.parse::<u32>().ok())?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(year * 10_000 + month * 100 + day)
+}
+
+/// The current UTC date as a `YYYYMMDD` number
+pub(crate) fn today() -> u32 {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() / 86_400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    (year * 10_000 + month * 100 + day) as u32
+}
+
+/// Parses a UTC timestamp like `2024-05-01T12:00:00Z` into seconds since 1970-01-01
+pub(crate) fn parse_timestamp(value: &str) -> Option<i64> {
+    let (date, time) = value.trim().strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Formats seconds since 1970-01-01 as a UTC timestamp like `2024-05-01T12:00:00Z`
+pub(crate) fn format_timestamp(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86_400));
+    let seconds = timestamp.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Days since 1970-01-01 of a civil date, https://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Civil date as `(year, month, day)` from days since 1970-01-01, see `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
 /// A structure for allowing a multithreaded producer to inject
 /// edges into quadkey buckets with minimal lock contention
 pub(crate) struct ParallelQuadkeyMap {
@@ -105,7 +992,7 @@ impl ParallelQuadkeyMap {
             .lock()
             // Poisoned mutex implied segfault in other part of code, avoid handling this for now
             .unwrap();
-        let tile = table.entry(quadkey).or_insert(Tile::default());
+        let tile = table.entry(quadkey).or_default();
         tile.edges.push(edge);
     }
 
@@ -128,29 +1015,16 @@ impl ParallelQuadkeyMap {
     }
 }
 
-//// Next, populate the polylines of the ways
-//parsed_ways.map.ways.par_iter_mut().for_each(|way| {
-//    let coords = way
-//        .nodes
-//        .iter()
-//        .filter_map(|node_id| match node_table.get(&node_id) {
-//            Some(node) => Some(geo_types::coord! {
-//                x: node.loc.lon,
-//                y: node.loc.lat,
-//            }),
-//            None => {
-//                println!("ERR: Could not find node");
-//                None
-//            }
-//        });
-//    let line_string: geo_types::LineString<f64> = coords.collect();
-//    match polyline::encode_coordinates(line_string, 6) {
-//        Ok(polyline) => {
-//            //println!("DEBUG: Polyline {}", polyline);
-//            way.polyline = polyline;
-//        }
-//        Err(err) => {
-//            println!("ERR: Failed creating polyline: {:?}", err);
-//        }
-//    }
-//});
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_are_exact_below_a_thousand() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(30), "30");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1000), "1k");
+        assert_eq!(format_count(71_520), "71k");
+    }
+}